@@ -1,14 +1,22 @@
-use anyhow::Context;
 use flate2::{
     read::{GzDecoder, ZlibDecoder},
     write::ZlibEncoder,
 };
-use std::path::{Path, PathBuf};
+use std::{
+    borrow::Cow,
+    cell::RefCell,
+    collections::{BTreeMap, HashSet},
+    fs::File,
+    io::{BufWriter, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
 use std::{
     fmt::Display,
     io::{Read, Write},
 };
 
+use crate::{guard::WriteGuard, lz4java};
+
 const SECTOR_SIZE: usize = 4096;
 const MAX_CHUNK_NUM: usize = 1024;
 
@@ -18,249 +26,1380 @@ const COMPRESSION_KIND_RAW: u8 = 3;
 const COMPRESSION_KIND_LZ4: u8 = 4;
 const COMPRESSION_EXTERNAL: u8 = 128;
 
+/// The level of everything compressed again, pinned so identical inputs give identical bytes
+/// whatever the default of the flate2 version, the gzip headers have no timestamp either
+pub const COMPRESSION_LEVEL: flate2::Compression = flate2::Compression::new(6);
+
+/// Regions larger than this are remapped with `AnvilReader` and `AnvilWriter`
+pub const LOW_MEMORY_THRESHOLD: u64 = 64 * 1024 * 1024;
+
+/// The default size limit of an uncompressed chunk, larger chunks are passed through as is
+pub const MAX_CHUNK_SIZE: usize = 256 * 1024 * 1024;
+
+/// Chunks larger than this are logged, as compressing them again takes a while
+const LARGE_CHUNK_SIZE: usize = 32 * 1024 * 1024;
+
+/// The chunk uncompresses to more than the limit, it is not read any further
+/// A corrupted chunk may claim any size, so the limit bounds the memory a chunk can take
+#[derive(Debug)]
+pub struct OversizedChunk {
+    pub limit: usize,
+}
+
+impl std::fmt::Display for OversizedChunk {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Chunk is larger than {} bytes uncompressed", self.limit)
+    }
+}
+
+impl std::error::Error for OversizedChunk {}
+
+/// The length field of a chunk stored in the region exceeds the sectors of the header,
+/// reading it would take the bytes of the next chunk, e.g. after a third-party pruning tool
+#[derive(Debug)]
+pub struct LengthMismatch {
+    /// The length field, including the compression byte
+    pub chunk_len: usize,
+    /// The bytes of the sectors, excluding the length field
+    pub allocated: usize,
+}
+
+impl std::fmt::Display for LengthMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Chunk length {} exceeds the {} bytes left in its sectors",
+            self.chunk_len, self.allocated
+        )
+    }
+}
+
+impl std::error::Error for LengthMismatch {}
+
+/// The errors of the region files, the binary wraps them with the file and the chunk
+#[derive(Debug)]
+pub enum AnvilError {
+    /// The path has no file name to name the external chunks or the temporary file after,
+    /// e.g. it ends in `..`, the name is not UTF-8 or it is only the extension
+    InvalidFilename(PathBuf),
+    /// The file is shorter than the header, or the header points outside of the file
+    InvalidHeader(String),
+    /// A local location outside of the 32 by 32 chunks of a region
+    ChunkOutOfRange((i32, i32)),
+    /// The header has no entry at the location
+    ChunkNotPresent((i32, i32)),
+    /// The chunk is stored in an external file, which can't be read
+    ExternalChunkMissing {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    UnknownCompression(u8),
+    /// The data of the chunk doesn't uncompress with its compression
+    Corrupted {
+        compression: &'static str,
+        source: std::io::Error,
+    },
+    Oversized(OversizedChunk),
+    LengthMismatch(LengthMismatch),
+    Io(std::io::Error),
+}
+
+impl std::fmt::Display for AnvilError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AnvilError::InvalidFilename(path) => {
+                write!(f, "Invalid region file name {}", path.display())
+            }
+            AnvilError::InvalidHeader(reason) => write!(f, "{}", reason),
+            AnvilError::ChunkOutOfRange((x, z)) => {
+                write!(f, "Chunk location ({}, {}) out of range", x, z)
+            }
+            AnvilError::ChunkNotPresent((x, z)) => write!(f, "Chunk ({}, {}) not present", x, z),
+            AnvilError::ExternalChunkMissing { path, source } => {
+                write!(f, "Reading external chunk {}: {}", path.display(), source)
+            }
+            AnvilError::UnknownCompression(kind) => write!(f, "Unknown compression type {}", kind),
+            AnvilError::Corrupted {
+                compression,
+                source,
+            } => write!(f, "Uncompressing {}: {}", compression, source),
+            AnvilError::Oversized(err) => err.fmt(f),
+            AnvilError::LengthMismatch(err) => err.fmt(f),
+            AnvilError::Io(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for AnvilError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AnvilError::ExternalChunkMissing { source, .. }
+            | AnvilError::Corrupted { source, .. } => Some(source),
+            AnvilError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for AnvilError {
+    fn from(err: std::io::Error) -> Self {
+        AnvilError::Io(err)
+    }
+}
+
+impl From<OversizedChunk> for AnvilError {
+    fn from(err: OversizedChunk) -> Self {
+        AnvilError::Oversized(err)
+    }
+}
+
+impl From<LengthMismatch> for AnvilError {
+    fn from(err: LengthMismatch) -> Self {
+        AnvilError::LengthMismatch(err)
+    }
+}
+
+/// Check that the sectors of the header entry are in the file, after the header
+/// A chunk always takes a sector, so its length field can be read
+fn check_sectors(
+    index: usize,
+    offset: u32,
+    sector_count: u32,
+    len: usize,
+) -> Result<(), AnvilError> {
+    let (x, z) = index_location(index);
+    if offset < 2 {
+        return Err(AnvilError::InvalidHeader(format!(
+            "Invalid sector offset {} of chunk ({}, {}), inside the header",
+            offset, x, z
+        )));
+    }
+    if sector_count == 0 || (offset + sector_count) as usize * SECTOR_SIZE > len {
+        return Err(AnvilError::InvalidHeader(format!(
+            "Invalid sector count {} of chunk ({}, {})",
+            sector_count, x, z
+        )));
+    }
+    Ok(())
+}
+
+fn short_file(len: usize) -> AnvilError {
+    AnvilError::InvalidHeader(format!(
+        "Invalid file size {}, shorter than the header",
+        len
+    ))
+}
+
+fn invalid_length(index: usize) -> AnvilError {
+    let (x, z) = index_location(index);
+    AnvilError::InvalidHeader(format!("Invalid chunk length of chunk ({}, {})", x, z))
+}
+
+/// Check that the chunk fits in its sectors, the data of external chunks is not in the region
+fn check_allocation(chunk_len: usize, sectors: usize, compression: u8) -> Result<(), AnvilError> {
+    let allocated = (sectors * SECTOR_SIZE).saturating_sub(4);
+    if compression & COMPRESSION_EXTERNAL == 0 && chunk_len > allocated {
+        return Err(LengthMismatch {
+            chunk_len,
+            allocated,
+        }
+        .into());
+    }
+    Ok(())
+}
+
 pub struct Anvil {
     path: PathBuf,
     content: Vec<u8>,
+    guard: WriteGuard,
+    chunk_limit: usize,
+    externals: Externals,
 }
 
 #[derive(Debug)]
 pub struct Chunk {
     // Whether the chunk is stored in an external file originally
-    // If so, the external chunk will be deleted when the chunk is written
+    // If so, the external chunk will be deleted when the region is saved, unless it is still too large
     pub external: bool,
     pub location: (i32, i32),
     pub timestamp: i32,
     pub uncompressed: Vec<u8>,
+    /// Compressed with lz4, written back with lz4 in the same framing, other chunks are written
+    /// with zlib
+    pub lz4: Option<Lz4Format>,
+}
+
+/// The framing of an lz4 chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lz4Format {
+    /// The block stream of lz4-java, which the server writes
+    Java,
+    /// A standard LZ4 frame, which other tools may write
+    Frame,
+}
+
+impl Display for Chunk {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Chunk({}, {})", self.location.0, self.location.1)
+    }
+}
+
+/// The chunks of a region, either loaded in memory or read from the file on demand
+pub trait Region {
+    fn path(&self) -> &Path;
+
+    /// Local locations of all the chunks present in the region
+    fn locations(&self) -> Vec<(i32, i32)>;
+
+    /// The sector where the chunk data starts, according to the header
+    fn sector_offset(&self, location: (i32, i32)) -> Result<usize, AnvilError>;
+
+    /// The last modification time of the chunk, according to the header
+    fn timestamp(&self, location: (i32, i32)) -> Result<i32, AnvilError>;
+
+    /// Read and uncompress a chunk
+    fn read(&self, location: (i32, i32)) -> Result<Chunk, AnvilError>;
+
+    /// The stored bytes of a chunk, including the length field and the compression byte
+    fn raw(&self, location: (i32, i32)) -> Result<Cow<'_, [u8]>, AnvilError>;
+
+    /// The total length of the external files of the chunks stored externally
+    fn externals_len(&self) -> u64;
+
+    /// The length of the stored chunk with its external file, 0 if the header entry is invalid
+    fn stored_len(&self, location: (i32, i32)) -> u64;
+}
+
+/// The destination of a rebuilt region
+pub trait RegionWriter {
+    /// Write a chunk, on failure the region is left unchanged
+    fn write(&mut self, chunk: &Chunk) -> Result<(), AnvilError>;
+
+    /// Copy a chunk from another region as is, without uncompressing it
+    /// External chunk files are kept, or copied if `from` is another region
+    fn copy_chunk(&mut self, from: &impl Region, location: (i32, i32)) -> Result<(), AnvilError>;
+
+    /// The length of the region written so far, in bytes, with the external chunks to write
+    fn written_len(&self) -> usize;
+
+    /// Write the region through the guard
+    fn finish(self) -> Result<(), AnvilError>;
+}
+
+pub struct AnvilIter<'a> {
+    index: usize,
+    anvil: &'a Anvil,
+}
+
+fn index_location(index: usize) -> (i32, i32) {
+    ((index & 0x1F) as i32, ((index >> 5) & 0x1F) as i32)
+}
+
+fn location_index(location: (i32, i32)) -> Result<usize, AnvilError> {
+    let (x, z) = location;
+    if !(0..32).contains(&x) || !(0..32).contains(&z) {
+        return Err(AnvilError::ChunkOutOfRange(location));
+    }
+    Ok(z as usize * 32 + x as usize)
+}
+
+/// Parse the region coordinates from a file name like `r.1.-2.mca`
+pub fn region_coords(path: &Path) -> Option<(i32, i32)> {
+    let filename = path.file_name()?.to_str()?;
+    let mut parts = filename.split('.').skip(1);
+    let x = parts.next()?.parse().ok()?;
+    let z = parts.next()?.parse().ok()?;
+    Some((x, z))
+}
+
+/// Parse the world chunk coordinates from an external chunk file name like `c.5.-40.mcc`
+pub fn external_chunk_coords(path: &Path) -> Option<(i32, i32)> {
+    let filename = path.file_name()?.to_str()?;
+    if !filename.starts_with("c.") || !filename.ends_with(".mcc") {
+        return None;
+    }
+    region_coords(path)
+}
+
+/// The region file owning an external chunk file, the reverse of `external_location`
+pub fn owning_region(path: &Path) -> Option<PathBuf> {
+    if let Some((x, z)) = external_chunk_coords(path) {
+        return Some(path.with_file_name(format!("r.{}.{}.mca", x >> 5, z >> 5)));
+    }
+    let filename = path.file_name()?.to_str()?.strip_suffix(".mcc")?;
+    let mut parts = filename.rsplitn(3, '.');
+    let z = parts.next()?.parse::<i32>().ok()?;
+    let x = parts.next()?.parse::<i32>().ok()?;
+    let stem = parts.next().filter(|stem| !stem.is_empty())?;
+    location_index((x, z)).ok()?;
+    Some(path.with_file_name(format!("{}.mca", stem)))
+}
+
+/// The external chunk files of a region named after it, each with its name once the region is
+/// renamed to `to`; the files of a region with coordinates in its name are named after those
+pub fn renamed_externals(from: &Path, to: &Path) -> Vec<(PathBuf, PathBuf)> {
+    if region_coords(from).is_some() || from.extension().is_none_or(|ext| ext != "mca") {
+        return vec![];
+    }
+    (0..1024)
+        .map(index_location)
+        .filter_map(|location| {
+            let old = external_location(from, location).ok()?;
+            let new = external_location(to, location).ok()?;
+            old.exists().then_some((old, new))
+        })
+        .collect()
+}
+
+/// The length of the external file of the chunk, 0 if it is missing
+fn external_len(region: &Path, location: (i32, i32)) -> u64 {
+    external_location(region, location)
+        .ok()
+        .and_then(|path| std::fs::metadata(path).ok())
+        .map_or(0, |metadata| metadata.len())
+}
+
+/// The file name of the region, the other files of the region are named after it
+fn file_name(path: &Path) -> Result<&str, AnvilError> {
+    path.file_name()
+        .and_then(|s| s.to_str())
+        .filter(|name| !name.is_empty())
+        .ok_or_else(|| AnvilError::InvalidFilename(path.to_path_buf()))
+}
+
+/// Get the location of the external chunk file of a chunk in the region
+/// A region without coordinates in its name, e.g. `<uuid>.mca` of a plugin,
+/// keeps its external chunks in `<uuid>.<local x>.<local z>.mcc`
+fn external_location(region: &Path, local: (i32, i32)) -> Result<PathBuf, AnvilError> {
+    let filename = file_name(region)?;
+    let Some((x, z)) = region_coords(region) else {
+        let stem = filename.strip_suffix(".mca").unwrap_or(filename);
+        if stem.is_empty() {
+            return Err(AnvilError::InvalidFilename(region.to_path_buf()));
+        }
+        return Ok(region.with_file_name(format!("{}.{}.{}.mcc", stem, local.0, local.1)));
+    };
+    Ok(region.with_file_name(format!(
+        "c.{}.{}.mcc",
+        x as i64 * 32 + local.0 as i64,
+        z as i64 * 32 + local.1 as i64
+    )))
+}
+
+fn u32_at(header: &[u8], pos: usize) -> u32 {
+    u32::from_be_bytes(header[pos..pos + 4].try_into().unwrap())
+}
+
+fn has_entry(header: &[u8], index: usize) -> bool {
+    header[index * 4..index * 4 + 4] != [0; 4]
+}
+
+fn entry_locations(header: &[u8]) -> Vec<(i32, i32)> {
+    (0..MAX_CHUNK_NUM)
+        .filter(|&index| has_entry(header, index))
+        .map(index_location)
+        .collect()
+}
+
+/// Point the header entry at the stored bytes written at the offset
+fn set_entry(
+    header: &mut [u8],
+    index: usize,
+    offset: usize,
+    stored_len: usize,
+    timestamp: [u8; 4],
+) {
+    let sector_count = stored_len.div_ceil(SECTOR_SIZE);
+    header[index * 4..index * 4 + 4].copy_from_slice(
+        &((((offset / SECTOR_SIZE) as u32) << 8) | sector_count as u32).to_be_bytes(),
+    );
+    header[index * 4 + SECTOR_SIZE..index * 4 + SECTOR_SIZE + 4].copy_from_slice(&timestamp);
+}
+
+/// Uncompress the stored bytes after the length field, returning whether the chunk is external
+/// and its lz4 framing if it is lz4
+/// At most `limit` bytes are uncompressed, `OversizedChunk` if there are more
+/// `pending` is the external file not written yet, read from the disk if `None`
+fn decode_stored(
+    region: &Path,
+    guard: &WriteGuard,
+    location: (i32, i32),
+    stored: &[u8],
+    pending: Option<&[u8]>,
+    limit: usize,
+) -> Result<(bool, Option<Lz4Format>, Vec<u8>), AnvilError> {
+    let mut uncompressed = Vec::new();
+    let mut lz4 = None;
+    let mut compression_type = stored[0];
+    let mut external = false;
+    let external_data;
+    let compressed = if compression_type >= COMPRESSION_EXTERNAL {
+        compression_type -= COMPRESSION_EXTERNAL;
+        external_data = match pending {
+            Some(pending) => Cow::Borrowed(pending),
+            None => {
+                let path = external_location(region, location)?;
+                Cow::Owned(
+                    guard
+                        .retry()
+                        .read(&path)
+                        .map_err(|source| AnvilError::ExternalChunkMissing { path, source })?,
+                )
+            }
+        };
+        external = true;
+        &external_data[..]
+    } else {
+        &stored[1..]
+    };
+    // One more byte than the limit, to tell a chunk of exactly the limit from a larger one
+    let take = (limit as u64).saturating_add(1);
+    match compression_type {
+        COMPRESSION_KIND_GZIP => {
+            let mut decoder = GzDecoder::new(compressed).take(take);
+            decoder
+                .read_to_end(&mut uncompressed)
+                .map_err(|source| AnvilError::Corrupted {
+                    compression: "Gzip",
+                    source,
+                })?;
+        }
+        COMPRESSION_KIND_ZLIB => {
+            let mut decoder = ZlibDecoder::new(compressed).take(take);
+            decoder
+                .read_to_end(&mut uncompressed)
+                .map_err(|source| AnvilError::Corrupted {
+                    compression: "Zlib",
+                    source,
+                })?;
+        }
+        COMPRESSION_KIND_RAW => {
+            uncompressed
+                .extend_from_slice(&compressed[..compressed.len().min(limit.saturating_add(1))]);
+        }
+        // The server writes the block stream of lz4-java, other tools may write a standard frame
+        COMPRESSION_KIND_LZ4 => {
+            let corrupted = |source| AnvilError::Corrupted {
+                compression: "lz4",
+                source,
+            };
+            if lz4java::is_block_stream(compressed) {
+                uncompressed = lz4java::decode(compressed, limit).map_err(corrupted)?;
+                lz4 = Some(Lz4Format::Java);
+            } else {
+                let frame = lz4::Decoder::new(compressed)
+                    .and_then(|decoder| decoder.take(take).read_to_end(&mut uncompressed));
+                lz4 = Some(Lz4Format::Frame);
+                if let Err(err) = frame {
+                    uncompressed = lz4java::decode(compressed, limit).map_err(|block| {
+                        corrupted(std::io::Error::new(
+                            err.kind(),
+                            format!(
+                                "neither an LZ4 frame ({}) nor lz4-java blocks ({})",
+                                err, block
+                            ),
+                        ))
+                    })?;
+                    lz4 = Some(Lz4Format::Java);
+                }
+            }
+        }
+        kind => return Err(AnvilError::UnknownCompression(kind)),
+    }
+    if uncompressed.len() > limit {
+        return Err(OversizedChunk { limit }.into());
+    }
+    if uncompressed.len() > LARGE_CHUNK_SIZE {
+        log::info!(
+            "Chunk ({}, {}) in file {} is {} MiB uncompressed, it may take a while",
+            location.0,
+            location.1,
+            region.display(),
+            uncompressed.len() / 1024 / 1024
+        );
+    }
+    Ok((external, lz4, uncompressed))
+}
+
+/// Compress the chunk into the bytes stored in the region, the packing rules shared by all writers
+/// Chunks too large for the region are stored externally, the content of the external file is returned
+fn encode_chunk(chunk: &Chunk) -> Result<(Vec<u8>, Option<Vec<u8>>), AnvilError> {
+    let mut stored = 0u32.to_be_bytes().to_vec();
+    let kind = match chunk.lz4 {
+        Some(_) => COMPRESSION_KIND_LZ4,
+        None => COMPRESSION_KIND_ZLIB,
+    };
+    stored.push(kind);
+    match chunk.lz4 {
+        Some(Lz4Format::Java) => stored.extend(lz4java::encode(&chunk.uncompressed)?),
+        Some(Lz4Format::Frame) => {
+            let mut encoder = lz4::EncoderBuilder::new().build(&mut stored)?;
+            encoder.write_all(&chunk.uncompressed)?;
+            encoder.finish().1?;
+        }
+        None => {
+            let mut encoder = ZlibEncoder::new(&mut stored, COMPRESSION_LEVEL);
+            encoder.write_all(&chunk.uncompressed)?;
+            encoder.finish()?;
+        }
+    }
+    // Unlikely: If the chunk is too large, we need to move it to external file
+    let mut external = None;
+    if stored.len().div_ceil(SECTOR_SIZE) > u8::MAX as usize {
+        external = Some(stored.split_off(5));
+        stored.truncate(4);
+        stored.push(COMPRESSION_EXTERNAL + kind);
+    }
+    let len = (stored.len() - 4) as u32;
+    stored[..4].copy_from_slice(&len.to_be_bytes());
+    Ok((stored, external))
+}
+
+/// The external chunk files of a region being written, applied when the region is saved
+/// They are written after the region, so an interrupted save never leaves new external files next
+/// to the old header, then the files the saved header no longer references are removed
+#[derive(Debug, Default)]
+struct Externals {
+    /// The content of the external files to write
+    pending: BTreeMap<(i32, i32), Payload>,
+    /// The chunks stored externally in the region
+    stored: HashSet<(i32, i32)>,
+    /// The chunks read from an external file, the others with one left are stale
+    read: HashSet<(i32, i32)>,
+    /// Spill the payloads to temporary files instead of holding them, for `AnvilWriter`
+    streamed: bool,
+    /// What was recorded for the chunk being written, put back if the write fails
+    saved: Option<Saved>,
+}
+
+/// What `Externals` records for a chunk
+#[derive(Debug)]
+struct Saved {
+    location: (i32, i32),
+    payload: Option<Payload>,
+    stored: bool,
+    read: bool,
+}
+
+/// The content of an external file to write
+#[derive(Debug)]
+enum Payload {
+    Loaded(Vec<u8>),
+    /// Written to a temporary file next to it, of the length, moved in place when saved
+    Spilled(PathBuf, usize),
+}
+
+impl Payload {
+    fn len(&self) -> usize {
+        match self {
+            Payload::Loaded(content) => content.len(),
+            Payload::Spilled(_, len) => *len,
+        }
+    }
+}
+
+impl Externals {
+    /// The chunks stored externally in the loaded region, their files are kept
+    fn loaded(content: &[u8]) -> Self {
+        let stored = entry_locations(content)
+            .into_iter()
+            .filter(|&(x, z)| {
+                let index = z as usize * 32 + x as usize;
+                let start = (u32_at(content, index * 4) >> 8) as usize * SECTOR_SIZE;
+                content
+                    .get(start + 4)
+                    .is_some_and(|&kind| kind >= COMPRESSION_EXTERNAL)
+            })
+            .collect();
+        Self {
+            stored,
+            ..Default::default()
+        }
+    }
+
+    /// The payloads are spilled to temporary files as they are written
+    fn streamed() -> Self {
+        Self {
+            streamed: true,
+            ..Default::default()
+        }
+    }
+
+    /// Record a chunk written to the region, with the external file if it is too large
+    fn written(
+        &mut self,
+        guard: &WriteGuard,
+        region: &Path,
+        chunk: &Chunk,
+        external: Option<Vec<u8>>,
+    ) -> Result<(), AnvilError> {
+        let location = chunk.location;
+        match external {
+            Some(external) => {
+                log::info!(
+                    "Chunk is too large, moved to external file {}",
+                    external_location(region, location)?.display()
+                );
+                self.insert(guard, region, location, &mut external.as_slice())?;
+                self.stored.insert(location);
+            }
+            None => {
+                self.remove(guard, location);
+                self.stored.remove(&location);
+            }
+        }
+        if chunk.external {
+            self.read.insert(location);
+        }
+        Ok(())
+    }
+
+    /// Record a chunk copied as is from the region `from`, its external file is kept, or copied
+    /// if `from` is another region
+    fn copied(
+        &mut self,
+        guard: &WriteGuard,
+        raw: &[u8],
+        region: &Path,
+        from: &Path,
+        location: (i32, i32),
+    ) -> Result<(), AnvilError> {
+        if raw.get(4).is_none_or(|&kind| kind < COMPRESSION_EXTERNAL) {
+            self.remove(guard, location);
+            self.stored.remove(&location);
+            return Ok(());
+        }
+        if from != region {
+            let path = external_location(from, location)?;
+            let missing = |source| AnvilError::ExternalChunkMissing {
+                path: path.clone(),
+                source,
+            };
+            let mut file = guard
+                .retry()
+                .run(&path, || File::open(&path))
+                .map_err(missing)?;
+            self.insert(guard, region, location, &mut file)?;
+        } else {
+            self.remove(guard, location);
+        }
+        self.stored.insert(location);
+        self.read.insert(location);
+        Ok(())
+    }
+
+    /// Hold the content of the external file, or spill it if streamed
+    fn insert(
+        &mut self,
+        guard: &WriteGuard,
+        region: &Path,
+        location: (i32, i32),
+        content: &mut impl Read,
+    ) -> Result<(), AnvilError> {
+        // A payload spilled before for the chunk is removed first, it may be staged
+        self.remove(guard, location);
+        let payload = if self.streamed {
+            let path = external_location(region, location)?;
+            let mut temp = path.with_file_name(format!("{}.tmp", file_name(&path)?));
+            // The saved payload is kept until the write is settled
+            if self.saved_temp() == Some(&temp) {
+                temp = path.with_file_name(format!("{}.1.tmp", file_name(&path)?));
+            }
+            let len = match guard.create(&temp)? {
+                Some(mut file) => std::io::copy(content, &mut file),
+                None => std::io::copy(content, &mut std::io::sink()),
+            };
+            let len = len.inspect_err(|_| discard_spilled(guard, &temp))?;
+            Payload::Spilled(temp, len as usize)
+        } else {
+            let mut loaded = vec![];
+            content.read_to_end(&mut loaded)?;
+            Payload::Loaded(loaded)
+        };
+        self.pending.insert(location, payload);
+        Ok(())
+    }
+
+    /// Take out what is recorded for the chunk before writing it, see `settle`
+    fn save(&mut self, location: (i32, i32)) {
+        self.saved = Some(Saved {
+            location,
+            payload: self.pending.remove(&location),
+            stored: self.stored.contains(&location),
+            read: self.read.contains(&location),
+        });
+    }
+
+    /// Keep what the write of the saved chunk recorded, or put back what was saved if it failed
+    fn settle(&mut self, guard: &WriteGuard, written: bool) {
+        let Some(saved) = self.saved.take() else {
+            return;
+        };
+        if written {
+            if let Some(Payload::Spilled(temp, _)) = &saved.payload {
+                discard_spilled(guard, temp);
+            }
+            return;
+        }
+        let location = saved.location;
+        self.remove(guard, location);
+        if let Some(payload) = saved.payload {
+            self.pending.insert(location, payload);
+        }
+        for (set, recorded) in [
+            (&mut self.stored, saved.stored),
+            (&mut self.read, saved.read),
+        ] {
+            match recorded {
+                true => set.insert(location),
+                false => set.remove(&location),
+            };
+        }
+    }
+
+    /// The temporary file of the saved payload, if it is spilled
+    fn saved_temp(&self) -> Option<&PathBuf> {
+        match self.saved.as_ref()?.payload.as_ref()? {
+            Payload::Spilled(temp, _) => Some(temp),
+            Payload::Loaded(_) => None,
+        }
+    }
+
+    /// Forget the external file to write, removing its spilled payload
+    fn remove(&mut self, guard: &WriteGuard, location: (i32, i32)) {
+        if let Some(Payload::Spilled(temp, _)) = self.pending.remove(&location) {
+            discard_spilled(guard, &temp);
+        }
+    }
+
+    /// The content of the external file to write, if it is held
+    fn held(&self, location: (i32, i32)) -> Option<&[u8]> {
+        match self.pending.get(&location)? {
+            Payload::Loaded(content) => Some(content),
+            Payload::Spilled(..) => None,
+        }
+    }
+
+    /// The length of the external files to write
+    fn pending_len(&self) -> usize {
+        self.pending.values().map(Payload::len).sum()
+    }
+
+    /// Write the external files of the region, after the region
+    fn write(&self, guard: &WriteGuard, region: &Path) -> Result<(), AnvilError> {
+        for (&location, payload) in &self.pending {
+            let path = external_location(region, location)?;
+            match payload {
+                Payload::Loaded(content) => guard.write(&path, content)?,
+                Payload::Spilled(temp, _) => guard.replace(temp, &path)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove the spilled payloads of a region never saved
+    fn discard(&self, guard: &WriteGuard) {
+        for payload in self.pending.values() {
+            if let Payload::Spilled(temp, _) = payload {
+                discard_spilled(guard, temp);
+            }
+        }
+    }
+
+    /// Remove the external files of the region the saved header does not reference, after the
+    /// region and its external files, only the file of each chunk stored in the region is checked
+    fn clean(&self, guard: &WriteGuard, region: &Path) -> Result<(), AnvilError> {
+        for location in (0..MAX_CHUNK_NUM).map(index_location) {
+            if self.stored.contains(&location) {
+                continue;
+            }
+            let path = external_location(region, location)?;
+            if path.symlink_metadata().is_err() {
+                continue;
+            }
+            if self.read.contains(&location) {
+                log::info!(
+                    "Chunk is previously in external file {}, but now moved to internal",
+                    path.display()
+                );
+            } else {
+                log::warn!(
+                    "Removing {}, a stale external chunk file not referenced by the region",
+                    path.display()
+                );
+            }
+            guard.remove_file(&path)?;
+        }
+        Ok(())
+    }
+}
+
+/// Remove a spilled payload, missing in dry-run mode or once moved in place
+fn discard_spilled(guard: &WriteGuard, temp: &Path) {
+    if guard.is_dry_run() {
+        return;
+    }
+    match guard.discard(temp) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+            log::warn!("Failed to remove {}: {}", temp.display(), err)
+        }
+        _ => {}
+    }
+}
+
+impl<'a> Iterator for AnvilIter<'a> {
+    type Item = Result<Chunk, AnvilError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.index < MAX_CHUNK_NUM && !has_entry(&self.anvil.content, self.index) {
+            self.index += 1;
+        }
+        if self.index == MAX_CHUNK_NUM {
+            return None;
+        }
+        let ret = self.anvil.read(index_location(self.index));
+        self.index += 1;
+        Some(ret)
+    }
+}
+
+impl Anvil {
+    /// Get the start, the length (excluding the 4-byte length field) of the chunk data and the
+    /// number of sectors
+    fn chunk_range(&self, index: usize) -> Result<(usize, usize, usize), AnvilError> {
+        let offset = u32_at(&self.content, index * 4);
+        let (offset, sector_count) = (offset >> 8, offset & 0xFF);
+        let start = offset as usize * SECTOR_SIZE;
+        check_sectors(index, offset, sector_count, self.content.len())?;
+        let chunk_len = u32_at(&self.content, start) as usize;
+        // A length past the sectors is a mismatch left to the callers, the sectors are in the file
+        let allocated =
+            check_allocation(chunk_len, sector_count as usize, self.content[start + 4]).is_ok();
+        if allocated && (start + chunk_len + 4 > self.content.len() || chunk_len < 1) {
+            return Err(invalid_length(index));
+        }
+        Ok((start, chunk_len, sector_count as usize))
+    }
+
+    fn read_index(&self, index: usize) -> Result<Chunk, AnvilError> {
+        let location = index_location(index);
+        let timestamp = u32_at(&self.content, index * 4 + SECTOR_SIZE) as i32;
+        let (start, chunk_len, sectors) = self.chunk_range(index)?;
+        check_allocation(chunk_len, sectors, self.content[start + 4])?;
+        let (external, lz4, uncompressed) = decode_stored(
+            &self.path,
+            &self.guard,
+            location,
+            &self.content[start + 4..start + chunk_len + 4],
+            self.externals.held(location),
+            self.chunk_limit,
+        )?;
+        Ok(Chunk {
+            external,
+            location,
+            timestamp,
+            uncompressed,
+            lz4,
+        })
+    }
+
+    /// Local locations of all the chunks present in the anvil
+    pub fn locations(&self) -> Vec<(i32, i32)> {
+        entry_locations(&self.content)
+    }
+
+    /// Read and uncompress a chunk
+    pub fn read(&self, location: (i32, i32)) -> Result<Chunk, AnvilError> {
+        let index = location_index(location)?;
+        if !has_entry(&self.content, index) {
+            return Err(AnvilError::ChunkNotPresent(location));
+        }
+        self.read_index(index)
+    }
+
+    /// The sector where the chunk data starts, according to the header
+    pub fn sector_offset(&self, location: (i32, i32)) -> Result<usize, AnvilError> {
+        let index = location_index(location)?;
+        Ok((u32_at(&self.content, index * 4) >> 8) as usize)
+    }
+
+    /// The last modification time of the chunk, according to the header
+    pub fn timestamp(&self, location: (i32, i32)) -> Result<i32, AnvilError> {
+        let index = location_index(location)?;
+        Ok(u32_at(&self.content, index * 4 + SECTOR_SIZE) as i32)
+    }
+
+    /// The stored bytes of a chunk, including the length field and the compression byte
+    /// A chunk longer than its sectors is cut to them, so it is copied as it is stored
+    pub fn raw(&self, location: (i32, i32)) -> Result<&[u8], AnvilError> {
+        let (start, chunk_len, sectors) = self.chunk_range(location_index(location)?)?;
+        if check_allocation(chunk_len, sectors, self.content[start + 4]).is_err() {
+            return Ok(&self.content[start..start + sectors * SECTOR_SIZE]);
+        }
+        Ok(&self.content[start..start + chunk_len + 4])
+    }
+
+    /// Copy a chunk from another region as is, without uncompressing it
+    /// External chunk files are kept, or copied if `from` is another region
+    pub fn copy_chunk(
+        &mut self,
+        from: &impl Region,
+        location: (i32, i32),
+    ) -> Result<(), AnvilError> {
+        let index = location_index(location)?;
+        let raw = from.raw(location)?;
+        let timestamp = from.timestamp(location)?;
+        self.externals.save(location);
+        let copied = self
+            .externals
+            .copied(&self.guard, &raw, &self.path, from.path(), location);
+        self.externals.settle(&self.guard, copied.is_ok());
+        copied?;
+        let offset = self.align();
+        self.content.extend_from_slice(&raw);
+        set_entry(
+            &mut self.content,
+            index,
+            offset,
+            raw.len(),
+            timestamp.to_be_bytes(),
+        );
+        self.align();
+        Ok(())
+    }
+
+    /// Open an anvil file
+    pub fn open(path: &Path) -> Result<Self, AnvilError> {
+        Self::open_with_guard(path, WriteGuard::default())
+    }
+
+    /// Open an anvil file, reading the file and the external chunks with the retry policy of the guard
+    pub fn open_with_guard(path: &Path, guard: WriteGuard) -> Result<Self, AnvilError> {
+        file_name(path)?;
+        let mut inner = guard.retry().read(path)?;
+        inner.resize(
+            (inner.len() + SECTOR_SIZE - 1) / SECTOR_SIZE * SECTOR_SIZE,
+            0,
+        );
+        if inner.len() < 2 * SECTOR_SIZE {
+            return Err(short_file(inner.len()));
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+            externals: Externals::loaded(&inner),
+            content: inner,
+            guard,
+            chunk_limit: MAX_CHUNK_SIZE,
+        })
+    }
+
+    /// Save the anvil file with the external chunks, see `Externals` for the order
+    /// The region is only written under a file name, never e.g. over a directory ending in `..`
+    pub fn save(&self) -> Result<(), AnvilError> {
+        file_name(&self.path)?;
+        self.guard.write(&self.path, &self.content)?;
+        self.externals.write(&self.guard, &self.path)?;
+        self.externals.clean(&self.guard, &self.path)?;
+        Ok(())
+    }
+
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            content: vec![0; SECTOR_SIZE * 2],
+            guard: WriteGuard::default(),
+            chunk_limit: MAX_CHUNK_SIZE,
+            externals: Externals::default(),
+        }
+    }
+
+    /// Write the anvil and the external chunks through the guard
+    pub fn with_guard(mut self, guard: WriteGuard) -> Self {
+        self.guard = guard;
+        self
+    }
+
+    /// Refuse to uncompress chunks larger than the limit, `MAX_CHUNK_SIZE` by default
+    pub fn with_chunk_limit(mut self, limit: usize) -> Self {
+        self.chunk_limit = limit;
+        self
+    }
+
+    pub fn align(&mut self) -> usize {
+        let len = self.content.len();
+        let align = (len + SECTOR_SIZE - 1) / SECTOR_SIZE * SECTOR_SIZE;
+        self.content.resize(align, 0);
+        align
+    }
+
+    pub fn iter(&self) -> AnvilIter {
+        AnvilIter {
+            index: 0,
+            anvil: self,
+        }
+    }
+
+    /// Write a chunk, on failure the anvil is left unchanged
+    pub fn write(&mut self, chunk: &Chunk) -> Result<(), AnvilError> {
+        let index = location_index(chunk.location)?;
+        let (stored, external) = encode_chunk(chunk)?;
+        self.externals.save(chunk.location);
+        let written = self
+            .externals
+            .written(&self.guard, &self.path, chunk, external);
+        self.externals.settle(&self.guard, written.is_ok());
+        written?;
+        let start = self.align();
+        self.content.extend_from_slice(&stored);
+        set_entry(
+            &mut self.content,
+            index,
+            start,
+            stored.len(),
+            chunk.timestamp.to_be_bytes(),
+        );
+        self.align();
+        Ok(())
+    }
+}
+
+impl Region for Anvil {
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn locations(&self) -> Vec<(i32, i32)> {
+        Anvil::locations(self)
+    }
+
+    fn sector_offset(&self, location: (i32, i32)) -> Result<usize, AnvilError> {
+        Anvil::sector_offset(self, location)
+    }
+
+    fn timestamp(&self, location: (i32, i32)) -> Result<i32, AnvilError> {
+        Anvil::timestamp(self, location)
+    }
+
+    fn read(&self, location: (i32, i32)) -> Result<Chunk, AnvilError> {
+        Anvil::read(self, location)
+    }
+
+    fn raw(&self, location: (i32, i32)) -> Result<Cow<'_, [u8]>, AnvilError> {
+        Anvil::raw(self, location).map(Cow::Borrowed)
+    }
+
+    fn externals_len(&self) -> u64 {
+        self.externals
+            .stored
+            .iter()
+            .map(|&location| external_len(&self.path, location))
+            .sum()
+    }
+
+    fn stored_len(&self, location: (i32, i32)) -> u64 {
+        let range = location_index(location).and_then(|index| self.chunk_range(index));
+        let Ok((start, chunk_len, _)) = range else {
+            return 0;
+        };
+        match self.content[start + 4] >= COMPRESSION_EXTERNAL {
+            true => chunk_len as u64 + external_len(&self.path, location),
+            false => chunk_len as u64,
+        }
+    }
 }
 
-impl Display for Chunk {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Chunk({}, {})", self.location.0, self.location.1)
+impl RegionWriter for Anvil {
+    fn write(&mut self, chunk: &Chunk) -> Result<(), AnvilError> {
+        Anvil::write(self, chunk)
+    }
+
+    fn copy_chunk(&mut self, from: &impl Region, location: (i32, i32)) -> Result<(), AnvilError> {
+        Anvil::copy_chunk(self, from, location)
+    }
+
+    fn written_len(&self) -> usize {
+        self.content.len() + self.externals.pending_len()
+    }
+
+    fn finish(self) -> Result<(), AnvilError> {
+        self.save()
     }
 }
 
-pub struct AnvilIter<'a> {
-    index: usize,
-    anvil: &'a Anvil,
+/// A region read from the file on demand, only the header is kept in memory
+pub struct AnvilReader {
+    path: PathBuf,
+    header: Vec<u8>,
+    /// The length of the file rounded up to sectors, like the padded content of `Anvil`
+    len: usize,
+    file: RefCell<File>,
+    guard: WriteGuard,
+    chunk_limit: usize,
 }
 
-impl AnvilIter<'_> {
-    fn peak(&mut self) -> anyhow::Result<Chunk> {
-        macro_rules! u32_at {
-            ($pos:expr) => {
-                u32::from_be_bytes(self.anvil.content[$pos..$pos + 4].try_into().unwrap())
-            };
+impl AnvilReader {
+    /// Open an anvil file, reading the file and the external chunks with the retry policy of the guard
+    pub fn open_with_guard(path: &Path, guard: WriteGuard) -> Result<Self, AnvilError> {
+        file_name(path)?;
+        let file = guard.retry().run(path, || File::open(path))?;
+        let len = guard.retry().run(path, || file.metadata())?.len() as usize;
+        let mut reader = Self {
+            path: path.to_path_buf(),
+            header: vec![],
+            len: len.div_ceil(SECTOR_SIZE) * SECTOR_SIZE,
+            file: RefCell::new(file),
+            guard,
+            chunk_limit: MAX_CHUNK_SIZE,
+        };
+        if reader.len < 2 * SECTOR_SIZE {
+            return Err(short_file(len));
         }
+        reader.header = reader.read_at(0, 2 * SECTOR_SIZE)?;
+        Ok(reader)
+    }
 
-        // Read chunk metadata
-        let location = (
-            (self.index & 0x1F) as i32,
-            ((self.index >> 5) & 0x1F) as i32,
-        );
-        let offset = u32_at!(self.index * 4);
+    /// Refuse to uncompress chunks larger than the limit, `MAX_CHUNK_SIZE` by default
+    pub fn with_chunk_limit(mut self, limit: usize) -> Self {
+        self.chunk_limit = limit;
+        self
+    }
+
+    /// Read the bytes at the position, the bytes past the end of the file are zeros
+    fn read_at(&self, start: usize, len: usize) -> Result<Vec<u8>, AnvilError> {
+        let mut file = self.file.borrow_mut();
+        let mut buf = Vec::with_capacity(len);
+        self.guard.retry().run(&self.path, || {
+            buf.clear();
+            file.seek(SeekFrom::Start(start as u64))?;
+            (&mut *file).take(len as u64).read_to_end(&mut buf)
+        })?;
+        buf.resize(len, 0);
+        Ok(buf)
+    }
+
+    /// Get the start, the length (excluding the 4-byte length field) of the chunk data, the
+    /// number of sectors and the compression byte
+    fn chunk_range(&self, index: usize) -> Result<(usize, usize, usize, u8), AnvilError> {
+        let offset = u32_at(&self.header, index * 4);
         let (offset, sector_count) = (offset >> 8, offset & 0xFF);
-        let timestamp = u32_at!(self.index * 4 + SECTOR_SIZE) as i32;
         let start = offset as usize * SECTOR_SIZE;
-        if start + SECTOR_SIZE * sector_count as usize > self.anvil.content.len() {
-            anyhow::bail!("Invalid sector count");
-        }
-        let chunk_len = u32_at!(start) as usize;
-        if start + chunk_len + 4 > self.anvil.content.len() || chunk_len < 1 {
-            anyhow::bail!("Invalid chunk length");
-        }
-
-        // Uncompress chunk
-        let mut uncompressed = Vec::new();
-        let mut compression_type = self.anvil.content[start + 4];
-        let mut external = false;
-        let external_data;
-        let compressed = if compression_type >= COMPRESSION_EXTERNAL {
-            compression_type -= COMPRESSION_EXTERNAL;
-            let external_path = self.anvil.external_location(location)?;
-            external_data = std::fs::read(external_path).context("Reading external chunk")?;
-            external = true;
-            &external_data
-        } else {
-            &self.anvil.content[start + 5..start + chunk_len + 4]
-        };
-        match compression_type {
-            COMPRESSION_KIND_GZIP => {
-                let mut decoder = GzDecoder::new(compressed);
-                decoder
-                    .read_to_end(&mut uncompressed)
-                    .context("Uncompressing Gzip")?;
-            }
-            COMPRESSION_KIND_ZLIB => {
-                let mut decoder = ZlibDecoder::new(compressed);
-                decoder
-                    .read_to_end(&mut uncompressed)
-                    .context("Uncompressing Zlib")?;
-            }
-            COMPRESSION_KIND_RAW => {
-                uncompressed.extend_from_slice(compressed);
-            }
-            COMPRESSION_KIND_LZ4 => {
-                let mut decoder = lz4::Decoder::new(compressed).context("Uncompressing lz4")?;
-                decoder
-                    .read_to_end(&mut uncompressed)
-                    .context("Uncompressing lz4")?;
-            }
-            _ => anyhow::bail!("Unknown compression type"),
+        check_sectors(index, offset, sector_count, self.len)?;
+        let head = self.read_at(start, 5)?;
+        let chunk_len = u32_at(&head, 0) as usize;
+        // A length past the sectors is a mismatch left to the callers, the sectors are in the file
+        let allocated = check_allocation(chunk_len, sector_count as usize, head[4]).is_ok();
+        if allocated && (start + chunk_len + 4 > self.len || chunk_len < 1) {
+            return Err(invalid_length(index));
         }
+        Ok((start, chunk_len, sector_count as usize, head[4]))
+    }
+
+    fn read_index(&self, index: usize) -> Result<Chunk, AnvilError> {
+        let location = index_location(index);
+        let timestamp = u32_at(&self.header, index * 4 + SECTOR_SIZE) as i32;
+        let (start, chunk_len, sectors, compression) = self.chunk_range(index)?;
+        check_allocation(chunk_len, sectors, compression)?;
+        let stored = self.read_at(start + 4, chunk_len)?;
+        let (external, lz4, uncompressed) = decode_stored(
+            &self.path,
+            &self.guard,
+            location,
+            &stored,
+            None,
+            self.chunk_limit,
+        )?;
         Ok(Chunk {
             external,
             location,
             timestamp,
             uncompressed,
+            lz4,
         })
     }
 }
 
-impl<'a> Iterator for AnvilIter<'a> {
-    type Item = anyhow::Result<Chunk>;
+impl Region for AnvilReader {
+    fn path(&self) -> &Path {
+        &self.path
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        while self.index < MAX_CHUNK_NUM
-            && self.anvil.content[self.index * 4..self.index * 4 + 4] == [0; 4]
-        {
-            self.index += 1;
+    fn locations(&self) -> Vec<(i32, i32)> {
+        entry_locations(&self.header)
+    }
+
+    fn sector_offset(&self, location: (i32, i32)) -> Result<usize, AnvilError> {
+        let index = location_index(location)?;
+        Ok((u32_at(&self.header, index * 4) >> 8) as usize)
+    }
+
+    fn timestamp(&self, location: (i32, i32)) -> Result<i32, AnvilError> {
+        let index = location_index(location)?;
+        Ok(u32_at(&self.header, index * 4 + SECTOR_SIZE) as i32)
+    }
+
+    fn read(&self, location: (i32, i32)) -> Result<Chunk, AnvilError> {
+        let index = location_index(location)?;
+        if !has_entry(&self.header, index) {
+            return Err(AnvilError::ChunkNotPresent(location));
         }
-        if self.index == MAX_CHUNK_NUM {
-            return None;
+        self.read_index(index)
+    }
+
+    fn raw(&self, location: (i32, i32)) -> Result<Cow<'_, [u8]>, AnvilError> {
+        let (start, chunk_len, sectors, compression) =
+            self.chunk_range(location_index(location)?)?;
+        if check_allocation(chunk_len, sectors, compression).is_err() {
+            return Ok(Cow::Owned(self.read_at(start, sectors * SECTOR_SIZE)?));
         }
-        let ret = self.peak().with_context(|| {
-            let (x, z) = (
-                (self.index & 0x1F) as i32,
-                ((self.index >> 5) & 0x1F) as i32,
-            );
-            format!(
-                "Failed to read chunk ({}, {}) in file {}",
-                x,
-                z,
-                self.anvil.path.display()
-            )
-        });
-        self.index += 1;
-        Some(ret)
+        Ok(Cow::Owned(self.read_at(start, chunk_len + 4)?))
     }
-}
 
-impl Anvil {
-    /// Get the global location of the anvil file
-    fn external_location(&self, local: (i32, i32)) -> anyhow::Result<PathBuf> {
-        let filename = self
-            .path
-            .file_name()
-            .and_then(|s| s.to_str())
-            .context("Invalid file name")?;
-        let mut parts = filename.split('.').skip(1);
-        let x = parts
-            .next()
-            .and_then(|s| s.parse::<i64>().ok())
-            .context("Invalid x coordinate")?;
-        let z = parts
-            .next()
-            .and_then(|s| s.parse::<i64>().ok())
-            .context("Invalid z coordinate")?;
-        Ok(self.path.with_file_name(format!(
-            "c.{}.{}.mcc",
-            x * 32 + local.0 as i64,
-            z * 32 + local.1 as i64
-        )))
+    fn externals_len(&self) -> u64 {
+        self.locations()
+            .into_iter()
+            .filter(|&location| {
+                location_index(location)
+                    .and_then(|index| self.chunk_range(index))
+                    .is_ok_and(|(.., compression)| compression >= COMPRESSION_EXTERNAL)
+            })
+            .map(|location| external_len(&self.path, location))
+            .sum()
     }
 
-    /// Open an anvil file
-    pub fn open(path: &Path) -> anyhow::Result<Self> {
-        let mut inner = std::fs::read(path)?;
-        inner.resize(
-            (inner.len() + SECTOR_SIZE - 1) / SECTOR_SIZE * SECTOR_SIZE,
-            0,
-        );
-        if inner.len() < 2 * SECTOR_SIZE {
-            anyhow::bail!("Invalid file size");
+    fn stored_len(&self, location: (i32, i32)) -> u64 {
+        let range = location_index(location).and_then(|index| self.chunk_range(index));
+        let Ok((_, chunk_len, _, compression)) = range else {
+            return 0;
+        };
+        match compression >= COMPRESSION_EXTERNAL {
+            true => chunk_len as u64 + external_len(&self.path, location),
+            false => chunk_len as u64,
+        }
+    }
+}
+
+/// A region written chunk by chunk to a temporary file next to it, packed exactly like `Anvil`
+/// The header is reserved up front and patched by `finish`, which then moves the file in place
+pub struct AnvilWriter {
+    path: PathBuf,
+    temp: PathBuf,
+    /// `None` in dry-run mode
+    file: Option<BufWriter<File>>,
+    header: Vec<u8>,
+    /// The length written so far, always a multiple of the sector size
+    len: usize,
+    guard: WriteGuard,
+    externals: Externals,
+}
+
+impl AnvilWriter {
+    /// Write the region and the external chunks through the guard
+    pub fn create(path: &Path, guard: WriteGuard) -> Result<Self, AnvilError> {
+        let temp = path.with_file_name(format!("{}.tmp", file_name(path)?));
+        let header = vec![0; SECTOR_SIZE * 2];
+        let mut file = guard.create(&temp)?.map(BufWriter::new);
+        if let Some(file) = &mut file {
+            file.write_all(&header)?;
         }
         Ok(Self {
             path: path.to_path_buf(),
-            content: inner,
+            temp,
+            file,
+            len: header.len(),
+            header,
+            guard,
+            externals: Externals::streamed(),
         })
     }
 
-    /// Save the anvil file, except for the external chunks, which is saved when the chunk is written
-    pub fn save(&self) -> anyhow::Result<()> {
-        std::fs::write(&self.path, &self.content)?;
+    /// Append the stored bytes padded to sectors, and point the header entry at them
+    fn append(&mut self, index: usize, stored: &[u8], timestamp: i32) -> Result<(), AnvilError> {
+        let padded = stored.len().div_ceil(SECTOR_SIZE) * SECTOR_SIZE;
+        if let Some(file) = &mut self.file {
+            file.write_all(stored)?;
+            file.write_all(&vec![0; padded - stored.len()])?;
+        }
+        set_entry(
+            &mut self.header,
+            index,
+            self.len,
+            stored.len(),
+            timestamp.to_be_bytes(),
+        );
+        self.len += padded;
         Ok(())
     }
+}
 
-    pub fn new(path: &Path) -> Self {
-        Self {
-            path: path.to_path_buf(),
-            content: vec![0; SECTOR_SIZE * 2],
-        }
+impl RegionWriter for AnvilWriter {
+    fn write(&mut self, chunk: &Chunk) -> Result<(), AnvilError> {
+        let index = location_index(chunk.location)?;
+        let (stored, external) = encode_chunk(chunk)?;
+        self.externals.save(chunk.location);
+        let written = self
+            .externals
+            .written(&self.guard, &self.path, chunk, external)
+            .and_then(|()| self.append(index, &stored, chunk.timestamp));
+        self.externals.settle(&self.guard, written.is_ok());
+        written
     }
 
-    pub fn align(&mut self) -> usize {
-        let len = self.content.len();
-        let align = (len + SECTOR_SIZE - 1) / SECTOR_SIZE * SECTOR_SIZE;
-        self.content.resize(align, 0);
-        align
+    fn copy_chunk(&mut self, from: &impl Region, location: (i32, i32)) -> Result<(), AnvilError> {
+        let index = location_index(location)?;
+        let raw = from.raw(location)?;
+        let timestamp = from.timestamp(location)?;
+        self.externals.save(location);
+        let copied = self
+            .externals
+            .copied(&self.guard, &raw, &self.path, from.path(), location)
+            .and_then(|()| self.append(index, &raw, timestamp));
+        self.externals.settle(&self.guard, copied.is_ok());
+        copied
     }
 
-    pub fn iter(&self) -> AnvilIter {
-        AnvilIter {
-            index: 0,
-            anvil: self,
-        }
+    fn written_len(&self) -> usize {
+        self.len + self.externals.pending_len()
     }
 
-    pub fn write(&mut self, chunk: &Chunk) -> anyhow::Result<()> {
-        let Chunk {
-            external,
-            location,
-            timestamp,
-            uncompressed,
-        } = chunk;
-        let index = location.1 as usize * 32 + location.0 as usize;
-        self.content[index * 4 + SECTOR_SIZE..index * 4 + SECTOR_SIZE + 4]
-            .copy_from_slice(&timestamp.to_be_bytes());
-        self.content.extend_from_slice(&0u32.to_be_bytes());
-        let start = self.content.len();
-        self.content.push(COMPRESSION_KIND_ZLIB);
-        let mut encoder = ZlibEncoder::new(&mut self.content, flate2::Compression::default());
-        encoder.write_all(uncompressed)?;
-        encoder.finish()?;
-        let end = self.content.len();
-        let mut len = end - start;
-        let mut sector_count = (len + 4).div_ceil(SECTOR_SIZE);
-        // Unlikely: If the chunk is too large, we need to move it to external file
-        if sector_count > u8::MAX as usize {
-            let external_path = self.external_location(*location)?;
-            log::info!(
-                "Chunk is too large, moved to external file {}",
-                external_path.display()
-            );
-            std::fs::write(&external_path, &self.content[start + 1..end])?;
-            self.content.truncate(start);
-            self.content
-                .push(COMPRESSION_EXTERNAL + COMPRESSION_KIND_ZLIB);
-            sector_count = 1;
-            len = 1;
-        } else if *external {
-            let external_path = self.external_location(*location)?;
-            log::info!(
-                "Chunk is previously in external file {}, but now moved to internal",
-                external_path.display()
-            );
-            std::fs::remove_file(&external_path)?;
-        };
-        self.content[start - 4..start].copy_from_slice(&(len as u32).to_be_bytes());
-        self.content[index * 4..index * 4 + 4].copy_from_slice(
-            &((((start / SECTOR_SIZE) as u32) << 8) | sector_count as u32).to_be_bytes(),
-        );
-        self.align();
+    /// Write the region with the external chunks, see `Externals` for the order
+    fn finish(mut self) -> Result<(), AnvilError> {
+        if let Some(file) = &mut self.file {
+            file.seek(SeekFrom::Start(0))?;
+            file.write_all(&self.header)?;
+            file.flush()?;
+            file.get_ref().sync_all()?;
+        }
+        // The file is closed before it is moved
+        let written = self.file.take().is_some();
+        if let Err(err) = self.guard.replace(&self.temp, &self.path) {
+            if written {
+                let _ = std::fs::remove_file(&self.temp);
+            }
+            self.externals.discard(&self.guard);
+            return Err(err.into());
+        }
+        // The spilled payloads are no longer removed on drop
+        if let Err(err) = self.externals.write(&self.guard, &self.path) {
+            self.externals.discard(&self.guard);
+            return Err(err);
+        }
+        self.externals.clean(&self.guard, &self.path)?;
         Ok(())
     }
 }
 
+impl Drop for AnvilWriter {
+    /// Remove the temporary files of an unfinished region
+    fn drop(&mut self) {
+        if self.file.take().is_some() {
+            if let Err(err) = self.guard.discard(&self.temp) {
+                log::warn!("Failed to remove {}: {}", self.temp.display(), err);
+            }
+            self.externals.discard(&self.guard);
+        }
+    }
+}
+
 #[cfg(test)]
 #[test]
 fn test() {
@@ -278,6 +1417,7 @@ fn test() {
             location: loc,
             timestamp: rng.gen(),
             uncompressed: uncompressed,
+            lz4: None,
         }
     };
 
@@ -303,7 +1443,8 @@ fn test() {
     anvil.write(&chunk).unwrap();
     let chunk1 = rand_chunk(&mut rand::thread_rng(), (22, 22), SECTOR_SIZE * 255 - 100); // Near the edge (above)
     anvil.write(&chunk1).unwrap();
-    assert!(Path::new("c.-32.-32.mcc").exists()); // External file
+    // The external file is written with the anvil, until then it is read from memory
+    assert!(!Path::new("c.-32.-32.mcc").exists());
     let mut iter = anvil.iter();
     let chunk_read = iter.next().unwrap().unwrap();
     assert_eq!(chunk.location, chunk_read.location);
@@ -316,6 +1457,7 @@ fn test() {
     assert_eq!(chunk1.uncompressed, chunk1_read.uncompressed);
     assert_eq!(true, chunk1_read.external);
     anvil.save().unwrap();
+    assert!(Path::new("c.-32.-32.mcc").exists()); // External file
     anvil = Anvil::open(Path::new("r.-1.-1.mca")).unwrap();
     let mut iter = anvil.iter();
     let chunk_read = iter.next().unwrap().unwrap();
@@ -329,6 +1471,7 @@ fn test() {
             location: (0, 0),
             timestamp: 0,
             uncompressed: vec![0; 1024],
+            lz4: None,
         })
         .unwrap();
     anvil
@@ -337,10 +1480,79 @@ fn test() {
             location: (22, 22),
             timestamp: 0,
             uncompressed: vec![0; 4524],
+            lz4: None,
         })
         .unwrap();
+    // Removed once the anvil no longer references it
+    assert!(Path::new("c.-32.-32.mcc").exists());
+    anvil.save().unwrap();
     assert!(!Path::new("c.-32.-32.mcc").exists());
 
+    // Failed writes leave the anvil unchanged
+    let mut anvil = Anvil::new(Path::new("invalid.mca"));
+    let chunk = rand_chunk(&mut rand::thread_rng(), (3, 4), 1024);
+    anvil.write(&chunk).unwrap();
+    let before = anvil.content.clone();
+    assert!(anvil
+        .write(&Chunk {
+            external: false,
+            location: (32, 0),
+            timestamp: 0,
+            uncompressed: vec![0; 16],
+            lz4: None,
+        })
+        .is_err());
+    assert_eq!(anvil.content, before);
+    let mut iter = anvil.iter();
+    let chunk_read = iter.next().unwrap().unwrap();
+    assert_eq!(chunk.location, chunk_read.location);
+    assert_eq!(chunk.timestamp, chunk_read.timestamp);
+    assert_eq!(chunk.uncompressed, chunk_read.uncompressed);
+    assert!(iter.next().is_none());
+    // Unaligned content is aligned before writing
+    anvil.content.push(0);
+    anvil
+        .write(&rand_chunk(&mut rand::thread_rng(), (7, 8), 1024))
+        .unwrap();
+    assert_eq!(anvil.iter().filter(|c| c.is_ok()).count(), 2);
+    // A write failing once its external chunk is recorded puts back the one written before
+    let dir = std::env::temp_dir().join("test_anvil_rollback");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let (region, mcc) = (dir.join("r.0.0.mca"), dir.join("c.1.0.mcc"));
+    let first = rand_chunk(&mut rand::thread_rng(), (1, 0), 2 * 1024 * 1024);
+    let second = rand_chunk(&mut rand::thread_rng(), (1, 0), 2 * 1024 * 1024);
+    let mut writer = AnvilWriter::create(&region, WriteGuard::default()).unwrap();
+    writer.write(&first).unwrap();
+    let spilled = std::fs::read(dir.join("c.1.0.mcc.tmp")).unwrap();
+    let len = writer.written_len();
+    // The external chunk of the second write can't be spilled
+    std::fs::create_dir(dir.join("c.1.0.mcc.1.tmp")).unwrap();
+    assert!(writer.write(&second).is_err());
+    assert_eq!(writer.written_len(), len);
+    assert_eq!(std::fs::read(dir.join("c.1.0.mcc.tmp")).unwrap(), spilled);
+    assert!(!mcc.exists());
+    writer.finish().unwrap();
+    let anvil = Anvil::open(&region).unwrap();
+    assert_eq!(anvil.read((1, 0)).unwrap().uncompressed, first.uncompressed);
+    assert_eq!(std::fs::read(&mcc).unwrap(), spilled);
+    assert!(!dir.join("c.1.0.mcc.tmp").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    // External chunk files of regions with and without coordinates in their names
+    let owner = |mcc: &str| owning_region(Path::new(mcc));
+    assert_eq!(
+        owner("region/c.37.-25.mcc"),
+        Some("region/r.1.-1.mca".into())
+    );
+    assert_eq!(owner("plots/abc.3.4.mcc"), Some("plots/abc.mca".into()));
+    assert_eq!(owner("plots/abc.32.4.mcc"), None);
+    assert_eq!(owner("plots/3.4.mcc"), None);
+    assert_eq!(
+        external_location(Path::new("plots/abc.mca"), (3, 4)).unwrap(),
+        Path::new("plots/abc.3.4.mcc")
+    );
+
     // TODO: Poor test coverage
 
     std::fs::remove_file("r.-1.-1.mca").unwrap();
@@ -374,3 +1586,662 @@ fn test() {
     }
     std::fs::remove_file("r.-1.-1.mca").unwrap();
 }
+
+#[cfg(test)]
+#[test]
+fn test_low_memory() {
+    use rand::Rng;
+
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    /// Copy the even chunks and rewrite the odd ones
+    fn rebuild(input: &impl Region, output: &mut impl RegionWriter) {
+        for location in input.locations() {
+            if location.0 % 2 == 0 {
+                output.copy_chunk(input, location).unwrap();
+            } else {
+                let mut chunk = input.read(location).unwrap();
+                chunk.uncompressed.push(0);
+                output.write(&chunk).unwrap();
+            }
+        }
+    }
+
+    let dir = std::env::temp_dir().join("test_low_memory");
+    let _ = std::fs::remove_dir_all(&dir);
+    for sub in ["in", "a", "b"] {
+        std::fs::create_dir_all(dir.join(sub)).unwrap();
+    }
+    let name = "r.1.-1.mca";
+    let path = dir.join("in").join(name);
+    let mut rng = rand::thread_rng();
+    let mut source = Anvil::new(&path);
+    // Random data does not compress, the last chunk goes to an external file
+    for (location, size) in [
+        ((0, 0), 1024),
+        ((1, 0), 5000),
+        ((2, 3), 3 * SECTOR_SIZE),
+        ((5, 7), 2 * 1024 * 1024),
+    ] {
+        let mut uncompressed = vec![0; size];
+        rng.fill(&mut uncompressed[..]);
+        source
+            .write(&Chunk {
+                external: false,
+                location,
+                timestamp: rng.gen(),
+                uncompressed,
+                lz4: None,
+            })
+            .unwrap();
+    }
+    source.save().unwrap();
+    assert!(dir.join("in").join("c.37.-25.mcc").exists());
+
+    let anvil = Anvil::open(&path).unwrap();
+    let reader = AnvilReader::open_with_guard(&path, WriteGuard::default()).unwrap();
+    assert_eq!(Region::locations(&reader), anvil.locations());
+    for location in anvil.locations() {
+        assert_eq!(
+            reader.timestamp(location).unwrap(),
+            anvil.timestamp(location).unwrap()
+        );
+        assert_eq!(
+            &*reader.raw(location).unwrap(),
+            anvil.raw(location).unwrap()
+        );
+        let (a, b) = (
+            anvil.read(location).unwrap(),
+            reader.read(location).unwrap(),
+        );
+        assert_eq!(a.external, b.external);
+        assert_eq!(a.uncompressed, b.uncompressed);
+    }
+    assert!(reader.read((9, 9)).is_err());
+
+    let mut in_memory = Anvil::new(&dir.join("a").join(name));
+    rebuild(&anvil, &mut in_memory);
+    in_memory.finish().unwrap();
+    let mut streamed =
+        AnvilWriter::create(&dir.join("b").join(name), WriteGuard::default()).unwrap();
+    rebuild(&reader, &mut streamed);
+    streamed.finish().unwrap();
+    for file in [name, "c.37.-25.mcc"] {
+        assert_eq!(
+            std::fs::read(dir.join("a").join(file)).unwrap(),
+            std::fs::read(dir.join("b").join(file)).unwrap()
+        );
+    }
+    assert!(!dir.join("b").join(format!("{}.tmp", name)).exists());
+
+    // An unfinished region leaves nothing behind
+    let other = dir.join("b").join("r.0.0.mca");
+    let mut unfinished = AnvilWriter::create(&other, WriteGuard::default()).unwrap();
+    unfinished.copy_chunk(&reader, (0, 0)).unwrap();
+    drop(unfinished);
+    assert!(!other.exists());
+    assert!(!dir.join("b").join("r.0.0.mca.tmp").exists());
+
+    // Nothing is created in dry-run mode
+    let guard = WriteGuard::dry_run();
+    let mut dry = AnvilWriter::create(&other, guard.clone()).unwrap();
+    rebuild(&reader, &mut dry);
+    dry.finish().unwrap();
+    assert!(!other.exists());
+    assert!(!dir.join("b").join("r.0.0.mca.tmp").exists());
+    assert_eq!(guard.writes(), 0);
+
+    // Truncated files are padded with zeros, like `Anvil::open` does
+    let mut content = std::fs::read(&path).unwrap();
+    content.truncate(content.len() - 10);
+    std::fs::write(&path, &content).unwrap();
+    let anvil = Anvil::open(&path).unwrap();
+    let reader = AnvilReader::open_with_guard(&path, WriteGuard::default()).unwrap();
+    for location in anvil.locations() {
+        assert_eq!(
+            anvil.raw(location).ok(),
+            reader.raw(location).ok().as_deref()
+        );
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(test)]
+#[test]
+fn test_deferred_externals() {
+    use rand::Rng;
+
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    let dir = std::env::temp_dir().join("test_deferred_externals");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("r.0.0.mca");
+    let chunk = |location, size| {
+        let mut uncompressed = vec![0; size];
+        rand::thread_rng().fill(&mut uncompressed[..]);
+        Chunk {
+            external: false,
+            location,
+            timestamp: 1700000000,
+            uncompressed,
+            lz4: None,
+        }
+    };
+    let (small, large) = (chunk((0, 0), 1024), chunk((1, 0), 2 * 1024 * 1024));
+    let mut anvil = Anvil::new(&path);
+    anvil.write(&small).unwrap();
+    anvil.save().unwrap();
+    let mccs = || {
+        let mut files = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name.ends_with(".mcc"))
+            .collect::<Vec<_>>();
+        files.sort();
+        files
+    };
+
+    // Interrupted before the region is saved, nothing references the large chunk yet
+    let mut anvil = Anvil::open(&path).unwrap();
+    anvil.write(&large).unwrap();
+    assert_eq!(anvil.read((1, 0)).unwrap().uncompressed, large.uncompressed);
+    drop(anvil);
+    assert!(mccs().is_empty());
+
+    // Left by an older version interrupted after writing the external file of the small chunk
+    std::fs::write(dir.join("c.0.0.mcc"), [1, 2, 3]).unwrap();
+    let mut anvil = Anvil::open(&path).unwrap();
+    anvil.write(&large).unwrap();
+    anvil.save().unwrap();
+    assert_eq!(mccs(), vec!["c.1.0.mcc"]);
+    let anvil = Anvil::open(&path).unwrap();
+    assert_eq!(anvil.read((0, 0)).unwrap().uncompressed, small.uncompressed);
+    assert_eq!(anvil.read((1, 0)).unwrap().uncompressed, large.uncompressed);
+    // Saving again keeps the referenced file
+    anvil.save().unwrap();
+    assert_eq!(mccs(), vec!["c.1.0.mcc"]);
+    // The stale files of chunks missing from the region are listed too, not those of other regions
+    std::fs::write(dir.join("c.5.0.mcc"), [1, 2, 3]).unwrap();
+    std::fs::write(dir.join("c.40.0.mcc"), [1, 2, 3]).unwrap();
+    anvil.save().unwrap();
+    assert_eq!(mccs(), vec!["c.1.0.mcc", "c.40.0.mcc"]);
+    std::fs::remove_file(dir.join("c.40.0.mcc")).unwrap();
+
+    // The payloads of a streamed region are spilled to temporary files until it is finished
+    let spilled = dir.join("c.1.0.mcc.tmp");
+    let mut writer = AnvilWriter::create(&path, WriteGuard::default()).unwrap();
+    writer.write(&small).unwrap();
+    writer.write(&large).unwrap();
+    assert!(spilled.exists());
+    assert!(writer.written_len() > large.uncompressed.len());
+    drop(writer);
+    assert!(!spilled.exists());
+    let mut writer = AnvilWriter::create(&path, WriteGuard::default()).unwrap();
+    writer.write(&small).unwrap();
+    writer.write(&large).unwrap();
+    writer.finish().unwrap();
+    assert!(!spilled.exists());
+    assert_eq!(mccs(), vec!["c.1.0.mcc"]);
+    // Copied from another region as well
+    let other = dir.join("r.1.0.mca");
+    let reader = AnvilReader::open_with_guard(&path, WriteGuard::default()).unwrap();
+    let mut writer = AnvilWriter::create(&other, WriteGuard::default()).unwrap();
+    writer.copy_chunk(&reader, (1, 0)).unwrap();
+    assert!(dir.join("c.33.0.mcc.tmp").exists());
+    writer.finish().unwrap();
+    assert_eq!(mccs(), vec!["c.1.0.mcc", "c.33.0.mcc"]);
+    assert_eq!(
+        Anvil::open(&other)
+            .unwrap()
+            .read((1, 0))
+            .unwrap()
+            .uncompressed,
+        large.uncompressed
+    );
+    std::fs::remove_file(&other).unwrap();
+    std::fs::remove_file(dir.join("c.33.0.mcc")).unwrap();
+
+    // The same when the region is streamed
+    std::fs::write(dir.join("c.0.0.mcc"), [1, 2, 3]).unwrap();
+    let reader = AnvilReader::open_with_guard(&path, WriteGuard::default()).unwrap();
+    let mut writer = AnvilWriter::create(&path, WriteGuard::default()).unwrap();
+    writer.copy_chunk(&reader, (0, 0)).unwrap();
+    writer.copy_chunk(&reader, (1, 0)).unwrap();
+    drop(reader);
+    writer.finish().unwrap();
+    assert_eq!(mccs(), vec!["c.1.0.mcc"]);
+    assert_eq!(
+        Anvil::open(&path)
+            .unwrap()
+            .read((1, 0))
+            .unwrap()
+            .uncompressed,
+        large.uncompressed
+    );
+
+    // A chunk copied from an unreadable external file keeps the external chunk written before
+    let unreadable = dir.join("unreadable");
+    std::fs::create_dir_all(unreadable.join("c.1.0.mcc")).unwrap();
+    let mut source = Anvil::new(&unreadable.join("r.0.0.mca"));
+    source.write(&large).unwrap();
+    let larger = chunk((1, 0), 2 * 1024 * 1024 + 1);
+    let mut anvil = Anvil::open(&path).unwrap();
+    anvil.write(&larger).unwrap();
+    assert!(anvil.copy_chunk(&source, (1, 0)).is_err());
+    assert_eq!(
+        anvil.read((1, 0)).unwrap().uncompressed,
+        larger.uncompressed
+    );
+    // The same when the region is streamed, the spilled payload is kept
+    let mut writer = AnvilWriter::create(&path, WriteGuard::default()).unwrap();
+    writer.write(&larger).unwrap();
+    assert!(writer.copy_chunk(&source, (1, 0)).is_err());
+    assert!(spilled.exists());
+    assert!(!dir.join("c.1.0.mcc.1.tmp").exists());
+    writer.finish().unwrap();
+    assert!(!spilled.exists());
+    let anvil = Anvil::open(&path).unwrap();
+    assert_eq!(
+        anvil.read((1, 0)).unwrap().uncompressed,
+        larger.uncompressed
+    );
+
+    // The region can't be written, so neither are its external files
+    let missing = dir.join("missing").join("r.0.0.mca");
+    let mut anvil = Anvil::new(&missing);
+    anvil.write(&large).unwrap();
+    assert!(anvil.save().is_err());
+    assert!(!missing.exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// `fixtures/lz4-java/r.0.0.mca` is framed like the `LZ4BlockOutputStream` of a server with
+/// `region-file-compression=lz4`, 64 KiB blocks with 28-bit checksums, compressed with liblz4:
+/// 90000 bytes of text at (0, 0), in two blocks, and the 256 byte values at (1, 0), stored raw
+#[cfg(test)]
+#[test]
+fn test_lz4_java() {
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    let dir = std::env::temp_dir().join("test_lz4_java");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("r.0.0.mca");
+    std::fs::write(&path, include_bytes!("../fixtures/lz4-java/r.0.0.mca")).unwrap();
+    let text = b"lz4-java ".repeat(10000);
+    let bytes = (0..=255).collect::<Vec<u8>>();
+
+    let anvil = Anvil::open(&path).unwrap();
+    let chunks = anvil.iter().collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(chunks.len(), 2);
+    assert_eq!(
+        (chunks[0].location, chunks[0].timestamp),
+        ((0, 0), 1700000000)
+    );
+    assert_eq!(chunks[0].uncompressed, text);
+    assert_eq!(
+        (chunks[1].location, chunks[1].timestamp),
+        ((1, 0), 1700000001)
+    );
+    assert_eq!(chunks[1].uncompressed, bytes);
+    assert!(chunks
+        .iter()
+        .all(|chunk| chunk.lz4 == Some(Lz4Format::Java) && !chunk.external));
+    let reader = AnvilReader::open_with_guard(&path, WriteGuard::default()).unwrap();
+    assert_eq!(reader.read((0, 0)).unwrap().uncompressed, text);
+
+    // Written back in the same framing
+    let written = dir.join("r.1.0.mca");
+    let mut copy = Anvil::new(&written);
+    for chunk in &chunks {
+        copy.write(chunk).unwrap();
+    }
+    copy.write(&Chunk {
+        external: false,
+        location: (2, 0),
+        timestamp: 0,
+        uncompressed: text.clone(),
+        lz4: None,
+    })
+    .unwrap();
+    copy.save().unwrap();
+    let copy = Anvil::open(&written).unwrap();
+    for location in [(0, 0), (1, 0)] {
+        let raw = copy.raw(location).unwrap();
+        assert_eq!(raw[4], COMPRESSION_KIND_LZ4);
+        assert!(lz4java::is_block_stream(&raw[5..]));
+    }
+    assert_eq!(copy.raw((2, 0)).unwrap()[4], COMPRESSION_KIND_ZLIB);
+    let reread = copy.iter().collect::<Result<Vec<_>, _>>().unwrap();
+    for (reread, chunk) in reread.iter().zip(&chunks) {
+        assert_eq!(
+            (reread.location, reread.timestamp, reread.lz4),
+            (chunk.location, chunk.timestamp, chunk.lz4)
+        );
+        assert_eq!(reread.uncompressed, chunk.uncompressed);
+    }
+    assert_eq!(reread[2].uncompressed, text);
+    assert_eq!(reread[2].lz4, None);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// `fixtures/lz4-frame/r.0.0.mca` holds standard LZ4 frames of 64 KiB blocks written by the `lz4`
+/// tool, like other tools may store chunks: 90000 bytes of text at (0, 0) and the 256 byte values
+/// at (1, 0)
+#[cfg(test)]
+#[test]
+fn test_lz4_frame() {
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    let dir = std::env::temp_dir().join("test_lz4_frame");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("r.0.0.mca");
+    std::fs::write(&path, include_bytes!("../fixtures/lz4-frame/r.0.0.mca")).unwrap();
+    let text = b"lz4 frame ".repeat(9000);
+    let bytes = (0..=255).collect::<Vec<u8>>();
+
+    let anvil = Anvil::open(&path).unwrap();
+    let chunks = anvil.iter().collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(chunks.len(), 2);
+    assert_eq!(chunks[0].uncompressed, text);
+    assert_eq!(chunks[1].uncompressed, bytes);
+    assert!(chunks
+        .iter()
+        .all(|chunk| chunk.lz4 == Some(Lz4Format::Frame) && !chunk.external));
+
+    // Written back as frames, not in the framing of lz4-java
+    let written = dir.join("r.1.0.mca");
+    let mut copy = Anvil::new(&written);
+    for chunk in &chunks {
+        copy.write(chunk).unwrap();
+    }
+    copy.save().unwrap();
+    let copy = Anvil::open(&written).unwrap();
+    for location in [(0, 0), (1, 0)] {
+        let raw = copy.raw(location).unwrap();
+        assert_eq!(raw[4], COMPRESSION_KIND_LZ4);
+        assert_eq!(raw[5..9], [0x04, 0x22, 0x4D, 0x18]);
+    }
+    let reread = copy.iter().collect::<Result<Vec<_>, _>>().unwrap();
+    for (reread, chunk) in reread.iter().zip(&chunks) {
+        assert_eq!(
+            (reread.location, reread.timestamp, reread.lz4),
+            (chunk.location, chunk.timestamp, chunk.lz4)
+        );
+        assert_eq!(reread.uncompressed, chunk.uncompressed);
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(test)]
+#[test]
+fn test_length_mismatch() {
+    use rand::Rng;
+
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    let dir = std::env::temp_dir().join("test_length_mismatch");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("r.0.0.mca");
+    let mut rng = rand::thread_rng();
+    // Random bytes don't compress, so the chunk takes exactly 255 sectors
+    let large = Chunk {
+        external: false,
+        location: (0, 0),
+        timestamp: 1,
+        uncompressed: (0..SECTOR_SIZE * 254).map(|_| rng.gen()).collect(),
+        lz4: None,
+    };
+    let small = Chunk {
+        external: false,
+        location: (1, 0),
+        timestamp: 2,
+        uncompressed: vec![0; 1024],
+        lz4: None,
+    };
+    let mut anvil = Anvil::new(&path);
+    anvil.write(&large).unwrap();
+    anvil.write(&small).unwrap();
+    assert_eq!(u32_at(&anvil.content, 0) & 0xFF, 255);
+    // A stale length field reaching into the next chunk
+    let start = (u32_at(&anvil.content, 0) >> 8) as usize * SECTOR_SIZE;
+    let stale = (SECTOR_SIZE * 255) as u32;
+    anvil.content[start..start + 4].copy_from_slice(&stale.to_be_bytes());
+    anvil.save().unwrap();
+
+    let anvil = Anvil::open(&path).unwrap();
+    let reader = AnvilReader::open_with_guard(&path, WriteGuard::default()).unwrap();
+    for region in [&anvil as &dyn Region, &reader] {
+        let err = region.read((0, 0)).unwrap_err();
+        let AnvilError::LengthMismatch(mismatch) = &err else {
+            panic!("Not a length mismatch: {}", err)
+        };
+        assert_eq!(
+            (mismatch.chunk_len, mismatch.allocated),
+            (SECTOR_SIZE * 255, SECTOR_SIZE * 255 - 4)
+        );
+        assert!(format!("{:#}", err).contains("1044480 exceeds the 1044476 bytes"));
+        // Only the sectors of the chunk are copied
+        assert_eq!(region.raw((0, 0)).unwrap().len(), SECTOR_SIZE * 255);
+        assert_eq!(
+            region.read((1, 0)).unwrap().uncompressed,
+            small.uncompressed
+        );
+    }
+    let mut copy = Anvil::new(&dir.join("r.1.0.mca"));
+    copy.copy_chunk(&anvil, (0, 0)).unwrap();
+    assert_eq!(copy.raw((0, 0)).unwrap(), anvil.raw((0, 0)).unwrap());
+    assert!(matches!(
+        copy.read((0, 0)),
+        Err(AnvilError::LengthMismatch(_))
+    ));
+    // A stale length reaching past the end of the file
+    let mut anvil = Anvil::open(&path).unwrap();
+    let start = (u32_at(&anvil.content, 4) >> 8) as usize * SECTOR_SIZE;
+    let stale = (SECTOR_SIZE * 2) as u32;
+    anvil.content[start..start + 4].copy_from_slice(&stale.to_be_bytes());
+    anvil.save().unwrap();
+    let anvil = Anvil::open(&path).unwrap();
+    let reader = AnvilReader::open_with_guard(&path, WriteGuard::default()).unwrap();
+    for region in [&anvil as &dyn Region, &reader] {
+        assert!(matches!(
+            region.read((1, 0)),
+            Err(AnvilError::LengthMismatch(_))
+        ));
+        assert_eq!(region.raw((1, 0)).unwrap().len(), SECTOR_SIZE);
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(test)]
+#[test]
+fn test_errors() {
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    // Names of the external chunks
+    assert_eq!(
+        external_location(Path::new("region/r.1.2.mca"), (3, 4)).unwrap(),
+        Path::new("region/c.35.68.mcc")
+    );
+    assert_eq!(
+        external_location(Path::new("foo.mca"), (3, 4)).unwrap(),
+        Path::new("foo.3.4.mcc")
+    );
+    assert_eq!(
+        external_location(Path::new("r.1.mca"), (3, 4)).unwrap(),
+        Path::new("r.1.3.4.mcc")
+    );
+    assert_eq!(
+        external_location(Path::new("region"), (0, 0)).unwrap(),
+        Path::new("region.0.0.mcc")
+    );
+    for path in ["", "/", "..", "region/..", ".mca", "region/.mca"] {
+        assert!(
+            matches!(
+                external_location(Path::new(path), (0, 0)),
+                Err(AnvilError::InvalidFilename(_))
+            ),
+            "{}",
+            path
+        );
+    }
+    #[cfg(target_family = "unix")]
+    {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        let path = Path::new(OsStr::from_bytes(b"r.\xff.0.mca"));
+        assert!(matches!(
+            external_location(path, (0, 0)),
+            Err(AnvilError::InvalidFilename(_))
+        ));
+    }
+
+    let dir = std::env::temp_dir().join("test_anvil_errors");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    // Nothing is written under a path without a file name
+    let parent = dir.join("region").join("..");
+    assert!(matches!(
+        Anvil::new(&parent).save(),
+        Err(AnvilError::InvalidFilename(_))
+    ));
+    assert!(matches!(
+        Anvil::open(&parent),
+        Err(AnvilError::InvalidFilename(_))
+    ));
+    assert!(matches!(
+        AnvilReader::open_with_guard(&parent, WriteGuard::default()),
+        Err(AnvilError::InvalidFilename(_))
+    ));
+    assert!(matches!(
+        AnvilWriter::create(&parent, WriteGuard::default()),
+        Err(AnvilError::InvalidFilename(_))
+    ));
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+    match Anvil::open(&dir.join("missing.mca")) {
+        Err(AnvilError::Io(err)) => assert_eq!(err.kind(), std::io::ErrorKind::NotFound),
+        _ => panic!("Expected an IO error"),
+    }
+    let short = dir.join("short.mca");
+    std::fs::write(&short, [0; SECTOR_SIZE]).unwrap();
+    assert!(matches!(
+        Anvil::open(&short),
+        Err(AnvilError::InvalidHeader(_))
+    ));
+    assert!(matches!(
+        AnvilReader::open_with_guard(&short, WriteGuard::default()),
+        Err(AnvilError::InvalidHeader(_))
+    ));
+
+    // Locations
+    let path = dir.join("r.0.0.mca");
+    let mut anvil = Anvil::new(&path);
+    let chunk = |location| Chunk {
+        external: false,
+        location,
+        timestamp: 0,
+        uncompressed: vec![10, 0, 0, 0],
+        lz4: None,
+    };
+    anvil.write(&chunk((0, 0))).unwrap();
+    for location in [(32, 0), (0, 32), (-1, 0), (0, i32::MIN)] {
+        assert!(matches!(
+            anvil.read(location),
+            Err(AnvilError::ChunkOutOfRange(l)) if l == location
+        ));
+        assert!(matches!(
+            anvil.timestamp(location),
+            Err(AnvilError::ChunkOutOfRange(_))
+        ));
+        assert!(matches!(
+            anvil.write(&chunk(location)),
+            Err(AnvilError::ChunkOutOfRange(_))
+        ));
+    }
+    assert!(matches!(
+        anvil.read((1, 0)),
+        Err(AnvilError::ChunkNotPresent((1, 0)))
+    ));
+    anvil.save().unwrap();
+
+    // Broken headers and chunks, read both in memory and from the file
+    let valid = std::fs::read(&path).unwrap();
+    let start = (u32_at(&valid, 0) >> 8) as usize * SECTOR_SIZE;
+    let broken = |edit: &dyn Fn(&mut Vec<u8>)| {
+        let mut content = valid.clone();
+        edit(&mut content);
+        std::fs::write(&path, content).unwrap();
+        let anvil = Anvil::open(&path).unwrap();
+        let reader = AnvilReader::open_with_guard(&path, WriteGuard::default()).unwrap();
+        [
+            anvil.read((0, 0)).unwrap_err(),
+            reader.read((0, 0)).unwrap_err(),
+        ]
+    };
+    let header_entry = |offset: u32, sectors: u32| {
+        move |content: &mut Vec<u8>| {
+            content[..4].copy_from_slice(&((offset << 8) | sectors).to_be_bytes())
+        }
+    };
+    for edit in [
+        header_entry(0, 1),
+        header_entry(1, 1),
+        header_entry(2, 0),
+        header_entry(3, 0),
+        header_entry(2, 2),
+        header_entry(0xFF_FFFF, 255),
+    ] {
+        for err in broken(&edit) {
+            assert!(matches!(err, AnvilError::InvalidHeader(_)), "{}", err);
+        }
+    }
+    for err in broken(&|content| content[start..start + 4].copy_from_slice(&[0; 4])) {
+        assert!(err.to_string().contains("Invalid chunk length"), "{}", err);
+    }
+    for err in broken(&|content| content[start + 4] = 9) {
+        assert!(matches!(err, AnvilError::UnknownCompression(9)));
+    }
+    for err in broken(&|content| content[start + 4] = COMPRESSION_KIND_GZIP) {
+        assert!(matches!(
+            err,
+            AnvilError::Corrupted {
+                compression: "Gzip",
+                ..
+            }
+        ));
+    }
+    for err in broken(&|content| content[start + 4] = COMPRESSION_KIND_LZ4) {
+        assert!(matches!(
+            err,
+            AnvilError::Corrupted {
+                compression: "lz4",
+                ..
+            }
+        ));
+    }
+    for err in broken(&|content| content[start + 4] = COMPRESSION_EXTERNAL + COMPRESSION_KIND_ZLIB)
+    {
+        let AnvilError::ExternalChunkMissing { path, source } = &err else {
+            panic!("Expected a missing external chunk: {}", err)
+        };
+        assert_eq!(path, &dir.join("c.0.0.mcc"));
+        assert_eq!(source.kind(), std::io::ErrorKind::NotFound);
+        assert!(std::error::Error::source(&err).is_some());
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,370 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::Write,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use anyhow::Context;
+use flate2::write::GzEncoder;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{
+    anvil::{Anvil, Chunk},
+    handler::{Registry, RemapOptions},
+    progress::Progress,
+    remap::head,
+    task::{remap_task, scan_world, split_tasks, WorkerResult},
+};
+
+/// The shape of the generated world
+#[derive(Debug, Clone)]
+pub struct Params {
+    pub regions: usize,
+    /// At most 1024, the size of a region
+    pub chunks: usize,
+    pub entities: usize,
+    /// The players in the mapping, each with a playerdata file
+    pub players: usize,
+    pub text_files: usize,
+    pub text_lines: usize,
+    /// The probability that a generated uuid is a player in the mapping, otherwise it is random
+    pub density: f64,
+    pub seed: u64,
+}
+
+/// A minimal writer of binary NBT, enough for the generated files
+struct Nbt(Vec<u8>);
+
+impl Nbt {
+    fn root() -> Self {
+        Nbt(vec![10, 0, 0])
+    }
+
+    fn tag(&mut self, id: u8, name: &str) -> &mut Self {
+        self.0.push(id);
+        self.0.extend((name.len() as u16).to_be_bytes());
+        self.0.extend(name.as_bytes());
+        self
+    }
+
+    fn int(&mut self, name: &str, value: i32) -> &mut Self {
+        self.tag(3, name).0.extend(value.to_be_bytes());
+        self
+    }
+
+    /// ASCII only, which is the same in Modified UTF-8
+    fn string(&mut self, name: &str, value: &str) -> &mut Self {
+        self.tag(8, name);
+        self.0.extend((value.len() as u16).to_be_bytes());
+        self.0.extend(value.as_bytes());
+        self
+    }
+
+    fn uuid(&mut self, name: &str, uuid: Uuid) -> &mut Self {
+        self.tag(11, name).0.extend(4i32.to_be_bytes());
+        self.0.extend(uuid.as_bytes());
+        self
+    }
+
+    /// A list of compounds, each closed with `end`
+    fn compounds(&mut self, name: &str, len: usize) -> &mut Self {
+        self.tag(9, name).0.push(10);
+        self.0.extend((len as i32).to_be_bytes());
+        self
+    }
+
+    fn compound(&mut self, name: &str) -> &mut Self {
+        self.tag(10, name)
+    }
+
+    fn end(&mut self) -> &mut Self {
+        self.0.push(0);
+        self
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.end();
+        self.0
+    }
+}
+
+fn random_uuid(rng: &mut StdRng) -> Uuid {
+    uuid::Builder::from_random_bytes(rng.gen()).into_uuid()
+}
+
+fn write_gzip(path: &Path, nbt: Nbt) -> anyhow::Result<()> {
+    let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(&nbt.finish())?;
+    std::fs::write(path, encoder.finish()?)
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Generate the world into the directory, the same seed always gives the same files
+/// Returns the mapping of the players to new uuids
+pub fn generate(world: &Path, params: &Params) -> anyhow::Result<HashMap<Uuid, Uuid>> {
+    let mut rng = StdRng::seed_from_u64(params.seed);
+    let players = (0..params.players)
+        .map(|_| random_uuid(&mut rng))
+        .collect::<Vec<_>>();
+    let mapping = players
+        .iter()
+        .map(|player| (*player, random_uuid(&mut rng)))
+        .collect();
+    let pick = |rng: &mut StdRng| {
+        if !players.is_empty() && rng.gen_bool(params.density) {
+            players[rng.gen_range(0..players.len())]
+        } else {
+            random_uuid(rng)
+        }
+    };
+    for dir in ["region", "playerdata"] {
+        std::fs::create_dir_all(world.join(dir))?;
+    }
+    let text_dir = world.join("plugins").join("Bench");
+    std::fs::create_dir_all(&text_dir)?;
+
+    let mut level = Nbt::root();
+    level
+        .compound("Data")
+        .string("LevelName", "bench")
+        .int("DataVersion", 3465)
+        .end();
+    write_gzip(&world.join("level.dat"), level)?;
+
+    for region in 0..params.regions {
+        let path = world.join("region").join(format!("r.{}.0.mca", region));
+        let mut anvil = Anvil::new(&path);
+        for i in 0..params.chunks.min(1024) {
+            let location = ((i % 32) as i32, (i / 32) as i32);
+            let mut chunk = Nbt::root();
+            chunk
+                .int("DataVersion", 3465)
+                .int("xPos", region as i32 * 32 + location.0)
+                .int("zPos", location.1)
+                .string("Status", "minecraft:full")
+                .compounds("Entities", params.entities);
+            for _ in 0..params.entities {
+                chunk
+                    .string("id", "minecraft:wolf")
+                    .uuid("UUID", pick(&mut rng))
+                    .uuid("Owner", pick(&mut rng))
+                    .string("CustomName", "{\"text\":\"Rex\"}")
+                    .end();
+            }
+            anvil.write(&Chunk {
+                external: false,
+                location,
+                timestamp: 1700000000,
+                uncompressed: chunk.finish(),
+                lz4: None,
+            })?;
+        }
+        anvil.save()?;
+    }
+
+    for player in &players {
+        let mut data = Nbt::root();
+        data.uuid("UUID", *player)
+            .compound("bukkit")
+            .string("lastKnownName", "Steve")
+            .end();
+        write_gzip(
+            &world.join("playerdata").join(format!("{}.dat", player)),
+            data,
+        )?;
+    }
+
+    for file in 0..params.text_files {
+        let mut text = String::from("members:\n");
+        for _ in 0..params.text_lines {
+            text.push_str(&format!("- owner: {}\n  balance: 100\n", pick(&mut rng)));
+        }
+        std::fs::write(text_dir.join(format!("{}.yml", file)), text)?;
+    }
+    Ok(mapping)
+}
+
+/// The files of a handler, remapped together
+#[derive(Debug, Serialize)]
+pub struct Kind {
+    pub handler: &'static str,
+    pub files: usize,
+    pub bytes: u64,
+    pub secs: f64,
+    pub mib_per_sec: f64,
+    pub replacements: usize,
+}
+
+/// The result of a benchmark, printed as JSON
+#[derive(Debug, Serialize)]
+pub struct Measurement {
+    pub kinds: Vec<Kind>,
+    pub files: usize,
+    pub bytes: u64,
+    pub secs: f64,
+    pub mib_per_sec: f64,
+    pub replacements: usize,
+    pub replacements_per_sec: f64,
+    pub errors: usize,
+    /// The peak resident set size of the whole process, including the generation, Linux only
+    pub peak_rss_bytes: Option<u64>,
+}
+
+fn mib_per_sec(bytes: u64, secs: f64) -> f64 {
+    bytes as f64 / 1024.0 / 1024.0 / secs.max(f64::EPSILON)
+}
+
+/// `VmHWM` in `/proc/self/status`
+fn peak_rss() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    let kib = line.split_whitespace().nth(1)?.parse::<u64>().ok()?;
+    Some(kib * 1024)
+}
+
+/// Remap the world with the workers of a normal run, one handler at a time to time each of them
+pub fn measure(
+    world: &Path,
+    mapping: &HashMap<Uuid, Uuid>,
+    threads: usize,
+) -> anyhow::Result<Measurement> {
+    let mut registry = Registry::builtin();
+    let options = RemapOptions::default();
+    let world = PathBuf::from(world);
+    let mut kinds = BTreeMap::<&'static str, Vec<_>>::new();
+    for task in scan_world(&world, &mut registry, &options)?.tasks {
+        let path = world.join(&task.path);
+        let handler = head(&path, &registry, &options)
+            .ok()
+            .and_then(|head| registry.find(&path, &head))
+            .map_or("unknown", |handler| handler.name());
+        kinds.entry(handler).or_default().push(task);
+    }
+    let mut measured = vec![];
+    let mut errors = 0;
+    for (handler, tasks) in &kinds {
+        let bytes = tasks
+            .iter()
+            .map(|task| std::fs::metadata(world.join(&task.path)).map_or(0, |meta| meta.len()))
+            .sum();
+        let (world, registry, options) = (&world, &registry, &options);
+        let start = Instant::now();
+        let results = std::thread::scope(|scope| {
+            let handles = split_tasks(&tasks[..], threads.max(1))
+                .into_iter()
+                .map(|tasks| {
+                    scope.spawn(move || {
+                        let mut result = WorkerResult::default();
+                        let mut failed = 0;
+                        for task in tasks {
+                            let remapped = remap_task(
+                                world,
+                                task,
+                                mapping,
+                                registry,
+                                options,
+                                &Progress::hidden(),
+                                &mut result,
+                            );
+                            if remapped.is_err() {
+                                failed += 1;
+                            }
+                        }
+                        (result, failed)
+                    })
+                })
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+        let secs = start.elapsed().as_secs_f64();
+        let mut total = WorkerResult::default();
+        for (result, failed) in results {
+            errors += failed + result.stats.chunk_errors;
+            total.merge(result);
+        }
+        measured.push(Kind {
+            handler,
+            files: tasks.len(),
+            bytes,
+            secs,
+            mib_per_sec: mib_per_sec(bytes, secs),
+            replacements: total.replacements,
+        });
+    }
+    let bytes = measured.iter().map(|kind| kind.bytes).sum();
+    let secs = measured.iter().map(|kind| kind.secs).sum::<f64>();
+    let replacements = measured.iter().map(|kind| kind.replacements).sum();
+    Ok(Measurement {
+        files: measured.iter().map(|kind| kind.files).sum(),
+        bytes,
+        secs,
+        mib_per_sec: mib_per_sec(bytes, secs),
+        replacements,
+        replacements_per_sec: replacements as f64 / secs.max(f64::EPSILON),
+        errors,
+        peak_rss_bytes: peak_rss(),
+        kinds: measured,
+    })
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    let dir = std::env::temp_dir().join("test_bench");
+    let _ = std::fs::remove_dir_all(&dir);
+    let params = Params {
+        regions: 2,
+        chunks: 40,
+        entities: 3,
+        players: 5,
+        text_files: 3,
+        text_lines: 10,
+        density: 0.5,
+        seed: 42,
+    };
+    let (a, b) = (dir.join("a"), dir.join("b"));
+    let mapping = generate(&a, &params).unwrap();
+    assert_eq!(mapping, generate(&b, &params).unwrap());
+    assert_eq!(mapping.len(), 5);
+    for file in ["level.dat", "region/r.1.0.mca", "plugins/Bench/2.yml"] {
+        assert_eq!(
+            std::fs::read(a.join(file)).unwrap(),
+            std::fs::read(b.join(file)).unwrap()
+        );
+    }
+    assert_eq!(
+        Anvil::open(&a.join("region").join("r.0.0.mca"))
+            .unwrap()
+            .locations()
+            .len(),
+        40
+    );
+
+    let measurement = measure(&a, &mapping, 2).unwrap();
+    assert_eq!(measurement.errors, 0);
+    // level.dat, 2 regions, 5 playerdata files and 3 text files
+    assert_eq!(measurement.files, 11);
+    let handlers = measurement
+        .kinds
+        .iter()
+        .map(|kind| kind.handler)
+        .collect::<Vec<_>>();
+    assert_eq!(handlers, vec!["mca", "nbt", "text"]);
+    assert!(measurement.replacements > 0);
+    assert!(measurement.mib_per_sec > 0.0);
+    let json = serde_json::to_value(&measurement).unwrap();
+    assert_eq!(json["kinds"][0]["files"], 2);
+    // Every player is remapped already
+    assert_eq!(measure(&a, &mapping, 2).unwrap().replacements, 0);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,155 @@
+use std::{
+    path::Path,
+    sync::{Condvar, Mutex},
+};
+
+#[derive(Debug, Default)]
+struct BudgetState {
+    /// Bytes held by the permits
+    in_use: usize,
+    /// The most bytes ever held at the same time
+    peak: usize,
+    /// Permits are granted in the order they are requested, so large requests are not starved
+    next_ticket: u64,
+    serving: u64,
+}
+
+/// A global budget of in-flight uncompressed data, shared by all the workers
+#[derive(Debug, Default)]
+pub struct MemoryBudget {
+    /// `None` for unlimited
+    limit: Option<usize>,
+    state: Mutex<BudgetState>,
+    released: Condvar,
+}
+
+/// Bytes held from the budget, released on drop
+pub struct BudgetPermit<'a> {
+    budget: &'a MemoryBudget,
+    bytes: usize,
+}
+
+impl MemoryBudget {
+    pub fn new(limit: Option<usize>) -> Self {
+        Self {
+            limit,
+            ..Default::default()
+        }
+    }
+
+    /// Wait until the bytes fit in the budget
+    /// A request larger than the limit holds the whole budget, so it proceeds alone
+    pub fn acquire(&self, bytes: usize) -> BudgetPermit<'_> {
+        let Some(limit) = self.limit else {
+            return BudgetPermit {
+                budget: self,
+                bytes: 0,
+            };
+        };
+        let bytes = bytes.min(limit);
+        let mut state = self.state.lock().unwrap();
+        let ticket = state.next_ticket;
+        state.next_ticket += 1;
+        while state.serving != ticket || state.in_use + bytes > limit {
+            state = self.released.wait(state).unwrap();
+        }
+        state.serving += 1;
+        state.in_use += bytes;
+        state.peak = state.peak.max(state.in_use);
+        // The next ticket may fit as well
+        self.released.notify_all();
+        BudgetPermit {
+            budget: self,
+            bytes,
+        }
+    }
+
+    /// Bytes currently held
+    pub fn in_use(&self) -> usize {
+        self.state.lock().unwrap().in_use
+    }
+
+    /// The most bytes ever held at the same time
+    pub fn peak(&self) -> usize {
+        self.state.lock().unwrap().peak
+    }
+}
+
+impl Drop for BudgetPermit<'_> {
+    fn drop(&mut self) {
+        if self.bytes == 0 {
+            return;
+        }
+        let mut state = self.budget.state.lock().unwrap();
+        state.in_use -= self.bytes;
+        self.budget.released.notify_all();
+    }
+}
+
+/// Estimate the uncompressed data in flight while remapping a file of the size
+/// Regions are charged per chunk with `estimate_chunk`, except when read ahead by the pipeline
+pub fn estimate(path: &Path, len: usize) -> usize {
+    match path.extension().and_then(|s| s.to_str()).unwrap_or("") {
+        // The region and its rebuild, chunks are uncompressed one at a time
+        "mca" => len.saturating_mul(3),
+        // Compressed NBT expands a lot
+        "dat" | "nbt" | "mcc" => len.saturating_mul(10),
+        _ => len.saturating_mul(2),
+    }
+}
+
+/// Estimate the uncompressed data in flight while remapping a chunk of the stored length,
+/// compressed like NBT files
+pub fn estimate_chunk(stored: u64) -> usize {
+    usize::try_from(stored)
+        .unwrap_or(usize::MAX)
+        .saturating_mul(10)
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use rand::Rng;
+
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    let unlimited = MemoryBudget::default();
+    let _a = unlimited.acquire(usize::MAX);
+    let _b = unlimited.acquire(usize::MAX);
+    assert_eq!(unlimited.in_use(), 0);
+
+    // A tiny limit with requests both smaller and larger than it
+    const LIMIT: usize = 100;
+    let budget = MemoryBudget::new(Some(LIMIT));
+    let done = AtomicUsize::new(0);
+    std::thread::scope(|s| {
+        for _ in 0..8 {
+            s.spawn(|| {
+                let mut rng = rand::thread_rng();
+                for _ in 0..50 {
+                    let bytes = rng.gen_range(1..300);
+                    let permit = budget.acquire(bytes);
+                    let in_use = budget.in_use();
+                    assert!(in_use <= LIMIT);
+                    assert!(in_use >= bytes.min(LIMIT));
+                    std::thread::sleep(std::time::Duration::from_micros(rng.gen_range(0..200)));
+                    drop(permit);
+                    done.fetch_add(1, Ordering::Relaxed);
+                }
+            });
+        }
+    });
+    assert_eq!(done.load(Ordering::Relaxed), 400);
+    assert_eq!(budget.in_use(), 0);
+    assert!(budget.peak() <= LIMIT);
+    assert_eq!(budget.peak(), LIMIT);
+
+    assert_eq!(estimate(Path::new("r.0.0.mca"), 10), 30);
+    assert_eq!(estimate(Path::new("level.dat"), 10), 100);
+    assert_eq!(estimate(Path::new("config.yml"), 10), 20);
+    assert_eq!(estimate_chunk(10), 100);
+}
@@ -0,0 +1,291 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    guard::WriteGuard,
+    handler::{Registry, RemapOptions},
+    nbt::{check_compound_root, visit_nbt_with, NbtRules},
+    progress::Progress,
+    remap::remap_file,
+    task::relative_path,
+};
+
+/// Larger inputs are not captured, only their metadata
+pub const MAX_PAYLOAD: u64 = 16 * 1024 * 1024;
+
+/// Nothing more is captured once this much has been written
+pub const MAX_TOTAL: u64 = 256 * 1024 * 1024;
+
+/// The metadata of a captured input, stored as `<hash>.json` next to the payload `<hash>.bin`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Captured {
+    /// The path relative to the world
+    pub path: PathBuf,
+    /// The coordinates in the region of a captured chunk, the payload is its decompressed data
+    /// Otherwise the payload is the whole file
+    pub chunk: Option<(i32, i32)>,
+    pub error: String,
+    pub version: String,
+    /// The payload file, `None` if the input was too large
+    pub payload: Option<String>,
+    pub remap_attribute_modifiers: bool,
+    pub legacy_pairs: bool,
+}
+
+/// Copy the inputs failing to be remapped into a directory, for bug reports
+#[derive(Debug)]
+pub struct Capture {
+    dir: PathBuf,
+    world: PathBuf,
+    all_nbt_errors: bool,
+    written: AtomicU64,
+}
+
+impl Capture {
+    /// The directory is created if missing, it must not be in the world
+    pub fn new(dir: &Path, world: &Path, all_nbt_errors: bool) -> anyhow::Result<Self> {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+        let canonical = dir.canonicalize()?;
+        if canonical.starts_with(world.canonicalize()?) {
+            anyhow::bail!(
+                "The capture directory {} must be outside the world",
+                dir.display()
+            );
+        }
+        Ok(Self {
+            dir: canonical,
+            world: world.to_path_buf(),
+            all_nbt_errors,
+            written: AtomicU64::new(0),
+        })
+    }
+
+    /// Also capture the chunks failing to be visited, which do not fail the region
+    pub fn all_nbt_errors(&self) -> bool {
+        self.all_nbt_errors
+    }
+
+    /// Capture the file failing to be remapped, read again from the world
+    pub fn file(&self, path: &Path, err: &anyhow::Error, rules: NbtRules) {
+        let full = self.world.join(path);
+        let payload = match std::fs::metadata(&full) {
+            Ok(meta) if meta.len() <= MAX_PAYLOAD => std::fs::read(&full).ok(),
+            _ => None,
+        };
+        self.save(path, None, payload.as_deref(), err, rules);
+    }
+
+    /// Capture the decompressed data of the chunk failing to be visited
+    pub fn chunk(
+        &self,
+        region: &Path,
+        location: (i32, i32),
+        payload: &[u8],
+        err: &anyhow::Error,
+        rules: NbtRules,
+    ) {
+        let payload = (payload.len() as u64 <= MAX_PAYLOAD).then_some(payload);
+        self.save(
+            &relative_path(&self.world, region),
+            Some(location),
+            payload,
+            err,
+            rules,
+        );
+    }
+
+    fn save(
+        &self,
+        path: &Path,
+        chunk: Option<(i32, i32)>,
+        payload: Option<&[u8]>,
+        err: &anyhow::Error,
+        rules: NbtRules,
+    ) {
+        let len = payload.map_or(0, |payload| payload.len() as u64);
+        if self.written.fetch_add(len, Ordering::Relaxed) + len > MAX_TOTAL {
+            log::warn!(
+                "Not capturing {}, {} MiB captured already",
+                path.display(),
+                MAX_TOTAL / 1024 / 1024
+            );
+            return;
+        }
+        if payload.is_none() {
+            log::warn!(
+                "Not capturing the content of {}, larger than {} MiB",
+                path.display(),
+                MAX_PAYLOAD / 1024 / 1024
+            );
+        }
+        // Named by the input, so the same input failing again is captured once
+        let mut key = md5::Context::new();
+        key.consume(format!("{}:{:?}:", path.display(), chunk));
+        key.consume(payload.unwrap_or_default());
+        let name = format!("{:x}", key.compute());
+        let captured = Captured {
+            path: path.to_path_buf(),
+            chunk,
+            error: format!("{:#}", err),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            payload: payload.map(|_| format!("{}.bin", name)),
+            remap_attribute_modifiers: rules.remap_attribute_modifiers,
+            legacy_pairs: rules.legacy_pairs,
+        };
+        let result = (|| -> anyhow::Result<()> {
+            if let Some(payload) = payload {
+                std::fs::write(self.dir.join(format!("{}.bin", name)), payload)?;
+            }
+            std::fs::write(
+                self.dir.join(format!("{}.json", name)),
+                serde_json::to_string_pretty(&captured)?,
+            )?;
+            Ok(())
+        })();
+        match result {
+            Ok(()) => log::info!("Captured {} as {}", path.display(), name),
+            Err(err) => log::warn!("Failed to capture {}: {:#}", path.display(), err),
+        }
+    }
+}
+
+/// The captured inputs in the directory, sorted by name
+pub fn load(dir: &Path) -> anyhow::Result<Vec<(PathBuf, Captured)>> {
+    let mut files = std::fs::read_dir(dir)
+        .with_context(|| format!("Failed to read {}", dir.display()))?
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect::<Vec<_>>();
+    files.sort();
+    files
+        .into_iter()
+        .map(|file| {
+            let captured = serde_json::from_slice(&std::fs::read(&file)?)
+                .with_context(|| format!("Malformed capture {}", file.display()))?;
+            Ok((file, captured))
+        })
+        .collect()
+}
+
+/// Run the captured input through the visitors again, nothing is replaced
+pub fn replay(dir: &Path, captured: &Captured) -> anyhow::Result<()> {
+    let Some(payload) = &captured.payload else {
+        anyhow::bail!("The content was not captured");
+    };
+    if captured
+        .path
+        .components()
+        .any(|c| !matches!(c, std::path::Component::Normal(_)))
+    {
+        anyhow::bail!("{} is not relative to the world", captured.path.display());
+    }
+    let mut content = std::fs::read(dir.join(payload))?;
+    let rules = NbtRules {
+        remap_attribute_modifiers: captured.remap_attribute_modifiers,
+        legacy_pairs: captured.legacy_pairs,
+    };
+    if captured.chunk.is_some() {
+        check_compound_root(&content)?;
+        return visit_nbt_with(&mut content, &|_| None, rules);
+    }
+    // The handler is chosen by the path, so the file is placed at the same path in a scratch world
+    let world = std::env::temp_dir().join(format!("uuid-remapper-replay-{}", payload));
+    let path = world.join(&captured.path);
+    std::fs::create_dir_all(path.parent().unwrap_or(&world))?;
+    std::fs::write(&path, content)?;
+    let options = RemapOptions {
+        guard: WriteGuard::dry_run(),
+        nbt_rules: rules,
+        ..Default::default()
+    };
+    let result = remap_file(
+        &world,
+        &captured.path,
+        &Registry::builtin(),
+        &options,
+        &Progress::hidden(),
+        &|_| None,
+    );
+    let _ = std::fs::remove_dir_all(&world);
+    result.map(|_| ())
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    let dir = std::env::temp_dir().join("test_capture");
+    let _ = std::fs::remove_dir_all(&dir);
+    let (world, captures) = (dir.join("world"), dir.join("captures"));
+    std::fs::create_dir_all(world.join("data")).unwrap();
+    assert!(Capture::new(&world.join("captures"), &world, false).is_err());
+
+    let capture = Capture::new(&captures, &world, true).unwrap();
+    let rules = NbtRules::default();
+    // A truncated compound, and a valid one
+    let broken = [10, 0, 0, 8, 0, 4, b'u', b'u', b'i', b'd', 0, 36];
+    std::fs::write(world.join("data").join("broken.dat"), broken).unwrap();
+    capture.file(
+        Path::new("data/broken.dat"),
+        &anyhow::anyhow!("Malformed NBT: Unexpected EOF"),
+        rules,
+    );
+    capture.chunk(
+        &world.join("region").join("r.0.0.mca"),
+        (3, 4),
+        &broken,
+        &anyhow::anyhow!("Malformed NBT: Unexpected EOF"),
+        rules,
+    );
+    capture.chunk(
+        &world.join("region").join("r.0.0.mca"),
+        (5, 6),
+        &[10, 0, 0, 0],
+        &anyhow::anyhow!("Transient"),
+        rules,
+    );
+    // Missing files are recorded without a payload
+    capture.file(Path::new("gone.dat"), &anyhow::anyhow!("Not found"), rules);
+
+    let loaded = load(&captures).unwrap();
+    assert_eq!(loaded.len(), 4);
+    let find = |chunk: Option<(i32, i32)>, path: &str| {
+        loaded
+            .iter()
+            .find(|(_, c)| c.chunk == chunk && c.path == Path::new(path))
+            .unwrap()
+            .1
+            .payload
+            .clone()
+    };
+    assert!(find(None, "gone.dat").is_none());
+    assert!(find(Some((3, 4)), "region/r.0.0.mca").is_some());
+    // The same input is captured once
+    capture.chunk(
+        &world.join("region").join("r.0.0.mca"),
+        (5, 6),
+        &[10, 0, 0, 0],
+        &anyhow::anyhow!("Transient"),
+        rules,
+    );
+    assert_eq!(load(&captures).unwrap().len(), 4);
+    for (_, captured) in &loaded {
+        assert_eq!(captured.version, env!("CARGO_PKG_VERSION"));
+        let replayed = replay(&captures, captured);
+        match (&captured.chunk, captured.path.to_str().unwrap()) {
+            (Some((5, 6)), _) => replayed.unwrap(),
+            (_, "gone.dat") => assert!(replayed.is_err()),
+            _ => assert!(replayed.is_err()),
+        }
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
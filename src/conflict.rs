@@ -0,0 +1,181 @@
+use std::{
+    io::BufRead,
+    path::Path,
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+use colored::Colorize;
+use serde::Serialize;
+
+use crate::{style::paint, MULTI};
+
+/// A conflict resolved with `--interactive-conflicts`, recorded in the report
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Decision {
+    /// `rename`, `mapping` or `duplicate_chunk`
+    pub conflict: &'static str,
+    /// What was presented, the paths and the uuids involved
+    pub details: Vec<String>,
+    pub resolution: String,
+}
+
+/// Ask the user to resolve the conflicts, one at a time
+/// A worker with a conflict waits until the prompt of another worker is answered
+pub struct Prompter {
+    input: Mutex<Box<dyn BufRead + Send>>,
+    decisions: Mutex<Vec<Decision>>,
+}
+
+impl std::fmt::Debug for Prompter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Prompter")
+            .field("decisions", &self.decisions)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Prompter {
+    pub fn new(input: impl BufRead + Send + 'static) -> Self {
+        Self {
+            input: Mutex::new(Box::new(input)),
+            decisions: Mutex::new(vec![]),
+        }
+    }
+
+    /// Ask on the standard input
+    pub fn stdin() -> Self {
+        Self::new(std::io::BufReader::new(std::io::stdin()))
+    }
+
+    /// Present the conflict and return the index of the chosen `(key, resolution)`
+    /// The first choice is the one taken without the flag, it is also taken at the end of the input
+    pub fn ask(
+        &self,
+        conflict: &'static str,
+        details: Vec<String>,
+        choices: &[(&str, &str)],
+    ) -> usize {
+        // Held until the decision is recorded, so the prompts never interleave
+        let mut input = self.input.lock().unwrap();
+        let chosen = MULTI.suspend(|| {
+            eprintln!(
+                "{}",
+                paint(format!("Conflict ({})", conflict), |s| s.yellow().bold())
+            );
+            for line in &details {
+                eprintln!("   {}", line);
+            }
+            let keys = choices
+                .iter()
+                .map(|(key, resolution)| format!("[{}] {}", key, resolution))
+                .collect::<Vec<_>>()
+                .join(", ");
+            loop {
+                eprint!("{}? ", keys);
+                let mut line = String::new();
+                match input.read_line(&mut line) {
+                    Ok(0) | Err(_) => {
+                        eprintln!();
+                        return None;
+                    }
+                    Ok(_) => {
+                        let answer = line.trim();
+                        if let Some(i) = choices
+                            .iter()
+                            .position(|(key, _)| key.eq_ignore_ascii_case(answer))
+                        {
+                            return Some(i);
+                        }
+                    }
+                }
+            }
+        });
+        let (index, resolution) = match chosen {
+            Some(i) => (i, choices[i].1.to_string()),
+            None => {
+                log::warn!("End of input, the conflict is resolved as without the flag");
+                (0, format!("{} (end of input)", choices[0].1))
+            }
+        };
+        self.decisions.lock().unwrap().push(Decision {
+            conflict,
+            details,
+            resolution,
+        });
+        index
+    }
+
+    /// The decisions so far, in the order they were taken
+    pub fn decisions(&self) -> Vec<Decision> {
+        self.decisions.lock().unwrap().clone()
+    }
+}
+
+/// The path with its size and age, for the details of a conflict
+pub fn describe(path: &Path) -> String {
+    match std::fs::metadata(path) {
+        Ok(meta) => {
+            let age = meta
+                .modified()
+                .ok()
+                .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+                .unwrap_or(Duration::ZERO);
+            format!(
+                "{}: {} bytes, modified {}s ago",
+                path.display(),
+                meta.len(),
+                age.as_secs()
+            )
+        }
+        Err(err) => format!("{}: {}", path.display(), err),
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    // Unknown answers are asked again
+    let prompter = Prompter::new(std::io::Cursor::new("x\nS\n"));
+    let choices = [("o", "overwrite"), ("s", "skip")];
+    assert_eq!(
+        prompter.ask("rename", vec!["a -> b".to_string()], &choices),
+        1
+    );
+    assert_eq!(
+        prompter.ask("rename", vec!["c -> d".to_string()], &choices),
+        0
+    );
+    assert_eq!(
+        prompter.decisions(),
+        vec![
+            Decision {
+                conflict: "rename",
+                details: vec!["a -> b".to_string()],
+                resolution: "skip".to_string(),
+            },
+            Decision {
+                conflict: "rename",
+                details: vec!["c -> d".to_string()],
+                resolution: "overwrite (end of input)".to_string(),
+            },
+        ]
+    );
+    // One prompt at a time
+    let prompter = Prompter::new(std::io::Cursor::new("s\n".repeat(8)));
+    std::thread::scope(|scope| {
+        for i in 0..8 {
+            let prompter = &prompter;
+            scope.spawn(move || prompter.ask("rename", vec![i.to_string()], &choices));
+        }
+    });
+    let decisions = prompter.decisions();
+    assert_eq!(decisions.len(), 8);
+    assert!(decisions.iter().all(|d| d.resolution == "skip"));
+
+    assert!(describe(Path::new("Cargo.toml")).starts_with("Cargo.toml: "));
+}
@@ -0,0 +1,269 @@
+use std::{ops::Range, path::Path};
+
+use uuid::Uuid;
+
+use crate::{
+    source::{located, tagged, Location, Source},
+    text::lines,
+};
+
+/// Keys of uuids stored as two signed 64-bit decimals, e.g. `most: -5740...` and `least: 1234...`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecimalKeys {
+    /// `(most, least)`
+    pairs: Vec<(String, String)>,
+}
+
+/// Parse keys like `most,least,uuidMost,uuidLeast`, each two consecutive keys form a pair
+pub fn parse_keys(s: &str) -> Result<DecimalKeys, String> {
+    let keys = s.split(',').map(|k| k.trim()).collect::<Vec<_>>();
+    if keys.iter().any(|k| k.is_empty()) || keys.len() % 2 != 0 {
+        return Err(format!("Expected pairs of keys like most,least, got {}", s));
+    }
+    Ok(DecimalKeys {
+        pairs: keys
+            .chunks(2)
+            .map(|pair| (pair[0].to_string(), pair[1].to_string()))
+            .collect(),
+    })
+}
+
+/// A value of one of the keys
+#[derive(Clone)]
+struct Field {
+    line: usize,
+    indent: usize,
+    value: Range<usize>,
+    parsed: i64,
+}
+
+/// Split a line like `  "most": -123,` or `most=-123` into the indent, the key and the value range
+fn split_line(line: &[u8]) -> Option<(usize, &[u8], Range<usize>)> {
+    let indent = line.iter().take_while(|c| c.is_ascii_whitespace()).count();
+    let rest = &line[indent..];
+    let (key, after_key) = if let Some(quoted) = rest.strip_prefix(b"\"") {
+        let end = quoted.iter().position(|&c| c == b'"')?;
+        (&quoted[..end], indent + end + 2)
+    } else {
+        let end = rest
+            .iter()
+            .position(|&c| c == b':' || c == b'=' || c.is_ascii_whitespace())?;
+        (&rest[..end], indent + end)
+    };
+    let mut pos = after_key;
+    pos += line[pos..]
+        .iter()
+        .take_while(|c| **c == b' ' || **c == b'\t')
+        .count();
+    if !matches!(line.get(pos), Some(b':' | b'=')) {
+        return None;
+    }
+    pos += 1;
+    pos += line[pos..]
+        .iter()
+        .take_while(|c| **c == b' ' || **c == b'\t' || **c == b'"' || **c == b'\'')
+        .count();
+    let len = line[pos..]
+        .iter()
+        .enumerate()
+        .take_while(|(i, c)| c.is_ascii_digit() || (*i == 0 && **c == b'-'))
+        .count();
+    Some((indent, key, pos..pos + len))
+}
+
+/// Remap the uuids stored as decimal pairs, returning the new text if anything is replaced
+/// The two keys of a pair must be in the same mapping block, i.e. at the same indent without
+/// a shallower line between them
+pub fn visit_decimal(
+    text: &[u8],
+    keys: &DecimalKeys,
+    path: &Path,
+    cb: &impl Fn(Uuid) -> Option<Uuid>,
+) -> Option<Vec<u8>> {
+    let mut pending = vec![(None::<Field>, None::<Field>); keys.pairs.len()];
+    let mut replacements = vec![];
+    // Split on any line ending, a file of lone `\r` would be a single line otherwise
+    for (line_no, range) in lines(text).into_iter().enumerate() {
+        let (line, line_start) = (&text[range.clone()], range.start);
+        let indent = line.iter().take_while(|c| c.is_ascii_whitespace()).count();
+        if indent < line.len() {
+            // A shallower line ends the blocks deeper than it
+            for (pair, (most, least)) in keys.pairs.iter().zip(pending.iter_mut()) {
+                for (field, key) in [(most, &pair.0), (least, &pair.1)] {
+                    if field.as_ref().is_some_and(|f| f.indent > indent) {
+                        let f = field.take().unwrap();
+                        log::warn!(
+                            "Unpaired decimal uuid key {} at {}:{}",
+                            key,
+                            path.display(),
+                            f.line + 1
+                        );
+                    }
+                }
+            }
+        }
+        let Some((indent, key, value)) = split_line(line) else {
+            continue;
+        };
+        for (i, (most_key, least_key)) in keys.pairs.iter().enumerate() {
+            let is_most = key == most_key.as_bytes();
+            if !is_most && key != least_key.as_bytes() {
+                continue;
+            }
+            let Some(parsed) = std::str::from_utf8(&line[value.clone()])
+                .ok()
+                .and_then(|v| v.parse::<i64>().ok())
+            else {
+                log::warn!(
+                    "Non-integer value of decimal uuid key {} at {}:{}",
+                    String::from_utf8_lossy(key),
+                    path.display(),
+                    line_no + 1
+                );
+                continue;
+            };
+            let field = Field {
+                line: line_no,
+                indent,
+                value: line_start + value.start..line_start + value.end,
+                parsed,
+            };
+            let (most, least) = &mut pending[i];
+            let (slot, other) = if is_most {
+                (most, least)
+            } else {
+                (least, most)
+            };
+            if let Some(old) = slot.replace(field) {
+                log::warn!(
+                    "Unpaired decimal uuid key {} at {}:{}",
+                    String::from_utf8_lossy(key),
+                    path.display(),
+                    old.line + 1
+                );
+            }
+            if other.as_ref().map(|f| f.indent) == slot.as_ref().map(|f| f.indent) {
+                let (Some(m), Some(l)) = (if is_most {
+                    (slot.take(), other.take())
+                } else {
+                    (other.take(), slot.take())
+                }) else {
+                    unreachable!();
+                };
+                let uuid = Uuid::from_u64_pair(m.parsed as u64, l.parsed as u64);
+                let offset = |location: &mut Location| location.offset = Some(m.value.start);
+                if let Some(new_uuid) = located(offset, || tagged(Source::TextDecimal, || cb(uuid)))
+                {
+                    let (nm, nl) = new_uuid.as_u64_pair();
+                    replacements.push((m.value, (nm as i64).to_string()));
+                    replacements.push((l.value, (nl as i64).to_string()));
+                }
+            }
+        }
+    }
+    for (pair, (most, least)) in keys.pairs.iter().zip(pending) {
+        for (field, key) in [(most, &pair.0), (least, &pair.1)] {
+            if let Some(f) = field {
+                log::warn!(
+                    "Unpaired decimal uuid key {} at {}:{}",
+                    key,
+                    path.display(),
+                    f.line + 1
+                );
+            }
+        }
+    }
+    if replacements.is_empty() {
+        return None;
+    }
+    // The lengths of the decimals change, so the text is rebuilt
+    replacements.sort_by_key(|(range, _)| range.start);
+    let mut ret = Vec::with_capacity(text.len());
+    let mut last = 0;
+    for (range, value) in replacements {
+        ret.extend_from_slice(&text[last..range.start]);
+        ret.extend_from_slice(value.as_bytes());
+        last = range.end;
+    }
+    ret.extend_from_slice(&text[last..]);
+    Some(ret)
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    use std::str::FromStr;
+
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    assert_eq!(
+        parse_keys("most,least,uuidMost,uuidLeast").unwrap().pairs,
+        vec![
+            ("most".to_string(), "least".to_string()),
+            ("uuidMost".to_string(), "uuidLeast".to_string())
+        ]
+    );
+    assert!(parse_keys("most").is_err());
+    assert!(parse_keys("most,,least").is_err());
+
+    let from = Uuid::from_str("2d318504-1a7b-39dc-8c18-44df798a5c06").unwrap();
+    let to = Uuid::from_str("fb1ad51e-cf1f-41f7-8fd1-10dff164b17d").unwrap();
+    let unmapped = Uuid::from_str("069a79f4-44e9-4726-a5be-fca90e38aaf5").unwrap();
+    let decimal = |uuid: Uuid| {
+        let (most, least) = uuid.as_u64_pair();
+        (most as i64, least as i64)
+    };
+    let (fm, fl) = decimal(from);
+    let (um, ul) = decimal(unmapped);
+    let (tm, tl) = decimal(to);
+    let yaml = format!(
+        "players:\n  a:\n    most: {}\n    name: Steve\n    least: {}\n  b:\n    uuidLeast: '{}'\n    uuidMost: '{}'\n  c:\n    most: twelve\n    least: 5\n  d:\n    most: {}\n  e:\n    least: {}\n",
+        fm, fl, ul, um, fm, fl
+    );
+    let keys = parse_keys("most,least,uuidMost,uuidLeast").unwrap();
+    let path = Path::new("plugin/players.yml");
+    let visited = std::cell::RefCell::new(vec![]);
+    let result = visit_decimal(yaml.as_bytes(), &keys, path, &|uuid| {
+        visited.borrow_mut().push(uuid);
+        (uuid == from).then_some(to)
+    })
+    .unwrap();
+    // d and e are in different blocks, so they are not paired
+    assert_eq!(visited.into_inner(), vec![from, unmapped]);
+    assert_eq!(
+        String::from_utf8(result).unwrap(),
+        format!(
+            "players:\n  a:\n    most: {}\n    name: Steve\n    least: {}\n  b:\n    uuidLeast: '{}'\n    uuidMost: '{}'\n  c:\n    most: twelve\n    least: 5\n  d:\n    most: {}\n  e:\n    least: {}\n",
+            tm, tl, ul, um, fm, fl
+        )
+    );
+
+    let json = format!("{{\n  \"most\": {},\n  \"least\": {}\n}}", fm, fl);
+    let result = visit_decimal(json.as_bytes(), &keys, path, &|uuid| {
+        (uuid == from).then_some(to)
+    })
+    .unwrap();
+    assert_eq!(
+        String::from_utf8(result).unwrap(),
+        format!("{{\n  \"most\": {},\n  \"least\": {}\n}}", tm, tl)
+    );
+    assert_eq!(
+        visit_decimal(b"most=1\nleast=2\n", &keys, path, &|_| None),
+        None
+    );
+
+    // The line endings are kept, lone `\r` also separates the lines
+    for ending in ["\r\n", "\r"] {
+        let text = format!("a:{0}  most: {1}{0}  least: {2}{0}", ending, fm, fl);
+        let result = visit_decimal(text.as_bytes(), &keys, path, &|uuid| {
+            (uuid == from).then_some(to)
+        })
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(result).unwrap(),
+            format!("a:{0}  most: {1}{0}  least: {2}{0}", ending, tm, tl)
+        );
+    }
+}
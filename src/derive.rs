@@ -0,0 +1,240 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+use uuid::Uuid;
+
+use crate::mapping::{offline_uuid, Mapping};
+
+/// Maximum number of derived pairs recorded
+const RECORD_LIMIT: usize = 1 << 20;
+
+/// Compute the new uuid from the original one
+#[derive(Debug)]
+pub enum DynamicMapping {
+    /// `hmac-sha256:<hexkey>`, a pseudonymous version 4 uuid from the HMAC of the uuid
+    HmacSha256(Vec<u8>),
+    /// `offline-from-name:<usercache.json>`, the offline uuid of the player with the uuid in the usercache
+    OfflineFromName(HashMap<Uuid, String>),
+    /// `xor:<hex>`, xor the uuid with 16 bytes, applying it twice gives the original uuid
+    Xor([u8; 16]),
+}
+
+fn parse_hex(s: &str) -> anyhow::Result<Vec<u8>> {
+    if s.len() % 2 != 0 || !s.is_ascii() {
+        anyhow::bail!("Invalid hex string {}", s);
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&s[i..i + 2], 16)
+                .map_err(|_| anyhow::anyhow!("Invalid hex string {}", s))
+        })
+        .collect()
+}
+
+fn load_usercache(path: &Path) -> anyhow::Result<HashMap<Uuid, String>> {
+    #[derive(Deserialize)]
+    struct Res {
+        name: String,
+        uuid: Uuid,
+    }
+    Ok(
+        serde_json::from_str::<Vec<Res>>(&std::fs::read_to_string(path)?)?
+            .into_iter()
+            .map(|x| (x.uuid, x.name))
+            .collect(),
+    )
+}
+
+impl DynamicMapping {
+    /// Parse a spec like `xor:<hex>`
+    pub fn parse(spec: &str) -> anyhow::Result<Self> {
+        let Some((kind, arg)) = spec.split_once(':') else {
+            anyhow::bail!("Expected <kind>:<argument>, got {}", spec);
+        };
+        match kind {
+            "hmac-sha256" => {
+                let key = parse_hex(arg)?;
+                if key.is_empty() {
+                    anyhow::bail!("Empty HMAC key");
+                }
+                Ok(DynamicMapping::HmacSha256(key))
+            }
+            "offline-from-name" => Ok(DynamicMapping::OfflineFromName(load_usercache(Path::new(
+                arg,
+            ))?)),
+            "xor" => {
+                let Ok(mask) = <[u8; 16]>::try_from(parse_hex(arg)?) else {
+                    anyhow::bail!("The xor mask must be 16 bytes");
+                };
+                Ok(DynamicMapping::Xor(mask))
+            }
+            _ => anyhow::bail!(
+                "Unknown derive kind {}, expected hmac-sha256, offline-from-name or xor",
+                kind
+            ),
+        }
+    }
+
+    pub fn derive(&self, uuid: Uuid) -> Option<Uuid> {
+        let ret = match self {
+            DynamicMapping::HmacSha256(key) => {
+                let mut mac =
+                    Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any size");
+                mac.update(uuid.as_bytes());
+                let mut bytes = [0; 16];
+                bytes.copy_from_slice(&mac.finalize().into_bytes()[..16]);
+                uuid::Builder::from_random_bytes(bytes).into_uuid()
+            }
+            DynamicMapping::OfflineFromName(names) => offline_uuid(names.get(&uuid)?),
+            DynamicMapping::Xor(mask) => {
+                let mut bytes = *uuid.as_bytes();
+                for (b, m) in bytes.iter_mut().zip(mask) {
+                    *b ^= m;
+                }
+                Uuid::from_bytes(bytes)
+            }
+        };
+        (ret != uuid).then_some(ret)
+    }
+}
+
+/// A static mapping with a dynamic fallback, recording the derived pairs actually applied
+#[derive(Debug)]
+pub struct DerivedMapping {
+    fixed: HashMap<Uuid, Uuid>,
+    dynamic: DynamicMapping,
+    /// Ignore the static mapping
+    exclusive: bool,
+    /// Never derived, the players of `--freeze`
+    frozen: HashSet<Uuid>,
+    applied: Mutex<HashMap<Uuid, Uuid>>,
+    overflowed: AtomicBool,
+}
+
+impl DerivedMapping {
+    pub fn new(fixed: HashMap<Uuid, Uuid>, dynamic: DynamicMapping, exclusive: bool) -> Self {
+        Self {
+            fixed,
+            dynamic,
+            exclusive,
+            frozen: HashSet::new(),
+            applied: Mutex::new(HashMap::new()),
+            overflowed: AtomicBool::new(false),
+        }
+    }
+
+    /// Leave the uuids as they are
+    pub fn with_frozen(self, frozen: impl IntoIterator<Item = Uuid>) -> Self {
+        Self {
+            frozen: frozen.into_iter().collect(),
+            ..self
+        }
+    }
+
+    /// The derived pairs applied, and whether some pairs are not recorded because of the limit
+    pub fn applied(&self) -> (HashMap<Uuid, Uuid>, bool) {
+        (
+            self.applied.lock().unwrap().clone(),
+            self.overflowed.load(Ordering::Relaxed),
+        )
+    }
+}
+
+impl Mapping for DerivedMapping {
+    fn get(&self, uuid: Uuid) -> Option<Uuid> {
+        if !self.exclusive {
+            if let Some(to) = self.fixed.get(&uuid) {
+                return Some(*to);
+            }
+        }
+        if self.frozen.contains(&uuid) {
+            return None;
+        }
+        let to = self.dynamic.derive(uuid)?;
+        let mut applied = self.applied.lock().unwrap();
+        if applied.len() < RECORD_LIMIT || applied.contains_key(&uuid) {
+            applied.insert(uuid, to);
+        } else {
+            self.overflowed.store(true, Ordering::Relaxed);
+        }
+        Some(to)
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    use std::str::FromStr;
+
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    let a = Uuid::from_str("2d318504-1a7b-39dc-8c18-44df798a5c06").unwrap();
+    let b = Uuid::from_str("069a79f4-44e9-4726-a5be-fca90e38aaf5").unwrap();
+
+    let xor = DynamicMapping::parse("xor:000102030405060708090a0b0c0d0e0f").unwrap();
+    let x = xor.derive(a).unwrap();
+    assert_ne!(x, a);
+    assert_eq!(xor.derive(x), Some(a));
+    let zero = DynamicMapping::parse("xor:00000000000000000000000000000000").unwrap();
+    assert_eq!(zero.derive(a), None);
+
+    let hmac = DynamicMapping::parse("hmac-sha256:73656372657421").unwrap();
+    let h = hmac.derive(a).unwrap();
+    assert_eq!(h.get_version_num(), 4);
+    assert_eq!(hmac.derive(a), Some(h));
+    assert_ne!(hmac.derive(b), Some(h));
+    let other = DynamicMapping::parse("hmac-sha256:6f74686572").unwrap();
+    assert_ne!(other.derive(a), Some(h));
+
+    let usercache = std::env::temp_dir().join("test_derive_usercache.json");
+    std::fs::write(
+        &usercache,
+        format!(r#"[{{"name":"Notch","uuid":"{}"}}]"#, b),
+    )
+    .unwrap();
+    let offline =
+        DynamicMapping::parse(&format!("offline-from-name:{}", usercache.display())).unwrap();
+    assert_eq!(offline.derive(b), Some(offline_uuid("Notch")));
+    assert_eq!(offline.derive(a), None);
+    std::fs::remove_file(&usercache).unwrap();
+
+    assert!(DynamicMapping::parse("xor:0102").is_err());
+    assert!(DynamicMapping::parse("xor:zz").is_err());
+    assert!(DynamicMapping::parse("hmac-sha256:").is_err());
+    assert!(DynamicMapping::parse("rot13:00").is_err());
+    assert!(DynamicMapping::parse("xor").is_err());
+
+    let fixed = HashMap::from([(a, b)]);
+    let mapping = DerivedMapping::new(
+        fixed.clone(),
+        DynamicMapping::parse("hmac-sha256:01").unwrap(),
+        false,
+    );
+    assert_eq!(mapping.get(a), Some(b));
+    let derived = mapping.get(b).unwrap();
+    assert_eq!(mapping.get(b), Some(derived));
+    let (applied, overflowed) = mapping.applied();
+    assert_eq!(applied, HashMap::from([(b, derived)]));
+    assert!(!overflowed);
+    let mapping = DerivedMapping::new(
+        fixed,
+        DynamicMapping::parse("hmac-sha256:01").unwrap(),
+        true,
+    );
+    assert_ne!(mapping.get(a), Some(b));
+    let mapping = mapping.with_frozen([a]);
+    assert_eq!(mapping.get(a), None);
+    assert!(mapping.get(b).is_some());
+}
@@ -0,0 +1,123 @@
+use std::path::{Component, Path, PathBuf};
+
+use serde::Serialize;
+
+/// Statistics of the files in a dimension
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct DimensionStats {
+    pub files: usize,
+    pub replacements: usize,
+    pub errors: usize,
+}
+
+impl DimensionStats {
+    pub fn merge(&mut self, other: &DimensionStats) {
+        self.files += other.files;
+        self.replacements += other.replacements;
+        self.errors += other.errors;
+    }
+}
+
+/// Rules to classify files into dimensions
+/// User rules are checked in order before the built-in rules
+#[derive(Debug, Clone, Default)]
+pub struct DimensionRules {
+    rules: Vec<(PathBuf, String)>,
+}
+
+impl DimensionRules {
+    /// Files with the prefix (relative to the world) belong to the dimension
+    pub fn add(&mut self, prefix: PathBuf, label: String) {
+        self.rules.push((prefix, label));
+    }
+
+    /// Classify a file by its path relative to the world
+    pub fn classify(&self, relative: &Path) -> String {
+        if let Some((_, label)) = self
+            .rules
+            .iter()
+            .find(|(prefix, _)| relative.starts_with(prefix))
+        {
+            return label.clone();
+        }
+        let parts = relative
+            .components()
+            .filter_map(|c| match c {
+                Component::Normal(s) => s.to_str(),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        // Only directories count, not the file name
+        let dirs = &parts[..parts.len().saturating_sub(1)];
+        if dirs.contains(&"DIM-1") || dirs.first() == Some(&"world_nether") {
+            return "the_nether".to_string();
+        }
+        if dirs.contains(&"DIM1") || dirs.first() == Some(&"world_the_end") {
+            return "the_end".to_string();
+        }
+        if let Some(i) = dirs.iter().position(|&d| d == "dimensions") {
+            if let [namespace, name, ..] = &dirs[i + 1..] {
+                return format!("{}:{}", namespace, name);
+            }
+        }
+        if dirs
+            .iter()
+            .any(|&d| matches!(d, "region" | "entities" | "poi"))
+        {
+            return "overworld".to_string();
+        }
+        "other".to_string()
+    }
+}
+
+/// Parse a dimension rule like `plugins/MyWorlds/mining=mining`
+pub fn parse_rule(s: &str) -> Result<(PathBuf, String), String> {
+    let (prefix, label) = s
+        .split_once('=')
+        .ok_or_else(|| format!("Expected <prefix>=<label>, got {}", s))?;
+    if label.is_empty() {
+        return Err(format!("Empty label in {}", s));
+    }
+    Ok((PathBuf::from(prefix), label.to_string()))
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    let mut rules = DimensionRules::default();
+    let classify = |rules: &DimensionRules, path: &str| rules.classify(Path::new(path));
+    assert_eq!(classify(&rules, "world/region/r.0.0.mca"), "overworld");
+    assert_eq!(classify(&rules, "region/r.0.0.mca"), "overworld");
+    assert_eq!(classify(&rules, "world/entities/r.0.0.mca"), "overworld");
+    assert_eq!(
+        classify(&rules, "world/DIM-1/region/r.0.0.mca"),
+        "the_nether"
+    );
+    assert_eq!(classify(&rules, "world/DIM1/poi/r.0.0.mca"), "the_end");
+    assert_eq!(
+        classify(&rules, "world_nether/DIM-1/region/r.0.0.mca"),
+        "the_nether"
+    );
+    assert_eq!(classify(&rules, "world_the_end/level.dat"), "the_end");
+    assert_eq!(
+        classify(&rules, "world/dimensions/mymod/mining/region/r.0.0.mca"),
+        "mymod:mining"
+    );
+    assert_eq!(classify(&rules, "world/playerdata/a.dat"), "other");
+    assert_eq!(classify(&rules, "world/level.dat"), "other");
+    assert_eq!(classify(&rules, "region.json"), "other");
+
+    let (prefix, label) = parse_rule("plugins/Worlds/mining=mining").unwrap();
+    rules.add(prefix, label);
+    assert_eq!(
+        classify(&rules, "plugins/Worlds/mining/region/r.0.0.mca"),
+        "mining"
+    );
+    assert_eq!(classify(&rules, "plugins/Worlds/miningx/a.yml"), "other");
+    assert!(parse_rule("nolabel").is_err());
+    assert!(parse_rule("prefix=").is_err());
+}
@@ -0,0 +1,134 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use aho_corasick::AhoCorasick;
+use uuid::Uuid;
+
+use crate::mapping::Mapping;
+
+/// No prefilter is built for larger mappings, the automaton would take too much memory
+pub const MAX_PREFILTER_ENTRIES: usize = 1_000_000;
+
+/// The mapping with a prefilter over every form of its old uuids, built once and shared by the workers
+pub struct RemapEngine {
+    mapping: HashMap<Uuid, Uuid>,
+    /// `None` if the mapping is empty or too large, everything is visited then
+    prefilter: Option<AhoCorasick>,
+    build_time: Duration,
+}
+
+/// The forms an old uuid can be found in, the visitors find nothing without one of them
+/// - the 16 bytes of NBT int arrays and lists, and the 8 bytes of the most significant half of
+///   `Most`/`Least` pairs, big-endian
+/// - the text, dashed or not, in any case
+/// - the digits of the most significant half, for the decimal pairs of `--decimal-uuid-keys`
+fn forms(uuid: Uuid) -> [Vec<u8>; 5] {
+    let (most, _) = uuid.as_u64_pair();
+    [
+        uuid.as_bytes().to_vec(),
+        most.to_be_bytes().to_vec(),
+        uuid.hyphenated().to_string().into_bytes(),
+        uuid.simple().to_string().into_bytes(),
+        (most as i64).unsigned_abs().to_string().into_bytes(),
+    ]
+}
+
+impl RemapEngine {
+    pub fn new(mapping: HashMap<Uuid, Uuid>) -> anyhow::Result<Self> {
+        let start = Instant::now();
+        let prefilter = if mapping.is_empty() || mapping.len() > MAX_PREFILTER_ENTRIES {
+            None
+        } else {
+            // Case-insensitive for the text, the binary forms only match more often
+            Some(
+                AhoCorasick::builder()
+                    .ascii_case_insensitive(true)
+                    .build(mapping.keys().flat_map(|&uuid| forms(uuid)))?,
+            )
+        };
+        Ok(Self {
+            mapping,
+            prefilter,
+            build_time: start.elapsed(),
+        })
+    }
+
+    /// Whether the data may have an old uuid, `false` only if it can be skipped
+    pub fn prefilter(&self, data: &[u8]) -> bool {
+        self.prefilter
+            .as_ref()
+            .is_none_or(|prefilter| prefilter.is_match(data))
+    }
+
+    /// The time spent building the prefilter
+    pub fn build_time(&self) -> Duration {
+        self.build_time
+    }
+}
+
+impl Mapping for RemapEngine {
+    fn get(&self, uuid: Uuid) -> Option<Uuid> {
+        self.mapping.get(&uuid).copied()
+    }
+}
+
+impl std::fmt::Debug for RemapEngine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RemapEngine")
+            .field("entries", &self.mapping.len())
+            .field("prefilter", &self.prefilter.is_some())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    let mut rng = StdRng::seed_from_u64(0);
+    let mapping = (0..10_000)
+        .map(|_| (Uuid::from_u128(rng.gen()), Uuid::from_u128(rng.gen())))
+        .collect::<HashMap<_, _>>();
+    let engine = RemapEngine::new(mapping.clone()).unwrap();
+    assert!(engine.prefilter.is_some());
+    assert_eq!(engine.mapping.len(), 10_000);
+    for (&from, &to) in &mapping {
+        assert_eq!(engine.get(from), Some(to));
+    }
+    let other = Uuid::from_u128(rng.gen());
+    assert_eq!(engine.get(other), None);
+
+    let (&from, _) = mapping.iter().next().unwrap();
+    let (most, least) = from.as_u64_pair();
+    let text = |s: String| format!("{{\"owner\": \"{}\"}}", s).into_bytes();
+    assert!(engine.prefilter(&text(from.hyphenated().to_string())));
+    assert!(engine.prefilter(&text(from.simple().to_string().to_uppercase())));
+    assert!(engine.prefilter(&text(format!(
+        "most: {}, least: {}",
+        most as i64, least as i64
+    ))));
+    let mut nbt = vec![11, 0, 4];
+    nbt.extend(b"UUID");
+    nbt.extend(4i32.to_be_bytes());
+    nbt.extend(from.as_bytes());
+    assert!(engine.prefilter(&nbt));
+    let mut pair = vec![4, 0, 8];
+    pair.extend(b"UUIDMost");
+    pair.extend(most.to_be_bytes());
+    assert!(engine.prefilter(&pair));
+    // Only the old uuids are looked for
+    assert!(!engine.prefilter(&text(other.hyphenated().to_string())));
+    assert!(!engine.prefilter(b"{\"motd\": \"A Minecraft Server\"}"));
+
+    // Everything is visited without a prefilter
+    let empty = RemapEngine::new(HashMap::new()).unwrap();
+    assert!(empty.prefilter.is_none());
+    assert!(empty.prefilter(b"anything"));
+}
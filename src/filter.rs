@@ -0,0 +1,310 @@
+use std::{
+    collections::HashSet,
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::mapping::parse_expires;
+
+/// A box of chunks, like the selections of MCA Selector
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Area {
+    /// The corners in world chunk coordinates, both inclusive
+    pub min: (i32, i32),
+    pub max: (i32, i32),
+}
+
+impl Area {
+    /// The box between two corners in any order
+    pub fn new(a: (i32, i32), b: (i32, i32)) -> Self {
+        Self {
+            min: (a.0.min(b.0), a.1.min(b.1)),
+            max: (a.0.max(b.0), a.1.max(b.1)),
+        }
+    }
+
+    pub fn contains(&self, chunk: (i32, i32)) -> bool {
+        (self.min.0..=self.max.0).contains(&chunk.0) && (self.min.1..=self.max.1).contains(&chunk.1)
+    }
+
+    /// Check if any chunk of the region is inside the area
+    pub fn intersects_region(&self, region: (i32, i32)) -> bool {
+        self.min.0 >> 5 <= region.0
+            && region.0 <= self.max.0 >> 5
+            && self.min.1 >> 5 <= region.1
+            && region.1 <= self.max.1 >> 5
+    }
+}
+
+/// Parse an area like `-100,-100,100,100`, in block coordinates unless suffixed with `c` for chunks
+/// A `b` suffix for blocks is accepted as well
+pub fn parse_area(s: &str) -> Result<Area, String> {
+    let (coords, shift) = match s.trim().strip_suffix('c') {
+        Some(coords) => (coords, 0),
+        None => (s.trim().strip_suffix('b').unwrap_or(s.trim()), 4),
+    };
+    let coords = coords
+        .split(',')
+        .map(|c| c.trim().parse::<i32>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| format!("Invalid coordinate in {}", s))?;
+    let [x1, z1, x2, z2] = coords[..] else {
+        return Err(format!("Expected <x1>,<z1>,<x2>,<z2>[b|c], got {}", s));
+    };
+    Ok(Area::new(
+        (x1 >> shift, z1 >> shift),
+        (x2 >> shift, z2 >> shift),
+    ))
+}
+
+/// Restrict the remapping of region files to some chunks
+/// Chunks outside the filter are copied as is
+#[derive(Debug, Clone, Default)]
+pub struct ChunkFilter {
+    /// Selected chunks in world chunk coordinates
+    chunks: HashSet<(i32, i32)>,
+    /// Chunks inside the area are selected as well
+    area: Option<Area>,
+}
+
+impl ChunkFilter {
+    pub fn new(chunks: impl IntoIterator<Item = (i32, i32)>) -> Self {
+        Self {
+            chunks: chunks.into_iter().collect(),
+            area: None,
+        }
+    }
+
+    pub fn with_area(mut self, area: Area) -> Self {
+        self.area = Some(area);
+        self
+    }
+
+    /// Selected chunks in world chunk coordinates, not including the area
+    pub fn chunks(&self) -> impl Iterator<Item = (i32, i32)> + '_ {
+        self.chunks.iter().copied()
+    }
+
+    pub fn area(&self) -> Option<Area> {
+        self.area
+    }
+
+    /// Check if any chunk in the region is selected
+    pub fn contains_region(&self, region: (i32, i32)) -> bool {
+        self.chunks.iter().any(|&(x, z)| (x >> 5, z >> 5) == region)
+            || self.area.is_some_and(|area| area.intersects_region(region))
+    }
+
+    /// Check if the chunk at the local location in the region is selected
+    pub fn contains(&self, region: (i32, i32), local: (i32, i32)) -> bool {
+        let chunk = world_chunk(region, local);
+        self.chunks.contains(&chunk) || self.area.is_some_and(|area| area.contains(chunk))
+    }
+}
+
+/// Convert a local chunk location in a region to world chunk coordinates
+pub fn world_chunk(region: (i32, i32), local: (i32, i32)) -> (i32, i32) {
+    (region.0 * 32 + local.0, region.1 * 32 + local.1)
+}
+
+/// Parse chunk coordinates like `12,-3`
+pub fn parse_chunk(s: &str) -> Result<(i32, i32), String> {
+    let (x, z) = s
+        .split_once(',')
+        .ok_or_else(|| format!("Expected <x>,<z>, got {}", s))?;
+    let x = x
+        .trim()
+        .parse()
+        .map_err(|_| format!("Invalid x coordinate {}", x))?;
+    let z = z
+        .trim()
+        .parse()
+        .map_err(|_| format!("Invalid z coordinate {}", z))?;
+    Ok((x, z))
+}
+
+/// Restrict the remapping to the files modified in a range, by their mtime
+/// `after` is inclusive and `before` is exclusive, so the same timestamp splits the files in two
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TimeFilter {
+    pub after: Option<SystemTime>,
+    pub before: Option<SystemTime>,
+}
+
+impl TimeFilter {
+    pub fn is_active(&self) -> bool {
+        self.after.is_some() || self.before.is_some()
+    }
+
+    /// Check if the modification time is in the range
+    pub fn contains(&self, modified: SystemTime) -> bool {
+        self.after.is_none_or(|after| modified >= after)
+            && self.before.is_none_or(|before| modified < before)
+    }
+
+    /// Check if the file is left out, a file without a modification time is never left out
+    pub fn excludes(&self, path: &Path) -> bool {
+        if !self.is_active() {
+            return false;
+        }
+        match std::fs::metadata(path).and_then(|meta| meta.modified()) {
+            Ok(modified) => !self.contains(modified),
+            Err(err) => {
+                log::warn!(
+                    "Failed to read the modification time of {}, it is not filtered: {}",
+                    path.display(),
+                    err
+                );
+                false
+            }
+        }
+    }
+}
+
+/// Parse a timestamp in RFC3339 like `2024-05-01T12:00:00Z`, or relative to now like `7d`,
+/// with the units `s`, `m`, `h`, `d` and `w`
+pub fn parse_timestamp(s: &str) -> Result<SystemTime, String> {
+    parse_timestamp_at(s, SystemTime::now())
+}
+
+fn parse_timestamp_at(s: &str, now: SystemTime) -> Result<SystemTime, String> {
+    let s = s.trim();
+    let unit = match s.chars().last() {
+        Some('s') => Some(1),
+        Some('m') => Some(60),
+        Some('h') => Some(3600),
+        Some('d') => Some(86400),
+        Some('w') => Some(7 * 86400),
+        _ => None,
+    };
+    if let Some(count) = unit.and_then(|unit| Some(s[..s.len() - 1].parse::<u64>().ok()? * unit)) {
+        return now
+            .checked_sub(Duration::from_secs(count))
+            .ok_or_else(|| format!("{} is too far in the past", s));
+    }
+    let seconds = parse_expires(s).ok_or_else(|| {
+        format!(
+            "Expected a RFC3339 timestamp like 2024-05-01T12:00:00Z, or a duration like 7d, got {}",
+            s
+        )
+    })?;
+    Ok(match u64::try_from(seconds) {
+        Ok(seconds) => UNIX_EPOCH + Duration::from_secs(seconds),
+        Err(_) => UNIX_EPOCH - Duration::from_secs(seconds.unsigned_abs()),
+    })
+}
+
+/// Format the time in RFC3339 in UTC, to the second
+pub fn format_timestamp(time: SystemTime) -> String {
+    let seconds = match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_secs() as i64,
+        Err(err) => -(err.duration().as_secs_f64().ceil() as i64),
+    };
+    let (days, second) = (seconds.div_euclid(86400), seconds.rem_euclid(86400));
+    // The inverse of `days_from_civil` in the mapping loaders
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = yoe + era * 400 + i64::from(m <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        y,
+        m,
+        d,
+        second / 3600,
+        second / 60 % 60,
+        second % 60
+    )
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    assert_eq!(parse_chunk("12,-3"), Ok((12, -3)));
+    assert_eq!(parse_chunk(" 0 , 0 "), Ok((0, 0)));
+    assert!(parse_chunk("12").is_err());
+    assert!(parse_chunk("a,3").is_err());
+    assert!(parse_chunk("1,2,3").is_err());
+
+    let filter = ChunkFilter::new([(33, -1), (0, 0)]);
+    assert!(filter.contains_region((1, -1)));
+    assert!(filter.contains_region((0, 0)));
+    assert!(!filter.contains_region((-1, 0)));
+    assert!(filter.contains((1, -1), (1, 31)));
+    assert!(!filter.contains((1, -1), (1, 30)));
+    assert!(filter.contains((0, 0), (0, 0)));
+
+    assert_eq!(
+        parse_area("0,0,47,31c"),
+        Ok(Area {
+            min: (0, 0),
+            max: (47, 31)
+        })
+    );
+    // Blocks by default, corners in any order
+    let area = parse_area("767, 511, -1, 0").unwrap();
+    assert_eq!(area, parse_area("-16,0,767,511b").unwrap());
+    assert_eq!(area, Area::new((-1, 0), (47, 31)));
+    assert!(parse_area("0,0,1").is_err());
+    assert!(parse_area("0,0,1,1x").is_err());
+    assert!(parse_area("0,0,1,1,1c").is_err());
+
+    let filter = ChunkFilter::new([(100, 100)]).with_area(area);
+    assert!(filter.contains_region((-1, 0)));
+    assert!(filter.contains_region((1, 0)));
+    assert!(filter.contains_region((3, 3)));
+    assert!(!filter.contains_region((2, 0)));
+    assert!(!filter.contains_region((0, 1)));
+    assert!(filter.contains((1, 0), (15, 31)));
+    assert!(!filter.contains((1, 0), (16, 0)));
+    assert!(filter.contains((-1, 0), (31, 0)));
+    assert!(!filter.contains((-1, 0), (30, 0)));
+    assert_eq!(filter.chunks().collect::<Vec<_>>(), vec![(100, 100)]);
+
+    let now = UNIX_EPOCH + Duration::from_secs(1_714_564_800);
+    assert_eq!(parse_timestamp_at("2024-05-01T12:00:00Z", now), Ok(now));
+    assert_eq!(
+        parse_timestamp_at("2024-05-01T14:00:00+02:00", now),
+        Ok(now)
+    );
+    assert_eq!(
+        parse_timestamp_at("7d", now),
+        Ok(now - Duration::from_secs(7 * 86400))
+    );
+    assert_eq!(
+        parse_timestamp_at("90m", now),
+        Ok(now - Duration::from_secs(5400))
+    );
+    assert!(parse_timestamp_at("7y", now).is_err());
+    assert!(parse_timestamp_at("-7d", now).is_err());
+    assert!(parse_timestamp_at("yesterday", now).is_err());
+    assert_eq!(format_timestamp(now), "2024-05-01T12:00:00Z");
+    assert_eq!(
+        format_timestamp(UNIX_EPOCH - Duration::from_secs(1)),
+        "1969-12-31T23:59:59Z"
+    );
+    assert_eq!(
+        format_timestamp(parse_timestamp_at("2000-02-29T23:59:59Z", now).unwrap()),
+        "2000-02-29T23:59:59Z"
+    );
+
+    let filter = TimeFilter {
+        after: Some(now - Duration::from_secs(60)),
+        before: Some(now),
+    };
+    assert!(filter.is_active());
+    assert!(!TimeFilter::default().is_active());
+    assert!(filter.contains(now - Duration::from_secs(60)));
+    assert!(filter.contains(now - Duration::from_secs(1)));
+    assert!(!filter.contains(now));
+    assert!(!filter.contains(now - Duration::from_secs(61)));
+}
@@ -0,0 +1,506 @@
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashSet},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+use anyhow::Context;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{
+    guard::WriteGuard,
+    handler::{Registry, RemapOptions},
+    journal::{self, append_synced, Entry},
+    progress::Progress,
+    remap::remap_file,
+    source::{self, Location, Source},
+    task::{scan_world, split_tasks},
+};
+
+/// Occurrences of the uuids in each file, relative to the world
+pub type Findings = BTreeMap<PathBuf, BTreeMap<Uuid, usize>>;
+
+/// Where a uuid is found in a file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Match {
+    pub source: Option<Source>,
+    #[serde(flatten)]
+    pub location: Location,
+}
+
+impl std::fmt::Display for Match {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.source.map_or("unknown", Source::label))?;
+        if self.location != Location::default() {
+            write!(f, " at {}", self.location)?;
+        }
+        Ok(())
+    }
+}
+
+/// The matches of each uuid in a file
+pub type Matches = BTreeMap<Uuid, Vec<Match>>;
+
+/// A line of the findings file
+#[derive(Debug, Serialize)]
+struct Finding<'a> {
+    file: &'a Path,
+    uuid: Uuid,
+    count: usize,
+    matches: &'a [Match],
+}
+
+/// How `find_uuids` reports its progress, to be resumed after an interruption
+/// Only a verification has a journal, a plain run leaves nothing behind
+#[derive(Default)]
+pub struct FindOptions<'a> {
+    /// Each checked file is appended to the journal, from `journal_for`
+    pub journal: Option<&'a Path>,
+    /// Each finding is appended as a line of JSON as soon as its file is checked
+    pub findings_file: Option<&'a Path>,
+    /// Skip the files checked by an interrupted run, their findings are read from the journal
+    pub resume: bool,
+    /// Checked before each file, set by `cancel_on_interrupt`, the journal is kept to resume if it is set
+    pub cancel: Option<&'a AtomicBool>,
+    /// Called with the matches in each file as soon as it is checked
+    pub on_found: Option<&'a (dyn Fn(&Path, &Matches) + Sync)>,
+}
+
+/// The result of `find_uuids`
+#[derive(Debug)]
+pub struct Found {
+    /// Also the findings of the interrupted run when resumed
+    pub findings: Findings,
+    /// The files checked by the interrupted run, skipped
+    pub resumed: usize,
+    /// Unset if cancelled, the journal is kept
+    pub complete: bool,
+}
+
+/// The files checked so far by a verification, one line of JSON per file, next to the world like
+/// the staging directory
+/// Keyed by the target set, a run verifying other uuids has its own journal
+pub fn journal_for(world: &Path, targets: &HashSet<Uuid>) -> anyhow::Result<PathBuf> {
+    let mut sorted = targets.iter().collect::<Vec<_>>();
+    sorted.sort();
+    let key = md5::compute(
+        sorted
+            .iter()
+            .flat_map(|uuid| *uuid.as_bytes())
+            .collect::<Vec<_>>(),
+    );
+    journal::path_for(world, &format!("verify-{}", &format!("{:x}", key)[..8]))
+}
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn on_interrupt(_: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::Relaxed);
+}
+
+/// Set on Ctrl-C instead of killing the process, so the checked files are journaled
+/// The default handler is kept where signals can't be caught
+pub fn cancel_on_interrupt() -> &'static AtomicBool {
+    #[cfg(unix)]
+    unsafe {
+        libc::signal(libc::SIGINT, on_interrupt as libc::sighandler_t);
+    }
+    &INTERRUPTED
+}
+
+/// Find the files referencing any of the uuids
+/// The visitors run through a dry-run guard with a callback that never replaces, so nothing is modified
+/// Refused if the journal of an interrupted run exists, unless resumed, it is removed once complete
+/// A run without a journal can't be resumed
+pub fn find_uuids(
+    world: &Path,
+    targets: &HashSet<Uuid>,
+    threads: usize,
+    guard: &WriteGuard,
+    find: &FindOptions,
+) -> anyhow::Result<Found> {
+    if !guard.is_dry_run() {
+        anyhow::bail!("Finding uuids requires a dry-run guard");
+    }
+    let mut findings = Findings::new();
+    let mut checked = HashSet::new();
+    match (find.journal.filter(|path| path.exists()), find.resume) {
+        (Some(journal_path), true) => {
+            for entry in journal::read(journal_path)? {
+                if !entry.found.is_empty() {
+                    findings.insert(entry.file.clone(), entry.found);
+                }
+                checked.insert(entry.file);
+            }
+            log::info!(
+                "Resuming, {} files are checked by the interrupted run",
+                checked.len()
+            );
+        }
+        (Some(journal_path), false) => anyhow::bail!(
+            "{} exists, a previous verification may have been interrupted, \
+            resume it with --resume-verify or remove the file",
+            journal_path.display()
+        ),
+        (None, true) => match find.journal {
+            Some(journal_path) => {
+                log::warn!("Nothing to resume, {} is missing", journal_path.display())
+            }
+            None => anyhow::bail!("Only a journaled run can be resumed"),
+        },
+        (None, false) => {}
+    }
+    let journal = match find.journal {
+        Some(journal_path) => Some(journal::open(journal_path, true)?),
+        None => None,
+    };
+    let findings_file = match find.findings_file {
+        Some(path) => Some(journal::open(path, true)?),
+        None => None,
+    };
+    let mut registry = Registry::builtin();
+    let options = RemapOptions {
+        guard: guard.clone(),
+        ..Default::default()
+    };
+    let tasks = scan_world(&world.to_path_buf(), &mut registry, &options)?
+        .tasks
+        .into_iter()
+        .filter(|task| !checked.contains(&task.path))
+        .collect::<Vec<_>>();
+    log::info!("{} files to check in {}", tasks.len(), world.display());
+    let resumed = checked.len();
+    let findings = Mutex::new(findings);
+    let done = AtomicUsize::new(0);
+    let cancelled = || {
+        find.cancel
+            .is_some_and(|cancel| cancel.load(Ordering::Relaxed))
+    };
+    std::thread::scope(|s| -> anyhow::Result<()> {
+        let mut handles = vec![];
+        for thread_tasks in split_tasks(&tasks, threads.max(1)) {
+            let (registry, options, findings) = (&registry, &options, &findings);
+            let (journal, findings_file, done) = (&journal, &findings_file, &done);
+            handles.push(s.spawn(move || -> anyhow::Result<()> {
+                for task in thread_tasks {
+                    if cancelled() {
+                        break;
+                    }
+                    let matches = RefCell::new(Matches::new());
+                    let result = source::locate(|| {
+                        remap_file(
+                            world,
+                            &task.path,
+                            registry,
+                            options,
+                            &Progress::hidden(),
+                            &|uuid| {
+                                if targets.contains(&uuid) {
+                                    let found = Match {
+                                        source: source::current(),
+                                        location: source::location().unwrap_or_default(),
+                                    };
+                                    matches.borrow_mut().entry(uuid).or_default().push(found);
+                                }
+                                None
+                            },
+                        )
+                    });
+                    if let Err(err) = result {
+                        log::error!("Failed to read file {}: {:#?}", task.path.display(), err);
+                    }
+                    let matches = matches.into_inner();
+                    // Streamed before the file is journaled, a crash in between finds it again
+                    if !matches.is_empty() {
+                        if let Some(on_found) = find.on_found {
+                            on_found(&task.path, &matches);
+                        }
+                        if let Some(file) = findings_file {
+                            for (&uuid, matches) in &matches {
+                                let finding = Finding {
+                                    file: &task.path,
+                                    uuid,
+                                    count: matches.len(),
+                                    matches,
+                                };
+                                append_synced(file, &serde_json::to_vec(&finding)?)?;
+                            }
+                        }
+                    }
+                    let entry = Entry {
+                        file: task.path.clone(),
+                        found: matches
+                            .into_iter()
+                            .map(|(uuid, matches)| (uuid, matches.len()))
+                            .collect(),
+                    };
+                    if let Some(journal) = journal {
+                        append_synced(journal, &serde_json::to_vec(&entry)?)
+                            .context("Failed to write the journal")?;
+                    }
+                    if !entry.found.is_empty() {
+                        findings.lock().unwrap().insert(entry.file, entry.found);
+                    }
+                    done.fetch_add(1, Ordering::Relaxed);
+                }
+                Ok(())
+            }));
+        }
+        for handle in handles {
+            handle.join().unwrap()?;
+        }
+        Ok(())
+    })?;
+    let complete = done.into_inner() == tasks.len();
+    match find.journal {
+        Some(journal_path) if complete => std::fs::remove_file(journal_path)
+            .with_context(|| format!("Failed to remove {}", journal_path.display()))?,
+        Some(journal_path) => log::warn!(
+            "Cancelled, the checked files are recorded in {}, pass --resume-verify to continue",
+            journal_path.display()
+        ),
+        None if !complete => {
+            log::warn!("Cancelled, use verify to record the checked files and resume")
+        }
+        None => {}
+    }
+    Ok(Found {
+        findings: findings.into_inner().unwrap(),
+        resumed,
+        complete,
+    })
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    use std::str::FromStr;
+
+    use crate::{
+        anvil::{Anvil, Chunk},
+        setup_test_logger,
+    };
+
+    setup_test_logger();
+
+    let a = Uuid::from_str("2d318504-1a7b-39dc-8c18-44df798a5c06").unwrap();
+    let b = Uuid::from_str("069a79f4-44e9-4726-a5be-fca90e38aaf5").unwrap();
+    let world = std::env::temp_dir().join("test_find");
+    let _ = std::fs::remove_dir_all(&world);
+    std::fs::create_dir_all(world.join("region")).unwrap();
+    std::fs::create_dir_all(world.join("stats")).unwrap();
+    let stats = format!("{{\"owner\": \"{}\", \"friend\": \"{}\"}}", a, a.simple());
+    std::fs::write(world.join("stats").join(format!("{}.json", a)), &stats).unwrap();
+    std::fs::write(world.join("config.yml"), "nothing: here\n").unwrap();
+    let mut nbt = vec![10, 0, 0, 8, 0, 1, b'u', 0, 36];
+    nbt.extend_from_slice(b.to_string().as_bytes());
+    nbt.push(0);
+    let mut anvil = Anvil::new(&world.join("region").join("r.0.0.mca"));
+    anvil
+        .write(&Chunk {
+            external: false,
+            location: (1, 2),
+            timestamp: 0,
+            uncompressed: nbt,
+            lz4: None,
+        })
+        .unwrap();
+    anvil.save().unwrap();
+
+    let snapshot = |world: &Path| {
+        let mut files = BTreeMap::new();
+        for dir in ["", "region", "stats"] {
+            for entry in std::fs::read_dir(world.join(dir)).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_file() {
+                    files.insert(path.clone(), std::fs::read(&path).unwrap());
+                }
+            }
+        }
+        files
+    };
+    let before = snapshot(world.as_path());
+    let guard = WriteGuard::dry_run();
+    let streamed = Mutex::new(BTreeMap::new());
+    let on_found = |file: &Path, matches: &Matches| {
+        streamed
+            .lock()
+            .unwrap()
+            .insert(file.to_path_buf(), matches.clone());
+    };
+    let options = FindOptions {
+        on_found: Some(&on_found),
+        ..Default::default()
+    };
+    let findings = find_uuids(&world, &HashSet::from([a, b]), 2, &guard, &options)
+        .unwrap()
+        .findings;
+    assert_eq!(guard.writes(), 0);
+    assert!(guard.discarded() > 0);
+    assert_eq!(snapshot(world.as_path()), before);
+
+    assert_eq!(findings.len(), 2);
+    // The file name is visited as well
+    assert_eq!(
+        findings[&PathBuf::from("stats").join(format!("{}.json", a))],
+        BTreeMap::from([(a, 3)])
+    );
+    assert_eq!(
+        findings[&PathBuf::from("region").join("r.0.0.mca")],
+        BTreeMap::from([(b, 1)])
+    );
+    // Each match is located in its file
+    let streamed = streamed.into_inner().unwrap();
+    let matches = &streamed[&PathBuf::from("stats").join(format!("{}.json", a))][&a];
+    let at = |source, offset| Match {
+        source: Some(source),
+        location: Location {
+            offset: Some(offset),
+            ..Default::default()
+        },
+    };
+    assert!(matches.contains(&at(Source::TextDashed, stats.find(&a.to_string()).unwrap())));
+    assert!(matches.contains(&at(
+        Source::TextUndashed,
+        stats.find(&a.simple().to_string()).unwrap()
+    )));
+    assert!(matches
+        .iter()
+        .any(|found| found.source == Some(Source::FileName)));
+    let region = &streamed[&PathBuf::from("region").join("r.0.0.mca")][&b];
+    assert_eq!(
+        region,
+        &vec![Match {
+            source: Some(Source::NbtString),
+            location: Location {
+                chunk: Some((1, 2)),
+                nbt_path: Some("u".to_string()),
+                offset: Some(0),
+            },
+        }]
+    );
+    assert_eq!(
+        region[0].to_string(),
+        "NBT string at chunk (1, 2), u, offset 0"
+    );
+    assert!(!journal_for(&world, &HashSet::from([a, b]))
+        .unwrap()
+        .exists());
+    let options = FindOptions::default();
+    assert!(find_uuids(
+        &world,
+        &HashSet::from([a]),
+        1,
+        &WriteGuard::default(),
+        &options
+    )
+    .is_err());
+    std::fs::remove_dir_all(&world).unwrap();
+}
+
+#[cfg(test)]
+#[test]
+fn test_resume() {
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    let dir = std::env::temp_dir().join("test_find_resume");
+    let _ = std::fs::remove_dir_all(&dir);
+    let world = dir.join("world");
+    std::fs::create_dir_all(world.join("stats")).unwrap();
+    let uuid = Uuid::from_u128;
+    let targets = HashSet::from([uuid(1), uuid(2)]);
+    for i in 0..6 {
+        let content = match i {
+            1 => format!("{{\"owner\": \"{}\"}}", uuid(1)),
+            4 => format!("{{\"owner\": \"{}\"}}", uuid(2)),
+            _ => "{}".to_string(),
+        };
+        std::fs::write(world.join("stats").join(format!("{}.json", i)), content).unwrap();
+    }
+    let ndjson = dir.join("findings.ndjson");
+    let journal = journal_for(&world, &targets).unwrap();
+    let cancel = AtomicBool::new(false);
+    let streamed = Mutex::new(vec![]);
+    let on_found = |file: &Path, _: &Matches| {
+        streamed.lock().unwrap().push(file.to_path_buf());
+        cancel.store(true, Ordering::Relaxed);
+    };
+    let options = FindOptions {
+        journal: Some(&journal),
+        findings_file: Some(&ndjson),
+        cancel: Some(&cancel),
+        on_found: Some(&on_found),
+        ..Default::default()
+    };
+    let guard = WriteGuard::dry_run();
+
+    // Cancelled after the first finding, which is already in the findings file
+    let found = find_uuids(&world, &targets, 1, &guard, &options).unwrap();
+    assert!(!found.complete);
+    assert_eq!(found.findings.len(), 1);
+    let first = streamed.lock().unwrap()[0].clone();
+    let lines = std::fs::read_to_string(&ndjson).unwrap();
+    let lines = lines
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(lines.len(), 1);
+    assert_eq!(lines[0]["file"], first.to_str().unwrap());
+    let checked = journal::read(&journal).unwrap();
+    assert!(checked.iter().any(|entry| entry.file == first));
+
+    // Refused without resuming
+    cancel.store(false, Ordering::Relaxed);
+    assert!(find_uuids(&world, &targets, 1, &guard, &options).is_err());
+    // Neither a run without a journal nor one finding other uuids is refused
+    let found = find_uuids(&world, &targets, 1, &guard, &FindOptions::default()).unwrap();
+    assert_eq!(found.findings.len(), 2);
+    let other = dir.join("other.ndjson");
+    let other_journal = journal_for(&world, &HashSet::from([uuid(2)])).unwrap();
+    assert_ne!(other_journal, journal);
+    let other_options = FindOptions {
+        journal: Some(&other_journal),
+        findings_file: Some(&other),
+        ..Default::default()
+    };
+    let found = find_uuids(&world, &HashSet::from([uuid(2)]), 1, &guard, &other_options).unwrap();
+    assert!(found.complete);
+    assert!(!other_journal.exists());
+    assert!(journal.exists());
+    // A run without a journal can't be resumed
+    let unjournaled = FindOptions {
+        resume: true,
+        ..Default::default()
+    };
+    assert!(find_uuids(&world, &targets, 1, &guard, &unjournaled).is_err());
+
+    // The checked files are skipped, their findings are read from the journal
+    let on_found = |file: &Path, _: &Matches| {
+        streamed.lock().unwrap().push(file.to_path_buf());
+    };
+    let options = FindOptions {
+        journal: Some(&journal),
+        findings_file: Some(&ndjson),
+        resume: true,
+        cancel: Some(&cancel),
+        on_found: Some(&on_found),
+    };
+    let found = find_uuids(&world, &targets, 1, &guard, &options).unwrap();
+    assert!(found.complete);
+    assert_eq!(found.resumed, checked.len());
+    assert_eq!(found.findings.len(), 2);
+    let streamed = streamed.into_inner().unwrap();
+    assert_eq!(streamed.len(), 2);
+    assert_eq!(streamed.iter().filter(|file| **file == first).count(), 1);
+    assert_eq!(std::fs::read_to_string(&ndjson).unwrap().lines().count(), 2);
+    assert!(!journal.exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,325 @@
+use std::{
+    collections::BTreeMap,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
+
+use flate2::{read::GzDecoder, write::GzEncoder};
+use uuid::Uuid;
+use valence_nbt::{binary::to_binary, from_binary, snbt::from_snbt_str, Compound, Value};
+
+use crate::anvil::{Anvil, Chunk};
+
+/// A player not in any mapping, it must never change
+pub const OTHER: Uuid = Uuid::from_u128(0x5c0e5b9e_2b1f_4a57_9b7d_0a6e44c1f00d);
+
+/// The uuids written into a mini server, a world with the configs of a few plugins
+pub struct Fixture {
+    /// The remapped player, written in every representation and used in the file names
+    pub player: Uuid,
+    /// The uuid of the attribute modifiers, which are left alone by default
+    pub modifier: Uuid,
+}
+
+/// The uuid as an `[I; ...]` SNBT literal
+fn ints(uuid: Uuid) -> String {
+    let u = uuid.as_u128();
+    format!(
+        "[I; {}, {}, {}, {}]",
+        (u >> 96) as i32,
+        (u >> 64) as i32,
+        (u >> 32) as i32,
+        u as i32
+    )
+}
+
+fn encode(snbt: &str) -> Vec<u8> {
+    let Value::Compound(nbt) = from_snbt_str(snbt).unwrap() else {
+        panic!("Not a compound: {}", snbt)
+    };
+    let mut binary = vec![];
+    to_binary(&nbt, &mut binary, "").unwrap();
+    binary
+}
+
+fn decode(binary: &[u8]) -> Compound<String> {
+    from_binary::<String>(&mut &binary[..]).unwrap().0
+}
+
+fn write_gzip(path: &Path, snbt: &str) {
+    let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(&encode(snbt)).unwrap();
+    std::fs::write(path, encoder.finish().unwrap()).unwrap();
+}
+
+fn write_region(path: &Path, chunks: &[((i32, i32), i32, String)]) {
+    let mut anvil = Anvil::new(path);
+    for (location, timestamp, snbt) in chunks {
+        anvil
+            .write(&Chunk {
+                external: false,
+                location: *location,
+                timestamp: *timestamp,
+                uncompressed: encode(snbt),
+                lz4: None,
+            })
+            .unwrap();
+    }
+    anvil.save().unwrap();
+}
+
+impl Fixture {
+    /// Create the server under `root`, which must not exist yet
+    pub fn build(&self, root: &Path) {
+        let (p, m) = (self.player, self.modifier);
+        let (most, least) = p.as_u64_pair();
+        let world = root.join("world");
+        for dir in ["region", "playerdata", "stats", "advancements"] {
+            std::fs::create_dir_all(world.join(dir)).unwrap();
+        }
+        std::fs::create_dir_all(root.join("plugins").join("Essentials").join("userdata")).unwrap();
+        std::fs::create_dir_all(root.join("plugins").join("Shop")).unwrap();
+
+        write_gzip(
+            &world.join("level.dat"),
+            &format!(
+                r#"{{Data: {{LevelName: "fixture", DataVersion: 3465, Player: {{UUID: {}, Health: 20.0f}}}}}}"#,
+                ints(p)
+            ),
+        );
+        write_gzip(
+            &world.join("playerdata").join(format!("{}.dat", p)),
+            &format!(
+                r#"{{
+                    UUID: {},
+                    Inventory: [{{
+                        id: "minecraft:diamond_sword",
+                        Count: 1b,
+                        tag: {{
+                            AttributeModifiers: [{{AttributeName: "generic.attack_damage", Amount: 5.0d, Operation: 0, UUID: {}}}],
+                            display: {{Lore: ["Crafted by {}"]}}
+                        }}
+                    }}],
+                    bukkit: {{lastKnownName: "Steve"}}
+                }}"#,
+                ints(p),
+                ints(m),
+                p
+            ),
+        );
+        write_gzip(
+            &world.join("playerdata").join(format!("{}.dat", OTHER)),
+            &format!(
+                r#"{{UUID: {}, bukkit: {{lastKnownName: "Alex"}}}}"#,
+                ints(OTHER)
+            ),
+        );
+        std::fs::write(
+            world.join("stats").join(format!("{}.json", p)),
+            r#"{"stats":{"minecraft:custom":{"minecraft:play_time":1200}},"DataVersion":3465}"#,
+        )
+        .unwrap();
+        std::fs::write(
+            world.join("advancements").join(format!("{}.json", p)),
+            r#"{"minecraft:story/root":{"criteria":{"crafting_table":"2024-01-01 00:00:00 +0000"},"done":true}}"#,
+        )
+        .unwrap();
+
+        // Entities and block entities in the current and the legacy formats
+        write_region(
+            &world.join("region").join("r.0.0.mca"),
+            &[
+                (
+                    (0, 0),
+                    1700000000,
+                    format!(
+                        r#"{{
+                            DataVersion: 3465, xPos: 0, zPos: 0, Status: "minecraft:full",
+                            block_entities: [
+                                {{id: "minecraft:skull", x: 0, y: 64, z: 0, SkullOwner: {{Id: {}, Name: "Steve"}}}},
+                                {{id: "minecraft:sign", x: 1, y: 64, z: 0, Text1: "Owner: {}", Text2: ""}}
+                            ]
+                        }}"#,
+                        ints(p),
+                        p
+                    ),
+                ),
+                (
+                    (1, 0),
+                    1700000001,
+                    format!(
+                        r#"{{
+                            DataVersion: 1343, xPos: 1, zPos: 0,
+                            Entities: [
+                                {{id: "minecraft:wolf", OwnerUUIDMost: {}L, OwnerUUIDLeast: {}L, UUID: {}}},
+                                {{id: "minecraft:horse", OwnerUUID: "{}"}}
+                            ]
+                        }}"#,
+                        most as i64,
+                        least as i64,
+                        ints(OTHER),
+                        p
+                    ),
+                ),
+                (
+                    (5, 7),
+                    1700000002,
+                    format!(
+                        r#"{{DataVersion: 3465, xPos: 5, zPos: 7, Status: "minecraft:full", Owner: {}}}"#,
+                        ints(OTHER)
+                    ),
+                ),
+            ],
+        );
+        write_region(
+            &world.join("region").join("r.-1.0.mca"),
+            &[(
+                (31, 0),
+                1700000003,
+                format!(
+                    r#"{{
+                        DataVersion: 3465, xPos: -1, zPos: 0,
+                        Entities: [
+                            {{id: "minecraft:cat", Owner: {}, Brain: {{memories: {{"minecraft:angry_at": {{value: {}}}}}}}}},
+                            {{
+                                id: "minecraft:zombie",
+                                ArmorItems: [{{
+                                    id: "minecraft:diamond_helmet",
+                                    Count: 1b,
+                                    tag: {{AttributeModifiers: [{{AttributeName: "generic.armor", Amount: 2.0d, Operation: 0, UUID: {}}}]}}
+                                }}]
+                            }}
+                        ]
+                    }}"#,
+                    ints(p),
+                    ints(p),
+                    ints(m)
+                ),
+            )],
+        );
+
+        // Saved by a structure block, and shipped in a data pack
+        let structure = format!(
+            r#"{{
+                DataVersion: 3465, size: [I; 1, 1, 2],
+                palette: [{{Name: "minecraft:player_head"}}],
+                blocks: [{{pos: [I; 0, 0, 0], state: 0, nbt: {{id: "minecraft:skull", SkullOwner: {{Id: {}, Name: "Steve"}}}}}}],
+                entities: [{{pos: [0.5d, 0.0d, 1.5d], blockPos: [I; 0, 0, 1], nbt: {{id: "minecraft:wolf", Owner: {}}}}}]
+            }}"#,
+            ints(p),
+            ints(p)
+        );
+        for dir in [
+            world.join("generated").join("minecraft").join("structures"),
+            world
+                .join("datapacks")
+                .join("village")
+                .join("data")
+                .join("village")
+                .join("structures"),
+        ] {
+            std::fs::create_dir_all(&dir).unwrap();
+            write_gzip(&dir.join("house.nbt"), &structure);
+        }
+
+        std::fs::write(
+            root.join("usercache.json"),
+            format!(
+                r#"[{{"name":"Steve","uuid":"{}","expiresOn":"2030-01-01 00:00:00 +0000"}},{{"name":"Alex","uuid":"{}","expiresOn":"2030-01-01 00:00:00 +0000"}}]"#,
+                p, OTHER
+            ),
+        )
+        .unwrap();
+        std::fs::write(
+            root.join("plugins")
+                .join("Essentials")
+                .join("userdata")
+                .join(format!("{}.yml", p)),
+            format!("uuid: {}\nlast-account-name: Steve\nmoney: '100'\n", p),
+        )
+        .unwrap();
+        // An index of the files renamed in the same run
+        std::fs::write(
+            root.join("plugins").join("Essentials").join("index.yml"),
+            format!("players:\n- userdata/{}.yml\n", p),
+        )
+        .unwrap();
+        std::fs::write(
+            root.join("plugins").join("Shop").join("config.yml"),
+            format!(
+                "shops:\n  spawn:\n    owner: {}\n    trusted:\n    - {}\n",
+                p, OTHER
+            ),
+        )
+        .unwrap();
+    }
+}
+
+/// The decoded content of a file, so compressed files compare by what they store
+#[derive(Debug, PartialEq)]
+pub enum Contents {
+    Text(String),
+    Nbt(Compound<String>),
+    /// The timestamp and the data of each chunk
+    Region(BTreeMap<(i32, i32), (i32, Compound<String>)>),
+}
+
+/// Every file under `root`
+fn files(root: &Path) -> Vec<PathBuf> {
+    let mut files = vec![];
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else {
+                files.push(path);
+            }
+        }
+    }
+    files
+}
+
+/// The bytes of every file under `root`, keyed by the relative path
+pub fn bytes(root: &Path) -> BTreeMap<PathBuf, Vec<u8>> {
+    files(root)
+        .into_iter()
+        .map(|path| {
+            let bytes = std::fs::read(&path).unwrap();
+            (path.strip_prefix(root).unwrap().to_path_buf(), bytes)
+        })
+        .collect()
+}
+
+/// Read every file under `root`, keyed by the relative path
+pub fn snapshot(root: &Path) -> BTreeMap<PathBuf, Contents> {
+    let mut snapshot = BTreeMap::new();
+    for path in files(root) {
+        let contents = match path.extension().and_then(|s| s.to_str()) {
+            Some("mca") => {
+                let anvil = Anvil::open(&path).unwrap();
+                Contents::Region(
+                    anvil
+                        .locations()
+                        .into_iter()
+                        .map(|location| {
+                            let chunk = anvil.read(location).unwrap();
+                            (location, (chunk.timestamp, decode(&chunk.uncompressed)))
+                        })
+                        .collect(),
+                )
+            }
+            Some("dat" | "nbt") => {
+                let mut uncompressed = vec![];
+                GzDecoder::new(std::fs::File::open(&path).unwrap())
+                    .read_to_end(&mut uncompressed)
+                    .unwrap();
+                Contents::Nbt(decode(&uncompressed))
+            }
+            _ => Contents::Text(std::fs::read_to_string(&path).unwrap()),
+        };
+        snapshot.insert(path.strip_prefix(root).unwrap().to_path_buf(), contents);
+    }
+    snapshot
+}
@@ -0,0 +1,205 @@
+use std::{
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use clap::ValueEnum;
+use flate2::read::GzDecoder;
+
+use crate::nbt::find_int;
+
+/// The `DataVersion` of 17w47a, the first snapshot of 1.13 changing the chunk format
+pub const FLATTENING: i32 = 1451;
+
+/// The layout of the world files, selecting the default rules
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum FormatVersion {
+    /// Read the `DataVersion` of `level.dat`
+    Auto,
+    /// Before 1.13
+    Legacy,
+    /// 1.13 and later
+    Modern,
+}
+
+/// The rule set chosen for the world
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Profile {
+    /// Also pair the uuid halves written by old versions
+    Legacy,
+    /// Skip the poi files, which never store uuids
+    #[default]
+    Modern,
+}
+
+impl Profile {
+    pub fn name(self) -> &'static str {
+        match self {
+            Profile::Legacy => "legacy",
+            Profile::Modern => "modern",
+        }
+    }
+
+    pub fn of_data_version(data_version: Option<i32>) -> Self {
+        match data_version {
+            // `DataVersion` was added in 1.9
+            None => Profile::Legacy,
+            Some(v) if v < FLATTENING => Profile::Legacy,
+            Some(_) => Profile::Modern,
+        }
+    }
+}
+
+/// The `level.dat` of the world, which may be in a subdirectory of the server
+pub fn find_level_dat(world: &Path) -> Option<PathBuf> {
+    let direct = world.join("level.dat");
+    if direct.is_file() {
+        return Some(direct);
+    }
+    let mut dirs = std::fs::read_dir(world)
+        .ok()?
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| path.is_dir())
+        .collect::<Vec<_>>();
+    dirs.sort();
+    dirs.into_iter()
+        .map(|dir| dir.join("level.dat"))
+        .find(|path| path.is_file())
+}
+
+/// Read `Data.DataVersion` of a gzipped or raw `level.dat`
+pub fn read_data_version(level_dat: &Path) -> anyhow::Result<Option<i32>> {
+    let content = std::fs::read(level_dat)?;
+    let mut uncompressed = Vec::new();
+    let nbt = match GzDecoder::new(content.as_slice()).read_to_end(&mut uncompressed) {
+        Ok(_) => &uncompressed,
+        Err(_) => &content,
+    };
+    find_int(nbt, &["Data", "DataVersion"])
+}
+
+impl FormatVersion {
+    /// Choose the profile, returning why it is chosen for the summary
+    pub fn resolve(self, world: &Path) -> (Profile, String) {
+        match self {
+            FormatVersion::Legacy => (Profile::Legacy, "--format-version".to_string()),
+            FormatVersion::Modern => (Profile::Modern, "--format-version".to_string()),
+            FormatVersion::Auto => {
+                let Some(level_dat) = find_level_dat(world) else {
+                    return (Profile::Modern, "no level.dat found".to_string());
+                };
+                let relative = crate::task::relative_path(world, &level_dat);
+                match read_data_version(&level_dat) {
+                    Ok(Some(v)) => (
+                        Profile::of_data_version(Some(v)),
+                        format!("DataVersion {} in {}", v, relative.display()),
+                    ),
+                    Ok(None) => (
+                        Profile::Legacy,
+                        format!("no DataVersion in {}", relative.display()),
+                    ),
+                    Err(err) => {
+                        log::warn!("Failed to read {}: {:#}", level_dat.display(), err);
+                        (
+                            Profile::Modern,
+                            format!("{} is unreadable", relative.display()),
+                        )
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Check if the file is a region of the poi tree, which stores points of interest of the villagers
+pub fn is_poi(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "mca")
+        && path
+            .parent()
+            .and_then(|dir| dir.file_name())
+            .is_some_and(|dir| dir == "poi")
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    use std::io::Write;
+
+    use flate2::write::GzEncoder;
+    use valence_nbt::{binary::to_binary, snbt::from_snbt_str, Value};
+
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    let write_level = |path: &Path, snbt: &str| {
+        let Value::Compound(nbt) = from_snbt_str(snbt).unwrap() else {
+            panic!()
+        };
+        let mut binary = vec![];
+        to_binary(&nbt, &mut binary, "").unwrap();
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&binary).unwrap();
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, encoder.finish().unwrap()).unwrap();
+    };
+    let dir = std::env::temp_dir().join("test_format");
+    let _ = std::fs::remove_dir_all(&dir);
+    let (legacy, modern, plain, none) = (
+        dir.join("legacy"),
+        dir.join("modern"),
+        dir.join("plain"),
+        dir.join("none"),
+    );
+    // 1.12.2, the version is after a list of compounds
+    write_level(
+        &legacy.join("world").join("level.dat"),
+        r#"{Data: {LevelName: "old", GameRules: {doFireTick: "true"}, Player: {Inventory: [{id: "minecraft:stone", Count: 1b}], Pos: [0.0d, 64.0d, 0.0d]}, DataVersion: 1343, Version: {Id: 1343, Name: "1.12.2"}}}"#,
+    );
+    write_level(
+        &modern.join("level.dat"),
+        r#"{Data: {LevelName: "new", DataVersion: 3465, WorldGenSettings: {seed: 0L}}}"#,
+    );
+    // 1.8, before `DataVersion`
+    write_level(
+        &plain.join("world").join("level.dat"),
+        r#"{Data: {LevelName: "older", version: 19133}}"#,
+    );
+    std::fs::create_dir_all(&none).unwrap();
+
+    let (profile, reason) = FormatVersion::Auto.resolve(&legacy);
+    assert_eq!(profile, Profile::Legacy);
+    assert_eq!(
+        reason,
+        format!(
+            "DataVersion 1343 in {}",
+            Path::new("world/level.dat").display()
+        )
+    );
+    assert_eq!(
+        FormatVersion::Auto.resolve(&modern),
+        (Profile::Modern, "DataVersion 3465 in level.dat".to_string())
+    );
+    assert_eq!(FormatVersion::Auto.resolve(&plain).0, Profile::Legacy);
+    assert_eq!(
+        FormatVersion::Auto.resolve(&none),
+        (Profile::Modern, "no level.dat found".to_string())
+    );
+    // Forced
+    assert_eq!(FormatVersion::Modern.resolve(&legacy).0, Profile::Modern);
+    assert_eq!(FormatVersion::Legacy.resolve(&modern).0, Profile::Legacy);
+    // A broken level.dat falls back to the modern rules
+    std::fs::write(modern.join("level.dat"), [10, 0, 0, 1]).unwrap();
+    assert_eq!(FormatVersion::Auto.resolve(&modern).0, Profile::Modern);
+
+    assert_eq!(
+        Profile::of_data_version(Some(FLATTENING - 1)),
+        Profile::Legacy
+    );
+    assert_eq!(Profile::of_data_version(Some(FLATTENING)), Profile::Modern);
+    assert!(is_poi(Path::new("world/poi/r.0.0.mca")));
+    assert!(is_poi(Path::new("world/DIM-1/poi/r.0.0.mca")));
+    assert!(!is_poi(Path::new("world/region/r.0.0.mca")));
+    assert!(!is_poi(Path::new("world/poi/notes.txt")));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,312 @@
+use std::{
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use crate::{
+    owner::Owners,
+    retry::RetryPolicy,
+    stage::{self, Op},
+    transaction::Staging,
+};
+
+#[derive(Debug, Default)]
+struct GuardInner {
+    dry_run: bool,
+    /// Modifications are staged instead, until committed
+    staging: Option<Staging>,
+    writes: AtomicUsize,
+    discarded: AtomicUsize,
+}
+
+/// All modifications to the world go through the guard
+/// In dry-run mode, modifications are discarded, so nothing is modified by construction
+/// In transactional mode, modifications are staged outside the world until committed
+/// On a CPU worker of the pipeline, modifications are deferred to the IO stage
+#[derive(Debug, Clone, Default)]
+pub struct WriteGuard {
+    inner: Arc<GuardInner>,
+    /// Modifications are retried on transient errors
+    retry: RetryPolicy,
+    /// The owners of the files are captured before they are modified, with `--allow-root`
+    owners: Option<Arc<Owners>>,
+}
+
+impl WriteGuard {
+    /// A guard that discards all modifications
+    pub fn dry_run() -> Self {
+        Self {
+            inner: Arc::new(GuardInner {
+                dry_run: true,
+                ..Default::default()
+            }),
+            retry: RetryPolicy::default(),
+            owners: None,
+        }
+    }
+
+    /// A guard that stages all modifications next to the world
+    pub fn transactional(world: &Path) -> anyhow::Result<Self> {
+        Ok(Self {
+            inner: Arc::new(GuardInner {
+                staging: Some(Staging::new(world)?),
+                ..Default::default()
+            }),
+            retry: RetryPolicy::default(),
+            owners: None,
+        })
+    }
+
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn with_owners(mut self, owners: Arc<Owners>) -> Self {
+        self.owners = Some(owners);
+        self
+    }
+
+    /// The retry policy, reads of the world should go through it as well
+    pub fn retry(&self) -> &RetryPolicy {
+        &self.retry
+    }
+
+    pub fn is_dry_run(&self) -> bool {
+        self.inner.dry_run
+    }
+
+    /// The staged modifications in transactional mode
+    pub fn staging(&self) -> Option<&Staging> {
+        self.inner.staging.as_ref()
+    }
+
+    /// Number of modifications performed
+    pub fn writes(&self) -> usize {
+        self.inner.writes.load(Ordering::Relaxed)
+    }
+
+    /// Number of modifications discarded in dry-run mode
+    pub fn discarded(&self) -> usize {
+        self.inner.discarded.load(Ordering::Relaxed)
+    }
+
+    /// Check if the modification should be performed, and count it
+    fn permit(&self) -> bool {
+        if self.inner.dry_run {
+            self.inner.discarded.fetch_add(1, Ordering::Relaxed);
+            false
+        } else {
+            self.inner.writes.fetch_add(1, Ordering::Relaxed);
+            true
+        }
+    }
+
+    fn capture(&self, path: &Path) {
+        if let Some(owners) = &self.owners {
+            owners.capture(path);
+        }
+    }
+
+    fn capture_rename(&self, from: &Path, to: &Path) {
+        if let Some(owners) = &self.owners {
+            owners.capture_rename(from, to);
+        }
+    }
+
+    pub fn write(&self, path: &Path, contents: impl AsRef<[u8]>) -> std::io::Result<()> {
+        let contents = contents.as_ref();
+        if stage::defer(|| Op::Write(path.to_path_buf(), contents.to_vec())) {
+            return Ok(());
+        }
+        if self.permit() {
+            self.capture(path);
+            match self.staging() {
+                Some(staging) => self.retry.run(path, || staging.write(path, contents))?,
+                None => self.retry.run(path, || std::fs::write(path, contents))?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Create a file to be written incrementally, `None` in dry-run mode
+    pub fn create(&self, path: &Path) -> std::io::Result<Option<std::fs::File>> {
+        if self.permit() {
+            self.capture(path);
+            return Ok(Some(match self.staging() {
+                Some(staging) => self.retry.run(path, || staging.create(path))?,
+                None => self.retry.run(path, || std::fs::File::create(path))?,
+            }));
+        }
+        Ok(None)
+    }
+
+    /// Remove the file created with `create` but never finished, not counted as a modification
+    pub fn discard(&self, path: &Path) -> std::io::Result<()> {
+        match self.staging() {
+            Some(staging) => staging.discard(path),
+            None => std::fs::remove_file(path),
+        }
+    }
+
+    pub fn rename(&self, from: &Path, to: &Path) -> std::io::Result<()> {
+        if stage::defer(|| Op::Rename(from.to_path_buf(), to.to_path_buf())) {
+            return Ok(());
+        }
+        if self.permit() {
+            self.capture_rename(from, to);
+            match self.staging() {
+                Some(staging) => staging.rename(from, to)?,
+                None => self.retry.run(from, || std::fs::rename(from, to))?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Move a file written next to the target with `create` over it, see `replace_file`
+    pub fn replace(&self, temp: &Path, target: &Path) -> std::io::Result<()> {
+        if stage::defer(|| Op::Replace(temp.to_path_buf(), target.to_path_buf())) {
+            return Ok(());
+        }
+        if self.permit() {
+            self.capture_rename(temp, target);
+            match self.staging() {
+                Some(staging) => staging.replace(temp, target)?,
+                None => self.retry.run(temp, || replace_file(temp, target))?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Copy the file in the world itself, `--clone` refuses the staged and deferred modes,
+    /// as the copy is read back to be remapped
+    pub fn copy(&self, from: &Path, to: &Path) -> std::io::Result<()> {
+        debug_assert!(self.staging().is_none() && !self.is_dry_run());
+        if self.permit() {
+            self.capture_rename(from, to);
+            self.retry
+                .run(from, || std::fs::copy(from, to).map(|_| ()))?;
+        }
+        Ok(())
+    }
+
+    pub fn remove_file(&self, path: &Path) -> std::io::Result<()> {
+        if stage::defer(|| Op::Remove(path.to_path_buf())) {
+            return Ok(());
+        }
+        if self.permit() {
+            match self.staging() {
+                Some(staging) => staging.remove(path)?,
+                None => self.retry.run(path, || std::fs::remove_file(path))?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Move the file over the target, with the permissions of the target
+/// A target with other hard links is overwritten in place instead, like `write` does, so every
+/// link sees the new content
+pub fn replace_file(temp: &Path, target: &Path) -> std::io::Result<()> {
+    let meta = match std::fs::metadata(target) {
+        Ok(meta) => meta,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            return std::fs::rename(temp, target)
+        }
+        Err(err) => return Err(err),
+    };
+    std::fs::set_permissions(temp, meta.permissions())?;
+    if hard_links(&meta) > 1 {
+        std::fs::copy(temp, target)?;
+        return std::fs::remove_file(temp);
+    }
+    std::fs::rename(temp, target)
+}
+
+/// The number of names of the file, 1 where it is unknown
+pub fn hard_links(meta: &std::fs::Metadata) -> u64 {
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::MetadataExt::nlink(meta)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = meta;
+        1
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    let dir = std::env::temp_dir().join("test_write_guard");
+    std::fs::create_dir_all(&dir).unwrap();
+    let a = dir.join("a.txt");
+    let b = dir.join("b.txt");
+
+    let guard = WriteGuard::dry_run();
+    guard.write(&a, b"hello").unwrap();
+    assert!(!a.exists());
+    std::fs::write(&a, b"hello").unwrap();
+    guard.write(&a, b"world").unwrap();
+    guard.rename(&a, &b).unwrap();
+    guard.remove_file(&a).unwrap();
+    assert!(guard.create(&b).unwrap().is_none());
+    assert_eq!(std::fs::read(&a).unwrap(), b"hello");
+    assert!(!b.exists());
+    assert_eq!(guard.writes(), 0);
+    assert_eq!(guard.discarded(), 5);
+
+    let guard = WriteGuard::default();
+    guard.write(&a, b"world").unwrap();
+    guard.rename(&a, &b).unwrap();
+    assert_eq!(std::fs::read(&b).unwrap(), b"world");
+    guard.remove_file(&b).unwrap();
+    assert!(!b.exists());
+    assert!(guard.create(&b).unwrap().is_some());
+    assert!(b.exists());
+    assert_eq!(guard.writes(), 4);
+    assert_eq!(guard.discarded(), 0);
+
+    // Replaced with the mode of the target, a hard-linked target is overwritten in place
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let (temp, link) = (dir.join("b.txt.tmp"), dir.join("link.txt"));
+        std::fs::write(&b, b"old").unwrap();
+        std::fs::set_permissions(&b, std::fs::Permissions::from_mode(0o640)).unwrap();
+        std::fs::write(&temp, b"new").unwrap();
+        guard.replace(&temp, &b).unwrap();
+        assert!(!temp.exists());
+        assert_eq!(std::fs::read(&b).unwrap(), b"new");
+        let mode = std::fs::metadata(&b).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o640);
+
+        std::fs::hard_link(&b, &link).unwrap();
+        std::fs::write(&temp, b"linked").unwrap();
+        guard.replace(&temp, &b).unwrap();
+        assert!(!temp.exists());
+        assert_eq!(std::fs::read(&link).unwrap(), b"linked");
+        assert_eq!(hard_links(&std::fs::metadata(&b).unwrap()), 2);
+        std::fs::remove_file(&link).unwrap();
+        std::fs::remove_file(&b).unwrap();
+    }
+
+    let guard = WriteGuard::transactional(&dir).unwrap();
+    guard.write(&a, b"staged").unwrap();
+    guard.rename(&a, &b).unwrap();
+    assert!(!a.exists());
+    assert_eq!(guard.writes(), 2);
+    assert_eq!(guard.staging().unwrap().commit().unwrap(), 2);
+    assert_eq!(std::fs::read(&b).unwrap(), b"staged");
+    drop(guard);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
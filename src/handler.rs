@@ -0,0 +1,641 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    io::Read,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::Context;
+use uuid::Uuid;
+
+use crate::{
+    budget::MemoryBudget,
+    capture::Capture,
+    conflict::Prompter,
+    decimal::DecimalKeys,
+    dimension::DimensionRules,
+    engine::RemapEngine,
+    filter::{ChunkFilter, TimeFilter},
+    format::Profile,
+    guard::WriteGuard,
+    nbt::NbtRules,
+    preview::Preview,
+    progress::Progress,
+    source::BySource,
+};
+
+/// Statistics of a single remapped file
+#[derive(Debug, Default, Clone)]
+pub struct FileStats {
+    /// Number of chunks visited, only meaningful for region files
+    pub chunks: usize,
+    /// Number of chunks that failed to remap and were dropped
+    pub chunk_errors: usize,
+    /// Number of chunks whose NBT root is not a compound, they are copied as is
+    pub non_compound_roots: usize,
+    /// Number of chunks discarded because another header entry points at the same sectors
+    pub duplicate_chunks: usize,
+    /// Number of chunks larger than the limit when uncompressed, they are copied as is
+    pub oversized_chunks: usize,
+    /// The new path of the file relative to the world, if it is renamed
+    pub renamed: Option<PathBuf>,
+    /// The path of the copy relative to the world, with `--clone`, the file itself is kept
+    pub cloned: Option<PathBuf>,
+    /// The length of a region file and its external chunk files before and after remapping, in bytes
+    pub region_size: Option<(u64, u64)>,
+    /// The region file grows by more than `AnomalyCheck::factor`
+    pub anomaly: bool,
+    /// Chunks selected by the chunk filter and found in the file, in world chunk coordinates
+    pub selected_chunks: Vec<(i32, i32)>,
+    /// The replacements in the file by source, counted by the callback of the task
+    pub replacements: BySource,
+}
+
+impl FileStats {
+    pub fn merge(&mut self, other: &FileStats) {
+        self.chunks += other.chunks;
+        self.chunk_errors += other.chunk_errors;
+        self.non_compound_roots += other.non_compound_roots;
+        self.duplicate_chunks += other.duplicate_chunks;
+        self.oversized_chunks += other.oversized_chunks;
+        self.selected_chunks
+            .extend_from_slice(&other.selected_chunks);
+        self.replacements.merge(&other.replacements);
+    }
+}
+
+/// Flag the region files growing suspiciously when rewritten, with `--anomaly-factor`
+/// The external chunk files count with the region, so moving a chunk in or out of them is no growth,
+/// a large growth is more often a sign of a broken header or a bug
+#[derive(Debug)]
+pub struct AnomalyCheck {
+    /// The output may be this many times larger than the input
+    pub factor: f64,
+    /// Keep a copy of the original of the flagged regions here, with `--keep-anomalies`
+    pub keep: Option<PathBuf>,
+    /// The root of the world, the copies are placed by their path relative to it
+    pub world: PathBuf,
+}
+
+impl AnomalyCheck {
+    /// The directory is created if missing, it must not be in the world
+    pub fn new(factor: f64, keep: Option<&Path>, world: &Path) -> anyhow::Result<Self> {
+        if factor.is_nan() || factor <= 0.0 {
+            anyhow::bail!("The anomaly factor must be positive, got {}", factor);
+        }
+        let keep = match keep {
+            Some(dir) => {
+                std::fs::create_dir_all(dir)
+                    .with_context(|| format!("Failed to create {}", dir.display()))?;
+                let canonical = dir.canonicalize()?;
+                if canonical.starts_with(world.canonicalize()?) {
+                    anyhow::bail!(
+                        "The directory {} keeping the anomalies must be outside the world",
+                        dir.display()
+                    );
+                }
+                Some(canonical)
+            }
+            None => None,
+        };
+        Ok(Self {
+            factor,
+            keep,
+            world: world.to_path_buf(),
+        })
+    }
+
+    pub fn is_anomaly(&self, input: u64, output: u64) -> bool {
+        output as f64 > input as f64 * self.factor
+    }
+
+    /// Copy the original of a flagged region, before it is replaced
+    pub fn keep(&self, path: &Path) -> anyhow::Result<Option<PathBuf>> {
+        let Some(dir) = &self.keep else {
+            return Ok(None);
+        };
+        let copy = dir.join(crate::task::relative_path(&self.world, path));
+        if let Some(parent) = copy.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::copy(path, &copy)?;
+        Ok(Some(copy))
+    }
+}
+
+/// Options of a run, shared by the scanning and all the handlers
+#[derive(Debug, Default)]
+pub struct RemapOptions {
+    /// Only remap these chunks in region files, other chunks are copied as is
+    pub chunk_filter: Option<ChunkFilter>,
+    /// All modifications go through the guard
+    pub guard: WriteGuard,
+    /// Classify the files into dimensions for the statistics
+    pub dimension_rules: DimensionRules,
+    /// Bound the uncompressed data in flight across all the workers
+    pub budget: MemoryBudget,
+    /// Keys of uuids stored as signed decimal pairs in YAML, JSON and properties files
+    pub decimal_uuid_keys: Option<DecimalKeys>,
+    /// Regexes capturing uuids in other forms in text files, see `--text-pattern`
+    pub text_patterns: Vec<regex::bytes::Regex>,
+    /// Show the changed fields of NBT files and the changed lines of text files, only used with a dry-run guard
+    pub preview: Option<Preview>,
+    /// Canonical paths of the files never remapped, i.e. the inputs and outputs of the run itself
+    pub excluded_files: HashSet<PathBuf>,
+    /// Stream all the region files, not only the large ones
+    pub low_memory: bool,
+    /// Rules of the uuid-like NBT fields that are not uuids of players
+    pub nbt_rules: NbtRules,
+    /// The rule set of the world format, the modern one skips the poi files
+    pub profile: Profile,
+    /// Copy the inputs failing to be remapped, for bug reports
+    pub capture: Option<Capture>,
+    /// Find the handlers by the path only, without reading the head of the files
+    pub no_sniff: bool,
+    /// Leave the renames in `FileStats::renamed` to `rename_files`, after all the contents
+    pub defer_renames: bool,
+    /// Copy the files named after a mapped uuid to the new name and remap only the copies,
+    /// with `--clone`, nothing else is modified
+    pub clone_files: bool,
+    /// Copy the chunks larger than this uncompressed as is, `MAX_CHUNK_SIZE` if not given
+    pub max_chunk_size: Option<usize>,
+    /// Ask the user to resolve the conflicts, with `--interactive-conflicts`
+    pub conflicts: Option<Prompter>,
+    /// Old uuids may share a new uuid, with `--allow-duplicate-targets`, a rename onto an existing
+    /// file keeps the old name rather than replacing it
+    pub duplicate_targets: bool,
+    /// Only remap the files modified in the range, with `--modified-after` and `--modified-before`
+    pub modified: TimeFilter,
+    /// Flag the regions growing too much when rewritten, not checked if `None`
+    pub anomalies: Option<AnomalyCheck>,
+    /// Skip the texts and chunks without any form of an old uuid, everything is visited if `None`
+    /// Must be `None` with `--derive` or `--text-pattern`, which find uuids the mapping does not list
+    pub prefilter: Option<Arc<RemapEngine>>,
+}
+
+/// The canonical path of the file, which may not exist yet
+fn canonical(path: &Path) -> Option<PathBuf> {
+    path.canonicalize().ok().or_else(|| {
+        let parent = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        Some(parent.canonicalize().ok()?.join(path.file_name()?))
+    })
+}
+
+impl RemapOptions {
+    /// Never remap the file, e.g. the mapping file placed in the world
+    pub fn exclude(&mut self, path: &Path) {
+        match canonical(path) {
+            Some(path) => {
+                self.excluded_files.insert(path);
+            }
+            None => log::warn!("Unable to resolve {}", path.display()),
+        }
+    }
+
+    pub fn is_excluded(&self, path: &Path) -> bool {
+        !self.excluded_files.is_empty()
+            && canonical(path).is_some_and(|path| self.excluded_files.contains(&path))
+    }
+}
+
+/// Information about the file being remapped
+pub struct RemapContext<'a> {
+    /// The root of the world
+    pub world: &'a Path,
+    /// The path of the file, relative to the world
+    pub relative: &'a Path,
+    pub options: &'a RemapOptions,
+    /// Report the progress within the file
+    pub progress: &'a Progress,
+}
+
+/// A handler for a kind of file
+///
+/// Both the scanning and the remapping consult the same handler, so they can never disagree
+pub trait FileHandler: Send + Sync {
+    /// The name of the handler, used in logs
+    fn name(&self) -> &'static str;
+
+    /// Check if the handler is able to remap the file
+    /// `sniff` contains the first few bytes of the file, it may be shorter than `SNIFF_LEN`
+    fn matches(&self, path: &Path, sniff: &[u8]) -> bool;
+
+    /// Whether `matches` looks at the sniffed bytes, otherwise it is given none
+    fn sniffs(&self) -> bool {
+        true
+    }
+
+    /// Remap the content of the file in place
+    fn remap(
+        &self,
+        path: &Path,
+        ctx: &RemapContext,
+        cb: &dyn Fn(Uuid) -> Option<Uuid>,
+    ) -> anyhow::Result<FileStats>;
+}
+
+/// Number of bytes read from the head of a file for sniffing
+pub const SNIFF_LEN: usize = 16;
+
+/// Read the first `SNIFF_LEN` bytes of the file, or less if the file is shorter
+pub fn sniff(path: &Path) -> std::io::Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity(SNIFF_LEN);
+    std::fs::File::open(path)?
+        .take(SNIFF_LEN as u64)
+        .read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+/// A handler forced for an extension with `--handler`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandlerKind {
+    Mca,
+    NbtGzip,
+    NbtRaw,
+    Text,
+    /// Never remap the files
+    Skip,
+}
+
+impl HandlerKind {
+    const ALL: [HandlerKind; 5] = [
+        HandlerKind::Mca,
+        HandlerKind::NbtGzip,
+        HandlerKind::NbtRaw,
+        HandlerKind::Text,
+        HandlerKind::Skip,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            HandlerKind::Mca => "mca",
+            HandlerKind::NbtGzip => "nbt-gzip",
+            HandlerKind::NbtRaw => "nbt-raw",
+            HandlerKind::Text => "text",
+            HandlerKind::Skip => "skip",
+        }
+    }
+
+    fn from_name(name: &str) -> Result<Self, String> {
+        Self::ALL
+            .into_iter()
+            .find(|k| k.name() == name.trim())
+            .ok_or_else(|| {
+                let kinds = Self::ALL.map(|k| k.name()).join(", ");
+                format!("Unknown handler {}, expected one of {}", name, kinds)
+            })
+    }
+
+    fn handler(self) -> Option<Box<dyn FileHandler>> {
+        match self {
+            HandlerKind::Mca => Some(Box::new(crate::remap::McaHandler)),
+            HandlerKind::NbtGzip => Some(Box::new(crate::remap::ForcedNbtHandler { gzip: true })),
+            HandlerKind::NbtRaw => Some(Box::new(crate::remap::ForcedNbtHandler { gzip: false })),
+            HandlerKind::Text => Some(Box::new(crate::remap::TextHandler)),
+            HandlerKind::Skip => None,
+        }
+    }
+}
+
+/// Parse an override like `json=nbt-gzip`
+pub fn parse_override(s: &str) -> Result<(String, HandlerKind), String> {
+    let Some((ext, kind)) = s.split_once('=') else {
+        return Err(format!("Expected <ext>=<kind>, got {}", s));
+    };
+    let ext = ext.trim().trim_start_matches('.');
+    if ext.is_empty() {
+        return Err(format!("Empty extension in {}", s));
+    }
+    Ok((ext.to_string(), HandlerKind::from_name(kind)?))
+}
+
+/// Collect the overrides, an extension overridden with different kinds is an error
+pub fn collect_overrides(
+    overrides: &[(String, HandlerKind)],
+) -> Result<BTreeMap<String, HandlerKind>, String> {
+    let mut map = BTreeMap::new();
+    for (ext, kind) in overrides {
+        match map.insert(ext.clone(), *kind) {
+            Some(old) if old != *kind => {
+                return Err(format!(
+                    "Conflicting handlers for .{}: {} and {}",
+                    ext,
+                    old.name(),
+                    kind.name()
+                ))
+            }
+            _ => {}
+        }
+    }
+    Ok(map)
+}
+
+/// The file declaring the handlers of the files in its directory and below,
+/// with lines like `"*.pdata" = "nbt-gzip"`
+pub const HINT_FILE: &str = ".remapper-handlers.toml";
+
+/// Parse the rules of a hint file, a pattern of file names and a handler per key, at the top level
+/// or in a `[handlers]` table, `*` matches any characters and `?` a single one
+pub fn parse_hints(content: &str) -> anyhow::Result<Vec<(String, HandlerKind)>> {
+    let rule = |pattern: String, kind: &toml::Value| -> anyhow::Result<(String, HandlerKind)> {
+        anyhow::ensure!(!pattern.is_empty(), "Empty pattern");
+        let name = kind
+            .as_str()
+            .with_context(|| format!("Expected a handler for {}, got {}", pattern, kind))?;
+        let kind = HandlerKind::from_name(name)
+            .map_err(|err| anyhow::anyhow!("{} for {}", err, pattern))?;
+        Ok((pattern, kind))
+    };
+    let mut rules = Vec::new();
+    for (key, value) in content.parse::<toml::Table>()? {
+        match value {
+            toml::Value::Table(table) if key == "handlers" => {
+                for (pattern, kind) in table {
+                    rules.push(rule(pattern, &kind)?);
+                }
+            }
+            value => rules.push(rule(key, &value)?),
+        }
+    }
+    Ok(rules)
+}
+
+/// Match the file name against the pattern of a hint file
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let (pattern, name) = (pattern.as_bytes(), name.as_bytes());
+    let (mut p, mut n) = (0, 0);
+    // The position after the last `*` and the position in the name it was tried at
+    let mut star = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                p += 1;
+                star = Some((p, n));
+            }
+            Some(&c) if c == b'?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((after, tried)) => {
+                    p = after;
+                    n = tried + 1;
+                    star = Some((after, n));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+/// Why a file uses its handler, shown in the verbose scan output
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Origin {
+    /// `--handler`
+    Override,
+    /// The hint file of the directory, relative to the world
+    Hint(PathBuf),
+    /// The first handler matching the file
+    Detected,
+}
+
+impl std::fmt::Display for Origin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Origin::Override => write!(f, "--handler"),
+            Origin::Hint(dir) => write!(f, "{}", dir.join(HINT_FILE).display()),
+            Origin::Detected => write!(f, "detected"),
+        }
+    }
+}
+
+/// An ordered list of handlers, the first matching handler wins
+/// Overridden extensions always use the handler of the override, without sniffing,
+/// then the rules of the nearest hint file matching the file apply
+pub struct Registry {
+    handlers: Vec<Box<dyn FileHandler>>,
+    /// `None` for skipped extensions
+    overrides: HashMap<String, Option<Box<dyn FileHandler>>>,
+    /// The rules of the hint files in their order, by their directory relative to `hint_root`,
+    /// `None` for skipped files
+    hints: HashMap<PathBuf, Vec<(String, Option<Box<dyn FileHandler>>)>>,
+    /// The world the hint files are found in
+    hint_root: PathBuf,
+}
+
+impl Registry {
+    /// A registry without any handler
+    pub fn empty() -> Self {
+        Self {
+            handlers: vec![],
+            overrides: HashMap::new(),
+            hints: HashMap::new(),
+            hint_root: PathBuf::new(),
+        }
+    }
+
+    /// A registry with all the built-in handlers
+    pub fn builtin() -> Self {
+        let mut registry = Self::empty();
+        registry.register(Box::new(crate::remap::McaHandler));
+        registry.register(Box::new(crate::remap::MccHandler));
+        registry.register(Box::new(crate::remap::NbtHandler));
+        registry.register(Box::new(crate::remap::TextHandler));
+        registry
+    }
+
+    /// Add a handler with the lowest priority
+    pub fn register(&mut self, handler: Box<dyn FileHandler>) {
+        self.handlers.push(handler);
+    }
+
+    /// Use the handler for all the files with the extension
+    pub fn set_override(&mut self, ext: &str, kind: HandlerKind) {
+        self.overrides.insert(ext.to_string(), kind.handler());
+    }
+
+    /// Apply the rules of the hint file of the directory, relative to the world, to its subtree
+    /// The rules of a nested directory win over the ones of its parents
+    pub fn add_hints(&mut self, world: &Path, dir: &Path, rules: Vec<(String, HandlerKind)>) {
+        self.hint_root = world.to_path_buf();
+        let rules = rules
+            .into_iter()
+            .map(|(pattern, kind)| (pattern, kind.handler()))
+            .collect();
+        self.hints.insert(dir.to_path_buf(), rules);
+    }
+
+    /// The nearest hint rule matching the file, with the directory of its hint file
+    fn hint(&self, path: &Path) -> Option<(&Path, Option<&dyn FileHandler>)> {
+        if self.hints.is_empty() {
+            return None;
+        }
+        let relative = path.strip_prefix(&self.hint_root).unwrap_or(path);
+        let name = relative.file_name()?.to_str()?;
+        relative.ancestors().skip(1).find_map(|dir| {
+            let (dir, rules) = self.hints.get_key_value(dir)?;
+            rules
+                .iter()
+                .find(|(pattern, _)| glob_match(pattern, name))
+                .map(|(_, handler)| (dir.as_path(), handler.as_deref()))
+        })
+    }
+
+    /// Find the handler for the file, and why it is used
+    /// The path is either relative to the world or under it
+    pub fn resolve(&self, path: &Path, sniff: &[u8]) -> (Option<&dyn FileHandler>, Origin) {
+        if let Some(handler) = path
+            .extension()
+            .and_then(|s| s.to_str())
+            .and_then(|ext| self.overrides.get(ext))
+        {
+            return (handler.as_deref(), Origin::Override);
+        }
+        if let Some((dir, handler)) = self.hint(path) {
+            return (handler, Origin::Hint(dir.to_path_buf()));
+        }
+        let handler = self
+            .handlers
+            .iter()
+            .find(|h| h.matches(path, sniff))
+            .map(|h| h.as_ref());
+        (handler, Origin::Detected)
+    }
+
+    /// Whether the head of the file is needed to find its handler
+    /// The extensions are checked first, so a file decided by them is not read
+    pub fn needs_sniff(&self, path: &Path) -> bool {
+        let ext = path.extension().and_then(|s| s.to_str());
+        if ext.is_some_and(|ext| self.overrides.contains_key(ext)) || self.hint(path).is_some() {
+            return false;
+        }
+        for handler in &self.handlers {
+            if handler.sniffs() {
+                return true;
+            }
+            if handler.matches(path, b"") {
+                return false;
+            }
+        }
+        false
+    }
+
+    /// Find the handler for the file
+    pub fn find(&self, path: &Path, sniff: &[u8]) -> Option<&dyn FileHandler> {
+        self.resolve(path, sniff).0
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    struct Dummy;
+    impl FileHandler for Dummy {
+        fn name(&self) -> &'static str {
+            "dummy"
+        }
+
+        fn matches(&self, _path: &Path, sniff: &[u8]) -> bool {
+            sniff.starts_with(b"DUMMY")
+        }
+
+        fn remap(
+            &self,
+            _path: &Path,
+            _ctx: &RemapContext,
+            _cb: &dyn Fn(Uuid) -> Option<Uuid>,
+        ) -> anyhow::Result<FileStats> {
+            Ok(FileStats::default())
+        }
+    }
+
+    let mut registry = Registry::builtin();
+    let name = |registry: &Registry, path: &str, sniff: &[u8]| {
+        registry.find(Path::new(path), sniff).map(|h| h.name())
+    };
+    assert_eq!(name(&registry, "r.0.0.mca", b""), Some("mca"));
+    assert_eq!(name(&registry, "c.5.10.mcc", b""), Some("mcc"));
+    assert_eq!(name(&registry, "level.dat", b""), Some("nbt"));
+    assert_eq!(name(&registry, "structure.nbt", b""), Some("nbt"));
+    assert_eq!(name(&registry, "config.yml", b""), Some("text"));
+    assert_eq!(name(&registry, "data.bin", b"DUMMY"), None);
+    // No built-in handler reads the file
+    assert!(!registry.needs_sniff(Path::new("data.bin")));
+    registry.register(Box::new(Dummy));
+    assert_eq!(name(&registry, "data.bin", b"DUMMY"), Some("dummy"));
+    assert!(registry.needs_sniff(Path::new("data.bin")));
+    // Built-in handlers have a higher priority, the file is not read for them
+    assert_eq!(name(&registry, "config.yml", b"DUMMY"), Some("text"));
+    assert!(!registry.needs_sniff(Path::new("config.yml")));
+
+    // Overrides win over everything
+    registry.set_override("yml", HandlerKind::NbtGzip);
+    registry.set_override("dat", HandlerKind::Skip);
+    assert_eq!(name(&registry, "config.yml", b"DUMMY"), Some("nbt-gzip"));
+    assert_eq!(name(&registry, "level.dat", b""), None);
+    assert_eq!(name(&registry, "structure.nbt", b""), Some("nbt"));
+
+    assert_eq!(
+        parse_override(".json=nbt-gzip"),
+        Ok(("json".to_string(), HandlerKind::NbtGzip))
+    );
+    assert!(parse_override("json").is_err());
+    assert!(parse_override("=text").is_err());
+    assert!(parse_override("json=yaml").is_err());
+    let overrides = collect_overrides(&[
+        ("json".to_string(), HandlerKind::NbtGzip),
+        ("dat".to_string(), HandlerKind::Text),
+        ("json".to_string(), HandlerKind::NbtGzip),
+    ])
+    .unwrap();
+    assert_eq!(overrides.len(), 2);
+    assert!(collect_overrides(&[
+        ("json".to_string(), HandlerKind::NbtGzip),
+        ("json".to_string(), HandlerKind::Text),
+    ])
+    .is_err());
+
+    assert_eq!(
+        parse_hints("# Modded\n\"*.pdata\" = \"nbt-gzip\"\n'cache-?.db' = 'skip' # Binary\n")
+            .unwrap(),
+        vec![
+            ("*.pdata".to_string(), HandlerKind::NbtGzip),
+            ("cache-?.db".to_string(), HandlerKind::Skip)
+        ]
+    );
+    // Any TOML, the rules may be in a table
+    assert_eq!(
+        parse_hints("[handlers]\n\"*.pdata\" = \"nbt-gzip\"\n\"*.db\" = \"skip\"\n").unwrap(),
+        vec![
+            ("*.pdata".to_string(), HandlerKind::NbtGzip),
+            ("*.db".to_string(), HandlerKind::Skip)
+        ]
+    );
+    assert!(parse_hints("[handlers]\n").unwrap().is_empty());
+    assert!(parse_hints("\"*.pdata\" = \"yaml\"\n").is_err());
+    assert!(parse_hints("\"*.pdata\" = nbt-gzip\n").is_err());
+    assert!(parse_hints("\"\" = \"skip\"\n").is_err());
+    assert!(parse_hints("[other]\n\"*.pdata\" = \"skip\"\n").is_err());
+    assert!(glob_match("*.pdata", "player.pdata"));
+    assert!(glob_match("*.pdata", ".pdata"));
+    assert!(!glob_match("*.pdata", "player.pdata.bak"));
+    assert!(glob_match("cache-?.db", "cache-1.db"));
+    assert!(!glob_match("cache-?.db", "cache-12.db"));
+    assert!(glob_match("a*b*c", "aXbYbZc"));
+    assert!(glob_match("*", "anything"));
+
+    let path = std::env::temp_dir().join("test_sniff.bin");
+    std::fs::write(&path, [7u8; 64]).unwrap();
+    assert_eq!(sniff(&path).unwrap(), vec![7u8; SNIFF_LEN]);
+    std::fs::write(&path, [7u8; 3]).unwrap();
+    assert_eq!(sniff(&path).unwrap(), vec![7u8; 3]);
+    std::fs::remove_file(&path).unwrap();
+}
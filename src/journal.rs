@@ -0,0 +1,115 @@
+//! The journals of the files a run is done with, one line of JSON per file, next to the world like
+//! the staging directory, appended and synced as the files are done so the next run continues
+//! after them
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    ffi::OsString,
+    fs::{File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// A file done by a run, with the occurrences of the uuids found in it by `verify`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Entry {
+    pub file: PathBuf,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub found: BTreeMap<Uuid, usize>,
+}
+
+/// `.<world>.uuid-remapper-<name>.journal`
+pub fn path_for(world: &Path, name: &str) -> anyhow::Result<PathBuf> {
+    let world = std::path::absolute(world)?;
+    let world_name = world.file_name().context("Invalid world path")?;
+    let mut journal = OsString::from(".");
+    journal.push(world_name);
+    journal.push(format!(".uuid-remapper-{}.journal", name));
+    Ok(world.with_file_name(journal))
+}
+
+/// Read the files done by the previous run, a line cut off by a crash is done again
+pub fn read(path: &Path) -> anyhow::Result<Vec<Entry>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let mut entries = vec![];
+    for (i, line) in content.lines().enumerate() {
+        match serde_json::from_str::<Entry>(line) {
+            Ok(entry) => entries.push(entry),
+            Err(err) => log::warn!("Ignoring line {} of {}: {}", i + 1, path.display(), err),
+        }
+    }
+    Ok(entries)
+}
+
+/// Open the file to append lines to it, created if missing, emptied first unless `append`
+pub fn open(path: &Path, append: bool) -> anyhow::Result<Mutex<File>> {
+    let file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(append)
+        .truncate(!append)
+        .open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    Ok(Mutex::new(file))
+}
+
+/// Append the line and flush it to the disk, like the journal of a transactional run
+pub fn append_synced(file: &Mutex<File>, line: &[u8]) -> std::io::Result<()> {
+    let mut file = file.lock().unwrap();
+    file.write_all(line)?;
+    file.write_all(b"\n")?;
+    file.sync_data()
+}
+
+/// The journal of a pilot run with `--limit`, read by `--resume`
+pub fn pilot_for(world: &Path) -> anyhow::Result<PathBuf> {
+    path_for(world, "pilot")
+}
+
+/// The files remapped by the pilot runs, relative to the world, to continue after them
+pub fn pilot_files(world: &Path) -> anyhow::Result<HashSet<PathBuf>> {
+    let path = pilot_for(world)?;
+    if !path.exists() {
+        log::warn!("Nothing to resume, {} is missing", path.display());
+        return Ok(HashSet::new());
+    }
+    Ok(read(&path)?.into_iter().map(|entry| entry.file).collect())
+}
+
+/// Record the files remapped by a pilot run, after those of the pilot it resumes
+/// A run of every file leaves nothing to resume, its journal is removed
+pub fn record_pilot<'a>(
+    world: &Path,
+    pilot: bool,
+    resumed: bool,
+    files: impl IntoIterator<Item = &'a PathBuf>,
+) -> anyhow::Result<()> {
+    let path = pilot_for(world)?;
+    if !pilot {
+        if path.exists() {
+            std::fs::remove_file(&path)
+                .with_context(|| format!("Failed to remove {}", path.display()))?;
+        }
+        return Ok(());
+    }
+    let journal = open(&path, resumed)?;
+    for file in files {
+        let entry = Entry {
+            file: file.clone(),
+            found: BTreeMap::new(),
+        };
+        append_synced(&journal, &serde_json::to_vec(&entry)?)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+    }
+    log::info!(
+        "The files of the pilot run are recorded in {}, pass --resume to remap the others",
+        path.display()
+    );
+    Ok(())
+}
@@ -0,0 +1,246 @@
+use std::{
+    fs::{File, TryLockError},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use crate::{format::find_level_dat, task::Task};
+
+/// A region file modified more recently than this is likely being saved by a server
+pub const RECENT: Duration = Duration::from_secs(5 * 60);
+
+/// How long `logs/latest.log` is watched for appended lines
+pub const SAMPLE: Duration = Duration::from_secs(2);
+
+/// The phrase to type when a check trips
+pub const PHRASE: &str = "I UNDERSTAND";
+
+/// The result of a heuristic, printed whether it trips or not so false positives can be understood
+#[derive(Debug)]
+pub struct Check {
+    pub name: &'static str,
+    /// The world looks open in a running server
+    pub tripped: bool,
+    pub detail: String,
+}
+
+/// How long ago the file was modified, zero if it is in the future
+fn age(path: &Path) -> Option<Duration> {
+    let modified = std::fs::metadata(path).ok()?.modified().ok()?;
+    Some(
+        SystemTime::now()
+            .duration_since(modified)
+            .unwrap_or_default(),
+    )
+}
+
+fn recent_regions(world: &Path, tasks: &[Task]) -> Check {
+    let ages = tasks
+        .iter()
+        .filter(|task| task.path.extension().is_some_and(|ext| ext == "mca"))
+        .filter_map(|task| Some((age(&world.join(&task.path))?, &task.path)))
+        .filter(|(age, _)| *age < RECENT)
+        .collect::<Vec<_>>();
+    let detail = match ages.iter().min() {
+        Some((age, path)) => format!(
+            "{} region files modified in the last {} minutes, {} {}s ago",
+            ages.len(),
+            RECENT.as_secs() / 60,
+            path.display(),
+            age.as_secs()
+        ),
+        None => format!(
+            "no region file modified in the last {} minutes",
+            RECENT.as_secs() / 60
+        ),
+    };
+    Check {
+        name: "recent region files",
+        tripped: !ages.is_empty(),
+        detail,
+    }
+}
+
+/// The log of the server, next to the world or in the server given as the world
+fn latest_log(world: &Path) -> Option<PathBuf> {
+    [Some(world), world.parent()]
+        .into_iter()
+        .flatten()
+        .map(|dir| dir.join("logs").join("latest.log"))
+        .find(|path| path.is_file())
+}
+
+fn growing_log(world: &Path, sample: Duration) -> Check {
+    let name = "growing latest.log";
+    let Some(log) = latest_log(world) else {
+        return Check {
+            name,
+            tripped: false,
+            detail: "no logs/latest.log found".to_string(),
+        };
+    };
+    // A log not touched for a while is not being appended, no need to wait
+    if age(&log).is_some_and(|age| age >= RECENT) {
+        return Check {
+            name,
+            tripped: false,
+            detail: format!("{} not modified recently", log.display()),
+        };
+    }
+    let size = || std::fs::metadata(&log).map(|meta| meta.len()).ok();
+    let before = size();
+    std::thread::sleep(sample);
+    let after = size();
+    let tripped = before != after;
+    Check {
+        name,
+        tripped,
+        detail: match (before, after) {
+            (Some(before), Some(after)) if tripped => format!(
+                "{} changed from {} to {} bytes in {}s",
+                log.display(),
+                before,
+                after,
+                sample.as_secs_f32()
+            ),
+            _ if tripped => format!("{} disappeared while watched", log.display()),
+            _ => format!("{} unchanged in {}s", log.display(), sample.as_secs_f32()),
+        },
+    }
+}
+
+/// Try to lock the file, released when the file is closed
+fn locked(path: &Path) -> Result<bool, String> {
+    let file = File::open(path).map_err(|err| err.to_string())?;
+    match file.try_lock_shared() {
+        Ok(()) => Ok(false),
+        Err(TryLockError::WouldBlock) => Ok(true),
+        Err(TryLockError::Error(err)) => Err(err.to_string()),
+    }
+}
+
+fn file_lock(world: &Path) -> Check {
+    let name = "lock on level.dat";
+    let Some(level_dat) = find_level_dat(world) else {
+        return Check {
+            name,
+            tripped: false,
+            detail: "no level.dat found".to_string(),
+        };
+    };
+    let session = level_dat.with_file_name("session.lock");
+    let mut files = vec![level_dat];
+    if session.is_file() {
+        files.push(session);
+    }
+    let mut details = vec![];
+    let mut tripped = false;
+    for file in &files {
+        match locked(file) {
+            Ok(true) => {
+                tripped = true;
+                details.push(format!("{} is locked by another process", file.display()));
+            }
+            Ok(false) => details.push(format!("{} is not locked", file.display())),
+            // Some filesystems don't support locks, which says nothing about the server
+            Err(err) => details.push(format!("unable to lock {}: {}", file.display(), err)),
+        }
+    }
+    Check {
+        name,
+        tripped,
+        detail: details.join(", "),
+    }
+}
+
+/// Guess if the world is open in a running server, as `session.lock` is not always reliable
+pub fn run_checks(world: &Path, tasks: &[Task], sample: Duration) -> Vec<Check> {
+    vec![
+        recent_regions(world, tasks),
+        growing_log(world, sample),
+        file_lock(world),
+    ]
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    use std::io::Write;
+
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    let server = std::env::temp_dir().join("test_live");
+    let _ = std::fs::remove_dir_all(&server);
+    let world = server.join("world");
+    std::fs::create_dir_all(world.join("region")).unwrap();
+    std::fs::create_dir_all(server.join("logs")).unwrap();
+    std::fs::write(world.join("level.dat"), [10, 0, 0, 0]).unwrap();
+    std::fs::write(world.join("region").join("r.0.0.mca"), []).unwrap();
+    std::fs::write(
+        server.join("logs").join("latest.log"),
+        "[Server thread/INFO]: Done\n",
+    )
+    .unwrap();
+    let tasks = vec![Task {
+        path: PathBuf::from("region").join("r.0.0.mca"),
+        dimension: "minecraft:overworld".to_string(),
+    }];
+    let sample = Duration::from_millis(200);
+    let tripped = |checks: Vec<Check>| {
+        checks
+            .into_iter()
+            .filter(|check| check.tripped)
+            .map(|check| check.name)
+            .collect::<Vec<_>>()
+    };
+
+    // Just written, but nothing is appended or locked
+    assert_eq!(
+        tripped(run_checks(&world, &tasks, sample)),
+        vec!["recent region files"]
+    );
+    // Old files
+    let old = SystemTime::now() - RECENT * 2;
+    for path in [
+        world.join("region").join("r.0.0.mca"),
+        server.join("logs").join("latest.log"),
+    ] {
+        File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+    }
+    assert!(tripped(run_checks(&world, &tasks, sample)).is_empty());
+
+    // A server appending to the log and holding the lock
+    let log = server.join("logs").join("latest.log");
+    writeln!(
+        File::options().append(true).open(&log).unwrap(),
+        "[Server thread/INFO]: Saving"
+    )
+    .unwrap();
+    let writer = {
+        let log = log.clone();
+        std::thread::spawn(move || {
+            let mut file = File::options().append(true).open(log).unwrap();
+            for _ in 0..10 {
+                writeln!(file, "[Server thread/INFO]: Saving chunks").unwrap();
+                std::thread::sleep(Duration::from_millis(50));
+            }
+        })
+    };
+    let lock = File::open(world.join("level.dat")).unwrap();
+    lock.lock().unwrap();
+    let checks = run_checks(&world, &tasks, sample);
+    writer.join().unwrap();
+    assert_eq!(
+        tripped(checks),
+        vec!["growing latest.log", "lock on level.dat"]
+    );
+    drop(lock);
+    std::fs::remove_dir_all(&server).unwrap();
+}
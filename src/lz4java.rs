@@ -0,0 +1,220 @@
+//! The block stream of lz4-java's `LZ4BlockOutputStream`, which the server writes lz4 chunks
+//! with, rather than the standard LZ4 frame
+//!
+//! Each block is `LZ4Block`, a token with the method and the block size, the compressed and the
+//! original length and the xxhash32 of the original bytes, little-endian, then the bytes.
+//! An empty block ends the stream.
+
+use std::io::{Error, ErrorKind};
+
+pub const MAGIC: &[u8; 8] = b"LZ4Block";
+
+const HEADER_LEN: usize = MAGIC.len() + 1 + 4 + 4 + 4;
+
+const METHOD_RAW: u8 = 0x10;
+const METHOD_LZ4: u8 = 0x20;
+
+/// The block size of the server, 64 KiB, stored in the token as a power of two above 1 KiB
+const BLOCK_LEVEL: u8 = 6;
+const BLOCK_SIZE: usize = 1 << (BLOCK_LEVEL + 10);
+
+const CHECKSUM_SEED: u32 = 0x9747b28c;
+/// lz4-java writes only the low 28 bits of the checksum
+const CHECKSUM_MASK: u32 = 0x0FFF_FFFF;
+
+const PRIME1: u32 = 0x9E3779B1;
+const PRIME2: u32 = 0x85EBCA77;
+const PRIME3: u32 = 0xC2B2AE3D;
+const PRIME4: u32 = 0x27D4EB2F;
+const PRIME5: u32 = 0x165667B1;
+
+fn xxhash32(data: &[u8], seed: u32) -> u32 {
+    let lane = |bytes: &[u8]| u32::from_le_bytes(bytes.try_into().unwrap());
+    let round = |acc: u32, lane: u32| {
+        acc.wrapping_add(lane.wrapping_mul(PRIME2))
+            .rotate_left(13)
+            .wrapping_mul(PRIME1)
+    };
+    let mut stripes = data.chunks_exact(16);
+    let mut hash = if data.len() >= 16 {
+        let mut acc = [
+            seed.wrapping_add(PRIME1).wrapping_add(PRIME2),
+            seed.wrapping_add(PRIME2),
+            seed,
+            seed.wrapping_sub(PRIME1),
+        ];
+        for stripe in &mut stripes {
+            for (i, acc) in acc.iter_mut().enumerate() {
+                *acc = round(*acc, lane(&stripe[i * 4..i * 4 + 4]));
+            }
+        }
+        acc[0]
+            .rotate_left(1)
+            .wrapping_add(acc[1].rotate_left(7))
+            .wrapping_add(acc[2].rotate_left(12))
+            .wrapping_add(acc[3].rotate_left(18))
+    } else {
+        seed.wrapping_add(PRIME5)
+    };
+    hash = hash.wrapping_add(data.len() as u32);
+    let mut words = stripes.remainder().chunks_exact(4);
+    for word in &mut words {
+        hash = hash
+            .wrapping_add(lane(word).wrapping_mul(PRIME3))
+            .rotate_left(17)
+            .wrapping_mul(PRIME4);
+    }
+    for &byte in words.remainder() {
+        hash = hash
+            .wrapping_add((byte as u32).wrapping_mul(PRIME5))
+            .rotate_left(11)
+            .wrapping_mul(PRIME1);
+    }
+    hash ^= hash >> 15;
+    hash = hash.wrapping_mul(PRIME2);
+    hash ^= hash >> 13;
+    hash = hash.wrapping_mul(PRIME3);
+    hash ^= hash >> 16;
+    hash
+}
+
+fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+/// Whether the data starts like a block stream
+pub fn is_block_stream(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+/// Uncompress the blocks, stopping once more than `limit` bytes are uncompressed
+pub fn decode(data: &[u8], limit: usize) -> std::io::Result<Vec<u8>> {
+    let mut uncompressed = Vec::new();
+    let mut rest = data;
+    loop {
+        if rest.len() < HEADER_LEN || !rest.starts_with(MAGIC) {
+            return Err(invalid(format!(
+                "lz4-java block header expected at byte {}",
+                data.len() - rest.len()
+            )));
+        }
+        let token = rest[MAGIC.len()];
+        let field = |i: usize| {
+            let start = MAGIC.len() + 1 + i * 4;
+            u32::from_le_bytes(rest[start..start + 4].try_into().unwrap())
+        };
+        let (compressed_len, original_len, checksum) =
+            (field(0) as usize, field(1) as usize, field(2));
+        let max_len = 1usize << ((token & 0x0F) as usize + 10);
+        if compressed_len == 0 && original_len == 0 {
+            return Ok(uncompressed);
+        }
+        if original_len > max_len || rest.len() < HEADER_LEN + compressed_len {
+            return Err(invalid(format!(
+                "lz4-java block of {} bytes, {} uncompressed, at byte {} is truncated or invalid",
+                compressed_len,
+                original_len,
+                data.len() - rest.len()
+            )));
+        }
+        let body = &rest[HEADER_LEN..HEADER_LEN + compressed_len];
+        let block = match token & 0xF0 {
+            METHOD_RAW if compressed_len == original_len => body.to_vec(),
+            METHOD_LZ4 => lz4::block::decompress(body, Some(original_len as i32))?,
+            _ => {
+                return Err(invalid(format!(
+                    "Unknown lz4-java block token {:#04x}",
+                    token
+                )))
+            }
+        };
+        let hash = xxhash32(&block, CHECKSUM_SEED);
+        if block.len() != original_len || (checksum != hash && checksum != hash & CHECKSUM_MASK) {
+            return Err(invalid(format!(
+                "lz4-java block checksum mismatch at byte {}",
+                data.len() - rest.len()
+            )));
+        }
+        uncompressed.extend_from_slice(&block);
+        if uncompressed.len() > limit {
+            return Ok(uncompressed);
+        }
+        rest = &rest[HEADER_LEN + compressed_len..];
+    }
+}
+
+fn push_header(
+    out: &mut Vec<u8>,
+    method: u8,
+    compressed_len: usize,
+    original_len: usize,
+    checksum: u32,
+) {
+    out.extend_from_slice(MAGIC);
+    out.push(method | BLOCK_LEVEL);
+    out.extend_from_slice(&(compressed_len as u32).to_le_bytes());
+    out.extend_from_slice(&(original_len as u32).to_le_bytes());
+    out.extend_from_slice(&checksum.to_le_bytes());
+}
+
+/// Compress the data like `LZ4BlockOutputStream` with its default block size,
+/// a block is stored raw if it doesn't get smaller
+pub fn encode(data: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    for block in data.chunks(BLOCK_SIZE) {
+        let checksum = xxhash32(block, CHECKSUM_SEED) & CHECKSUM_MASK;
+        let compressed = lz4::block::compress(block, None, false)?;
+        if compressed.len() < block.len() {
+            push_header(
+                &mut out,
+                METHOD_LZ4,
+                compressed.len(),
+                block.len(),
+                checksum,
+            );
+            out.extend_from_slice(&compressed);
+        } else {
+            push_header(&mut out, METHOD_RAW, block.len(), block.len(), checksum);
+            out.extend_from_slice(block);
+        }
+    }
+    push_header(&mut out, METHOD_RAW, 0, 0, 0);
+    Ok(out)
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    // The reference values of xxhash32
+    assert_eq!(xxhash32(b"", 0), 0x02CC5D05);
+    assert_eq!(xxhash32(b"lz4-java", CHECKSUM_SEED), 0x353F15B3);
+    assert_eq!(xxhash32(b"lz4-java block stream", 0), 0xFDFCDC13);
+
+    let text = b"lz4-java ".repeat(10000);
+    let encoded = encode(&text).unwrap();
+    assert!(is_block_stream(&encoded));
+    assert!(encoded.len() < text.len() / 10);
+    assert_eq!(decode(&encoded, usize::MAX).unwrap(), text);
+    // Stopped right after the limit
+    assert_eq!(decode(&encoded, 10).unwrap().len(), BLOCK_SIZE);
+
+    // Incompressible bytes are stored raw
+    let bytes = (0..=255).collect::<Vec<u8>>();
+    let encoded = encode(&bytes).unwrap();
+    assert_eq!(encoded[MAGIC.len()], METHOD_RAW | BLOCK_LEVEL);
+    assert_eq!(decode(&encoded, usize::MAX).unwrap(), bytes);
+    assert_eq!(decode(&encode(b"").unwrap(), usize::MAX).unwrap(), b"");
+
+    let mut corrupted = encoded.clone();
+    corrupted[HEADER_LEN] ^= 1;
+    let err = decode(&corrupted, usize::MAX).unwrap_err();
+    assert!(err.to_string().contains("checksum mismatch"), "{}", err);
+    let err = decode(&encoded[..encoded.len() - HEADER_LEN], usize::MAX).unwrap_err();
+    assert!(err.to_string().contains("header expected"), "{}", err);
+    let err = decode(&encoded[..100], usize::MAX).unwrap_err();
+    assert!(err.to_string().contains("truncated"), "{}", err);
+}
@@ -1,42 +1,778 @@
-use clap::Parser;
+use budget::MemoryBudget;
+use clap::{CommandFactory, Parser};
 use colored::Colorize;
+use conflict::Prompter;
+use decimal::DecimalKeys;
+use derive::{DerivedMapping, DynamicMapping};
+use dimension::DimensionRules;
+use engine::RemapEngine;
+use filter::{format_timestamp, parse_timestamp, Area, ChunkFilter, TimeFilter};
+use format::{FormatVersion, Profile};
+use guard::WriteGuard;
+use handler::{AnomalyCheck, HandlerKind, Registry, RemapOptions};
 use indicatif::MultiProgress;
 use indicatif_log_bridge::LogWrapper;
-use mapping::MappingKind;
+use mapping::{Mapping, MappingFormat, MappingKind, OfflineScheme, OnConflict};
+use nbt::NbtRules;
+use notify::{Notification, NotifyOn};
 use once_cell::sync::Lazy;
-use rand::{seq::SliceRandom, thread_rng};
-use std::path::PathBuf;
-use task::split_tasks;
+use online::{OnlineLookup, Resolver};
+use owner::Owners;
+use preview::Preview;
+use progress::{Progress, Totals};
+use rand::{rngs::StdRng, thread_rng, Rng, SeedableRng};
+use report::{Alias, ClonedFile, Partial, RenamePhase, RenamedFile, Report, Summary};
+use retry::RetryPolicy;
+use status::{Phase, StatusFile};
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::OsString,
+    io::{BufRead, IsTerminal},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+use style::paint;
+use task::{split_tasks, WorkerResult};
+use uuid::Uuid;
 
 mod anvil;
+mod bench;
+mod budget;
+mod capture;
+mod conflict;
+mod decimal;
+mod derive;
+mod dimension;
+mod engine;
+mod filter;
+mod find;
+#[cfg(test)]
+mod fixture;
+mod format;
+mod guard;
+mod handler;
+mod journal;
+mod live;
+mod lz4java;
 mod mapping;
+mod mutf8;
 mod nbt;
+mod notify;
+mod online;
+mod owner;
+mod pattern;
+mod pipeline;
+mod preview;
+mod profiles;
+mod progress;
 mod remap;
+mod report;
+mod reporter;
+mod retry;
+mod scan;
+mod source;
+mod stage;
+mod status;
+mod style;
 mod task;
 mod text;
+mod transaction;
 
 static MULTI: Lazy<MultiProgress> = Lazy::new(MultiProgress::new);
 
 #[derive(Debug, Parser)]
+#[command(
+    after_help = "Other commands:\n  find    Find the files referencing the uuids, see `uuid-remapper find --help`\n  \
+    verify  Check that no file references the uuids, see `uuid-remapper verify --help`"
+)]
 struct Cli {
     /// The path to the world
     path: PathBuf,
     /// The kind of mapping
-    mapping_kind: MappingKind,
+    #[clap(required_unless_present_any = ["derive_only", "maps"])]
+    mapping_kind: Option<MappingKind>,
     /// The path to the mapping file
-    mapping_file: PathBuf,
-    /// The number of threads to use
+    #[clap(required_unless_present_any = ["derive_only", "maps"])]
+    mapping_file: Option<PathBuf>,
+    /// Map the uuid to another uuid, can be repeated, added to the mapping file if given
+    #[clap(long = "map", value_name = "FROM=TO", value_parser = mapping::parse_inline)]
+    maps: Vec<(Uuid, Uuid)>,
+    /// Which entry wins when `--map` and the mapping file map a uuid differently
+    #[clap(long, value_enum, default_value = "error")]
+    on_conflict: OnConflict,
+    /// The number of threads to use, the CPU stage only with `--threads-io`
     #[clap(short, long, default_value = "24")]
     threads: usize,
+    /// Read and write the files on this many separate threads, handing them to `--threads`
+    /// workers for remapping, e.g. more IO threads for a world on network storage
+    /// Without it, each worker reads and writes its own files
+    #[clap(long, value_name = "N", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    threads_io: Option<usize>,
     /// Skip the confirmation
-    #[clap(short, long)]
+    #[clap(short, long, conflicts_with = "no")]
     yes: bool,
     /// Do not modify the world
     #[clap(short, long)]
     no: bool,
+    /// Proceed even if names of the list or the usercache are not in the mapping,
+    /// without it `--yes` refuses to and the confirmation defaults to no
+    #[clap(long)]
+    ignore_unresolved: bool,
+    /// Write a JSON report of the run to the path
+    #[clap(long)]
+    report: Option<PathBuf>,
+    /// The seed used to shuffle the files, random if not given
+    #[clap(long)]
+    seed: Option<u64>,
+    /// Sort the files before shuffling, so that the assignment of files to workers
+    /// only depends on the seed
+    #[clap(long)]
+    deterministic_split: bool,
+    /// Only remap the chunk at the world chunk coordinates in region files, can be repeated
+    /// Other chunks are copied as is
+    #[clap(long = "chunk", value_name = "X,Z", value_parser = filter::parse_chunk)]
+    chunks: Vec<(i32, i32)>,
+    /// Only remap the chunks inside the box in region files, like `--chunk` for each of them
+    /// In block coordinates, or in chunk coordinates with a `c` suffix, e.g. `-16,-16,15,15c`
+    #[clap(long, value_name = "X1,Z1,X2,Z2", value_parser = filter::parse_area)]
+    area: Option<Area>,
+    /// Count the files with the prefix (relative to the world) as the dimension in the statistics,
+    /// can be repeated, checked in order before the built-in rules
+    #[clap(long = "dimension-rule", value_name = "PREFIX=LABEL", value_parser = dimension::parse_rule)]
+    dimension_rules: Vec<(PathBuf, String)>,
+    /// Compute the new uuid of the uuids missing in the mapping,
+    /// one of `hmac-sha256:<hexkey>`, `offline-from-name:<usercache.json>` or `xor:<hex>`
+    #[clap(long, value_name = "SPEC")]
+    derive: Option<String>,
+    /// Ignore the mapping, compute the new uuid of every uuid with `--derive`
+    #[clap(long, requires = "derive")]
+    derive_only: bool,
+    /// Do not style the output, also disabled by the `NO_COLOR` environment variable
+    #[clap(long)]
+    no_color: bool,
+    /// The prefix of the name hashed into offline uuids, for servers not using the vanilla one
+    #[clap(long, default_value = "OfflinePlayer:")]
+    offline_prefix: String,
+    /// Lowercase the names before computing offline uuids
+    #[clap(long)]
+    offline_lowercase: bool,
+    #[clap(flatten)]
+    online_lookup: OnlineArgs,
+    /// Keep the entry with the uuid when a name appears more than once in `usercache.json`,
+    /// can be repeated, otherwise the entry expiring last is kept
+    #[clap(long = "prefer-uuid", value_name = "UUID")]
+    prefer_uuids: Vec<Uuid>,
+    /// Leave the player with the uuid or the name as it is, can be repeated
+    /// Their entries are removed from the mapping, and the files whose path names them are left out
+    #[clap(long = "freeze", value_name = "UUID_OR_NAME", value_parser = mapping::parse_frozen)]
+    frozen: Vec<mapping::Frozen>,
+    /// Bound the uncompressed data held by all the workers at the same time, in MiB
+    /// Unlimited if not given, regions are charged per chunk, a file or a chunk larger than the
+    /// limit is remapped alone
+    #[clap(long, value_name = "MB")]
+    memory_limit: Option<usize>,
+    /// Also remap uuids stored as two signed 64-bit decimals under the keys in YAML, JSON and
+    /// properties files, each two keys form a pair of the most and the least significant bits
+    #[clap(long, value_name = "MOST,LEAST,...", value_parser = decimal::parse_keys)]
+    decimal_uuid_keys: Option<DecimalKeys>,
+    /// Also remap the uuids captured as `(?P<uuid>...)` by the regex in text files, can be repeated
+    /// The new uuid is written dashed if the captured text is, and in its case
+    #[clap(long = "text-pattern", value_name = "REGEX", value_parser = pattern::parse_pattern)]
+    text_patterns: Vec<regex::bytes::Regex>,
+    /// Run without modifying the world, showing the changed fields of NBT files as SNBT
+    /// and the changed lines of text files, with their line endings
+    /// No confirmation is asked, as nothing is modified
+    #[clap(long, conflicts_with = "no")]
+    preview: bool,
+    /// Retry reading and writing a file this many times on transient errors, e.g. on network storage
+    #[clap(long, default_value_t = 3)]
+    fs_retries: u32,
+    /// The delay before the first retry, doubled for each further retry
+    #[clap(long, value_name = "MS", default_value_t = 100)]
+    fs_retry_delay_ms: u64,
+    /// Remap the files with the extension with the handler, skipping the detection, can be repeated
+    /// One of mca, nbt-gzip, nbt-raw, text or skip
+    #[clap(long = "handler", value_name = "EXT=KIND", value_parser = handler::parse_override)]
+    handlers: Vec<(String, HandlerKind)>,
+    /// Apply the reverse of the mapping file, mapping its new uuids back to the old ones,
+    /// e.g. to undo a remapping, the `--map` entries are added as they are
+    #[clap(long)]
+    invert: bool,
+    /// Proceed even if some new uuids are mapped again, e.g. `A -> B` and `B -> C`
+    /// Each occurrence is mapped once, so `A` becomes `B`, but running again would make it `C`
+    #[clap(long)]
+    allow_chained_mapping: bool,
+    /// Map every old uuid to the end of its chain instead, `A -> C` and `B -> C` for `A -> B`
+    /// and `B -> C`, refused if a chain loops back, e.g. `A -> B` and `B -> A`
+    #[clap(long, conflicts_with = "allow_chained_mapping")]
+    compose_chains: bool,
+    /// Proceed even if some old uuids are mapped to the same new uuid, merging the players
+    /// A file renamed onto one already renamed keeps its old name, to be merged by hand
+    #[clap(long)]
+    allow_duplicate_targets: bool,
+    /// Stream every region file instead of loading it, keeping one chunk in memory at a time
+    /// Regions larger than 64 MiB are always streamed
+    #[clap(long)]
+    low_memory: bool,
+    /// Visit and rewrite every file and chunk, even those without any form of an old uuid
+    #[clap(long)]
+    no_prefilter: bool,
+    /// Copy the chunks larger than this uncompressed as is, reported as oversized, in MiB
+    /// Uncompressing stops at the limit, so a corrupted chunk can't take more memory
+    #[clap(long, value_name = "MB", default_value_t = 256, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    max_chunk_size: usize,
+    /// Flag the region files growing more than this many times when rewritten, in the summary
+    /// and the report, a sign of a broken header or of chunks rewritten wrongly
+    #[clap(long, value_name = "FACTOR", default_value_t = 1.5)]
+    anomaly_factor: f64,
+    /// Keep a copy of the original of each flagged region in the directory, by its path in the world
+    #[clap(long, value_name = "DIR")]
+    keep_anomalies: Option<PathBuf>,
+    /// Also remap the `UUID` of item attribute modifiers, which identifies the modifier, not a player
+    /// They are skipped by default, as mappings from large uuid dumps may collide with them
+    #[clap(long)]
+    remap_attribute_modifiers: bool,
+    /// Proceed with an empty mapping, rewriting every file with the identity mapping
+    /// Refused by default, as it usually means the lookups failed or the mapping file is wrong
+    #[clap(long)]
+    assume_empty_ok: bool,
+    /// The layout of the world, selecting the default rules, read from `level.dat` with auto
+    /// Legacy (before 1.13) also pairs `M`/`L` and `xxxMost`/`xxxLeast` longs, modern skips
+    /// the poi files
+    #[clap(long, value_enum, default_value = "auto")]
+    format_version: FormatVersion,
+    /// Log a plain progress line every this many seconds when the log is not a terminal,
+    /// e.g. piped to `tee`, as no bar is drawn then
+    #[clap(long, value_name = "SECS", default_value_t = 30, value_parser = clap::builder::RangedU64ValueParser::<u64>::new().range(1..))]
+    progress_interval: u64,
+    /// Skip the checks guessing if the world is open in a running server, e.g. recently modified
+    /// region files, a growing `logs/latest.log` or a locked `level.dat`
+    /// Otherwise `I UNDERSTAND` must be typed to proceed when one of them trips
+    #[clap(long)]
+    world_is_offline: bool,
+    /// Run as root anyway, the files modified are given back to their owners afterwards
+    /// Otherwise running as root is refused, as the server could no longer write the files
+    #[clap(long)]
+    allow_root: bool,
+    /// Copy the input of every file failing to be remapped into the directory, with the error,
+    /// to reproduce the failure with `replay` without sharing the world
+    /// The captured data may contain world content, e.g. signs, books and player names
+    #[clap(long, value_name = "DIR")]
+    capture_failures: Option<PathBuf>,
+    /// Also capture the decompressed data of each chunk failing to be visited,
+    /// which is dropped without failing the region
+    #[clap(long, requires = "capture_failures")]
+    capture_all_nbt_errors: bool,
+    /// Rewrite a JSON file with the phase of the run, the counters and the file of each worker
+    /// every 2 seconds, for wrappers like web panels, the last state is written when the run ends
+    #[clap(long, value_name = "PATH")]
+    progress_file: Option<PathBuf>,
+    /// Rewrite the counters of the run every 2 seconds in the text format of Prometheus,
+    /// e.g. `uuid-remapper.prom` in the directory of the textfile collector of node_exporter
+    /// `uuid_remapper_finished` is set to 1 in the last state, and the file is left in place
+    #[clap(long, value_name = "PATH")]
+    metrics_textfile: Option<PathBuf>,
+    /// Stage every modified file next to the world, and move them in place only if every file is
+    /// remapped without error, otherwise nothing is modified
+    /// The run is refused if the filesystem of the staging has less free space than the files
+    /// to remap, see the README for recovery
+    #[clap(long, conflicts_with = "preview")]
+    transactional: bool,
+    /// POST the outcome and the totals of the run as JSON to the URL when it ends,
+    /// a failed notification is only logged
+    #[clap(long, value_name = "URL")]
+    notify_webhook: Option<String>,
+    /// Rename each file right after remapping its content, instead of after all the contents
+    #[clap(long)]
+    single_phase: bool,
+    /// Copy the files named after a mapped uuid, e.g. `playerdata/<old>.dat`, to the new name and
+    /// remap only the copies, keeping the originals, to try a migration on a copy of the player
+    /// The rest of the world, e.g. the regions and `level.dat`, is left as is
+    #[clap(
+        long = "clone",
+        conflicts_with_all = ["preview", "transactional", "threads_io", "derive"]
+    )]
+    clone_files: bool,
+    /// Only remap the first N files in path order, `level.dat` first, for a pilot run with real
+    /// writes, the run is marked as partial in the summary and the report
+    /// Refused with a chained mapping, e.g. swapped players, as the run for the other files would
+    /// remap the files of the pilot again
+    /// The remapped files are recorded in a journal next to the world, for `--resume`
+    #[clap(long, value_name = "N", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    limit: Option<usize>,
+    /// Continue after the files of a pilot run of `--limit`, recorded in its journal next to the
+    /// world, with `--limit` again to remap the next files
+    #[clap(long, conflicts_with = "clone_files")]
+    resume: bool,
+    /// Only remap the files modified at or after the time, by their modification time,
+    /// RFC3339 like `2024-05-01T12:00:00Z` or relative like `7d`, e.g. to repair the files
+    /// restored from a backup on top of a remapped world
+    #[clap(long, value_name = "TIMESTAMP", value_parser = parse_timestamp)]
+    modified_after: Option<SystemTime>,
+    /// Only remap the files modified before the time, excluding it, in the same format
+    #[clap(long, value_name = "TIMESTAMP", value_parser = parse_timestamp)]
+    modified_before: Option<SystemTime>,
+    /// Notify on every run, or only when the run fails, is cancelled or has errors
+    #[clap(
+        long,
+        value_enum,
+        default_value = "always",
+        requires = "notify_webhook"
+    )]
+    notify_on: NotifyOn,
+    /// Ask how to resolve each conflict, i.e. a file renamed to an existing name, several uuids
+    /// mapped to the same one and chunks at the same sectors with the same timestamp,
+    /// instead of the default resolution, the decisions are recorded in the report
+    /// Only with a terminal as the standard input
+    #[clap(long)]
+    interactive_conflicts: bool,
+    /// Take the options not given on the command line from the profile in the config file,
+    /// see `uuid-remapper profiles list`
+    #[clap(long, value_name = "NAME")]
+    profile: Option<String>,
+    /// The config file with the profiles, `uuid-remapper.json` in the working directory by default
+    #[clap(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+    /// Exit with code 0 even if the run fails or is cancelled
+    #[clap(long)]
+    exit_zero: bool,
+    /// Restore the quirks of older versions for the scripts depending on them, a preset of
+    /// `--no-prefilter`, `--exit-zero` and `--assume-empty-ok`, the options given on the command
+    /// line or by the profile win; the other checks added since still apply
+    #[clap(long)]
+    legacy_behavior: bool,
+    /// The options taken from the profile, printed in the summary
+    #[clap(skip)]
+    profile_options: Vec<String>,
+}
+
+/// Subcommands, dispatched by the first argument
+/// A world with the same name as a subcommand can be passed as `./<name>`
+#[derive(Debug, Parser)]
+#[command(name = "uuid-remapper")]
+enum Tool {
+    /// Find the files referencing the uuids and where in them, without modifying anything
+    Find(FindArgs),
+    /// Check that no file references the uuids, e.g. the old uuids after a run, exiting with 1 if
+    /// any does
+    /// The checked files are journaled next to the world, so an interrupted run can be resumed
+    Verify(VerifyArgs),
+    /// Run the inputs captured with `--capture-failures` through the visitors again, for debugging
+    Replay(ReplayArgs),
+    /// Count the files that would be remapped and estimate the duration, without a mapping
+    /// Only the metadata and the first bytes of the files are read
+    Scan(ScanArgs),
+    /// Remap a synthetic world generated from a seed and print the throughput, also as JSON
+    Bench(BenchArgs),
+    /// Convert or validate a mapping file, without a world
+    Mapping(MappingArgs),
+    /// List or show the profiles of the config file
+    Profiles(ProfilesArgs),
+}
+
+#[derive(Debug, clap::Args)]
+struct ProfilesArgs {
+    #[command(subcommand)]
+    command: ProfilesCommand,
+    /// The config file, `uuid-remapper.json` in the working directory by default
+    #[clap(long, value_name = "FILE", global = true)]
+    config: Option<PathBuf>,
+    /// Do not style the output, also disabled by the `NO_COLOR` environment variable
+    #[clap(long, global = true)]
+    no_color: bool,
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum ProfilesCommand {
+    /// List the names of the profiles
+    List,
+    /// Print the options a run with the profile takes, as they are passed on the command line
+    Show { name: String },
+}
+
+#[derive(Debug, clap::Args)]
+struct MappingArgs {
+    #[command(subcommand)]
+    command: MappingCommand,
+    /// Do not style the output, also disabled by the `NO_COLOR` environment variable
+    #[clap(long, global = true)]
+    no_color: bool,
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum MappingCommand {
+    /// Load the mapping with the loader of `--from` and write it sorted by the old uuid
+    Convert {
+        #[clap(long, value_enum)]
+        from: MappingFormat,
+        #[clap(long, value_enum)]
+        to: MappingFormat,
+        input: PathBuf,
+        output: PathBuf,
+    },
+    /// Load the mapping and check it for chained and colliding entries
+    /// The names are resolved like in a run
+    Validate {
+        #[clap(value_enum)]
+        kind: MappingKind,
+        path: PathBuf,
+        /// The prefix of the name hashed into offline uuids, for servers not using the vanilla one
+        #[clap(long, default_value = "OfflinePlayer:")]
+        offline_prefix: String,
+        /// Lowercase the names before computing offline uuids
+        #[clap(long)]
+        offline_lowercase: bool,
+        #[clap(flatten)]
+        online_lookup: OnlineArgs,
+    },
+}
+
+#[derive(Debug, clap::Args)]
+struct BenchArgs {
+    /// The number of region files
+    #[clap(long, default_value_t = 4)]
+    regions: usize,
+    /// The number of chunks in each region, at most 1024
+    #[clap(long, default_value_t = 256, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(0..=1024))]
+    chunks: usize,
+    /// The number of entities in each chunk, each with two uuids
+    #[clap(long, default_value_t = 8)]
+    entities: usize,
+    /// The number of players in the mapping, each with a playerdata file
+    #[clap(long, default_value_t = 100)]
+    players: usize,
+    /// The number of text files
+    #[clap(long, default_value_t = 50)]
+    text_files: usize,
+    /// The number of uuids in each text file
+    #[clap(long, default_value_t = 200)]
+    text_lines: usize,
+    /// The probability that a generated uuid is in the mapping
+    #[clap(long, default_value_t = 0.5)]
+    density: f64,
+    /// The seed of the generated world, the same seed gives the same world on every machine
+    #[clap(long, default_value_t = 0)]
+    seed: u64,
+    /// The number of threads to use
+    #[clap(short, long, default_value = "24")]
+    threads: usize,
+    /// Keep the generated world instead of removing it
+    #[clap(long)]
+    keep: bool,
+    /// Do not style the output, also disabled by the `NO_COLOR` environment variable
+    #[clap(long)]
+    no_color: bool,
+}
+
+#[derive(Debug, clap::Args)]
+struct ScanArgs {
+    /// The path to the world
+    path: PathBuf,
+    /// Only count the region files with the chunk, like the remapping, can be repeated
+    #[clap(long = "chunk", value_name = "X,Z", value_parser = filter::parse_chunk)]
+    chunks: Vec<(i32, i32)>,
+    /// Only count the region files intersecting the box, like the remapping
+    #[clap(long, value_name = "X1,Z1,X2,Z2", value_parser = filter::parse_area)]
+    area: Option<Area>,
+    /// The layout of the world, the poi files are not counted for modern worlds
+    #[clap(long, value_enum, default_value = "auto")]
+    format_version: FormatVersion,
+    /// Remap the files with the extension with the handler, like the remapping, can be repeated
+    #[clap(long = "handler", value_name = "EXT=KIND", value_parser = handler::parse_override)]
+    handlers: Vec<(String, HandlerKind)>,
+    /// Find the handlers by the path only, without opening any file
+    #[clap(long)]
+    no_sniff: bool,
+    /// The assumed remapping speed for the estimate, in MiB per second
+    #[clap(long, value_name = "MIB_PER_SEC", default_value_t = 50.0)]
+    throughput: f64,
+    /// Do not style the output, also disabled by the `NO_COLOR` environment variable
+    #[clap(long)]
+    no_color: bool,
+}
+
+#[derive(Debug, clap::Args)]
+struct ReplayArgs {
+    /// The directory given to `--capture-failures`
+    dir: PathBuf,
+    /// Do not style the output, also disabled by the `NO_COLOR` environment variable
+    #[clap(long)]
+    no_color: bool,
+}
+
+/// How the names are looked up with the Mojang API or a mirror
+#[derive(Debug, Clone, clap::Args)]
+struct OnlineArgs {
+    /// The API looking up the online uuids, the mirrors help when the Mojang API is rate limited
+    /// or blocked, they are asked one name per request
+    #[clap(long, value_enum, default_value = "mojang")]
+    resolver: Resolver,
+    /// Replace `https://api.mojang.com` by the API of an authlib-injector server, e.g. Ely.by,
+    /// asked with the same requests, the Yggdrasil root of Blessing Skin is also accepted
+    #[clap(long, value_name = "URL", conflicts_with = "resolver")]
+    api_base: Option<String>,
+    /// Proceed when fewer than half of the names are found online, the run fails by default,
+    /// as it usually means the lookups failed
+    #[clap(long)]
+    allow_partial_mapping: bool,
+    /// Retry a failed Mojang API request this many times, waiting for `Retry-After` when rate limited,
+    /// at most 60 retry delays
+    #[clap(long, default_value_t = 5)]
+    online_retries: u32,
+    /// The delay before the first retry of a Mojang API request, doubled for each further retry
+    #[clap(long, value_name = "MS", default_value_t = 1000)]
+    online_retry_delay_ms: u64,
+    /// Cache the looked up names in the file, `~/.cache/uuid-remapper/mojang.json` by default,
+    /// or named after the host of `--api-base`
+    #[clap(long, value_name = "FILE")]
+    online_cache: Option<PathBuf>,
+    /// Look up the cached names again after this many hours
+    #[clap(long, value_name = "HOURS", default_value_t = 24)]
+    online_cache_ttl_hours: u64,
+    /// Always ask the Mojang API, neither reading nor writing the cache
+    #[clap(long, conflicts_with = "online_cache")]
+    no_cache: bool,
+}
+
+impl OnlineArgs {
+    fn lookup(&self) -> OnlineLookup {
+        OnlineLookup {
+            retries: self.online_retries,
+            retry_delay: Duration::from_millis(self.online_retry_delay_ms),
+            cache: if self.no_cache {
+                None
+            } else {
+                self.online_cache
+                    .clone()
+                    .or_else(|| online::default_cache(self.api_base.as_deref()))
+            },
+            cache_ttl: Duration::from_secs(self.online_cache_ttl_hours.saturating_mul(60 * 60)),
+            resolver: self.resolver,
+            api_base: self.api_base.clone(),
+            allow_partial: self.allow_partial_mapping,
+        }
+    }
+
+    /// The tests never touch the cache of the user
+    #[cfg(test)]
+    fn uncached() -> Self {
+        Self {
+            resolver: Resolver::Mojang,
+            api_base: None,
+            allow_partial_mapping: false,
+            online_retries: 5,
+            online_retry_delay_ms: 0,
+            online_cache: None,
+            online_cache_ttl_hours: 24,
+            no_cache: true,
+        }
+    }
+}
+
+#[derive(Debug, clap::Args)]
+struct FindArgs {
+    /// The path to the world
+    path: PathBuf,
+    #[clap(flatten)]
+    targets: TargetArgs,
+    /// Do not style the output, also disabled by the `NO_COLOR` environment variable
+    #[clap(long)]
+    no_color: bool,
+}
+
+#[derive(Debug, clap::Args)]
+struct VerifyArgs {
+    /// The path to the world
+    path: PathBuf,
+    #[clap(flatten)]
+    targets: TargetArgs,
+    /// Continue an interrupted verification, the files recorded in its journal are not checked
+    /// again and their findings count
+    #[clap(long)]
+    resume_verify: bool,
+    /// Append each finding as a line of JSON as soon as it is found, kept across resumed runs
+    #[clap(long, value_name = "FILE")]
+    findings: Option<PathBuf>,
+    /// Do not style the output, also disabled by the `NO_COLOR` environment variable
+    #[clap(long)]
+    no_color: bool,
+}
+
+/// The uuids looked for by `find` and `verify`
+#[derive(Debug, clap::Args)]
+struct TargetArgs {
+    /// The uuids to find
+    uuids: Vec<Uuid>,
+    /// Also find the uuid of the player, can be repeated
+    #[clap(long = "name")]
+    names: Vec<String>,
+    /// Resolve the names as offline players
+    #[clap(long, conflicts_with = "online")]
+    offline: bool,
+    /// Resolve the names as online players with the Mojang API
+    #[clap(long)]
+    online: bool,
+    /// The prefix of the name hashed into offline uuids, for servers not using the vanilla one
+    #[clap(long, default_value = "OfflinePlayer:")]
+    offline_prefix: String,
+    /// Lowercase the names before computing offline uuids
+    #[clap(long)]
+    offline_lowercase: bool,
+    #[clap(flatten)]
+    online_lookup: OnlineArgs,
+    /// The number of threads to use
+    #[clap(short, long, default_value = "24")]
+    threads: usize,
+}
+
+/// The outcome of a run, reported as the exit code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    /// Finished, or nothing to do
+    Done,
+    /// Failed before modifying the world, except `level.dat` which is remapped first
+    Failed,
+    /// Cancelled by the user, or the confirmation can't be asked
+    Cancelled,
+}
+
+impl From<Outcome> for ExitCode {
+    fn from(outcome: Outcome) -> Self {
+        match outcome {
+            Outcome::Done => ExitCode::SUCCESS,
+            Outcome::Failed => ExitCode::from(1),
+            Outcome::Cancelled => ExitCode::from(2),
+        }
+    }
+}
+
+/// An answer to the confirmation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Answer {
+    Yes,
+    No,
+    Unknown,
 }
 
-fn start(cli: Cli) {
+fn parse_answer(input: &str) -> Answer {
+    match input.trim().to_lowercase().as_str() {
+        "yes" | "y" => Answer::Yes,
+        "no" | "n" => Answer::No,
+        _ => Answer::Unknown,
+    }
+}
+
+/// Read one line, proceeding only if it is the phrase
+fn confirm_phrase(input: &mut impl BufRead, phrase: &str) -> bool {
+    let mut line = String::new();
+    matches!(input.read_line(&mut line), Ok(n) if n > 0) && line.trim() == phrase
+}
+
+/// Ask until the user answers yes or no, EOF is treated as no, and so is an empty line
+/// with `empty_is_no`
+fn confirm(input: &mut impl BufRead, empty_is_no: bool) -> bool {
+    loop {
+        let mut line = String::new();
+        if !matches!(input.read_line(&mut line), Ok(n) if n > 0) {
+            return false;
+        }
+        if empty_is_no && line.trim().is_empty() {
+            return false;
+        }
+        match parse_answer(&line) {
+            Answer::Yes => return true,
+            Answer::No => return false,
+            Answer::Unknown => {
+                log::info!("{}", paint("Please answer YES or NO", |s| s.green().bold()))
+            }
+        }
+    }
+}
+
+fn start(cli: Cli) -> Outcome {
+    if owner::is_root() && !cli.allow_root {
+        log::error!(
+            "Running as root, the files modified would be owned by root and the server \
+        could no longer write them, which looks like a corrupted world"
+        );
+        log::error!(
+            "Run as the user of the server, or pass --allow-root to give the files back \
+        to their owners once remapped"
+        );
+        return Outcome::Failed;
+    }
+    #[cfg(not(feature = "online"))]
+    if cli.notify_webhook.is_some() {
+        log::error!("{:#}", online::unsupported("--notify-webhook"));
+        return Outcome::Failed;
+    }
+    let outputs = [
+        (cli.progress_file.clone(), status::Format::Json),
+        (cli.metrics_textfile.clone(), status::Format::Metrics),
+    ]
+    .into_iter()
+    .filter_map(|(path, format)| Some((path?, format)))
+    .collect::<Vec<_>>();
+    let status = (!outputs.is_empty()).then(|| StatusFile::start(outputs, status::INTERVAL));
+    let (webhook, notify_on, world) = (cli.notify_webhook.clone(), cli.notify_on, cli.path.clone());
+    let mut summary = Summary::default();
+    let owners = (cli.allow_root && owner::is_root()).then(|| Arc::new(Owners::default()));
+    let outcome = run(cli, status.as_ref(), owners.clone(), &mut summary);
+    if let Some(owners) = owners {
+        let (restored, failed) = owners.restore(owner::chown);
+        if restored > 0 {
+            log::info!("{} files are given back to their owners", restored);
+        }
+        for (path, err) in failed {
+            log::error!(
+                "Failed to give {} back to its owner: {}",
+                path.display(),
+                err
+            );
+        }
+    }
+    if let Some(url) = webhook {
+        let notification = Notification {
+            outcome: match outcome {
+                Outcome::Done => "done",
+                Outcome::Failed => "failed",
+                Outcome::Cancelled => "cancelled",
+            },
+            world,
+            summary,
+        };
+        if notify_on.wants(&notification) {
+            match notify::post(&url, &notification) {
+                Ok(()) => log::info!("Notified {}", url),
+                Err(err) => log::warn!("Failed to notify {}: {:#}", url, err),
+            }
+        }
+    }
+    if let Some(status) = status {
+        status.finish(match outcome {
+            Outcome::Done => Phase::Done,
+            Outcome::Failed => Phase::Failed,
+            Outcome::Cancelled => Phase::Cancelled,
+        });
+    }
+    outcome
+}
+
+/// The totals are written to `summary` once the files are remapped,
+/// the owners of the files modified are captured into `owners`
+fn run(
+    cli: Cli,
+    status: Option<&StatusFile>,
+    owners: Option<Arc<Owners>>,
+    summary: &mut Summary,
+) -> Outcome {
+    let set_phase = |phase| {
+        if let Some(status) = status {
+            status.set_phase(phase);
+        }
+    };
     if std::mem::size_of::<usize>() < 8 {
         log::error!(
             "usize is less than 64-bit, you may encounter integer overflow when \
@@ -49,100 +785,1612 @@ fn start(cli: Cli) {
         );
     }
 
-    let path = cli.path;
-    let tasks = task::scan_world(&path);
-    let Ok(mut tasks) = tasks else {
-        log::error!("Failed to scan world: {:#?}", tasks);
-        return;
-    };
-    log::info!("{} files found in {}", tasks.len(), path.display());
-    let mapping = match mapping::get_mapping(cli.mapping_kind, &cli.mapping_file) {
-        Ok(m) => m,
+    if cli.interactive_conflicts && !std::io::stdin().is_terminal() {
+        log::error!("The standard input is not a terminal, unable to ask how to resolve conflicts");
+        log::error!("Remove --interactive-conflicts to resolve them as usual");
+        return Outcome::Failed;
+    }
+
+    let path = match task::normalize_world(&cli.path) {
+        Ok(path) => path,
+        Err(err) => {
+            log::error!("{:#}", err);
+            return Outcome::Failed;
+        }
+    };
+    let overrides = match handler::collect_overrides(&cli.handlers) {
+        Ok(overrides) => overrides,
+        Err(err) => {
+            log::error!("{}", err);
+            return Outcome::Failed;
+        }
+    };
+    let mut registry = Registry::builtin();
+    for (ext, kind) in &overrides {
+        registry.set_override(ext, *kind);
+    }
+    let (profile, profile_reason) = cli.format_version.resolve(&path);
+    let mut dimension_rules = DimensionRules::default();
+    for (prefix, label) in cli.dimension_rules {
+        dimension_rules.add(prefix, label);
+    }
+    // A transactional run interrupted while committing would leave the world half remapped
+    if cli.preview {
+        if transaction::Staging::dir_for(&path).is_ok_and(|dir| dir.exists()) {
+            log::error!("A transactional run was interrupted, run without --preview to revert it");
+            return Outcome::Failed;
+        }
+    } else {
+        match transaction::recover(&path) {
+            Ok(Some(steps)) => log::warn!(
+                "A transactional run was interrupted, {} steps of its commit are reverted",
+                steps
+            ),
+            Ok(None) => {}
+            Err(err) => {
+                log::error!("{:#}", err);
+                return Outcome::Failed;
+            }
+        }
+    }
+    let guard = if cli.preview {
+        WriteGuard::dry_run()
+    } else if cli.transactional {
+        match WriteGuard::transactional(&path) {
+            Ok(guard) => guard,
+            Err(err) => {
+                log::error!("{:#}", err);
+                return Outcome::Failed;
+            }
+        }
+    } else {
+        WriteGuard::default()
+    };
+    let guard = match owners {
+        Some(owners) => guard.with_owners(owners),
+        None => guard,
+    };
+    let mut options = RemapOptions {
+        chunk_filter: (!cli.chunks.is_empty() || cli.area.is_some()).then(|| {
+            let filter = ChunkFilter::new(cli.chunks.clone());
+            match cli.area {
+                Some(area) => filter.with_area(area),
+                None => filter,
+            }
+        }),
+        dimension_rules,
+        budget: MemoryBudget::new(cli.memory_limit.map(|mb| mb.saturating_mul(1024 * 1024))),
+        decimal_uuid_keys: cli.decimal_uuid_keys.clone(),
+        text_patterns: cli.text_patterns.clone(),
+        guard: guard.with_retry(RetryPolicy::new(
+            cli.fs_retries,
+            Duration::from_millis(cli.fs_retry_delay_ms),
+        )),
+        preview: cli.preview.then(Preview::default),
+        low_memory: cli.low_memory,
+        max_chunk_size: Some(cli.max_chunk_size.saturating_mul(1024 * 1024)),
+        nbt_rules: NbtRules {
+            remap_attribute_modifiers: cli.remap_attribute_modifiers,
+            legacy_pairs: profile == Profile::Legacy,
+        },
+        profile,
+        defer_renames: !cli.single_phase && !cli.clone_files,
+        clone_files: cli.clone_files,
+        conflicts: cli.interactive_conflicts.then(Prompter::stdin),
+        duplicate_targets: cli.allow_duplicate_targets,
+        modified: TimeFilter {
+            after: cli.modified_after,
+            before: cli.modified_before,
+        },
+        ..Default::default()
+    };
+    match AnomalyCheck::new(cli.anomaly_factor, cli.keep_anomalies.as_deref(), &path) {
+        Ok(check) => options.anomalies = Some(check),
+        Err(err) => {
+            log::error!("{:#}", err);
+            return Outcome::Failed;
+        }
+    }
+    if let Some(dir) = &cli.capture_failures {
+        match capture::Capture::new(dir, &path, cli.capture_all_nbt_errors) {
+            Ok(capture) => options.capture = Some(capture),
+            Err(err) => {
+                log::error!("{:#}", err);
+                return Outcome::Failed;
+            }
+        }
+        log::warn!(
+            "Failing inputs are captured to {}, they may contain world content, \
+            e.g. signs, books and player names, review them before sharing",
+            dir.display()
+        );
+    }
+    // The mapping or the report may be placed in the world
+    if let Some(mapping_file) = &cli.mapping_file {
+        options.exclude(mapping_file);
+    }
+    if let Some(report) = &cli.report {
+        options.exclude(report);
+    }
+    for status_file in [&cli.progress_file, &cli.metrics_textfile]
+        .into_iter()
+        .flatten()
+    {
+        options.exclude(status_file);
+        options.exclude(&status::temp_path(status_file));
+    }
+    if let Some(usercache) = cli
+        .derive
+        .as_deref()
+        .and_then(|spec| spec.strip_prefix("offline-from-name:"))
+    {
+        options.exclude(Path::new(usercache));
+    }
+    let scan = task::scan_world(&path, &mut registry, &options);
+    let Ok(scan) = scan else {
+        log::error!("Failed to scan world: {:#?}", scan);
+        return Outcome::Failed;
+    };
+    let mut tasks = scan.tasks;
+    log::info!("{} files found in {}", tasks.len(), path.display());
+    let excluded_by_time = scan.excluded_by_time;
+    if cli.resume {
+        let done = match journal::pilot_files(&path) {
+            Ok(done) => done,
+            Err(err) => {
+                log::error!("{:#}", err);
+                return Outcome::Failed;
+            }
+        };
+        tasks.retain(|task| !done.contains(&task.path));
+        log::info!(
+            "Resuming, {} files are remapped by the pilot run, {} left",
+            done.len(),
+            tasks.len()
+        );
+    }
+    let partial = cli.limit.map(|limit| Partial {
+        limit,
+        files_skipped: task::limit_tasks(&mut tasks, limit),
+    });
+    let scheme = OfflineScheme {
+        prefix: cli.offline_prefix.clone(),
+        lowercase: cli.offline_lowercase,
+    };
+    let resolved = match (cli.mapping_kind, &cli.mapping_file) {
+        (Some(kind), Some(file)) if !cli.derive_only => match mapping::resolve_mapping(
+            kind,
+            file,
+            &scheme,
+            &cli.online_lookup.lookup(),
+            &cli.prefer_uuids,
+        ) {
+            Ok(m) => m,
+            Err(err) => {
+                log::error!("Failed to load mapping: {:?}", err);
+                return Outcome::Failed;
+            }
+        },
+        _ => mapping::Resolved::default(),
+    };
+    let mapping = if cli.invert {
+        match mapping::invert(resolved.mapping) {
+            Ok(m) => m,
+            Err(err) => {
+                log::error!("{:#}", err);
+                return Outcome::Failed;
+            }
+        }
+    } else {
+        resolved.mapping
+    };
+    let mut mapping = match mapping::merge_inline(mapping, &cli.maps, cli.on_conflict) {
+        Ok(m) => m,
+        Err(err) => {
+            log::error!("{:#}", err);
+            return Outcome::Failed;
+        }
+    };
+    let frozen = mapping::freeze(&mut mapping, &cli.frozen, &scheme);
+    let files = tasks.len();
+    tasks.retain(|task| !task::names_uuid(&task.path, &frozen));
+    let files_frozen = files - tasks.len();
+    let dynamic = match cli.derive.as_deref().map(DynamicMapping::parse).transpose() {
+        Ok(d) => d,
+        Err(err) => {
+            log::error!("Failed to parse --derive: {:#?}", err);
+            return Outcome::Failed;
+        }
+    };
+    let chained = mapping::chained_entries(&mapping);
+    if !chained.is_empty() {
+        log::warn!("Some new uuids are mapped again:");
+        for (a, b, c) in &chained {
+            log::warn!("   {} -> {} -> {}", a, b, c);
+        }
+        if cli.compose_chains {
+            log::warn!("Every old uuid is mapped to the end of its chain");
+        } else if !cli.allow_chained_mapping {
+            log::error!(
+                "Each occurrence is mapped once, so running again maps the new uuids again"
+            );
+            log::error!(
+                "Pass --allow-chained-mapping if this is intended, or --compose-chains to map \
+            them to the end of their chains"
+            );
+            return Outcome::Failed;
+        } else {
+            log::warn!(
+                "Each occurrence is mapped once, e.g. {} becomes {}",
+                chained[0].0,
+                chained[0].1
+            );
+        }
+    }
+    if let Some(conflicts) = &options.conflicts {
+        for (target, sources) in mapping::colliding_targets(&mapping) {
+            let keys = (1..=sources.len())
+                .map(|i| i.to_string())
+                .collect::<Vec<_>>();
+            let resolutions = sources
+                .iter()
+                .map(|source| format!("only map {}", source))
+                .collect::<Vec<_>>();
+            let mut choices = vec![("a", "map all of them")];
+            choices.extend(
+                keys.iter()
+                    .map(String::as_str)
+                    .zip(resolutions.iter().map(String::as_str)),
+            );
+            let details = sources
+                .iter()
+                .map(|source| format!("{} -> {}", source, target))
+                .collect();
+            // The other sources are left as they are
+            let chosen = conflicts.ask("mapping", details, &choices);
+            if chosen > 0 {
+                for (i, source) in sources.iter().enumerate() {
+                    if i + 1 != chosen {
+                        mapping.remove(source);
+                    }
+                }
+            }
+        }
+    } else if !cli.allow_duplicate_targets {
+        if let Err(err) = mapping::check_targets(&mapping) {
+            log::error!("{:#}", err);
+            return Outcome::Failed;
+        }
+    }
+    // The uuids of a chain all end at its last one, checked again for the players they merge
+    if cli.compose_chains && !chained.is_empty() {
+        mapping = match mapping::compose_chains(&mapping) {
+            Ok(m) => m,
+            Err(err) => {
+                log::error!("{:#}", err);
+                return Outcome::Failed;
+            }
+        };
+        if !cli.allow_duplicate_targets {
+            if let Err(err) = mapping::check_targets(&mapping) {
+                log::error!("{:#}", err);
+                log::error!(
+                    "The composed chains merge their players, pass --allow-duplicate-targets \
+                if the uuids of each chain are the same player"
+                );
+                return Outcome::Failed;
+            }
+        }
+    }
+    if cli.limit.is_some() && !mapping::chained_entries(&mapping).is_empty() {
+        log::error!(
+            "--limit is refused with a chained mapping, the run for the other files would remap \
+        the new uuids of the files remapped already"
+        );
+        log::error!("Pass --compose-chains, or remap the whole world at once");
+        return Outcome::Failed;
+    }
+    if mapping.is_empty() && dynamic.is_none() {
+        if !cli.assume_empty_ok {
+            log::error!("Empty mapping, the lookups may have failed or the mapping file is wrong");
+            log::error!("Pass --assume-empty-ok to rewrite every file with the identity mapping");
+            return Outcome::Failed;
+        }
+        log::warn!("Empty mapping");
+        log::warn!("The program will do identity mapping, i.e. f(x) = x");
+        log::warn!("This is only used for testing the program on your world");
+    }
+    if cli.clone_files {
+        let files = tasks.len();
+        tasks.retain(|task| {
+            remap::remapped_path(&task.path, &|uuid| mapping.get(&uuid).copied())
+                .is_ok_and(|copy| copy != task.path)
+        });
+        log::info!(
+            "Cloning: {} of {} files are named after a mapped uuid, the rest are left as they are",
+            tasks.len(),
+            files
+        );
+    }
+    log::info!("{}", paint("Task Summary", |s| s.bold().underline()));
+    if let Some(profile) = &cli.profile {
+        log::info!(
+            "{}",
+            paint(format!("Options from profile {}:", profile), |s| s.yellow())
+        );
+        for option in &cli.profile_options {
+            log::info!("   {}", option);
+        }
+    }
+    log::info!("{}", paint("Files:", |s| s.yellow()));
+    for task in &tasks {
+        log::info!("   {}", task.path.display());
+    }
+    if !frozen.is_empty() {
+        log::info!(
+            "{}",
+            paint(
+                format!(
+                    "Frozen players, {} files naming them are left out:",
+                    files_frozen
+                ),
+                |s| s.yellow()
+            )
+        );
+        for uuid in &frozen {
+            log::info!("   {}", uuid);
+        }
+    }
+    if cli.invert {
+        log::info!(
+            "{}",
+            paint(
+                "Mapping, inverted, the new uuids of the file are mapped back to the old ones:",
+                |s| s.yellow()
+            )
+        );
+    } else {
+        log::info!("{}", paint("Mapping:", |s| s.yellow()));
+    }
+    for (k, v) in &mapping {
+        log::info!("   {} -> {}", k, v);
+    }
+    if let Some(spec) = &cli.derive {
+        if cli.derive_only {
+            log::info!("   every uuid -> {}", spec);
+        } else {
+            log::info!("   other uuids -> {}", spec);
+        }
+    }
+    if !overrides.is_empty() {
+        log::info!("{}", paint("Handler overrides:", |s| s.yellow()));
+        for (ext, kind) in &overrides {
+            log::info!("   .{} -> {}", ext, kind.name());
+        }
+    }
+    if let Some(filter) = &options.chunk_filter {
+        log::info!(
+            "{}",
+            paint("Only these chunks in region files:", |s| s.yellow())
+        );
+        for (x, z) in filter.chunks() {
+            log::info!("   ({}, {})", x, z);
+        }
+        if let Some(area) = filter.area() {
+            let (regions, chunks) = task::count_area(&path, &tasks, &area, &options.guard);
+            log::info!(
+                "   ({}, {}) to ({}, {}), {} regions and {} chunks inside",
+                area.min.0,
+                area.min.1,
+                area.max.0,
+                area.max.1,
+                regions,
+                chunks
+            );
+        }
+    }
+    if options.modified.is_active() {
+        let after = options.modified.after.map(format_timestamp);
+        let before = options.modified.before.map(format_timestamp);
+        let range = match (after, before) {
+            (Some(after), Some(before)) => format!("from {} to before {}", after, before),
+            (Some(after), None) => format!("from {}", after),
+            (None, Some(before)) => format!("before {}", before),
+            (None, None) => unreachable!(),
+        };
+        log::info!(
+            "{} {}, {} files excluded by the time filters",
+            paint("Only files modified", |s| s.yellow()),
+            range,
+            excluded_by_time
+        );
+    }
+    if let Some(partial) = &partial {
+        log::warn!(
+            "{}",
+            paint(
+                format!(
+                    "Pilot run: only the first {} files in path order, {} files are left as they are",
+                    tasks.len(),
+                    partial.files_skipped
+                ),
+                |s| s.yellow().bold()
+            )
+        );
+    }
+    let seed = cli.seed.unwrap_or_else(|| thread_rng().gen());
+    log::info!("{} {}", paint("Seed:", |s| s.yellow()), seed);
+    log::info!(
+        "{} {} ({})",
+        paint("Format:", |s| s.yellow()),
+        profile.name(),
+        profile_reason
+    );
+    if cli.clone_files {
+        log::info!(
+            "{}",
+            paint(
+                "Clone mode: each file is copied to the new name and only the copy is remapped, \
+                the originals are kept",
+                |s| s.yellow().bold()
+            )
+        );
+    }
+    log::info!(
+        "{} {} {} {}",
+        paint("We will modify", |s| s.red()),
+        tasks.len(),
+        paint("files in the world at", |s| s.red()),
+        path.display()
+    );
+    log::info!(
+        "{}",
+        paint(
+            "Make sure to backup your world before running this program",
+            |s| s.red()
+        )
+    );
+    if let Some(staging) = options.guard.staging() {
+        let files = tasks
+            .iter()
+            .map(|task| path.join(&task.path))
+            .collect::<Vec<_>>();
+        if let Err(err) = staging.check_space(&files) {
+            log::error!("{:#}", err);
+            return Outcome::Failed;
+        }
+    }
+    let direction = mapping::Direction::of(&mapping, &mapping::playerdata_uuids(&path));
+    if let Some(warning) = direction.warning() {
+        log::warn!("{}", paint(&warning, |s| s.red().bold()));
+    }
+    if !resolved.unresolved.is_empty() {
+        log::warn!(
+            "{} {}",
+            paint(
+                format!(
+                    "{} names are not in the mapping, their players are left as they are:",
+                    resolved.unresolved.len()
+                ),
+                |s| s.red().bold()
+            ),
+            paint(resolved.unresolved.join(", "), |s| s.red())
+        );
+    }
+    let unresolved = !resolved.unresolved.is_empty() && !cli.ignore_unresolved;
+    set_phase(Phase::Confirming);
+    // Nothing is modified with --no or --preview
+    let checks = if cli.no || cli.preview || cli.world_is_offline {
+        vec![]
+    } else {
+        live::run_checks(&path, &tasks, live::SAMPLE)
+    };
+    let live = checks.iter().any(|check| check.tripped);
+    if !checks.is_empty() {
+        log::info!("{}", paint("Running server checks:", |s| s.yellow()));
+        for check in &checks {
+            let result = if check.tripped {
+                paint("TRIPPED", |s| s.red().bold())
+            } else {
+                paint("ok", |s| s.green())
+            };
+            log::info!("   {} {}: {}", result, check.name, check.detail);
+        }
+    }
+    if live {
+        log::warn!(
+            "{}",
+            paint(
+                "The world may be open in a running server, remapping it would corrupt the world",
+                |s| s.red().bold()
+            )
+        );
+        log::info!(
+            "{}",
+            paint(
+                format!(
+                    "Stop the server first, then type {} to proceed, or pass --world-is-offline",
+                    live::PHRASE
+                ),
+                |s| s.green().bold()
+            )
+        );
+    } else if unresolved {
+        log::info!(
+            "{}",
+            paint(
+                "Proceed without the players not in the mapping? [yes/NO]",
+                |s| s.green().bold()
+            )
+        );
+    } else {
+        log::info!(
+            "{}",
+            paint("Is this correct? [YES/NO/Y/N]", |s| s.green().bold())
+        );
+    }
+    if cli.no {
+        log::info!("{}", paint("Nothing to do!", |s| s.red()));
+        *summary = Summary {
+            files_scanned: tasks.len(),
+            files_skipped: partial.map_or(0, |partial| partial.files_skipped),
+            files_frozen,
+            ..Summary::default()
+        };
+        println!("{}", result_line(tasks.len(), 0, 0, 0));
+        return Outcome::Done;
+    } else if cli.preview {
+        log::info!(
+            "{}",
+            paint("Previewing, the world is not modified", |s| s.green())
+        );
+    } else if live && cli.yes {
+        log::error!("--yes is not enough when the world may be open in a running server");
+        log::error!("Pass --world-is-offline if the server is stopped");
+        return Outcome::Cancelled;
+    } else if unresolved && cli.yes {
+        log::error!("--yes is not enough when names are not in the mapping");
+        log::error!("Pass --ignore-unresolved to leave their players as they are");
+        return Outcome::Cancelled;
+    } else if cli.yes {
+        log::info!("{}", paint("YES", |s| s.green()));
+    } else if !std::io::stdin().is_terminal() {
+        log::error!("The standard input is not a terminal, unable to ask for confirmation");
+        log::error!("Pass --yes to proceed without confirmation");
+        return Outcome::Cancelled;
+    } else if live {
+        if !confirm_phrase(&mut std::io::stdin().lock(), live::PHRASE) {
+            log::error!("Cancelled by user");
+            return Outcome::Cancelled;
+        }
+    } else if !confirm(&mut std::io::stdin().lock(), unresolved) {
+        log::error!("Cancelled by user");
+        return Outcome::Cancelled;
+    }
+
+    let (mapping, derived) = match dynamic {
+        Some(d) => (
+            HashMap::new(),
+            Some(DerivedMapping::new(mapping, d, cli.derive_only).with_frozen(frozen.clone())),
+        ),
+        None => (mapping, None),
+    };
+    let engine = match RemapEngine::new(mapping) {
+        Ok(engine) => Arc::new(engine),
+        Err(err) => {
+            log::error!("Failed to build the prefilter: {:#}", err);
+            return Outcome::Failed;
+        }
+    };
+    log::debug!(
+        "The prefilter is built in {:.1}s",
+        engine.build_time().as_secs_f64()
+    );
+    let lookup: &dyn Mapping = match &derived {
+        Some(derived) => derived,
+        None => engine.as_ref(),
+    };
+    // Derived uuids and the forms of `--text-pattern` are not known to the prefilter
+    if derived.is_none() && options.text_patterns.is_empty() && !cli.no_prefilter {
+        options.prefilter = Some(engine.clone());
+    }
+
+    let remap_start = Instant::now();
+    // level.dat first, the rest of the world is likely inconsistent if it can't be remapped
+    let (level_tasks, mut tasks): (Vec<_>, Vec<_>) =
+        tasks.into_iter().partition(|t| task::is_level_dat(&t.path));
+    let mut total = WorkerResult::default();
+    let mut level_replacements = vec![];
+    for level_task in &level_tasks {
+        let before = total.replacements;
+        if let Err(err) = task::remap_task(
+            &path,
+            level_task,
+            lookup,
+            &registry,
+            &options,
+            &Progress::hidden(),
+            &mut total,
+        ) {
+            log::error!("Failed to remap {}: {:#?}", level_task.path.display(), err);
+            log::error!("Aborted, no other file is modified");
+            return Outcome::Failed;
+        }
+        level_replacements.push((&level_task.path, total.replacements - before));
+    }
+
+    task::shuffle_tasks(
+        &mut tasks,
+        &mut StdRng::seed_from_u64(seed),
+        cli.deterministic_split,
+    );
+    let total_bar = style::progress_bar(tasks.len() as u64, "total:     ", "{pos}/{len} {msg} ");
+    let totals = Arc::new(Totals::new(total_bar));
+    if let Some(status) = status {
+        status.attach(totals.clone(), tasks.len());
+    }
+    // The bars are drawn on the standard error, nothing is drawn there if it is not a terminal,
+    // or a dumb one
+    let reporter = (!style::bars_drawn()).then(|| {
+        MULTI.set_draw_target(indicatif::ProgressDrawTarget::hidden());
+        reporter::Reporter::start(
+            totals.clone(),
+            tasks.len(),
+            Duration::from_secs(cli.progress_interval),
+        )
+    });
+    let worker_bar = |i: usize| {
+        style::progress_bar(
+            tasks.len() as u64,
+            &format!("worker-{:02}: ", i),
+            "{pos}/{len} {msg} ",
+        )
+    };
+    if let Some(io_threads) = cli.threads_io {
+        let stages = pipeline::Stages {
+            io_threads,
+            cpu_bars: (0..cli.threads).map(worker_bar).collect(),
+        };
+        total.merge(pipeline::run_pipeline(
+            &path,
+            &tasks,
+            stages,
+            totals.clone(),
+            lookup,
+            &registry,
+            &options,
+        ));
+    } else {
+        let mut handles = vec![];
+        for (i, thread_task) in split_tasks(&tasks, cli.threads).iter().enumerate() {
+            handles.push(task::run_tasks(
+                path.clone(),
+                unsafe { std::mem::transmute(*thread_task) },
+                worker_bar(i),
+                totals.clone(),
+                unsafe { std::mem::transmute(lookup) },
+                unsafe { std::mem::transmute(&registry) },
+                unsafe { std::mem::transmute(&options) },
+            ));
+        }
+        for handle in handles {
+            total.merge(handle.join().unwrap());
+        }
+    }
+    drop(reporter);
+    // Every content is remapped, so references to the old names, e.g. in index files, are
+    // consistent with the new names whatever the order of the files
+    let mut rename_errors = 0;
+    let rename_phase = if options.defer_renames {
+        RenamePhase::Renames
+    } else {
+        RenamePhase::Contents
+    };
+    if options.defer_renames && !total.renamed.is_empty() {
+        set_phase(Phase::Renaming);
+        total.renamed.sort();
+        log::info!("Renaming {} files", total.renamed.len());
+        let results = remap::rename_files(
+            &path,
+            &total.renamed,
+            &options.guard,
+            options.conflicts.as_ref(),
+            options.duplicate_targets,
+        );
+        let mut results = results.into_iter();
+        total.renamed.retain(|(from, to)| match results.next() {
+            Some(Ok(false)) => false,
+            Some(Err(err)) => {
+                log::error!(
+                    "Failed to rename {} to {}: {:#}",
+                    from.display(),
+                    to.display(),
+                    err
+                );
+                rename_errors += 1;
+                false
+            }
+            _ => true,
+        });
+    }
+    set_phase(Phase::Finishing);
+    let files_scanned = level_tasks.len() + tasks.len();
+    let WorkerResult {
+        replacements: stat,
+        by_source,
+        files_modified,
+        stats,
+        renamed,
+        cloned,
+        mut regions,
+        dimensions,
+        cpu_busy,
+        io_busy,
+        failed,
+    } = total;
+    regions.sort_by(|a, b| a.path.cmp(&b.path));
+    let anomalies = regions
+        .iter()
+        .filter(|region| region.anomaly)
+        .collect::<Vec<_>>();
+    let files_failed = dimensions.values().map(|d| d.errors).sum::<usize>();
+    let errors = files_failed + stats.chunk_errors + rename_errors;
+    *summary = Summary {
+        files_scanned,
+        files_modified,
+        files_skipped: partial.map_or(0, |partial| partial.files_skipped),
+        files_frozen,
+        files_failed,
+        replacements: stat,
+        replacements_by_source: by_source,
+        renames: renamed.len(),
+        rename_conflicts: rename_errors,
+        region_anomalies: anomalies.len(),
+        errors,
+        prefilter_build_secs: engine.build_time().as_secs_f64(),
+        elapsed_secs: remap_start.elapsed().as_secs_f64(),
+    };
+    // The report and the result line are still written when the staged modifications fail
+    let mut outcome = Outcome::Done;
+    if let Some(staging) = options.guard.staging() {
+        if errors > 0 {
+            log::error!(
+                "{} errors, the staged modifications are discarded, the world is not modified",
+                errors
+            );
+            outcome = Outcome::Failed;
+        } else {
+            log::info!("Moving {} staged modifications in place", staging.steps());
+            if let Err(err) = staging.commit() {
+                log::error!("{:#}", err);
+                outcome = Outcome::Failed;
+            }
+        }
+    }
+    // The failed files are left to the next run, a renamed file is found by its new name
+    if outcome == Outcome::Done && !cli.preview {
+        let failed = failed.iter().collect::<HashSet<_>>();
+        let done = level_tasks
+            .iter()
+            .chain(&tasks)
+            .map(|task| &task.path)
+            .filter(|file| !failed.contains(file))
+            .map(|file| {
+                renamed
+                    .iter()
+                    .find(|(from, _)| from == file)
+                    .map_or(file, |(_, to)| to)
+            });
+        if let Err(err) = journal::record_pilot(&path, partial.is_some(), cli.resume, done) {
+            log::error!("Failed to record the pilot run: {:#}", err);
+        }
+    }
+    if cli.clone_files {
+        log::info!(
+            "{}",
+            paint(
+                format!(
+                    "Clone mode: {} files are copied, the originals are not modified:",
+                    cloned.len()
+                ),
+                |s| s.yellow().bold()
+            )
+        );
+        for (from, to) in &cloned {
+            log::info!("   {} -> {}", from.display(), to.display());
+        }
+    }
+    if !anomalies.is_empty() {
+        log::warn!(
+            "{}",
+            paint(
+                format!(
+                    "{} region files grow more than {}x when rewritten, check them in game:",
+                    anomalies.len(),
+                    cli.anomaly_factor
+                ),
+                |s| s.yellow().bold()
+            )
+        );
+        for region in &anomalies {
+            log::warn!(
+                "   {}: {} -> {} bytes ({:.2}x)",
+                region.path.display(),
+                region.input_bytes,
+                region.output_bytes,
+                region.ratio
+            );
+        }
+    }
+    for (from, to) in &renamed {
+        for (alias, _) in scan.aliases.iter().filter(|(_, original)| original == from) {
+            log::warn!(
+                "{} is renamed to {}, but its hardlink {} still has the old name",
+                from.display(),
+                to.display(),
+                alias.display()
+            );
+        }
+    }
+    let mut missing_chunks = vec![];
+    if let Some(filter) = &options.chunk_filter {
+        let found = stats
+            .selected_chunks
+            .iter()
+            .copied()
+            .collect::<HashSet<_>>();
+        missing_chunks = filter.chunks().filter(|c| !found.contains(c)).collect();
+        log::info!("{} selected chunks are found", found.len());
+        for (x, z) in &missing_chunks {
+            log::warn!("Selected chunk ({}, {}) is not found", x, z);
+        }
+    }
+    if stats.duplicate_chunks > 0 {
+        log::warn!(
+            "{} chunks sharing sectors with a newer chunk are discarded",
+            stats.duplicate_chunks
+        );
+    }
+    if stats.oversized_chunks > 0 {
+        log::warn!(
+            "{} chunks are larger than {} MiB uncompressed, they are oversized, skipped and \
+            copied as is",
+            stats.oversized_chunks,
+            cli.max_chunk_size
+        );
+    }
+    if stats.non_compound_roots > 0 {
+        log::warn!(
+            "{} chunks have a non-compound NBT root, they are copied as is",
+            stats.non_compound_roots
+        );
+    }
+    // The busy time over the thread time, to see which stage needs more threads
+    match cli.threads_io {
+        Some(io_threads) => log::info!(
+            "Busy time: remapping {:.1?} over {} threads, reading and writing {:.1?} over {} threads",
+            cpu_busy,
+            cli.threads,
+            io_busy,
+            io_threads
+        ),
+        None => log::info!("Busy time: {:.1?} over {} threads", cpu_busy, cli.threads),
+    }
+    let retried = options.guard.retry().retried();
+    if !retried.is_empty() {
+        log::warn!(
+            "{} files needed retries after transient filesystem errors",
+            retried.len()
+        );
+    }
+    log::info!(
+        "{}",
+        paint(
+            format!(
+                "{:<24} {:>8} {:>14} {:>8}",
+                "Dimension", "Files", "Replacements", "Errors"
+            ),
+            |s| s.yellow()
+        )
+    );
+    for (dimension, dimension_stats) in &dimensions {
+        log::info!(
+            "{:<24} {:>8} {:>14} {:>8}",
+            dimension,
+            dimension_stats.files,
+            dimension_stats.replacements,
+            dimension_stats.errors
+        );
+    }
+    for (level_path, replacements) in &level_replacements {
+        log::info!(
+            "{} {} replacements",
+            paint(format!("{}:", level_path.display()), |s| s.yellow()),
+            replacements
+        );
+    }
+    if let Some(partial) = &partial {
+        log::warn!(
+            "{}",
+            paint(
+                format!(
+                    "Partial run with --limit {}, {} files are not remapped, run again with \
+                    --resume to remap them, the remapped files have no old uuid left",
+                    partial.limit, partial.files_skipped
+                ),
+                |s| s.yellow().bold()
+            )
+        );
+    }
+    log::info!(
+        "{} {} {}",
+        paint("Done!", |s| s.green().bold()),
+        stat,
+        paint("uuid fields are modified", |s| s.green().bold())
+    );
+    for line in summary.table() {
+        log::info!("{}", line);
+    }
+    if !frozen.is_empty() {
+        log::info!(
+            "{} {}",
+            paint("Frozen players:", |s| s.yellow()),
+            frozen
+                .iter()
+                .map(Uuid::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+    let (derived, derived_truncated) = derived.map(|d| d.applied()).unwrap_or_default();
+    if cli.derive.is_some() {
+        log::info!("{} uuids are derived", derived.len());
+        if derived_truncated {
+            log::warn!("Too many derived uuids, only some of them are recorded in the report");
+        }
+    }
+
+    if let Some(report_path) = &cli.report {
+        let report = Report {
+            seed,
+            replacements: stat,
+            summary: *summary,
+            renamed_files: renamed
+                .iter()
+                .map(|(from, to)| RenamedFile {
+                    from: from.clone(),
+                    to: to.clone(),
+                    phase: rename_phase,
+                })
+                .collect(),
+            cloned_files: cloned
+                .iter()
+                .map(|(from, to)| ClonedFile {
+                    from: from.clone(),
+                    to: to.clone(),
+                })
+                .collect(),
+            regions,
+            skipped_aliases: scan
+                .aliases
+                .into_iter()
+                .map(|(alias, original)| Alias { alias, original })
+                .collect(),
+            selected_chunks_found: stats.selected_chunks,
+            selected_chunks_missing: missing_chunks,
+            non_compound_roots: stats.non_compound_roots,
+            oversized_chunks: stats.oversized_chunks,
+            duplicate_chunks: stats.duplicate_chunks,
+            dimensions,
+            derived: derived.into_iter().collect(),
+            derived_truncated,
+            retried_files: retried,
+            cpu_busy_secs: cpu_busy.as_secs_f64(),
+            io_busy_secs: cli.threads_io.map(|_| io_busy.as_secs_f64()),
+            partial,
+            files_excluded_by_time: excluded_by_time,
+            frozen_players: frozen,
+            conflict_decisions: options
+                .conflicts
+                .as_ref()
+                .map(Prompter::decisions)
+                .unwrap_or_default(),
+        };
+        if let Err(err) = report.save(report_path) {
+            log::error!("Failed to write report: {:#?}", err);
+        }
+    }
+    MULTI.suspend(|| {
+        println!(
+            "{}",
+            result_line(
+                summary.files_scanned,
+                summary.files_modified,
+                summary.replacements,
+                summary.errors
+            )
+        )
+    });
+    outcome
+}
+
+fn find(args: FindArgs) -> Outcome {
+    match find_targets(&args.path, &args.targets, None) {
+        Ok(found) if !found.complete => Outcome::Cancelled,
+        Ok(_) => Outcome::Done,
+        Err(outcome) => outcome,
+    }
+}
+
+/// A find with its own journal, failed by any finding, also those of the interrupted run
+fn verify(args: VerifyArgs) -> Outcome {
+    let found = match find_targets(&args.path, &args.targets, Some(&args)) {
+        Ok(found) => found,
+        Err(outcome) => return outcome,
+    };
+    if !found.findings.is_empty() {
+        log::error!("{} files still reference the uuids", found.findings.len());
+        return Outcome::Failed;
+    }
+    if !found.complete {
+        return Outcome::Cancelled;
+    }
+    log::info!("No file references the uuids");
+    Outcome::Done
+}
+
+/// Resolve the uuids and find them, journaled when verifying
+fn find_targets(
+    path: &Path,
+    args: &TargetArgs,
+    verify: Option<&VerifyArgs>,
+) -> Result<find::Found, Outcome> {
+    let mut targets = args.uuids.iter().copied().collect::<HashSet<_>>();
+    let mut names = HashMap::new();
+    if !args.names.is_empty() {
+        if !args.offline && !args.online {
+            log::error!("Specify --offline or --online to resolve the names");
+            return Err(Outcome::Failed);
+        }
+        let scheme = OfflineScheme {
+            prefix: args.offline_prefix.clone(),
+            lowercase: args.offline_lowercase,
+        };
+        let resolved = match mapping::resolve_names(
+            &args.names,
+            args.online,
+            &scheme,
+            &args.online_lookup.lookup(),
+        ) {
+            Ok(resolved) => resolved,
+            Err(err) => {
+                log::error!("{:#}", err);
+                return Err(Outcome::Failed);
+            }
+        };
+        for (name, uuid) in resolved {
+            log::info!("{} -> {}", name, uuid);
+            targets.insert(uuid);
+            names.insert(uuid, name);
+        }
+        for name in &args.names {
+            if !names.values().any(|n| n == name) {
+                log::warn!("Unable to resolve the uuid of {}", name);
+            }
+        }
+    }
+    if targets.is_empty() {
+        log::error!("Nothing to find");
+        return Err(Outcome::Failed);
+    }
+    let world = match task::normalize_world(path) {
+        Ok(world) => world,
+        Err(err) => {
+            log::error!("{:#}", err);
+            return Err(Outcome::Failed);
+        }
+    };
+    let guard = WriteGuard::dry_run();
+    let on_found = |file: &Path, found: &find::Matches| {
+        let mut lines = vec![paint(file.display(), |s| s.yellow())];
+        for (uuid, matches) in found {
+            lines.push(match names.get(uuid) {
+                Some(name) => format!("   {} ({}): {}", uuid, name, matches.len()),
+                None => format!("   {}: {}", uuid, matches.len()),
+            });
+            for found in matches {
+                lines.push(format!("      {}", found));
+            }
+        }
+        log::info!("{}", lines.join("\n"));
+    };
+    let journal = match verify.map(|_| find::journal_for(&world, &targets)) {
+        Some(Ok(journal)) => Some(journal),
+        Some(Err(err)) => {
+            log::error!("{:#}", err);
+            return Err(Outcome::Failed);
+        }
+        None => None,
+    };
+    let options = find::FindOptions {
+        journal: journal.as_deref(),
+        findings_file: verify.and_then(|verify| verify.findings.as_deref()),
+        resume: verify.is_some_and(|verify| verify.resume_verify),
+        cancel: Some(find::cancel_on_interrupt()),
+        on_found: Some(&on_found),
+    };
+    let found = match find::find_uuids(&world, &targets, args.threads, &guard, &options) {
+        Ok(found) => found,
+        Err(err) => {
+            log::error!("Failed to scan world: {:#}", err);
+            return Err(Outcome::Failed);
+        }
+    };
+    let findings = &found.findings;
+    let mut total = HashMap::<Uuid, usize>::new();
+    for found in findings.values() {
+        for (uuid, count) in found {
+            *total.entry(*uuid).or_insert(0) += count;
+        }
+    }
+    if found.resumed > 0 {
+        log::info!(
+            "{} files are checked by the interrupted run, their findings are included",
+            found.resumed
+        );
+    }
+    log::info!("{}", paint("Total:", |s| s.yellow()));
+    for uuid in &targets {
+        let count = total.get(uuid).copied().unwrap_or(0);
+        let files = findings.values().filter(|f| f.contains_key(uuid)).count();
+        match names.get(uuid) {
+            Some(name) => log::info!(
+                "   {} ({}): {} occurrences in {} files",
+                uuid,
+                name,
+                count,
+                files
+            ),
+            None => log::info!("   {}: {} occurrences in {} files", uuid, count, files),
+        }
+    }
+    Ok(found)
+}
+
+fn scan(args: ScanArgs) -> Outcome {
+    let world = match task::normalize_world(&args.path) {
+        Ok(world) => world,
+        Err(err) => {
+            log::error!("{:#}", err);
+            return Outcome::Failed;
+        }
+    };
+    if args.throughput.is_nan() || args.throughput <= 0.0 {
+        log::error!("--throughput must be positive");
+        return Outcome::Failed;
+    }
+    let overrides = match handler::collect_overrides(&args.handlers) {
+        Ok(overrides) => overrides,
+        Err(err) => {
+            log::error!("{}", err);
+            return Outcome::Failed;
+        }
+    };
+    let mut registry = Registry::builtin();
+    for (ext, kind) in &overrides {
+        registry.set_override(ext, *kind);
+    }
+    let (profile, profile_reason) = args.format_version.resolve(&world);
+    let options = RemapOptions {
+        chunk_filter: (!args.chunks.is_empty() || args.area.is_some()).then(|| {
+            let filter = ChunkFilter::new(args.chunks.clone());
+            match args.area {
+                Some(area) => filter.with_area(area),
+                None => filter,
+            }
+        }),
+        guard: WriteGuard::dry_run(),
+        profile,
+        no_sniff: args.no_sniff,
+        ..Default::default()
+    };
+    let tasks = match task::scan_world(&world, &mut registry, &options) {
+        Ok(scan) => scan.tasks,
+        Err(err) => {
+            log::error!("Failed to scan world: {:#?}", err);
+            return Outcome::Failed;
+        }
+    };
+    log::info!(
+        "{} {} ({})",
+        paint("Format:", |s| s.yellow()),
+        profile.name(),
+        profile_reason
+    );
+    let mib = |bytes: u64| bytes as f64 / 1024.0 / 1024.0;
+    let breakdown = scan::breakdown(&world, &tasks, &registry, &options);
+    for ((dir, handler), count) in &breakdown {
+        log::info!(
+            "   {:<24} {:<8} {:>8} files {:>10.1} MiB",
+            dir,
+            handler,
+            count.files,
+            mib(count.bytes)
+        );
+    }
+    let bytes = breakdown.values().map(|count| count.bytes).sum::<u64>();
+    log::info!(
+        "{} {} files, {:.1} MiB, about {} at {} MiB/s",
+        paint("Total:", |s| s.yellow()),
+        tasks.len(),
+        mib(bytes),
+        reporter::human_duration(scan::estimate(bytes, args.throughput)),
+        args.throughput
+    );
+    Outcome::Done
+}
+
+fn bench(args: BenchArgs) -> Outcome {
+    if !(0.0..=1.0).contains(&args.density) {
+        log::error!("--density must be between 0 and 1");
+        return Outcome::Failed;
+    }
+    let params = bench::Params {
+        regions: args.regions,
+        chunks: args.chunks,
+        entities: args.entities,
+        players: args.players,
+        text_files: args.text_files,
+        text_lines: args.text_lines,
+        density: args.density,
+        seed: args.seed,
+    };
+    let world = std::env::temp_dir().join(format!("uuid-remapper-bench-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&world);
+    let measured = bench::generate(&world, &params).and_then(|mapping| {
+        log::info!(
+            "{} {} ({:?})",
+            paint("Generated:", |s| s.yellow()),
+            world.display(),
+            params
+        );
+        bench::measure(&world, &mapping, args.threads)
+    });
+    if args.keep {
+        log::info!("The world is kept in {}", world.display());
+    } else if let Err(err) = std::fs::remove_dir_all(&world) {
+        log::warn!("Failed to remove {}: {}", world.display(), err);
+    }
+    let measurement = match measured {
+        Ok(measurement) => measurement,
+        Err(err) => {
+            log::error!("Benchmark failed: {:#}", err);
+            return Outcome::Failed;
+        }
+    };
+    let mib = |bytes: u64| bytes as f64 / 1024.0 / 1024.0;
+    for kind in &measurement.kinds {
+        log::info!(
+            "   {:<8} {:>8} files {:>10.1} MiB {:>8.2}s {:>10.1} MiB/s {:>10} replacements",
+            kind.handler,
+            kind.files,
+            mib(kind.bytes),
+            kind.secs,
+            kind.mib_per_sec,
+            kind.replacements
+        );
+    }
+    log::info!(
+        "{} {} files, {:.1} MiB in {:.2}s, {:.1} MiB/s, {:.0} replacements/s",
+        paint("Total:", |s| s.yellow()),
+        measurement.files,
+        mib(measurement.bytes),
+        measurement.secs,
+        measurement.mib_per_sec,
+        measurement.replacements_per_sec
+    );
+    if let Some(rss) = measurement.peak_rss_bytes {
+        log::info!("Peak RSS: {:.1} MiB", mib(rss));
+    }
+    if measurement.errors > 0 {
+        log::error!("{} files failed to be remapped", measurement.errors);
+        return Outcome::Failed;
+    }
+    match serde_json::to_string(&measurement) {
+        Ok(json) => println!("{}", json),
+        Err(err) => log::error!("Failed to serialize the measurement: {}", err),
+    }
+    Outcome::Done
+}
+
+fn profiles_tool(args: ProfilesArgs) -> Outcome {
+    let config = match profiles::Config::load(args.config.as_deref()) {
+        Ok(config) => config,
+        Err(err) => {
+            log::error!("{:#}", err);
+            return Outcome::Failed;
+        }
+    };
+    match args.command {
+        ProfilesCommand::List => {
+            if config.profiles.is_empty() {
+                log::info!("No profile is defined");
+            }
+            for (name, options) in &config.profiles {
+                log::info!("{} ({} options)", name, options.len());
+            }
+        }
+        ProfilesCommand::Show { name } => {
+            let options = match config.profile(&name) {
+                Ok(options) => options,
+                Err(err) => {
+                    log::error!("{:#}", err);
+                    return Outcome::Failed;
+                }
+            };
+            // Merged the same as a run with the profile, where nothing is given on the command line
+            let command = Cli::command().ignore_errors(true);
+            let args = vec![OsString::from("uuid-remapper")];
+            let matches = command.clone().get_matches_from(&args);
+            let merged =
+                profiles::apply(&command, args, &matches, options).and_then(|(args, applied)| {
+                    let (_, legacy) = with_legacy_behavior(&command, args)?;
+                    Ok((applied, legacy))
+                });
+            match merged {
+                Ok((applied, legacy)) => {
+                    for option in applied {
+                        log::info!("   {}", option);
+                    }
+                    if !legacy.is_empty() {
+                        log::info!("Legacy behavior: {}", legacy.join(" "));
+                    }
+                }
+                Err(err) => {
+                    log::error!("{:#}", err);
+                    return Outcome::Failed;
+                }
+            }
+        }
+    }
+    Outcome::Done
+}
+
+fn mapping_tool(command: MappingCommand) -> Outcome {
+    match command {
+        MappingCommand::Convert {
+            from,
+            to,
+            input,
+            output,
+        } => match mapping::convert_mapping(from, &input, to, &output) {
+            Ok(entries) => {
+                log::info!("{} entries written to {}", entries, output.display());
+                Outcome::Done
+            }
+            Err(err) => {
+                log::error!("{:#}", err);
+                Outcome::Failed
+            }
+        },
+        MappingCommand::Validate {
+            kind,
+            path,
+            offline_prefix,
+            offline_lowercase,
+            online_lookup,
+        } => {
+            let scheme = OfflineScheme {
+                prefix: offline_prefix,
+                lowercase: offline_lowercase,
+            };
+            let mapping =
+                match mapping::get_mapping(kind, &path, &scheme, &online_lookup.lookup(), &[]) {
+                    Ok(mapping) => mapping,
+                    Err(err) => {
+                        log::error!("Failed to load mapping: {:#}", err);
+                        return Outcome::Failed;
+                    }
+                };
+            log::info!("{} entries in {}", mapping.len(), path.display());
+            let chained = mapping::chained_entries(&mapping);
+            if !chained.is_empty() {
+                log::warn!("Some new uuids are mapped again:");
+                for (a, b, c) in &chained {
+                    log::warn!("   {} -> {} -> {}", a, b, c);
+                }
+                if let Err(err) = mapping::compose_chains(&mapping) {
+                    log::warn!("{:#}", err);
+                }
+            }
+            let colliding = mapping::colliding_targets(&mapping);
+            if !colliding.is_empty() {
+                log::warn!("Some old uuids are mapped to the same new uuid:");
+                for (to, from) in &colliding {
+                    let from = from.iter().map(Uuid::to_string).collect::<Vec<_>>();
+                    log::warn!("   {} -> {}", from.join(", "), to);
+                }
+            }
+            if chained.is_empty() && colliding.is_empty() {
+                log::info!("{}", paint("Valid", |s| s.green().bold()));
+                Outcome::Done
+            } else {
+                log::error!(
+                    "{}",
+                    paint(
+                        format!(
+                            "Invalid, {} chained and {} colliding entries",
+                            chained.len(),
+                            colliding.len()
+                        ),
+                        |s| s.red().bold()
+                    )
+                );
+                Outcome::Failed
+            }
+        }
+    }
+}
+
+/// Outcome is failed if any captured input still fails
+fn replay(args: ReplayArgs) -> Outcome {
+    let captures = match capture::load(&args.dir) {
+        Ok(captures) => captures,
         Err(err) => {
-            log::error!("Failed to load mapping: {:#?}", err);
-            return;
+            log::error!("{:#}", err);
+            return Outcome::Failed;
         }
     };
-    if mapping.is_empty() {
-        log::warn!("Empty mapping");
-        log::warn!("The program will do identity mapping, i.e. f(x) = x");
-        log::warn!("This is only used for testing the program on your world");
-    }
-    log::info!("{}", "Task Summary".bold().underline());
-    log::info!("{}", "Files:".yellow());
-    for task in &tasks {
-        log::info!("   {}", task.display());
-    }
-    log::info!("{}", "Mapping:".yellow());
-    for (k, v) in &mapping {
-        log::info!("   {} -> {}", k, v);
+    let mut failed = 0;
+    for (file, captured) in &captures {
+        let location = match captured.chunk {
+            Some((x, z)) => format!("chunk ({}, {}) in {}", x, z, captured.path.display()),
+            None => captured.path.display().to_string(),
+        };
+        log::info!(
+            "{} {} (captured by {})",
+            paint(file.display(), |s| s.yellow()),
+            location,
+            captured.version
+        );
+        log::info!("   captured: {}", captured.error);
+        match capture::replay(&args.dir, captured) {
+            Ok(()) => log::info!("   replayed: {}", paint("ok", |s| s.green())),
+            Err(err) => {
+                failed += 1;
+                log::info!("   replayed: {}", paint(format!("{:#}", err), |s| s.red()));
+            }
+        }
     }
     log::info!(
-        "{} {} {} {}",
-        "We will modify".red(),
-        tasks.len(),
-        "files in the world at".red(),
-        path.display()
-    );
-    log::info!(
-        "{}",
-        "Make sure to backup your world before running this program".red()
+        "{} of {} captured inputs still fail",
+        failed,
+        captures.len()
     );
-    log::info!("{}", "Is this correct? [YES/NO/Y/N]".green().bold());
-    if cli.no {
-        log::info!("{}", "Nothing to do!".red());
-        return;
-    } else if cli.yes {
-        log::info!("{}", "YES".green());
+    if failed > 0 {
+        Outcome::Failed
     } else {
-        let mut input = String::new();
-        std::io::stdin().read_line(&mut input).unwrap();
-        if input.trim().to_lowercase() != "yes" && input.trim().to_lowercase() != "y" {
-            log::error!("Cancelled by user");
-            return;
-        }
-    }
-
-    tasks.shuffle(&mut thread_rng());
-    let mut handles = vec![];
-    for (i, thread_task) in split_tasks(&tasks, cli.threads).iter().enumerate() {
-        let pg = MULTI.add(indicatif::ProgressBar::new(tasks.len() as u64));
-        let template = format!("worker-{:02}: ", i) + "[{bar:60.cyan/blue}] {pos}/{len} {msg} ";
-        pg.set_style(
-            indicatif::ProgressStyle::default_bar()
-                .template(&template)
-                .unwrap()
-                .progress_chars("#>-"),
-        );
-        handles.push(task::run_tasks(
-            path.clone(),
-            unsafe { std::mem::transmute(*thread_task) },
-            pg,
-            unsafe { std::mem::transmute(&mapping) },
-        ));
-    }
-
-    let mut stat = 0;
-    for handle in handles {
-        stat += handle.join().unwrap();
+        Outcome::Done
     }
-    log::info!(
-        "{} {} {}",
-        "Done!".green().bold(),
-        stat,
-        "uuid fields are modified".green().bold()
-    );
 }
 
-fn main() {
-    let logger =
-        env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).build();
-    LogWrapper::new((*MULTI).clone(), logger)
+fn init_logger(no_color: bool) {
+    let mut builder =
+        env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"));
+    if !style::init(no_color) {
+        builder.write_style(env_logger::WriteStyle::Never);
+    }
+    LogWrapper::new((*MULTI).clone(), builder.build())
         .try_init()
         .unwrap();
+}
+
+/// The machine-parsable summary of a run, printed regardless of the log level
+fn result_line(
+    files_scanned: usize,
+    files_modified: usize,
+    replacements: usize,
+    errors: usize,
+) -> String {
+    format!(
+        "RESULT files_scanned={} files_modified={} replacements={} errors={}",
+        files_scanned, files_modified, replacements, errors
+    )
+}
+
+fn main() -> ExitCode {
+    let is_subcommand = std::env::args_os()
+        .nth(1)
+        .is_some_and(|arg| Tool::command().find_subcommand(arg).is_some());
+    if is_subcommand {
+        match Tool::parse() {
+            Tool::Find(args) => {
+                init_logger(args.no_color);
+                find(args).into()
+            }
+            Tool::Verify(args) => {
+                init_logger(args.no_color);
+                verify(args).into()
+            }
+            Tool::Scan(args) => {
+                init_logger(args.no_color);
+                scan(args).into()
+            }
+            Tool::Replay(args) => {
+                init_logger(args.no_color);
+                replay(args).into()
+            }
+            Tool::Bench(args) => {
+                init_logger(args.no_color);
+                bench(args).into()
+            }
+            Tool::Mapping(args) => {
+                init_logger(args.no_color);
+                mapping_tool(args.command).into()
+            }
+            Tool::Profiles(args) => {
+                init_logger(args.no_color);
+                profiles_tool(args).into()
+            }
+        }
+    } else {
+        let mut args = std::env::args_os().collect::<Vec<_>>();
+        let matches = Cli::command().get_matches_from(&args);
+        let mut profile_options = vec![];
+        if let Some(name) = matches.get_one::<String>("profile") {
+            let config = matches.get_one::<PathBuf>("config").map(PathBuf::as_path);
+            let merged = profiles::Config::load(config).and_then(|config| {
+                profiles::apply(
+                    &Cli::command(),
+                    args.clone(),
+                    &matches,
+                    config.profile(name)?,
+                )
+            });
+            match merged {
+                Ok((merged, applied)) => (args, profile_options) = (merged, applied),
+                Err(err) => Cli::command()
+                    .error(clap::error::ErrorKind::InvalidValue, format!("{:#}", err))
+                    .exit(),
+            }
+        }
+        let (args, legacy_options) = match with_legacy_behavior(&Cli::command(), args) {
+            Ok(merged) => merged,
+            Err(err) => Cli::command()
+                .error(clap::error::ErrorKind::InvalidValue, format!("{:#}", err))
+                .exit(),
+        };
+        let mut cli = Cli::parse_from(args);
+        cli.profile_options = profile_options;
+        if let Err(err) = handler::collect_overrides(&cli.handlers) {
+            Cli::command()
+                .error(clap::error::ErrorKind::ArgumentConflict, err)
+                .exit();
+        }
+        init_logger(cli.no_color);
+        if !legacy_options.is_empty() {
+            log::warn!("Legacy behavior: {}", legacy_options.join(" "));
+        }
+        let exit_zero = cli.exit_zero;
+        match start(cli) {
+            _ if exit_zero => ExitCode::SUCCESS,
+            outcome => outcome.into(),
+        }
+    }
+}
 
-    let cli = Cli::parse();
-    start(cli);
+/// Append the preset of `--legacy-behavior` to the arguments if it is given,
+/// with the options taken from it
+fn with_legacy_behavior(
+    command: &clap::Command,
+    args: Vec<OsString>,
+) -> anyhow::Result<(Vec<OsString>, Vec<String>)> {
+    let matches = command.clone().get_matches_from(&args);
+    if !matches.get_flag("legacy_behavior") {
+        return Ok((args, vec![]));
+    }
+    let preset = profiles::legacy_behavior();
+    profiles::apply(command, args, &matches, &preset)
 }
 
 #[cfg(test)]
@@ -160,9 +2408,240 @@ fn setup_test_logger() {
     });
 }
 
+/// A run on the world with the defaults of the command line, confirmed and on one thread,
+/// without looking up or caching names online and without the checks of the environment
+#[cfg(test)]
+fn test_cli(path: &Path) -> Cli {
+    use clap::FromArgMatches;
+
+    // The mapping is left out, the tests give it as fields
+    let matches = Cli::command()
+        .ignore_errors(true)
+        .get_matches_from([Path::new("uuid-remapper"), path]);
+    Cli {
+        threads: 1,
+        yes: true,
+        online_lookup: OnlineArgs::uncached(),
+        world_is_offline: true,
+        allow_root: true,
+        ..Cli::from_arg_matches(&matches).unwrap()
+    }
+}
+
 #[cfg(test)]
 #[test]
 fn test() {
+    use fixture::{snapshot, Contents, Fixture, OTHER};
+
+    setup_test_logger();
+
+    let dir = std::env::temp_dir().join("test_fixture");
+    let _ = std::fs::remove_dir_all(&dir);
+    let (server, expected) = (dir.join("server"), dir.join("expected"));
+    let from = Uuid::from_u128(0x2d318504_1a7b_39dc_8c18_44df798a5c06);
+    let to = Uuid::from_u128(0xfb1ad51e_cf1f_41f7_8fd1_10dff164b17d);
+    // The modifier uuid collides with the player, it must be kept anyway
+    Fixture {
+        player: from,
+        modifier: from,
+    }
+    .build(&server);
+    Fixture {
+        player: to,
+        modifier: from,
+    }
+    .build(&expected);
+    let original = snapshot(&server);
+    std::fs::write(
+        dir.join("forward.json"),
+        format!("{{\"{}\": \"{}\"}}", from, to),
+    )
+    .unwrap();
+    std::fs::write(
+        dir.join("back.json"),
+        format!("{{\"{}\": \"{}\"}}", to, from),
+    )
+    .unwrap();
+    let cli = |mapping_file: PathBuf| Cli {
+        mapping_kind: Some(MappingKind::Json),
+        mapping_file: Some(mapping_file),
+        threads: 2,
+        ..test_cli(&server)
+    };
+
+    let progress_file = dir.join("status.json");
+    let metrics_textfile = dir.join("uuid-remapper.prom");
+    let report = dir.join("report.json");
+    let forward = Cli {
+        progress_file: Some(progress_file.clone()),
+        metrics_textfile: Some(metrics_textfile.clone()),
+        report: Some(report.clone()),
+        ..cli(dir.join("forward.json"))
+    };
+    assert_eq!(start(forward), Outcome::Done);
+    let status: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(&progress_file).unwrap()).unwrap();
+    assert_eq!(status["phase"], "done");
+    // level.dat is remapped before the bars are created, it is not counted
+    assert_eq!(status["files_done"], status["files_total"]);
+    assert!(status["files_total"].as_u64().unwrap() > 5);
+    assert!(status["replacements"].as_u64().unwrap() > 0);
+    assert_eq!(status["errors"], 0);
+    assert_eq!(status["workers"], serde_json::json!([null, null]));
+    let metrics = std::fs::read_to_string(&metrics_textfile).unwrap();
+    assert!(metrics
+        .lines()
+        .any(|line| line == "uuid_remapper_finished 1"));
+    assert!(metrics
+        .lines()
+        .any(|line| line == format!("uuid_remapper_files_done {}", status["files_done"])));
+    let report: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(&report).unwrap()).unwrap();
+    let renamed = report["renamed_files"].as_array().unwrap();
+    assert!(renamed.len() >= 4);
+    assert!(renamed.iter().all(|file| file["phase"] == "renames"));
+    assert_eq!(report["summary"]["errors"], 0);
+    // Every replacement is attributed to a source
+    let sources = report["summary"]["replacements_by_source"]
+        .as_object()
+        .unwrap();
+    assert_eq!(
+        sources.values().map(|n| n.as_u64().unwrap()).sum::<u64>(),
+        report["summary"]["replacements"].as_u64().unwrap()
+    );
+    for source in [
+        "nbt_array",
+        "most_least",
+        "nbt_string",
+        "text_dashed",
+        "file_name",
+    ] {
+        assert!(sources[source].as_u64().unwrap() > 0, "{}", source);
+    }
+    assert_eq!(
+        report["summary"]["renames"].as_u64().unwrap(),
+        renamed.len() as u64
+    );
+    let remapped = snapshot(&server);
+    assert_eq!(remapped, snapshot(&expected));
+    // The skull and the wolf in the structures
+    for structure in [
+        "world/generated/minecraft/structures/house.nbt",
+        "world/datapacks/village/data/village/structures/house.nbt",
+    ] {
+        assert_ne!(
+            remapped[Path::new(structure)],
+            original[Path::new(structure)]
+        );
+    }
+    let playerdata = Path::new("world").join("playerdata");
+    assert!(remapped.contains_key(&playerdata.join(format!("{}.dat", to))));
+    assert!(!remapped.contains_key(&playerdata.join(format!("{}.dat", from))));
+    assert!(remapped.contains_key(&playerdata.join(format!("{}.dat", OTHER))));
+    assert_eq!(
+        remapped[Path::new("plugins/Essentials/index.yml")],
+        Contents::Text(format!("players:\n- userdata/{}.yml\n", to))
+    );
+    assert!(remapped
+        .contains_key(&Path::new("plugins/Essentials/userdata").join(format!("{}.yml", to))));
+    assert_eq!(
+        remapped[Path::new("plugins/Shop/config.yml")],
+        Contents::Text(format!(
+            "shops:\n  spawn:\n    owner: {}\n    trusted:\n    - {}\n",
+            to, OTHER
+        ))
+    );
+
+    // And back to the original server, nothing is modified while a file fails
+    let back = || Cli {
+        transactional: true,
+        ..cli(dir.join("back.json"))
+    };
+    let broken = server.join("world").join("data").join("broken.dat");
+    std::fs::create_dir_all(broken.parent().unwrap()).unwrap();
+    std::fs::write(&broken, [10, 0, 0, 8, 0, 4, b'u', b'u', b'i', b'd']).unwrap();
+    // The failed run is reported too
+    let failed_report = dir.join("failed.json");
+    let failed = Cli {
+        report: Some(failed_report.clone()),
+        ..back()
+    };
+    assert_eq!(start(failed), Outcome::Failed);
+    let report: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(&failed_report).unwrap()).unwrap();
+    assert!(report["summary"]["errors"].as_u64().unwrap() > 0);
+    std::fs::remove_file(&broken).unwrap();
+    assert_eq!(snapshot(&server), remapped);
+    assert_eq!(start(back()), Outcome::Done);
+    assert!(!transaction::Staging::dir_for(&server).unwrap().exists());
+    assert_eq!(snapshot(&server), original);
+
+    // The forward mapping inverted goes back as well
+    assert_eq!(start(cli(dir.join("forward.json"))), Outcome::Done);
+    assert_eq!(snapshot(&server), remapped);
+    let inverted = Cli {
+        invert: true,
+        ..cli(dir.join("forward.json"))
+    };
+    assert_eq!(start(inverted), Outcome::Done);
+    assert_eq!(snapshot(&server), original);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(test)]
+#[test]
+fn test_deterministic_output() {
+    use fixture::{bytes, Fixture};
+
+    setup_test_logger();
+
+    let dir = std::env::temp_dir().join("test_deterministic_output");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let from = Uuid::from_u128(0x2d318504_1a7b_39dc_8c18_44df798a5c06);
+    let to = Uuid::from_u128(0xfb1ad51e_cf1f_41f7_8fd1_10dff164b17d);
+    std::fs::write(
+        dir.join("mapping.json"),
+        format!("{{\"{}\": \"{}\"}}", from, to),
+    )
+    .unwrap();
+    // The order of the files and the split among the workers must not matter
+    let remap = |name: &str, threads: usize, seed: u64| {
+        let path = dir.join(name);
+        Fixture {
+            player: from,
+            modifier: from,
+        }
+        .build(&path);
+        let original = bytes(&path);
+        let outcome = start(Cli {
+            mapping_kind: Some(MappingKind::Json),
+            mapping_file: Some(dir.join("mapping.json")),
+            threads,
+            seed: Some(seed),
+            ..test_cli(&path)
+        });
+        assert_eq!(outcome, Outcome::Done);
+        let remapped = bytes(&path);
+        assert_ne!(remapped, original);
+        remapped
+    };
+    let first = remap("first", 1, 1);
+    let second = remap("second", 4, 2);
+    assert_eq!(
+        first.keys().collect::<Vec<_>>(),
+        second.keys().collect::<Vec<_>>()
+    );
+    for (path, content) in &first {
+        assert!(&second[path] == content, "{} differs", path.display());
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(all(test, feature = "online"))]
+#[test]
+#[ignore = "downloads a world from CurseForge"]
+fn test_downloaded_world() {
     setup_test_logger();
 
     // Download a world on the internet for testing, if you don't have an own testing world
@@ -199,21 +2678,684 @@ fn test() {
     let player_list = "NotLaama\nNoxGame1230\n";
     std::fs::write(path.join("playerlist.txt"), player_list).unwrap();
     // Map to online
-    start(Cli {
-        path: path.clone(),
-        mapping_kind: MappingKind::ListToOffline,
-        mapping_file: PathBuf::from("test/playerlist.txt"),
+    let outcome = start(Cli {
+        mapping_kind: Some(MappingKind::ListToOffline),
+        mapping_file: Some(PathBuf::from("test/playerlist.txt")),
         threads: 4,
-        yes: true,
-        no: false,
+        ..test_cli(&path)
     });
+    assert_eq!(outcome, Outcome::Done);
     // Map back to offline
-    start(Cli {
-        path,
-        mapping_kind: MappingKind::ListToOnline,
-        mapping_file: PathBuf::from("test/playerlist.txt"),
+    let outcome = start(Cli {
+        mapping_kind: Some(MappingKind::ListToOnline),
+        mapping_file: Some(PathBuf::from("test/playerlist.txt")),
         threads: 4,
-        yes: true,
-        no: false,
+        ..test_cli(&path)
+    });
+    assert_eq!(outcome, Outcome::Done);
+}
+
+#[cfg(test)]
+#[test]
+fn test_exclude_own_files() {
+    setup_test_logger();
+
+    let world = std::env::temp_dir().join("test_exclude_own_files");
+    let _ = std::fs::remove_dir_all(&world);
+    std::fs::create_dir_all(&world).unwrap();
+    let from = "2d318504-1a7b-39dc-8c18-44df798a5c06";
+    let to = "fb1ad51e-cf1f-41f7-8fd1-10dff164b17d";
+    let mapping = format!("{{\"{}\": \"{}\"}}", from, to);
+    std::fs::write(world.join("mapping.json"), &mapping).unwrap();
+    std::fs::write(world.join("config.yml"), format!("owner: {}\n", from)).unwrap();
+    let outcome = start(Cli {
+        mapping_kind: Some(MappingKind::Json),
+        mapping_file: Some(world.join("mapping.json")),
+        report: Some(world.join("report.json")),
+        ..test_cli(&world)
+    });
+    assert_eq!(outcome, Outcome::Done);
+    assert_eq!(
+        std::fs::read_to_string(world.join("config.yml")).unwrap(),
+        format!("owner: {}\n", to)
+    );
+    assert_eq!(
+        std::fs::read_to_string(world.join("mapping.json")).unwrap(),
+        mapping
+    );
+    assert!(world.join("report.json").exists());
+    std::fs::remove_dir_all(&world).unwrap();
+}
+
+#[cfg(test)]
+#[test]
+fn test_clone() {
+    use std::io::{Read, Write};
+
+    use valence_nbt::{binary::to_binary, from_binary, Compound, Value};
+
+    setup_test_logger();
+
+    let world = std::env::temp_dir().join("test_clone");
+    let _ = std::fs::remove_dir_all(&world);
+    for dir in ["playerdata", "stats", "advancements"] {
+        std::fs::create_dir_all(world.join(dir)).unwrap();
+    }
+    let from = "2d318504-1a7b-39dc-8c18-44df798a5c06";
+    let to = "fb1ad51e-cf1f-41f7-8fd1-10dff164b17d";
+    let mapping = format!("{{\"{}\": \"{}\"}}", from, to);
+    std::fs::write(world.join("mapping.json"), &mapping).unwrap();
+    let uuid_nbt = |uuid: &str| {
+        let uuid = uuid.parse::<Uuid>().unwrap();
+        let ints = uuid
+            .as_bytes()
+            .chunks(4)
+            .map(|int| i32::from_be_bytes(int.try_into().unwrap()))
+            .collect();
+        let mut nbt = Compound::<String>::new();
+        nbt.insert("UUID".to_string(), Value::IntArray(ints));
+        let mut raw = vec![];
+        to_binary(&nbt, &mut raw, "").unwrap();
+        let mut gz = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        gz.write_all(&raw).unwrap();
+        gz.finish().unwrap()
+    };
+    let stats = |uuid: &str| format!("{{\"stats\": {{}}, \"owner\": \"{}\"}}", uuid);
+    let advancements = |uuid: &str| {
+        format!(
+            "{{\"minecraft:story/root\": {{\"done\": true}}, \"by\": \"{}\"}}",
+            uuid
+        )
+    };
+    let files = [
+        (format!("playerdata/{}.dat", from), uuid_nbt(from)),
+        (format!("stats/{}.json", from), stats(from).into_bytes()),
+        (
+            format!("advancements/{}.json", from),
+            advancements(from).into_bytes(),
+        ),
+        // Not named after the player, so not cloned
+        (
+            "config.yml".to_string(),
+            format!("owner: {}\n", from).into_bytes(),
+        ),
+    ];
+    for (path, content) in &files {
+        std::fs::write(world.join(path), content).unwrap();
+    }
+    let cli = || Cli {
+        mapping_kind: Some(MappingKind::Json),
+        mapping_file: Some(world.join("mapping.json")),
+        report: Some(world.join("report.json")),
+        clone_files: true,
+        ..test_cli(&world)
+    };
+    let outcome = start(cli());
+    assert_eq!(outcome, Outcome::Done);
+    // The originals are untouched
+    for (path, content) in &files {
+        assert_eq!(
+            &std::fs::read(world.join(path)).unwrap(),
+            content,
+            "{}",
+            path
+        );
+    }
+    // The copies are remapped
+    let mut raw = vec![];
+    flate2::read::GzDecoder::new(
+        std::fs::read(world.join(format!("playerdata/{}.dat", to)))
+            .unwrap()
+            .as_slice(),
+    )
+    .read_to_end(&mut raw)
+    .unwrap();
+    let mut expected = vec![];
+    flate2::read::GzDecoder::new(uuid_nbt(to).as_slice())
+        .read_to_end(&mut expected)
+        .unwrap();
+    assert_eq!(
+        from_binary::<String>(&mut raw.as_slice()).unwrap(),
+        from_binary::<String>(&mut expected.as_slice()).unwrap()
+    );
+    assert_eq!(
+        std::fs::read_to_string(world.join(format!("stats/{}.json", to))).unwrap(),
+        stats(to)
+    );
+    assert_eq!(
+        std::fs::read_to_string(world.join(format!("advancements/{}.json", to))).unwrap(),
+        advancements(to)
+    );
+    let report: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(world.join("report.json")).unwrap()).unwrap();
+    let cloned = report["cloned_files"].as_array().unwrap();
+    assert_eq!(cloned.len(), 3);
+    assert!(cloned
+        .iter()
+        .any(|file| file["to"] == format!("stats/{}.json", to)));
+    assert_eq!(report["summary"]["renames"], 0);
+
+    // The copies exist now, cloning again is refused for each of them
+    let outcome = start(Cli {
+        report: Some(world.join("again.json")),
+        ..cli()
     });
+    assert_eq!(outcome, Outcome::Done);
+    let report: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(world.join("again.json")).unwrap()).unwrap();
+    assert_eq!(report["summary"]["files_failed"], 3);
+    assert!(report["cloned_files"].as_array().unwrap().is_empty());
+    std::fs::remove_dir_all(&world).unwrap();
+}
+
+#[cfg(test)]
+#[test]
+fn test_chained_mapping() {
+    setup_test_logger();
+
+    let world = std::env::temp_dir().join("test_chained_mapping");
+    let _ = std::fs::remove_dir_all(&world);
+    std::fs::create_dir_all(world.join("stats")).unwrap();
+    let a = "00000000-0000-0000-0000-00000000000a";
+    let b = "00000000-0000-0000-0000-00000000000b";
+    let c = "00000000-0000-0000-0000-00000000000c";
+    std::fs::write(
+        world.join("mapping.json"),
+        format!("{{\"{}\": \"{}\", \"{}\": \"{}\"}}", a, b, b, c),
+    )
+    .unwrap();
+    let stats = world.join("stats").join(format!("{}.json", a));
+    let content = format!("{{\"owner\": \"{}\", \"friend\": \"{}\"}}", a, b);
+    std::fs::write(&stats, &content).unwrap();
+    let cli = |allow_chained_mapping| Cli {
+        mapping_kind: Some(MappingKind::Json),
+        mapping_file: Some(world.join("mapping.json")),
+        allow_chained_mapping,
+        ..test_cli(&world)
+    };
+    assert_eq!(start(cli(false)), Outcome::Failed);
+    assert_eq!(std::fs::read_to_string(&stats).unwrap(), content);
+
+    // Every occurrence is mapped exactly once, both in the content and in the name
+    assert_eq!(start(cli(true)), Outcome::Done);
+    assert!(!stats.exists());
+    assert_eq!(
+        std::fs::read_to_string(world.join("stats").join(format!("{}.json", b))).unwrap(),
+        format!("{{\"owner\": \"{}\", \"friend\": \"{}\"}}", b, c)
+    );
+
+    // A chain looping back can't be composed
+    let parse = |uuid: &str| uuid.parse::<Uuid>().unwrap();
+    let cycle = Cli {
+        maps: vec![(parse(c), parse(a))],
+        compose_chains: true,
+        ..cli(false)
+    };
+    assert_eq!(start(cycle), Outcome::Failed);
+    // Composed, both uuids of the chain end at the last one, which merges their players
+    let stats = world.join("stats").join(format!("{}.json", b));
+    std::fs::write(&stats, &content).unwrap();
+    let compose = Cli {
+        compose_chains: true,
+        ..cli(false)
+    };
+    assert_eq!(start(compose), Outcome::Failed);
+    assert_eq!(std::fs::read_to_string(&stats).unwrap(), content);
+    let compose = Cli {
+        compose_chains: true,
+        allow_duplicate_targets: true,
+        ..cli(false)
+    };
+    assert_eq!(start(compose), Outcome::Done);
+    assert!(!stats.exists());
+    assert_eq!(
+        std::fs::read_to_string(world.join("stats").join(format!("{}.json", c))).unwrap(),
+        format!("{{\"owner\": \"{}\", \"friend\": \"{}\"}}", c, c)
+    );
+    std::fs::remove_dir_all(&world).unwrap();
+}
+
+#[cfg(test)]
+#[test]
+fn test_empty_mapping() {
+    setup_test_logger();
+
+    let err = Cli::try_parse_from(["uuid-remapper", "world", "json", "mapping.json", "-y", "-n"])
+        .unwrap_err();
+    assert_eq!(err.kind(), clap::error::ErrorKind::ArgumentConflict);
+    assert!(Cli::try_parse_from(["uuid-remapper", "world", "json", "mapping.json", "-y"]).is_ok());
+
+    let world = std::env::temp_dir().join("test_empty_mapping");
+    let _ = std::fs::remove_dir_all(&world);
+    std::fs::create_dir_all(&world).unwrap();
+    std::fs::write(world.join("mapping.json"), "{}").unwrap();
+    let config = world.join("config.yml");
+    std::fs::write(&config, "owner: 2d318504-1a7b-39dc-8c18-44df798a5c06\n").unwrap();
+    let cli = |assume_empty_ok| Cli {
+        mapping_kind: Some(MappingKind::Json),
+        mapping_file: Some(world.join("mapping.json")),
+        assume_empty_ok,
+        ..test_cli(&world)
+    };
+    let modified = || std::fs::metadata(&config).unwrap().modified().unwrap();
+    let before = modified();
+    assert_eq!(start(cli(false)), Outcome::Failed);
+    assert_eq!(modified(), before);
+    // Every file is rewritten as is
+    assert_eq!(start(cli(true)), Outcome::Done);
+    assert_eq!(
+        std::fs::read_to_string(&config).unwrap(),
+        "owner: 2d318504-1a7b-39dc-8c18-44df798a5c06\n"
+    );
+    std::fs::remove_dir_all(&world).unwrap();
+}
+
+#[cfg(test)]
+#[test]
+fn test_inline_mapping() {
+    setup_test_logger();
+
+    let from = Uuid::from_u128(0x2d318504_1a7b_39dc_8c18_44df798a5c06);
+    let to = Uuid::from_u128(0xfb1ad51e_cf1f_41f7_8fd1_10dff164b17d);
+    let inline = format!("--map={}={}", from, to);
+    let parsed = Cli::try_parse_from(["uuid-remapper", "world", &inline]).unwrap();
+    assert_eq!(parsed.maps, vec![(from, to)]);
+    assert!(parsed.mapping_file.is_none());
+    assert!(Cli::try_parse_from(["uuid-remapper", "world"]).is_err());
+    assert!(Cli::try_parse_from(["uuid-remapper", "world", "--map", "Notch=Steve"]).is_err());
+
+    let world = std::env::temp_dir().join("test_inline_mapping");
+    let _ = std::fs::remove_dir_all(&world);
+    std::fs::create_dir_all(&world).unwrap();
+    let config = world.join("config.yml");
+    std::fs::write(&config, format!("owner: {}\n", from)).unwrap();
+    let cli = Cli {
+        maps: vec![(from, to)],
+        ..test_cli(&world)
+    };
+    assert_eq!(start(cli), Outcome::Done);
+    assert_eq!(
+        std::fs::read_to_string(&config).unwrap(),
+        format!("owner: {}\n", to)
+    );
+    std::fs::remove_dir_all(&world).unwrap();
+}
+
+#[cfg(test)]
+#[test]
+fn test_live_world() {
+    setup_test_logger();
+
+    let from = Uuid::from_u128(0x2d318504_1a7b_39dc_8c18_44df798a5c06);
+    let to = Uuid::from_u128(0xfb1ad51e_cf1f_41f7_8fd1_10dff164b17d);
+    let world = std::env::temp_dir().join("test_live_world");
+    let _ = std::fs::remove_dir_all(&world);
+    std::fs::create_dir_all(world.join("region")).unwrap();
+    let config = world.join("config.yml");
+    std::fs::write(&config, format!("owner: {}\n", from)).unwrap();
+    // The region was just written, as if a server was saving the world
+    anvil::Anvil::new(&world.join("region").join("r.0.0.mca"))
+        .save()
+        .unwrap();
+    let live = Cli {
+        maps: vec![(from, to)],
+        world_is_offline: false,
+        ..test_cli(&world)
+    };
+    assert_eq!(start(live), Outcome::Cancelled);
+    // Nothing was modified
+    assert_eq!(
+        std::fs::read_to_string(&config).unwrap(),
+        format!("owner: {}\n", from)
+    );
+    std::fs::remove_dir_all(&world).unwrap();
+}
+
+#[cfg(test)]
+#[test]
+fn test_allow_root() {
+    setup_test_logger();
+
+    let from = Uuid::from_u128(0x2d318504_1a7b_39dc_8c18_44df798a5c06);
+    let to = Uuid::from_u128(0xfb1ad51e_cf1f_41f7_8fd1_10dff164b17d);
+    let world = std::env::temp_dir().join("test_allow_root");
+    let _ = std::fs::remove_dir_all(&world);
+    std::fs::create_dir_all(&world).unwrap();
+    let config = world.join("config.yml");
+    std::fs::write(&config, format!("owner: {}\n", from)).unwrap();
+    let cli = |allow_root| Cli {
+        maps: vec![(from, to)],
+        allow_root,
+        ..test_cli(&world)
+    };
+    // Running as root is refused unless allowed
+    if owner::is_root() {
+        assert_eq!(start(cli(false)), Outcome::Failed);
+        assert_eq!(
+            std::fs::read_to_string(&config).unwrap(),
+            format!("owner: {}\n", from)
+        );
+    }
+    assert_eq!(start(cli(true)), Outcome::Done);
+    assert_eq!(
+        std::fs::read_to_string(&config).unwrap(),
+        format!("owner: {}\n", to)
+    );
+    std::fs::remove_dir_all(&world).unwrap();
+}
+
+#[cfg(test)]
+#[test]
+fn test_duplicate_targets() {
+    setup_test_logger();
+
+    let from = Uuid::from_u128(0x2d318504_1a7b_39dc_8c18_44df798a5c06);
+    let to = Uuid::from_u128(0xfb1ad51e_cf1f_41f7_8fd1_10dff164b17d);
+    let renamed = Uuid::from_u128(0x853c80ef_3c37_49fd_aa49_938b674adae6);
+    let world = std::env::temp_dir().join("test_duplicate_targets");
+    let _ = std::fs::remove_dir_all(&world);
+    std::fs::create_dir_all(&world).unwrap();
+    let config = world.join("config.yml");
+    std::fs::write(&config, format!("owner: {}\nadmin: {}\n", from, to)).unwrap();
+    let merged = vec![(from, renamed), (to, renamed)];
+    // Two players merged into one are refused unless allowed
+    let cli = |allow_duplicate_targets| Cli {
+        maps: merged.clone(),
+        allow_duplicate_targets,
+        ..test_cli(&world)
+    };
+    assert_eq!(start(cli(false)), Outcome::Failed);
+    assert_eq!(
+        std::fs::read_to_string(&config).unwrap(),
+        format!("owner: {}\nadmin: {}\n", from, to)
+    );
+    assert_eq!(start(cli(true)), Outcome::Done);
+    assert_eq!(
+        std::fs::read_to_string(&config).unwrap(),
+        format!("owner: {}\nadmin: {}\n", renamed, renamed)
+    );
+    std::fs::remove_dir_all(&world).unwrap();
+}
+
+#[cfg(test)]
+#[test]
+fn test_limit() {
+    setup_test_logger();
+
+    let from = Uuid::from_u128(0x2d318504_1a7b_39dc_8c18_44df798a5c06);
+    let to = Uuid::from_u128(0xfb1ad51e_cf1f_41f7_8fd1_10dff164b17d);
+    let world = std::env::temp_dir().join("test_limit");
+    let _ = std::fs::remove_dir_all(&world);
+    std::fs::create_dir_all(&world).unwrap();
+    let config = world.join("config.yml");
+    let other = world.join("z.yml");
+    for file in [&config, &other] {
+        std::fs::write(file, format!("owner: {}\n", from)).unwrap();
+    }
+    let cli = |maps: Vec<(Uuid, Uuid)>| Cli {
+        maps,
+        ..test_cli(&world)
+    };
+    // A pilot run only remaps the first file in path order
+    let report = std::env::temp_dir().join("test_limit_report.json");
+    let pilot = Cli {
+        limit: Some(1),
+        report: Some(report.clone()),
+        ..cli(vec![(from, to)])
+    };
+    assert_eq!(start(pilot), Outcome::Done);
+    assert_eq!(
+        std::fs::read_to_string(&config).unwrap(),
+        format!("owner: {}\n", to)
+    );
+    assert_eq!(
+        std::fs::read_to_string(&other).unwrap(),
+        format!("owner: {}\n", from)
+    );
+    let json: serde_json::Value = serde_json::from_slice(&std::fs::read(&report).unwrap()).unwrap();
+    assert_eq!(
+        json["partial"],
+        serde_json::json!({"limit": 1, "files_skipped": 1})
+    );
+    std::fs::remove_file(&report).unwrap();
+    assert_eq!(start(cli(vec![(from, to)])), Outcome::Done);
+    assert_eq!(
+        std::fs::read_to_string(&other).unwrap(),
+        format!("owner: {}\n", to)
+    );
+    // Refused with swapped players, the run for the other files would swap the pilot back
+    let swapped = Cli {
+        limit: Some(1),
+        allow_chained_mapping: true,
+        ..cli(vec![(from, to), (to, from)])
+    };
+    assert_eq!(start(swapped), Outcome::Failed);
+    assert_eq!(
+        std::fs::read_to_string(&config).unwrap(),
+        format!("owner: {}\n", to)
+    );
+    std::fs::remove_dir_all(&world).unwrap();
+}
+
+#[cfg(test)]
+#[test]
+fn test_limit_resume() {
+    setup_test_logger();
+
+    let from = Uuid::from_u128(0x2d318504_1a7b_39dc_8c18_44df798a5c06);
+    let to = Uuid::from_u128(0xfb1ad51e_cf1f_41f7_8fd1_10dff164b17d);
+    let world = std::env::temp_dir().join("test_limit_resume");
+    let _ = std::fs::remove_dir_all(&world);
+    std::fs::create_dir_all(&world).unwrap();
+    let files = ["a.yml", "b.yml", "c.yml"].map(|name| world.join(name));
+    for file in &files {
+        std::fs::write(file, format!("owner: {}\n", from)).unwrap();
+    }
+    let owner = |file: &Path| std::fs::read_to_string(file).unwrap();
+    let cli = || Cli {
+        maps: vec![(from, to)],
+        ..test_cli(&world)
+    };
+    let journal = journal::pilot_for(&world).unwrap();
+    let _ = std::fs::remove_file(&journal);
+
+    // The pilot run records the file it remapped
+    let pilot = Cli {
+        limit: Some(1),
+        ..cli()
+    };
+    assert_eq!(start(pilot), Outcome::Done);
+    assert_eq!(owner(&files[0]), format!("owner: {}\n", to));
+    assert_eq!(owner(&files[1]), format!("owner: {}\n", from));
+    let recorded = journal::pilot_files(&world).unwrap();
+    assert_eq!(recorded, HashSet::from([PathBuf::from("a.yml")]));
+
+    // The files of the pilot are skipped, even if they hold the old uuid again
+    std::fs::write(&files[0], format!("owner: {}\n", from)).unwrap();
+    let next = Cli {
+        limit: Some(1),
+        resume: true,
+        ..cli()
+    };
+    assert_eq!(start(next), Outcome::Done);
+    assert_eq!(owner(&files[0]), format!("owner: {}\n", from));
+    assert_eq!(owner(&files[1]), format!("owner: {}\n", to));
+    assert_eq!(owner(&files[2]), format!("owner: {}\n", from));
+    assert_eq!(journal::pilot_files(&world).unwrap().len(), 2);
+
+    // Resuming without --limit remaps the rest, nothing is left to resume
+    let report = std::env::temp_dir().join("test_limit_resume_report.json");
+    let rest = Cli {
+        resume: true,
+        report: Some(report.clone()),
+        ..cli()
+    };
+    assert_eq!(start(rest), Outcome::Done);
+    assert_eq!(owner(&files[0]), format!("owner: {}\n", from));
+    assert_eq!(owner(&files[2]), format!("owner: {}\n", to));
+    let json: serde_json::Value = serde_json::from_slice(&std::fs::read(&report).unwrap()).unwrap();
+    assert_eq!(json["summary"]["files_scanned"], 1);
+    assert!(!journal.exists());
+    std::fs::remove_file(&report).unwrap();
+    std::fs::remove_dir_all(&world).unwrap();
+}
+
+#[cfg(test)]
+#[test]
+fn test_freeze() {
+    setup_test_logger();
+
+    let from = Uuid::from_u128(0x2d318504_1a7b_39dc_8c18_44df798a5c06);
+    let to = Uuid::from_u128(0xfb1ad51e_cf1f_41f7_8fd1_10dff164b17d);
+    let frozen = Uuid::from_u128(0x069a79f4_44e9_4726_a5be_fca90e38aaf5);
+    let renamed = Uuid::from_u128(0x853c80ef_3c37_49fd_aa49_938b674adae6);
+    let world = std::env::temp_dir().join("test_freeze");
+    let _ = std::fs::remove_dir_all(&world);
+    std::fs::create_dir_all(world.join("stats")).unwrap();
+    let config = world.join("config.yml");
+    let report = std::env::temp_dir().join("test_freeze_report.json");
+
+    // A frozen player is not remapped, and the files naming them are not touched
+    let stats = world.join("stats").join(format!("{}.json", frozen));
+    let content = format!("{{\"friend\": \"{}\"}}", from);
+    std::fs::write(&stats, &content).unwrap();
+    std::fs::write(&config, format!("owner: {}\nadmin: {}\n", from, frozen)).unwrap();
+    let freeze = Cli {
+        maps: vec![(from, to), (frozen, renamed)],
+        frozen: vec![mapping::Frozen::Uuid(frozen)],
+        report: Some(report.clone()),
+        ..test_cli(&world)
+    };
+    assert_eq!(start(freeze), Outcome::Done);
+    assert_eq!(std::fs::read_to_string(&stats).unwrap(), content);
+    assert_eq!(
+        std::fs::read_to_string(&config).unwrap(),
+        format!("owner: {}\nadmin: {}\n", to, frozen)
+    );
+    let json: serde_json::Value = serde_json::from_slice(&std::fs::read(&report).unwrap()).unwrap();
+    assert_eq!(json["summary"]["files_frozen"], 1);
+    // Only the given player, the other side of their entry is not
+    assert_eq!(json["frozen_players"], serde_json::json!([frozen]));
+    std::fs::remove_file(&report).unwrap();
+    std::fs::remove_dir_all(&world).unwrap();
+}
+
+#[cfg(test)]
+#[test]
+fn test_legacy_behavior() {
+    setup_test_logger();
+
+    let args = |extra: &[&str]| {
+        ["uuid-remapper", "world", "json", "mapping.json"]
+            .iter()
+            .chain(extra)
+            .map(OsString::from)
+            .collect::<Vec<_>>()
+    };
+    let (merged, applied) = with_legacy_behavior(&Cli::command(), args(&[])).unwrap();
+    assert_eq!(merged, args(&[]));
+    assert!(applied.is_empty());
+
+    let (merged, applied) =
+        with_legacy_behavior(&Cli::command(), args(&["--legacy-behavior"])).unwrap();
+    assert_eq!(
+        applied,
+        ["--assume-empty-ok", "--exit-zero", "--no-prefilter"]
+    );
+    let cli = Cli::parse_from(merged);
+    assert!(cli.legacy_behavior);
+    assert!(cli.no_prefilter && cli.exit_zero && cli.assume_empty_ok);
+    // The other checks still apply
+    assert!(!cli.online_lookup.allow_partial_mapping && !cli.ignore_unresolved);
+    assert!(!cli.allow_chained_mapping && !cli.allow_duplicate_targets);
+    assert!(!cli.world_is_offline && !cli.allow_root && !cli.single_phase);
+    assert!(!cli.yes && !cli.transactional && cli.threads_io.is_none());
+
+    // The options given win
+    let (merged, applied) =
+        with_legacy_behavior(&Cli::command(), args(&["--legacy-behavior", "--exit-zero"])).unwrap();
+    assert_eq!(applied, ["--assume-empty-ok", "--no-prefilter"]);
+    assert!(Cli::parse_from(merged).exit_zero);
+
+    // The same run as the legacy versions: the refused empty mapping is accepted
+    let world = std::env::temp_dir().join("test_legacy_behavior");
+    let _ = std::fs::remove_dir_all(&world);
+    std::fs::create_dir_all(&world).unwrap();
+    std::fs::write(world.join("mapping.json"), "{}").unwrap();
+    let config = world.join("config.yml");
+    std::fs::write(&config, "owner: nobody\n").unwrap();
+    let run = |extra: &[&str]| {
+        let mut args = vec![
+            "uuid-remapper".to_string(),
+            world.display().to_string(),
+            "json".to_string(),
+            world.join("mapping.json").display().to_string(),
+            "--yes".to_string(),
+            "--world-is-offline".to_string(),
+            "--allow-root".to_string(),
+        ];
+        args.extend(extra.iter().map(|arg| arg.to_string()));
+        let (merged, _) = with_legacy_behavior(
+            &Cli::command(),
+            args.into_iter().map(OsString::from).collect(),
+        )
+        .unwrap();
+        start(Cli::parse_from(merged))
+    };
+    assert_eq!(run(&[]), Outcome::Failed);
+    assert_eq!(run(&["--legacy-behavior"]), Outcome::Done);
+    std::fs::remove_dir_all(&world).unwrap();
+}
+
+#[cfg(test)]
+#[test]
+fn test_result_line() {
+    setup_test_logger();
+
+    style::set_enabled(false);
+    let line = result_line(10, 3, 42, 1);
+    assert_eq!(
+        line,
+        "RESULT files_scanned=10 files_modified=3 replacements=42 errors=1"
+    );
+    assert!(!line.as_bytes().contains(&0x1b));
+}
+
+#[cfg(test)]
+#[test]
+fn test_confirm() {
+    setup_test_logger();
+
+    assert_eq!(parse_answer("yes\n"), Answer::Yes);
+    assert_eq!(parse_answer("  Y \r\n"), Answer::Yes);
+    assert_eq!(parse_answer("YES"), Answer::Yes);
+    assert_eq!(parse_answer("no\n"), Answer::No);
+    assert_eq!(parse_answer("N"), Answer::No);
+    assert_eq!(parse_answer("yess"), Answer::Unknown);
+    assert_eq!(parse_answer(""), Answer::Unknown);
+
+    assert!(confirm(&mut "yes\n".as_bytes(), false));
+    assert!(!confirm(&mut "n\n".as_bytes(), false));
+    // Unrecognized input is asked again
+    assert!(confirm(&mut "sure\n\ny\n".as_bytes(), false));
+    assert!(!confirm(&mut "maybe\nno\n".as_bytes(), false));
+    // EOF is no
+    assert!(!confirm(&mut "".as_bytes(), false));
+    assert!(!confirm(&mut "what\n".as_bytes(), false));
+    // An empty line is an answer only when defaulting to no
+    assert!(confirm(&mut "\nyes\n".as_bytes(), false));
+    assert!(!confirm(&mut "\nyes\n".as_bytes(), true));
+    assert!(confirm(&mut "y\n".as_bytes(), true));
+    assert!(confirm_phrase(
+        &mut "I UNDERSTAND\n".as_bytes(),
+        live::PHRASE
+    ));
+    assert!(!confirm_phrase(
+        &mut "yes\nI UNDERSTAND\n".as_bytes(),
+        live::PHRASE
+    ));
+    assert!(!confirm_phrase(
+        &mut "i understand\n".as_bytes(),
+        live::PHRASE
+    ));
 }
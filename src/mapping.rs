@@ -1,13 +1,21 @@
-use std::{collections::HashMap, path::Path, str::FromStr};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    io::Read,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
+use anyhow::Context;
 use clap::ValueEnum;
-use indicatif::ProgressBar;
 use serde::Deserialize;
 use uuid::Uuid;
 
-use crate::MULTI;
+use crate::nbt::{find_long, find_string};
+#[cfg(feature = "online")]
+use crate::online::online_uuids;
+use crate::online::OnlineLookup;
 
-#[derive(Debug, Clone, Copy, ValueEnum)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
 /// Specify the mapping kind
 pub enum MappingKind {
     /// Read mapping from csv file, the first column is the original uuid, the second column is the new uuid
@@ -15,91 +23,218 @@ pub enum MappingKind {
     Csv,
     /// Read mapping from json file
     Json,
+    /// Read mapping from yaml file, either `old: new` lines or a list of `{from: old, to: new}`
+    Yaml,
+    /// Read mapping from toml file, either a table of `"old" = "new"` or `[[mappings]]` with `from` and `to`
+    Toml,
     /// Convert the following player to offline mode, each line is a player name
     ListToOffline,
     /// Convert the following player to online mode, each line is a player name
     ListToOnline,
     /// Convert the following player to offline mode, input is usercache.json
+    /// The cached uuids are the old uuids, the names without one are looked up
     UsercacheToOffline,
     /// Convert the following player to online mode, input is usercache.json
+    /// The cached uuids are the old uuids, the names without one are hashed
+    /// A cached online uuid is the new uuid of the offline one, without a lookup
     UsercacheToOnline,
+    /// Map the uuid of each name in a usercache.json to its uuid in another one, e.g. of another
+    /// server, input is `{"from": "old/usercache.json", "to": "new/usercache.json"}`
+    /// The paths are relative to the input, the names in only one of them are left as they are
+    UsercacheToUsercache,
+    /// Map the uuid of each player in a playerdata directory to the one of the same name in another,
+    /// input is `{"from": "old/world/playerdata", "to": "new/world/playerdata"}`
+    /// The name is the `bukkit.lastKnownName` of each `<uuid>.dat`, vanilla servers don't store one
+    PlayerdataToPlayerdata,
     /// Read two username from each line, the first is the original username, the second is the new username
     /// Can be used to rename players in offline mode.
     /// The first line does not matter
     OfflineRenameCsv,
+    /// Read two username from each line like offline-rename-csv, both are looked up online
+    /// Maps the account of the original username to the one of the new username, e.g. to move the
+    /// data of an alt account to the main one
+    OnlineRenameCsv,
     /// Convert offline names to specific uuid
     OfflineToSpecificCsv,
 }
 
-fn load_csv(path: &Path) -> anyhow::Result<HashMap<Uuid, Uuid>> {
+/// Look up the new uuid of a uuid, consulted by the workers
+pub trait Mapping: Send + Sync {
+    fn get(&self, uuid: Uuid) -> Option<Uuid>;
+}
+
+impl Mapping for HashMap<Uuid, Uuid> {
+    fn get(&self, uuid: Uuid) -> Option<Uuid> {
+        HashMap::get(self, &uuid).copied()
+    }
+}
+
+/// An entry of the list form of the YAML and TOML mappings, the other fields are ignored
+#[derive(Debug, Deserialize)]
+struct MappingEntry {
+    from: Uuid,
+    to: Uuid,
+}
+
+/// Parse a YAML map of `old: new` uuids, or a list of entries with a `from` and a `to` uuid
+fn parse_yaml(content: &str) -> anyhow::Result<HashMap<Uuid, Uuid>> {
+    Ok(match serde_yaml::from_str(content)? {
+        serde_yaml::Value::Null => HashMap::new(),
+        // Parsed again in its form, so the errors point at their line
+        serde_yaml::Value::Sequence(_) => serde_yaml::from_str::<Vec<MappingEntry>>(content)?
+            .into_iter()
+            .map(|entry| (entry.from, entry.to))
+            .collect(),
+        _ => serde_yaml::from_str(content)?,
+    })
+}
+
+/// Parse tables of `"old" = "new"` uuids, or arrays of tables with a `from` and a `to` uuid
+fn parse_toml(content: &str) -> anyhow::Result<HashMap<Uuid, Uuid>> {
+    fn insert(map: &mut HashMap<Uuid, Uuid>, key: &str, value: &toml::Value) -> anyhow::Result<()> {
+        let from = Uuid::from_str(key)
+            .map_err(|err| anyhow::anyhow!("Invalid uuid in the key {:?} ({})", key, err))?;
+        let to = value
+            .as_str()
+            .with_context(|| format!("Expected a uuid for the key {}, got {}", key, value))?;
+        let to = Uuid::from_str(to).map_err(|err| {
+            anyhow::anyhow!("Invalid uuid {:?} for the key {} ({})", to, key, err)
+        })?;
+        map.insert(from, to);
+        Ok(())
+    }
+
     let mut map = HashMap::new();
-    for line in std::fs::read_to_string(path)?.lines().skip(1) {
-        let mut iter = line.split(',');
-        let Some(x) = iter.next().and_then(|x| Uuid::from_str(x).ok()) else {
-            continue;
-        };
-        let Some(y) = iter.next().and_then(|y| Uuid::from_str(y).ok()) else {
-            continue;
-        };
-        if iter.next().is_some() {
-            continue;
+    for (key, value) in content.parse::<toml::Table>()? {
+        match value {
+            toml::Value::Table(table) => {
+                for (key, value) in &table {
+                    insert(&mut map, key, value)?;
+                }
+            }
+            toml::Value::Array(entries) => {
+                for (i, entry) in entries.into_iter().enumerate() {
+                    let entry = entry
+                        .try_into::<MappingEntry>()
+                        .with_context(|| format!("Invalid entry {} of {}", i + 1, key))?;
+                    map.insert(entry.from, entry.to);
+                }
+            }
+            value => insert(&mut map, &key, &value)?,
+        }
+    }
+    Ok(map)
+}
+
+/// The two columns of the rows after the header line, with the line of each row
+/// Quoted fields, spaces around the fields and CRLF are accepted, as are the empty trailing
+/// columns of spreadsheet exports; any other row is an error
+fn csv_rows(content: &str) -> anyhow::Result<Vec<(u64, String, String)>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(true)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(content.as_bytes());
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        let record = record?;
+        let line = record.position().map_or(0, |position| position.line());
+        let mut fields = record.iter().collect::<Vec<_>>();
+        while fields.len() > 2 && fields.last() == Some(&"") {
+            fields.pop();
+        }
+        let [from, to] = fields[..] else {
+            anyhow::bail!(
+                "Expected 2 columns on line {}, got {}: {}",
+                line,
+                fields.len(),
+                fields.join(",")
+            );
         };
-        map.insert(x, y);
+        rows.push((line, from.to_string(), to.to_string()));
+    }
+    Ok(rows)
+}
+
+fn parse_csv_uuid(uuid: &str, line: u64) -> anyhow::Result<Uuid> {
+    Uuid::from_str(uuid).with_context(|| format!("Invalid uuid {:?} on line {}", uuid, line))
+}
+
+fn load_csv(content: &str) -> anyhow::Result<HashMap<Uuid, Uuid>> {
+    let mut map = HashMap::new();
+    for (line, x, y) in csv_rows(content)? {
+        map.insert(parse_csv_uuid(&x, line)?, parse_csv_uuid(&y, line)?);
     }
     Ok(map)
 }
 
-fn online_uuids<'a>(name: impl IntoIterator<Item = &'a String>) -> HashMap<String, Uuid> {
-    #[derive(Deserialize)]
-    struct Res {
-        id: Uuid,
-        name: String,
+/// How offline uuids are computed from the names
+/// Some server forks use another prefix than `OfflinePlayer:`, or lowercase the names
+#[derive(Debug, Clone)]
+pub struct OfflineScheme {
+    pub prefix: String,
+    pub lowercase: bool,
+}
+
+impl Default for OfflineScheme {
+    fn default() -> Self {
+        Self {
+            prefix: "OfflinePlayer:".to_string(),
+            lowercase: false,
+        }
     }
-    let mut ret = HashMap::new();
-    let list = name.into_iter().collect::<Vec<_>>();
-    let chunks = list.chunks(10); // Mojang API limit
-    let pg = MULTI.add(ProgressBar::new(chunks.len() as u64));
-    pg.set_style(
-        indicatif::ProgressStyle::default_bar()
-            .template("Receiving online uuids: [{bar:60.cyan/blue}] {pos} chunks / {len} chunks")
-            .unwrap()
-            .progress_chars("#>-"),
-    );
-    for chunk in chunks {
-        ret.extend(
-            reqwest::blocking::Client::new()
-                .post("https://api.mojang.com/profiles/minecraft")
-                .json(&chunk.iter().collect::<Vec<_>>())
-                .send()
-                .ok()
-                .and_then(|x| x.json::<Vec<Res>>().ok())
-                .unwrap_or_default()
-                .into_iter()
-                .map(|x| (x.name, x.id)),
-        );
-        pg.inc(1);
+}
+
+impl OfflineScheme {
+    pub fn uuid(&self, name: &str) -> Uuid {
+        let str = if self.lowercase {
+            self.prefix.clone() + &name.to_lowercase()
+        } else {
+            self.prefix.clone() + name
+        };
+        let mut md5 = md5::compute(str.as_bytes());
+        // Copied from JDK source code, don't know why
+        md5[6] &= 0x0f; /* clear version        */
+        md5[6] |= 0x30; /* set to version 3     */
+        md5[8] &= 0x3f; /* clear variant        */
+        md5[8] |= 0x80; /* set to IETF variant  */
+        Uuid::from_bytes(md5.0)
     }
-    ret
 }
 
-fn offline_uuid(name: &str) -> Uuid {
-    let str = "OfflinePlayer:".to_owned() + name;
-    let mut md5 = md5::compute(str.as_bytes());
-    // Copied from JDK source code, don't know why
-    md5[6] &= 0x0f; /* clear version        */
-    md5[6] |= 0x30; /* set to version 3     */
-    md5[8] &= 0x3f; /* clear variant        */
-    md5[8] |= 0x80; /* set to IETF variant  */
-    Uuid::from_bytes(md5.0)
+/// The offline uuid of vanilla servers
+pub fn offline_uuid(name: &str) -> Uuid {
+    OfflineScheme::default().uuid(name)
 }
 
-fn offline_uuids<'a>(name: impl IntoIterator<Item = &'a String>) -> HashMap<String, Uuid> {
+fn offline_uuids<'a>(
+    name: impl IntoIterator<Item = &'a String>,
+    scheme: &OfflineScheme,
+) -> HashMap<String, Uuid> {
     name.into_iter()
-        .map(|x| (x.to_string(), offline_uuid(x)))
+        .map(|x| (x.to_string(), scheme.uuid(x)))
         .collect()
 }
 
+/// Resolve the uuids of the players, either online or offline
+#[cfg_attr(not(feature = "online"), allow(unused_variables))]
+pub fn resolve_names(
+    names: &[String],
+    online: bool,
+    scheme: &OfflineScheme,
+    lookup: &OnlineLookup,
+) -> anyhow::Result<HashMap<String, Uuid>> {
+    if online {
+        #[cfg(feature = "online")]
+        return online_uuids(names, lookup).map(|online| online.found);
+        #[cfg(not(feature = "online"))]
+        return Err(crate::online::unsupported("--online"));
+    }
+    Ok(offline_uuids(names, scheme))
+}
+
 // a_compose_b_inverse(a, b) = { (x, y) | exists z: a(z) = x and b(z) = y }
+#[cfg_attr(not(feature = "online"), allow(dead_code))]
 fn a_compose_b_inverse(
     a: &HashMap<String, Uuid>,
     b: &HashMap<String, Uuid>,
@@ -113,100 +248,1150 @@ fn a_compose_b_inverse(
     map
 }
 
-fn load_name_list(path: &Path) -> anyhow::Result<Vec<String>> {
-    Ok(std::fs::read_to_string(path)?
+/// One name per line, blank lines are skipped, so they are never reported as unresolved
+#[cfg_attr(not(feature = "online"), allow(dead_code))]
+fn load_name_list(content: &str) -> Vec<String> {
+    content
         .lines()
-        .map(|x| x.trim().to_string())
-        .collect())
+        .map(str::trim)
+        .filter(|x| !x.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// An entry of `usercache.json`, the uuid is the one used by the server writing it
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+struct CacheEntry {
+    name: String,
+    #[serde(default)]
+    uuid: Option<Uuid>,
+    #[serde(default, rename = "expiresOn")]
+    expires_on: Option<String>,
+}
+
+/// Days since 1970-01-01 of a date in the proleptic Gregorian calendar
+fn days_from_civil(y: i64, m: i64, d: i64) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * (m + if m > 2 { -3 } else { 9 }) + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
 }
 
-fn load_name_list_from_usercache(path: &Path) -> anyhow::Result<Vec<String>> {
-    #[derive(Deserialize)]
-    struct Res {
-        name: String,
+/// Seconds since the epoch of an `expiresOn` date, the format varies across server versions,
+/// e.g. `2024-01-31 12:00:00 +0800`, `2024-01-31T12:00:00.000Z` or `2024-01-31`
+pub fn parse_expires(s: &str) -> Option<i64> {
+    let s = s.trim();
+    let (date, rest) = s.split_at(s.find([' ', 'T']).unwrap_or(s.len()));
+    let mut ymd = date.split('-').map(|x| x.parse::<i64>().ok());
+    let (y, m, d) = (ymd.next()??, ymd.next()??, ymd.next()??);
+    if ymd.next().is_some() || !(1..=12).contains(&m) || !(1..=31).contains(&d) {
+        return None;
     }
-    Ok(
-        serde_json::from_str::<Vec<Res>>(&std::fs::read_to_string(path)?)?
-            .into_iter()
-            .map(|x| x.name)
-            .collect(),
+    let rest = rest.trim_start_matches([' ', 'T']);
+    let (time, zone) = rest.split_at(rest.find([' ', '+', '-', 'Z']).unwrap_or(rest.len()));
+    let mut seconds = 0;
+    if !time.is_empty() {
+        let time = time.split('.').next()?;
+        let mut hms = time.split(':').map(|x| x.parse::<i64>().ok());
+        seconds += hms.next()?? * 3600 + hms.next()?? * 60 + hms.next().unwrap_or(Some(0))?;
+        if hms.next().is_some() {
+            return None;
+        }
+    }
+    let zone = zone.trim();
+    let offset = match zone {
+        "" | "Z" | "UTC" | "GMT" => 0,
+        _ => {
+            let sign = match zone.as_bytes()[0] {
+                b'+' => 1,
+                b'-' => -1,
+                _ => return None,
+            };
+            let digits = zone[1..].replace(':', "");
+            if !digits.bytes().all(|c| c.is_ascii_digit()) {
+                return None;
+            }
+            let (h, m) = match digits.len() {
+                2 => (digits.parse::<i64>().ok()?, 0),
+                4 => (
+                    digits[..2].parse::<i64>().ok()?,
+                    digits[2..].parse::<i64>().ok()?,
+                ),
+                _ => return None,
+            };
+            sign * (h * 3600 + m * 60)
+        }
+    };
+    Some(days_from_civil(y, m, d) * 86400 + seconds - offset)
+}
+
+/// Keep one entry of each name, a name may be taken by another player after a rename
+/// An entry with a preferred uuid wins, then the one expiring last, then the last one in the file
+/// Returns the kept entries in the order of the file, and the discarded ones
+fn dedup_usercache(
+    entries: Vec<CacheEntry>,
+    prefer: &[Uuid],
+) -> (Vec<CacheEntry>, Vec<CacheEntry>) {
+    let rank = |entry: &CacheEntry| {
+        (
+            entry.uuid.is_some_and(|uuid| prefer.contains(&uuid)),
+            entry.expires_on.as_deref().and_then(parse_expires),
+        )
+    };
+    let mut best = HashMap::<&str, usize>::new();
+    for (i, entry) in entries.iter().enumerate() {
+        let kept = best.entry(entry.name.as_str()).or_insert(i);
+        if rank(entry) >= rank(&entries[*kept]) {
+            *kept = i;
+        }
+    }
+    let kept = best.into_values().collect::<std::collections::HashSet<_>>();
+    let (kept, discarded): (Vec<_>, Vec<_>) = entries
+        .iter()
+        .enumerate()
+        .partition(|(i, _)| kept.contains(i));
+    (
+        kept.into_iter().map(|(_, e)| e.clone()).collect(),
+        discarded.into_iter().map(|(_, e)| e.clone()).collect(),
     )
 }
 
-pub fn load_offline_rename(path: &Path) -> anyhow::Result<HashMap<Uuid, Uuid>> {
-    let mut map = HashMap::new();
-    for line in std::fs::read_to_string(path)?.lines().skip(1) {
-        let mut iter = line.split(',');
-        let Some(x) = iter.next() else {
+/// Drop the commas before a closing bracket outside strings, left by hand edits
+fn strip_trailing_commas(json: &str) -> String {
+    let mut out = String::with_capacity(json.len());
+    let (mut in_string, mut escaped) = (false, false);
+    for (i, c) in json.char_indices() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+        } else if c == '"' {
+            in_string = true;
+        } else if c == ',' && json[i + 1..].trim_start().starts_with([']', '}']) {
             continue;
-        };
-        let Some(y) = iter.next() else {
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Parse the entries one by one, an invalid entry is skipped with a warning
+fn parse_usercache(json: &str) -> anyhow::Result<Vec<CacheEntry>> {
+    let json = strip_trailing_commas(json.trim_start_matches('\u{feff}'));
+    let values = serde_json::from_str::<Vec<serde_json::Value>>(&json)
+        .map_err(|err| anyhow::anyhow!("The usercache is not a JSON array: {}", err))?;
+    let total = values.len();
+    let mut entries = vec![];
+    for (i, value) in values.into_iter().enumerate() {
+        if let Some(object) = value.as_object() {
+            for key in object.keys() {
+                if !matches!(key.as_str(), "name" | "uuid" | "expiresOn") {
+                    log::warn!("Unknown key {:?} in usercache entry {}", key, i);
+                }
+            }
+        }
+        match serde_json::from_value::<CacheEntry>(value) {
+            Ok(entry) if entry.name.trim().is_empty() => {
+                log::warn!("Skipping usercache entry {}: empty name", i)
+            }
+            Ok(entry) => entries.push(entry),
+            Err(err) => log::warn!("Skipping usercache entry {}: {}", i, err),
+        }
+    }
+    if entries.is_empty() && total > 0 {
+        anyhow::bail!("None of the {} usercache entries is valid", total);
+    }
+    if entries.len() < total {
+        log::warn!(
+            "{} of {} usercache entries are skipped",
+            total - entries.len(),
+            total
+        );
+    }
+    Ok(entries)
+}
+
+/// Load `usercache.json`, keeping one entry of each name, see `dedup_usercache`
+fn load_usercache(content: &str, prefer: &[Uuid]) -> anyhow::Result<Vec<CacheEntry>> {
+    let entries = parse_usercache(content)?;
+    let (kept, discarded) = dedup_usercache(entries, prefer);
+    let describe = |entry: &CacheEntry| {
+        format!(
+            "{} (expires {})",
+            entry
+                .uuid
+                .map_or("no uuid".to_string(), |uuid| uuid.to_string()),
+            entry.expires_on.as_deref().unwrap_or("never")
+        )
+    };
+    for entry in &discarded {
+        let winner = kept.iter().find(|k| k.name == entry.name).unwrap();
+        log::warn!(
+            "Duplicate name {} in usercache, kept {}, discarded {}",
+            entry.name,
+            describe(winner),
+            describe(entry)
+        );
+    }
+    if !discarded.is_empty() {
+        log::warn!("Pass --prefer-uuid to keep another entry of the name");
+    }
+    Ok(kept)
+}
+
+/// The old and the new side of `--mapping-kind usercache-to-usercache` or `playerdata-to-playerdata`
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PathPair {
+    /// The usercache or the playerdata of the server the world comes from
+    from: PathBuf,
+    /// The usercache or the playerdata of the server the world moves to
+    to: PathBuf,
+}
+
+/// Read the pair of paths from the content of the input, relative to it
+fn load_path_pair(path: &Path, content: &str, what: &str) -> anyhow::Result<(PathBuf, PathBuf)> {
+    let pair = serde_json::from_str::<PathPair>(content)
+        .with_context(|| format!("Expected the paths of the old and the new {}", what))?;
+    let dir = path.parent().unwrap_or(Path::new(""));
+    Ok((dir.join(pair.from), dir.join(pair.to)))
+}
+
+/// Map the uuid of each name on the old side to the one on the new side, the names on only
+/// one side are warned about, `what` is where a name is found, e.g. `cached`
+fn match_names(
+    from: (&Path, &BTreeMap<String, Uuid>),
+    to: (&Path, &BTreeMap<String, Uuid>),
+    what: &str,
+) -> HashMap<Uuid, Uuid> {
+    for ((file, names), (_, other)) in [(from, to), (to, from)] {
+        let only = names
+            .keys()
+            .filter(|name| !other.contains_key(*name))
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        if !only.is_empty() {
+            log::warn!(
+                "{} names are only {} in {}: {}",
+                only.len(),
+                what,
+                file.display(),
+                only.join(", ")
+            );
+        }
+    }
+    from.1
+        .iter()
+        .filter_map(|(name, old)| Some((*old, *to.1.get(name)?)))
+        .collect()
+}
+
+/// Map the uuid cached for each name in the `from` usercache to the one in the `to` usercache
+fn load_usercache_pair(
+    path: &Path,
+    content: &str,
+    prefer: &[Uuid],
+) -> anyhow::Result<HashMap<Uuid, Uuid>> {
+    let (from, to) = load_path_pair(path, content, "usercaches")?;
+    let load = |file: &Path| -> anyhow::Result<BTreeMap<String, Uuid>> {
+        let entries = std::fs::read_to_string(file)
+            .map_err(anyhow::Error::from)
+            .and_then(|content| load_usercache(&content, prefer))
+            .with_context(|| format!("Failed to load {}", file.display()))?;
+        Ok(entries
+            .into_iter()
+            .filter_map(|entry| Some((entry.name, entry.uuid?)))
+            .collect())
+    };
+    let (old, new) = (load(&from)?, load(&to)?);
+    Ok(match_names((&from, &old), (&to, &new), "cached"))
+}
+
+/// The keys of the name stored in a playerdata, vanilla stores none, Bukkit and its forks do
+const PLAYERDATA_NAME: &[&str] = &["bukkit", "lastKnownName"];
+
+/// The name stored in each `<uuid>.dat` of the playerdata directory,
+/// the corrupt files and the files without a name are skipped with a warning
+/// `prefer` picks the uuid of a name stored in more than one file, then the one played last
+fn load_playerdata(dir: &Path, prefer: &[Uuid]) -> anyhow::Result<BTreeMap<String, Uuid>> {
+    let mut names = BTreeMap::<String, (Uuid, (bool, i64))>::new();
+    let (mut skipped, mut unnamed) = (0, 0);
+    let entries =
+        std::fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir.display()))?;
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_none_or(|ext| ext != "dat") {
             continue;
-        };
-        if iter.next().is_some() {
+        }
+        let Some(uuid) = path
+            .file_stem()
+            .and_then(|stem| Uuid::from_str(&stem.to_string_lossy()).ok())
+        else {
             continue;
         };
-        map.insert(offline_uuid(x), offline_uuid(y));
+        let read = || -> anyhow::Result<_> {
+            let mut nbt = vec![];
+            flate2::read::GzDecoder::new(std::fs::File::open(&path)?).read_to_end(&mut nbt)?;
+            let name = find_string(&nbt, PLAYERDATA_NAME)?;
+            let last_played = find_long(&nbt, &["bukkit", "lastPlayed"])?.unwrap_or(0);
+            Ok((name, last_played))
+        };
+        let (name, last_played) = match read() {
+            Ok((Some(name), last_played)) => (name, last_played),
+            Ok((None, _)) => {
+                log::warn!(
+                    "Skipping {}: no {}",
+                    path.display(),
+                    PLAYERDATA_NAME.join(".")
+                );
+                unnamed += 1;
+                continue;
+            }
+            Err(err) => {
+                log::warn!("Skipping {}: {}", path.display(), err);
+                skipped += 1;
+                continue;
+            }
+        };
+        let rank = (prefer.contains(&uuid), last_played);
+        if let Some((other, other_rank)) = names.get(&name) {
+            log::warn!(
+                "Duplicate name {} in {}, stored by {} and {}",
+                name,
+                dir.display(),
+                other,
+                uuid
+            );
+            if (rank, uuid) < (*other_rank, *other) {
+                continue;
+            }
+        }
+        names.insert(name, (uuid, rank));
+    }
+    if skipped + unnamed > 0 {
+        log::warn!(
+            "{} playerdata files in {} are skipped, {} corrupt and {} without a name",
+            skipped + unnamed,
+            dir.display(),
+            skipped,
+            unnamed
+        );
+    }
+    Ok(names
+        .into_iter()
+        .map(|(name, (uuid, _))| (name, uuid))
+        .collect())
+}
+
+/// Map the uuid of each player in the `from` playerdata to the one of the same name in the `to` playerdata
+fn load_playerdata_pair(
+    path: &Path,
+    content: &str,
+    prefer: &[Uuid],
+) -> anyhow::Result<HashMap<Uuid, Uuid>> {
+    let (from, to) = load_path_pair(path, content, "playerdata directories")?;
+    let (old, new) = (
+        load_playerdata(&from, prefer)?,
+        load_playerdata(&to, prefer)?,
+    );
+    let mapping = match_names((&from, &old), (&to, &new), "stored");
+    log::info!(
+        "{} players are matched by name, {} of {} and {} of {} are unmatched",
+        mapping.len(),
+        old.len() - mapping.len(),
+        from.display(),
+        new.len() - mapping.len(),
+        to.display()
+    );
+    Ok(mapping)
+}
+
+/// The uuid cached for each name, resolved in `fallback` if the entry has none
+#[cfg_attr(not(feature = "online"), allow(dead_code))]
+fn cached_uuids(entries: &[CacheEntry], fallback: &HashMap<String, Uuid>) -> HashMap<String, Uuid> {
+    entries
+        .iter()
+        .filter_map(|entry| {
+            let uuid = entry.uuid.or_else(|| fallback.get(&entry.name).copied())?;
+            Some((entry.name.clone(), uuid))
+        })
+        .collect()
+}
+
+pub fn load_offline_rename(
+    content: &str,
+    scheme: &OfflineScheme,
+) -> anyhow::Result<HashMap<Uuid, Uuid>> {
+    let mut map = HashMap::new();
+    for (_, x, y) in csv_rows(content)? {
+        map.insert(scheme.uuid(&x), scheme.uuid(&y));
     }
     Ok(map)
 }
 
-pub fn load_offline_to_specific(path: &Path) -> anyhow::Result<HashMap<Uuid, Uuid>> {
+pub fn load_offline_to_specific(
+    content: &str,
+    scheme: &OfflineScheme,
+) -> anyhow::Result<HashMap<Uuid, Uuid>> {
     let mut map = HashMap::new();
-    for line in std::fs::read_to_string(path)?.lines().skip(1) {
-        let mut iter = line.split(',');
-        let Some(x) = iter.next().map(offline_uuid) else {
-            continue;
-        };
-        let Some(y) = iter.next().and_then(|y| Uuid::from_str(y).ok()) else {
-            continue;
-        };
-        if iter.next().is_some() {
-            continue;
-        };
-        map.insert(x, y);
+    for (line, x, y) in csv_rows(content)? {
+        map.insert(scheme.uuid(&x), parse_csv_uuid(&y, line)?);
     }
     Ok(map)
 }
 
-pub fn get_mapping(kind: MappingKind, path: &Path) -> anyhow::Result<HashMap<Uuid, Uuid>> {
+/// What the loader of the kind expects, for the errors
+fn expected(kind: MappingKind) -> &'static str {
     match kind {
-        MappingKind::Csv => load_csv(path),
-        MappingKind::Json => {
-            let map = std::fs::read_to_string(path)?;
-            Ok(serde_json::from_str(&map)?)
+        MappingKind::Csv => "a CSV file of old,new uuids after a header line",
+        MappingKind::Json => "a JSON object of old uuids to new uuids",
+        MappingKind::Yaml => "a YAML map of old uuids to new uuids, or a list of from and to",
+        MappingKind::Toml => {
+            "a TOML table of old uuids to new uuids, or an array of tables of from and to"
+        }
+        MappingKind::ListToOffline | MappingKind::ListToOnline => {
+            "a list of player names, one per line"
+        }
+        MappingKind::UsercacheToOffline | MappingKind::UsercacheToOnline => {
+            "a usercache.json array of names and uuids"
+        }
+        MappingKind::UsercacheToUsercache => {
+            "a JSON object of the paths of two usercache.json, like {\"from\": ..., \"to\": ...}"
+        }
+        MappingKind::PlayerdataToPlayerdata => {
+            "a JSON object of the paths of two playerdata directories, like {\"from\": ..., \"to\": ...}"
         }
+        MappingKind::OfflineRenameCsv | MappingKind::OnlineRenameCsv => {
+            "a CSV file of old,new names after a header line"
+        }
+        MappingKind::OfflineToSpecificCsv => "a CSV file of name,uuid after a header line",
+    }
+}
+
+/// The name of the kind on the command line
+fn kind_name(kind: MappingKind) -> String {
+    kind.to_possible_value().unwrap().get_name().to_string()
+}
+
+/// The kinds the content looks like, a usercache or a name list fits two of them
+pub fn guess_kinds(content: &str) -> Vec<MappingKind> {
+    let trimmed = content.trim_start();
+    if trimmed.starts_with('{') || trimmed.starts_with('[') {
+        return match serde_json::from_str::<serde_json::Value>(content) {
+            Ok(serde_json::Value::Object(object))
+                if object.len() == 2
+                    && object.contains_key("from")
+                    && object.contains_key("to") =>
+            {
+                let is_file = |key| {
+                    object[key]
+                        .as_str()
+                        .is_some_and(|path| path.ends_with(".json"))
+                };
+                if is_file("from") && is_file("to") {
+                    vec![MappingKind::UsercacheToUsercache]
+                } else {
+                    vec![MappingKind::PlayerdataToPlayerdata]
+                }
+            }
+            Ok(serde_json::Value::Object(_)) => vec![MappingKind::Json],
+            Ok(serde_json::Value::Array(entries))
+                if entries.iter().all(|entry| entry.get("name").is_some()) =>
+            {
+                vec![
+                    MappingKind::UsercacheToOffline,
+                    MappingKind::UsercacheToOnline,
+                ]
+            }
+            _ => vec![],
+        };
+    }
+    if parse_yaml(content).is_ok_and(|map| !map.is_empty()) {
+        return vec![MappingKind::Yaml];
+    }
+    if parse_toml(content).is_ok_and(|map| !map.is_empty()) {
+        return vec![MappingKind::Toml];
+    }
+    let lines = content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>();
+    let rows = lines
+        .iter()
+        .skip(1)
+        .map(|line| line.split(',').map(str::trim).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    if !rows.is_empty() && rows.iter().all(|row| row.len() == 2) {
+        let is_uuid = |column: usize| rows.iter().all(|row| Uuid::from_str(row[column]).is_ok());
+        return match (is_uuid(0), is_uuid(1)) {
+            (true, true) => vec![MappingKind::Csv],
+            (false, true) => vec![MappingKind::OfflineToSpecificCsv],
+            (false, false) => vec![MappingKind::OfflineRenameCsv, MappingKind::OnlineRenameCsv],
+            (true, false) => vec![],
+        };
+    }
+    let is_name = |line: &&str| {
+        line.len() <= 16 && line.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    };
+    if !lines.is_empty() && lines.iter().all(is_name) {
+        return vec![MappingKind::ListToOffline, MappingKind::ListToOnline];
+    }
+    vec![]
+}
+
+/// Explain a mapping that does not load: the expected format, the first line,
+/// and the kinds the content looks like if the kind is not one of them
+fn mismatch_hint(kind: MappingKind, content: &str) -> String {
+    let mut hint = format!(
+        "Expected {} for --mapping-kind {}",
+        expected(kind),
+        kind_name(kind)
+    );
+    if let Some(line) = content.lines().map(str::trim).find(|line| !line.is_empty()) {
+        let line = match line.char_indices().nth(80) {
+            Some((end, _)) => format!("{}...", &line[..end]),
+            None => line.to_string(),
+        };
+        hint.push_str(&format!(", the first line is {:?}", line));
+    }
+    let guesses = guess_kinds(content);
+    if !guesses.is_empty() && !guesses.iter().any(|&guess| guess == kind) {
+        let names = guesses
+            .into_iter()
+            .map(kind_name)
+            .collect::<Vec<_>>()
+            .join(" or ");
+        hint.push_str(&format!(
+            "\nIt looks like another kind, try --mapping-kind {}",
+            names
+        ));
+    }
+    hint
+}
+
+/// A loaded mapping, with the names of the input left out of it
+#[derive(Debug, Default)]
+pub struct Resolved {
+    pub mapping: HashMap<Uuid, Uuid>,
+    /// The names of the list, the usercache or the rename rows not in the mapping, as they were given
+    pub unresolved: Vec<String>,
+}
+
+/// Load the mapping, `prefer` picks the entry of a name appearing more than once in `usercache.json`
+pub fn get_mapping(
+    kind: MappingKind,
+    path: &Path,
+    scheme: &OfflineScheme,
+    lookup: &OnlineLookup,
+    prefer: &[Uuid],
+) -> anyhow::Result<HashMap<Uuid, Uuid>> {
+    resolve_mapping(kind, path, scheme, lookup, prefer).map(|resolved| resolved.mapping)
+}
+
+/// Load the mapping like `get_mapping`, also returning the names that could not be resolved
+pub fn resolve_mapping(
+    kind: MappingKind,
+    path: &Path,
+    scheme: &OfflineScheme,
+    lookup: &OnlineLookup,
+    prefer: &[Uuid],
+) -> anyhow::Result<Resolved> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let Resolved {
+        mut mapping,
+        unresolved,
+    } = load_mapping(kind, path, &content, scheme, lookup, prefer)
+        .with_context(|| mismatch_hint(kind, &content))?;
+    if mapping.is_empty() && !content.trim().is_empty() && !guess_kinds(&content).contains(&kind) {
+        log::warn!("Nothing loaded: {}", mismatch_hint(kind, &content));
+    }
+    // Identity entries change nothing, but would be counted as replacements
+    let len = mapping.len();
+    mapping.retain(|from, to| from != to);
+    if mapping.len() < len {
+        log::info!(
+            "{} identity entries are removed from the mapping",
+            len - mapping.len()
+        );
+    }
+    Ok(Resolved {
+        mapping,
+        unresolved,
+    })
+}
+
+/// Fewer names found online than this share of the names looked up fail the mapping,
+/// as the lookups likely failed, unless `--allow-partial-mapping` is given
+pub const MIN_RESOLVED: f64 = 0.5;
+
+/// Fail if too few names are found online, rather than remapping the world with a partial mapping
+#[cfg_attr(not(feature = "online"), allow(dead_code))]
+fn check_resolved(
+    found: usize,
+    requested: usize,
+    unresolved: &[String],
+    lookup: &OnlineLookup,
+) -> anyhow::Result<()> {
+    if requested == 0 || lookup.allow_partial || found as f64 >= requested as f64 * MIN_RESOLVED {
+        return Ok(());
+    }
+    let mut names = unresolved.iter().take(10).cloned().collect::<Vec<_>>();
+    if unresolved.len() > names.len() {
+        names.push(format!("and {} more", unresolved.len() - names.len()));
+    }
+    anyhow::bail!(
+        "Only {} of the {} names are found online, {} are not: {}, \
+        check the network and the name list, or pass --allow-partial-mapping to proceed",
+        found,
+        requested,
+        requested - found,
+        names.join(", ")
+    )
+}
+
+/// The names of the input none of whose uuids, offline or cached, is in the mapping, their
+/// players are left as they are, whether the lookup found nothing or the composition dropped them
+#[cfg_attr(not(feature = "online"), allow(dead_code))]
+fn missing_names<'a>(
+    names: impl IntoIterator<Item = (&'a String, Option<Uuid>)>,
+    mapping: &HashMap<Uuid, Uuid>,
+    scheme: &OfflineScheme,
+) -> Vec<String> {
+    let uuids = mapping
+        .keys()
+        .chain(mapping.values())
+        .collect::<HashSet<_>>();
+    let mut seen = HashSet::new();
+    names
+        .into_iter()
+        .filter(|(name, cached)| {
+            let offline = scheme.uuid(name);
+            seen.insert(name.as_str())
+                && !uuids.contains(&offline)
+                && cached.is_none_or(|uuid| !uuids.contains(&uuid))
+        })
+        .map(|(name, _)| name.clone())
+        .collect()
+}
+
+// `prefer` only picks among the usercache entries
+#[cfg_attr(not(feature = "online"), allow(unused_variables))]
+fn load_mapping(
+    kind: MappingKind,
+    path: &Path,
+    content: &str,
+    scheme: &OfflineScheme,
+    lookup: &OnlineLookup,
+    prefer: &[Uuid],
+) -> anyhow::Result<Resolved> {
+    #[cfg_attr(not(feature = "online"), allow(unused_mut))]
+    let mut unresolved = vec![];
+    #[cfg(feature = "online")]
+    let online = |names: Vec<&String>| {
+        let requested = names.len();
+        let looked_up = online_uuids(names, lookup)?;
+        check_resolved(
+            looked_up.found.len(),
+            requested,
+            &looked_up.unresolved,
+            lookup,
+        )?;
+        anyhow::Ok(looked_up.found)
+    };
+    let mapping = match kind {
+        MappingKind::Csv => load_csv(content)?,
+        MappingKind::Json => serde_json::from_str(content)?,
+        MappingKind::Yaml => parse_yaml(content)?,
+        MappingKind::Toml => parse_toml(content)?,
+        #[cfg(feature = "online")]
         MappingKind::ListToOffline => {
-            let names = load_name_list(path)?;
-            Ok(a_compose_b_inverse(
-                &online_uuids(&names),
-                &offline_uuids(&names),
-            ))
+            let names = load_name_list(content);
+            let mapping = a_compose_b_inverse(
+                &online(names.iter().collect())?,
+                &offline_uuids(&names, scheme),
+            );
+            unresolved = missing_names(names.iter().map(|name| (name, None)), &mapping, scheme);
+            mapping
         }
+        #[cfg(feature = "online")]
         MappingKind::ListToOnline => {
-            let names = load_name_list(path)?;
-            Ok(a_compose_b_inverse(
-                &offline_uuids(&names),
-                &online_uuids(&names),
-            ))
+            let names = load_name_list(content);
+            let mapping = a_compose_b_inverse(
+                &offline_uuids(&names, scheme),
+                &online(names.iter().collect())?,
+            );
+            unresolved = missing_names(names.iter().map(|name| (name, None)), &mapping, scheme);
+            mapping
         }
+        // The uuids in the world are the ones cached by the server, the lookups fill in the rest
+        #[cfg(feature = "online")]
         MappingKind::UsercacheToOffline => {
-            let names = load_name_list_from_usercache(path)?;
-            Ok(a_compose_b_inverse(
-                &online_uuids(&names),
-                &offline_uuids(&names),
-            ))
+            let entries = load_usercache(content, prefer)?;
+            let names = entries.iter().map(|e| e.name.clone()).collect::<Vec<_>>();
+            let missing = entries.iter().filter(|e| e.uuid.is_none()).map(|e| &e.name);
+            let mapping = a_compose_b_inverse(
+                &cached_uuids(&entries, &online(missing.collect())?),
+                &offline_uuids(&names, scheme),
+            );
+            let cached = entries.iter().map(|e| (&e.name, e.uuid));
+            unresolved = missing_names(cached, &mapping, scheme);
+            mapping
         }
+        // An entry cached with an online uuid is not looked up, its offline uuid is mapped to it
+        #[cfg(feature = "online")]
         MappingKind::UsercacheToOnline => {
-            let names = load_name_list_from_usercache(path)?;
-            Ok(a_compose_b_inverse(
-                &offline_uuids(&names),
-                &online_uuids(&names),
-            ))
+            let (online, entries): (Vec<_>, Vec<_>) = load_usercache(content, prefer)?
+                .into_iter()
+                .partition(|e| e.uuid.is_some_and(|uuid| uuid.get_version_num() == 4));
+            let names = entries.iter().map(|e| e.name.clone()).collect::<Vec<_>>();
+            let mut mapping = a_compose_b_inverse(
+                &cached_uuids(&entries, &offline_uuids(&names, scheme)),
+                &online(names.iter().collect())?,
+            );
+            for entry in &online {
+                if let Some(uuid) = entry.uuid {
+                    mapping.insert(scheme.uuid(&entry.name), uuid);
+                }
+            }
+            let cached = online.iter().chain(&entries).map(|e| (&e.name, e.uuid));
+            unresolved = missing_names(cached, &mapping, scheme);
+            mapping
+        }
+        // The accounts of both names are looked up, a row with a name no player has is left out
+        #[cfg(feature = "online")]
+        MappingKind::OnlineRenameCsv => {
+            let rows = csv_rows(content)?;
+            let names = rows
+                .iter()
+                .flat_map(|(_, x, y)| [x, y])
+                .collect::<HashSet<_>>();
+            let found = online(names.into_iter().collect())?;
+            let (mut old, mut new) = (HashMap::new(), HashMap::new());
+            for (line, x, y) in &rows {
+                match (found.get(x), found.get(y)) {
+                    (Some(&from), Some(&to)) => {
+                        old.insert(x.clone(), from);
+                        new.insert(x.clone(), to);
+                    }
+                    (from, to) => {
+                        let missing = [(x, from), (y, to)]
+                            .into_iter()
+                            .filter(|(_, uuid)| uuid.is_none())
+                            .map(|(name, _)| name.as_str())
+                            .collect::<Vec<_>>();
+                        log::warn!(
+                            "{} on line {} not found online, the row is left out",
+                            missing.join(" and "),
+                            line
+                        );
+                        unresolved.push(x.clone());
+                    }
+                }
+            }
+            a_compose_b_inverse(&old, &new)
+        }
+        #[cfg(not(feature = "online"))]
+        MappingKind::ListToOffline
+        | MappingKind::ListToOnline
+        | MappingKind::UsercacheToOffline
+        | MappingKind::UsercacheToOnline
+        | MappingKind::OnlineRenameCsv => {
+            return Err(crate::online::unsupported(&format!(
+                "--mapping-kind {}",
+                kind_name(kind)
+            )))
+        }
+        MappingKind::UsercacheToUsercache => load_usercache_pair(path, content, prefer)?,
+        MappingKind::PlayerdataToPlayerdata => load_playerdata_pair(path, content, prefer)?,
+        MappingKind::OfflineRenameCsv => load_offline_rename(content, scheme)?,
+        MappingKind::OfflineToSpecificCsv => load_offline_to_specific(content, scheme)?,
+    };
+    Ok(Resolved {
+        mapping,
+        unresolved,
+    })
+}
+
+/// Which entry wins when an inline `--map` entry and the mapping file map a uuid differently
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OnConflict {
+    /// Refuse to proceed
+    Error,
+    /// Use the inline entry
+    Inline,
+    /// Use the entry of the mapping file
+    File,
+}
+
+/// Parse an inline entry `FROM=TO`, the uuids are parsed like in the mapping files
+pub fn parse_inline(s: &str) -> Result<(Uuid, Uuid), String> {
+    let (from, to) = s
+        .split_once('=')
+        .ok_or_else(|| format!("Expected FROM=TO, got {}", s))?;
+    let parse = |uuid: &str| {
+        Uuid::from_str(uuid.trim()).map_err(|err| format!("Invalid uuid {}: {}", uuid, err))
+    };
+    Ok((parse(from)?, parse(to)?))
+}
+
+/// Add the inline entries to the mapping loaded from the file
+pub fn merge_inline(
+    mut mapping: HashMap<Uuid, Uuid>,
+    inline: &[(Uuid, Uuid)],
+    policy: OnConflict,
+) -> anyhow::Result<HashMap<Uuid, Uuid>> {
+    let mut seen = HashMap::new();
+    for &(from, to) in inline {
+        if let Some(other) = seen.insert(from, to).filter(|&other| other != to) {
+            anyhow::bail!("--map {} is given twice, to {} and {}", from, other, to);
+        }
+        if from == to {
+            log::info!("Identity entry --map {}={} is ignored", from, to);
+            continue;
+        }
+        match mapping.get(&from) {
+            Some(&existing) if existing != to => match policy {
+                OnConflict::Error => anyhow::bail!(
+                    "--map {}={} conflicts with {} -> {} in the mapping file, \
+                    pass --on-conflict inline or --on-conflict file to choose one",
+                    from,
+                    to,
+                    from,
+                    existing
+                ),
+                OnConflict::Inline => {
+                    log::warn!(
+                        "{} -> {} in the mapping file is overridden by --map",
+                        from,
+                        existing
+                    );
+                    mapping.insert(from, to);
+                }
+                OnConflict::File => {
+                    log::warn!("--map {}={} is overridden by the mapping file", from, to);
+                }
+            },
+            _ => {
+                mapping.insert(from, to);
+            }
+        }
+    }
+    Ok(mapping)
+}
+
+/// A player given to `--freeze`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Frozen {
+    Uuid(Uuid),
+    /// Frozen by the offline uuid of the name, and the uuid it is mapped from or to
+    Name(String),
+}
+
+/// Parse a `--freeze` player, a uuid if it parses as one, a name otherwise
+pub fn parse_frozen(s: &str) -> Result<Frozen, String> {
+    let s = s.trim();
+    if s.is_empty() {
+        return Err("Expected a uuid or a player name".to_string());
+    }
+    Ok(Uuid::from_str(s).map_or_else(|_| Frozen::Name(s.to_string()), Frozen::Uuid))
+}
+
+/// Remove the entries from or to the frozen players, so their references are never rewritten
+/// Returns the uuids of the frozen players, the files named after them are left out
+pub fn freeze(
+    mapping: &mut HashMap<Uuid, Uuid>,
+    frozen: &[Frozen],
+    scheme: &OfflineScheme,
+) -> Vec<Uuid> {
+    let given = frozen
+        .iter()
+        .map(|frozen| match frozen {
+            Frozen::Uuid(uuid) => *uuid,
+            Frozen::Name(name) => scheme.uuid(name),
+        })
+        .collect::<BTreeSet<_>>();
+    mapping.retain(|from, to| !given.contains(from) && !given.contains(to));
+    given.into_iter().collect()
+}
+
+/// Entries whose new uuid is also mapped, `(a, b, c)` for `a -> b` and `b -> c`
+/// Each occurrence is looked up once, so `a` becomes `b` and never `c` within one run,
+/// but running again would push it to `c`
+pub fn chained_entries(mapping: &HashMap<Uuid, Uuid>) -> Vec<(Uuid, Uuid, Uuid)> {
+    let mut chained = mapping
+        .iter()
+        .filter_map(|(a, b)| mapping.get(b).map(|c| (*a, *b, *c)))
+        .collect::<Vec<_>>();
+    chained.sort();
+    chained
+}
+
+/// Map every old uuid to the end of its chain, `a -> c` and `b -> c` for `a -> b` and `b -> c`
+/// Fails listing the cycles, e.g. `a -> b` and `b -> a`, which have no end
+pub fn compose_chains(mapping: &HashMap<Uuid, Uuid>) -> anyhow::Result<HashMap<Uuid, Uuid>> {
+    let mut composed = HashMap::with_capacity(mapping.len());
+    let mut cycles = BTreeSet::new();
+    for (&from, &to) in mapping {
+        // Identity entries change nothing, they neither loop nor extend a chain
+        if from == to {
+            continue;
+        }
+        let mut path = vec![from];
+        let mut end = to;
+        while let Some(&next) = mapping.get(&end).filter(|&&next| next != end) {
+            if let Some(start) = path.iter().position(|&uuid| uuid == end) {
+                // Rotated to start at the smallest uuid, so each cycle is listed once
+                let mut cycle = path[start..].to_vec();
+                let min = (0..cycle.len()).min_by_key(|&i| cycle[i]).unwrap();
+                cycle.rotate_left(min);
+                cycles.insert(cycle);
+                break;
+            }
+            path.push(end);
+            end = next;
+        }
+        composed.insert(from, end);
+    }
+    if !cycles.is_empty() {
+        let cycles = cycles
+            .into_iter()
+            .map(|cycle| {
+                let first = cycle[0];
+                let mut cycle = cycle.iter().map(Uuid::to_string).collect::<Vec<_>>();
+                cycle.push(first.to_string());
+                format!("   {}", cycle.join(" -> "))
+            })
+            .collect::<Vec<_>>();
+        anyhow::bail!(
+            "Some uuids are mapped back to themselves, the chains can't be composed:\n{}",
+            cycles.join("\n")
+        );
+    }
+    Ok(composed)
+}
+
+/// Old uuids mapped to the same new uuid, each new uuid with its sorted old uuids
+/// The players would share one identity after the remapping
+pub fn colliding_targets(mapping: &HashMap<Uuid, Uuid>) -> Vec<(Uuid, Vec<Uuid>)> {
+    let mut sources = BTreeMap::<Uuid, Vec<Uuid>>::new();
+    for (from, to) in mapping {
+        sources.entry(*to).or_default().push(*from);
+    }
+    sources
+        .into_iter()
+        .filter(|(_, from)| from.len() > 1)
+        .map(|(to, mut from)| {
+            from.sort();
+            (to, from)
+        })
+        .collect()
+}
+
+/// Fail if old uuids share a new uuid, their files would be renamed onto each other
+pub fn check_targets(mapping: &HashMap<Uuid, Uuid>) -> anyhow::Result<()> {
+    let colliding = colliding_targets(mapping);
+    if colliding.is_empty() {
+        return Ok(());
+    }
+    let pairs = colliding
+        .iter()
+        .flat_map(|(to, sources)| {
+            sources
+                .iter()
+                .map(move |from| format!("{} -> {}", from, to))
+        })
+        .collect::<Vec<_>>();
+    anyhow::bail!(
+        "{} new uuids are mapped from more than one old uuid, one player's data would \
+        replace the other's: {}, pass --allow-duplicate-targets if they are merged on purpose",
+        colliding.len(),
+        pairs.join(", ")
+    )
+}
+
+/// Swap the old and the new uuids, to undo a remapping
+/// Fails if old uuids share a new uuid, as it can't be told which one to map back to
+pub fn invert(mapping: HashMap<Uuid, Uuid>) -> anyhow::Result<HashMap<Uuid, Uuid>> {
+    let colliding = colliding_targets(&mapping);
+    if !colliding.is_empty() {
+        let entries = colliding
+            .iter()
+            .map(|(to, sources)| {
+                let sources = sources.iter().map(Uuid::to_string).collect::<Vec<_>>();
+                format!("{} -> {}", sources.join(", "), to)
+            })
+            .collect::<Vec<_>>();
+        anyhow::bail!(
+            "The mapping can't be inverted, {} new uuids are mapped from more than one old \
+            uuid: {}",
+            colliding.len(),
+            entries.join("; ")
+        );
+    }
+    Ok(mapping.into_iter().map(|(from, to)| (to, from)).collect())
+}
+
+/// The formats a mapping can be written in by `mapping convert`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum MappingFormat {
+    Csv,
+    Json,
+    Yaml,
+    Toml,
+}
+
+impl MappingFormat {
+    /// The kind reading the format back
+    pub fn kind(self) -> MappingKind {
+        match self {
+            MappingFormat::Csv => MappingKind::Csv,
+            MappingFormat::Json => MappingKind::Json,
+            MappingFormat::Yaml => MappingKind::Yaml,
+            MappingFormat::Toml => MappingKind::Toml,
+        }
+    }
+}
+
+/// Serialize the mapping sorted by the old uuid, so converting the same mapping twice gives the same file
+pub fn write_mapping(format: MappingFormat, mapping: &HashMap<Uuid, Uuid>) -> String {
+    let sorted = mapping.iter().collect::<BTreeMap<_, _>>();
+    match format {
+        MappingFormat::Csv => {
+            let mut csv = String::from("old,new\n");
+            for (from, to) in sorted {
+                csv.push_str(&format!("{},{}\n", from, to));
+            }
+            csv
+        }
+        MappingFormat::Json => {
+            let mut json = serde_json::to_string_pretty(&sorted).unwrap();
+            json.push('\n');
+            json
+        }
+        MappingFormat::Yaml => sorted
+            .into_iter()
+            .map(|(from, to)| format!("{}: {}\n", from, to))
+            .collect(),
+        MappingFormat::Toml => {
+            let mut toml = String::from("[mappings]\n");
+            for (from, to) in sorted {
+                toml.push_str(&format!("\"{}\" = \"{}\"\n", from, to));
+            }
+            toml
+        }
+    }
+}
+
+/// Load the mapping with the loader of `from` and write it in `to`
+/// The output is read back with its own loader, so a converted mapping always loads the same
+pub fn convert_mapping(
+    from: MappingFormat,
+    input: &Path,
+    to: MappingFormat,
+    output: &Path,
+) -> anyhow::Result<usize> {
+    let mapping = get_mapping(
+        from.kind(),
+        input,
+        &OfflineScheme::default(),
+        &OnlineLookup::default(),
+        &[],
+    )
+    .with_context(|| format!("Failed to load {}", input.display()))?;
+    std::fs::write(output, write_mapping(to, &mapping))
+        .with_context(|| format!("Failed to write {}", output.display()))?;
+    let written = get_mapping(
+        to.kind(),
+        output,
+        &OfflineScheme::default(),
+        &OnlineLookup::default(),
+        &[],
+    )
+    .with_context(|| format!("Failed to read back {}", output.display()))?;
+    anyhow::ensure!(
+        written == mapping,
+        "{} does not load the same as {}",
+        output.display(),
+        input.display()
+    );
+    Ok(mapping.len())
+}
+
+/// The share of the players in the world that must be new uuids of the mapping to suspect it is inverted
+pub const INVERTED_TARGETS: f64 = 0.5;
+
+/// The players that are old uuids must also be fewer than this share of the ones that are new uuids
+pub const INVERTED_SOURCES: f64 = 0.1;
+
+/// How the players in the world match the mapping
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Direction {
+    pub players: usize,
+    /// Players that are old uuids of the mapping
+    pub sources: usize,
+    /// Players that are new uuids of the mapping
+    pub targets: usize,
+}
+
+impl Direction {
+    pub fn of(mapping: &HashMap<Uuid, Uuid>, players: &[Uuid]) -> Self {
+        let targets = mapping.values().collect::<HashSet<_>>();
+        Self {
+            players: players.len(),
+            sources: players.iter().filter(|p| mapping.contains_key(p)).count(),
+            targets: players.iter().filter(|p| targets.contains(p)).count(),
         }
-        MappingKind::OfflineRenameCsv => load_offline_rename(path),
-        MappingKind::OfflineToSpecificCsv => load_offline_to_specific(path),
     }
+
+    /// Most players are already new uuids but few are old ones, e.g. list-to-offline on an offline world
+    pub fn looks_inverted(&self) -> bool {
+        self.targets > 0
+            && self.targets as f64 >= self.players as f64 * INVERTED_TARGETS
+            && (self.sources as f64) < self.targets as f64 * INVERTED_SOURCES
+    }
+
+    pub fn warning(&self) -> Option<String> {
+        self.looks_inverted().then(|| {
+            format!(
+                "{} of the {} players in playerdata already have a new uuid of the mapping, \
+                but only {} have an old one, the mapping may be inverted, \
+                e.g. list-to-offline for an offline world, swap the columns or use the opposite kind",
+                self.targets, self.players, self.sources
+            )
+        })
+    }
+}
+
+/// The uuids of the player files next to level.dat, without reading them
+pub fn playerdata_uuids(world: &Path) -> Vec<Uuid> {
+    let level = crate::format::find_level_dat(world);
+    let dir = level
+        .as_deref()
+        .and_then(Path::parent)
+        .unwrap_or(world)
+        .join("playerdata");
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return vec![];
+    };
+    let mut uuids = entries
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            if path.extension()? != "dat" {
+                return None;
+            }
+            Uuid::from_str(path.file_stem()?.to_str()?).ok()
+        })
+        .collect::<Vec<_>>();
+    uuids.sort();
+    uuids.dedup();
+    uuids
 }
 
 #[cfg(test)]
@@ -222,7 +1407,14 @@ fn test() {
     let csv_path = std::env::temp_dir().join("test.csv");
     std::fs::write(&csv_path, csv_file).unwrap();
     assert_eq!(
-        get_mapping(MappingKind::Csv, &csv_path).unwrap(),
+        get_mapping(
+            MappingKind::Csv,
+            &csv_path,
+            &OfflineScheme::default(),
+            &OnlineLookup::default(),
+            &[]
+        )
+        .unwrap(),
         vec![
             (
                 Uuid::from_str("00000000-0000-0000-0000-000000000000").unwrap(),
@@ -238,6 +1430,23 @@ fn test() {
     );
     std::fs::remove_file(csv_path).unwrap();
 
+    // Identity entries are removed, leaving an empty mapping
+    let csv_file = "from,to\n\
+    00000000-0000-0000-0000-000000000000,00000000-0000-0000-0000-000000000000\n\
+    00000000-0000-0000-0000-000000000002,00000000-0000-0000-0000-000000000002";
+    let csv_path = std::env::temp_dir().join("test_identity.csv");
+    std::fs::write(&csv_path, csv_file).unwrap();
+    assert!(get_mapping(
+        MappingKind::Csv,
+        &csv_path,
+        &OfflineScheme::default(),
+        &OnlineLookup::default(),
+        &[]
+    )
+    .unwrap()
+    .is_empty());
+    std::fs::remove_file(csv_path).unwrap();
+
     let json_file = r#"{
         "00000000-0000-0000-0000-000000000000": "00000000-0000-0000-0000-000000000001",
         "00000000-0000-0000-0000-000000000002": "00000000-0000-0000-0000-000000000003"
@@ -245,7 +1454,14 @@ fn test() {
     let json_path = std::env::temp_dir().join("test.json");
     std::fs::write(&json_path, json_file).unwrap();
     assert_eq!(
-        get_mapping(MappingKind::Json, &json_path).unwrap(),
+        get_mapping(
+            MappingKind::Json,
+            &json_path,
+            &OfflineScheme::default(),
+            &OnlineLookup::default(),
+            &[]
+        )
+        .unwrap(),
         vec![
             (
                 Uuid::from_str("00000000-0000-0000-0000-000000000000").unwrap(),
@@ -261,29 +1477,34 @@ fn test() {
     );
     std::fs::remove_file(json_path).unwrap();
 
-    let list_file = "a\nb\nc";
-    let list_path = std::env::temp_dir().join("test.list");
-    std::fs::write(&list_path, list_file).unwrap();
+    let list_file = "a\n b\t\n\n  \nc\r\n\n";
     assert_eq!(
-        load_name_list(&list_path).unwrap(),
+        load_name_list(list_file),
         vec!["a".to_string(), "b".to_string(), "c".to_string(),]
     );
-    std::fs::remove_file(list_path).unwrap();
 
     let usercache_file = r#"[{"name":"a"},{"name":"b"},{"name":"c"}]"#;
-    let usercache_path = std::env::temp_dir().join("test.usercache.json");
-    std::fs::write(&usercache_path, usercache_file).unwrap();
     assert_eq!(
-        load_name_list_from_usercache(&usercache_path).unwrap(),
+        load_usercache(usercache_file, &[])
+            .unwrap()
+            .into_iter()
+            .map(|e| e.name)
+            .collect::<Vec<_>>(),
         vec!["a".to_string(), "b".to_string(), "c".to_string(),]
     );
-    std::fs::remove_file(usercache_path).unwrap();
 
     let offline_rename_file = "from,to\na,b\nc,d";
     let offline_rename_path = std::env::temp_dir().join("test.offline_rename.csv");
     std::fs::write(&offline_rename_path, offline_rename_file).unwrap();
     assert_eq!(
-        get_mapping(MappingKind::OfflineRenameCsv, &offline_rename_path).unwrap(),
+        get_mapping(
+            MappingKind::OfflineRenameCsv,
+            &offline_rename_path,
+            &OfflineScheme::default(),
+            &OnlineLookup::default(),
+            &[]
+        )
+        .unwrap(),
         vec![
             (offline_uuid("a"), offline_uuid("b")),
             (offline_uuid("c"), offline_uuid("d")),
@@ -293,30 +1514,83 @@ fn test() {
     );
     std::fs::remove_file(offline_rename_path).unwrap();
 
+    // Quoted names, spaces, CRLF and the empty trailing column of spreadsheets
+    let offline_rename_file = "from,to\r\n\"Smith, John\",Notch\r\n\"a\"\"b\" , c ,\r\n";
     assert_eq!(
-        offline_uuid("CaveNightingale"),
-        Uuid::from_str("2d318504-1a7b-39dc-8c18-44df798a5c06").unwrap()
+        load_offline_rename(offline_rename_file, &OfflineScheme::default()).unwrap(),
+        HashMap::from([
+            (offline_uuid("Smith, John"), offline_uuid("Notch")),
+            (offline_uuid("a\"b"), offline_uuid("c")),
+        ])
     );
-    let online_uuids_result = online_uuids(
-        vec![
-            "CaveNightingale".to_string(),
-            "Notch".to_string(),
-            "Dinnerbone".to_string(),
-        ]
-        .iter(),
+    // A row with another column is refused with its line
+    let offline_rename_file = "from,to\na,b\nSmith, John,Notch\n";
+    let err = load_offline_rename(offline_rename_file, &OfflineScheme::default()).unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("Expected 2 columns on line 3, got 3"),
+        "{}",
+        err
     );
+
     assert_eq!(
-        online_uuids_result.get("CaveNightingale").unwrap(),
-        &Uuid::from_str("fb1ad51e-cf1f-41f7-8fd1-10dff164b17d").unwrap()
+        offline_uuid("CaveNightingale"),
+        Uuid::from_str("2d318504-1a7b-39dc-8c18-44df798a5c06").unwrap()
     );
+    let lowercase = OfflineScheme {
+        lowercase: true,
+        ..Default::default()
+    };
     assert_eq!(
-        online_uuids_result.get("Notch").unwrap(),
-        &Uuid::from_str("069a79f4-44e9-4726-a5be-fca90e38aaf5").unwrap()
+        lowercase.uuid("CaveNightingale"),
+        Uuid::from_str("7b60f6b2-2fbb-39b0-b9a1-58535d26ab61").unwrap()
+    );
+    assert_eq!(
+        lowercase.uuid("CaveNightingale"),
+        offline_uuid("cavenightingale")
+    );
+    let prefixed = OfflineScheme {
+        prefix: "MyFork:".to_string(),
+        lowercase: false,
+    };
+    assert_eq!(
+        prefixed.uuid("CaveNightingale"),
+        Uuid::from_str("ad62b781-d479-36ed-9727-6e2c46487dd9").unwrap()
+    );
+    // Deterministic, and still a version 3 uuid
+    let again = OfflineScheme {
+        prefix: "MyFork:".to_string(),
+        lowercase: false,
+    };
+    assert_eq!(
+        prefixed.uuid("CaveNightingale"),
+        again.uuid("CaveNightingale")
+    );
+    assert_eq!(prefixed.uuid("CaveNightingale").get_version_num(), 3);
+    assert_ne!(
+        prefixed.uuid("CaveNightingale"),
+        offline_uuid("CaveNightingale")
     );
+    // The list and rename kinds use the scheme
     assert_eq!(
-        online_uuids_result.get("Dinnerbone").unwrap(),
-        &Uuid::from_str("61699b2e-d327-4a01-9f1e-0ea8c3f06bc6").unwrap()
+        load_offline_rename("old,new\nCaveNightingale,Notch\n", &prefixed).unwrap(),
+        HashMap::from([(prefixed.uuid("CaveNightingale"), prefixed.uuid("Notch"))])
     );
+    // The online uuids are looked up in the test of the online module
+    let online_uuids_result = HashMap::from([
+        (
+            "CaveNightingale".to_string(),
+            Uuid::from_str("fb1ad51e-cf1f-41f7-8fd1-10dff164b17d").unwrap(),
+        ),
+        (
+            "Notch".to_string(),
+            Uuid::from_str("069a79f4-44e9-4726-a5be-fca90e38aaf5").unwrap(),
+        ),
+        (
+            "Dinnerbone".to_string(),
+            Uuid::from_str("61699b2e-d327-4a01-9f1e-0ea8c3f06bc6").unwrap(),
+        ),
+    ]);
     let composed = a_compose_b_inverse(
         &online_uuids_result,
         &offline_uuids(
@@ -326,6 +1600,7 @@ fn test() {
                 "Dinnerbone".to_string(),
             ]
             .iter(),
+            &OfflineScheme::default(),
         ),
     );
     assert_eq!(
@@ -347,19 +1622,897 @@ fn test() {
     let offline_to_specific_path = std::env::temp_dir().join("test.offline_to_specific.csv");
     std::fs::write(&offline_to_specific_path, offline_to_specific_file).unwrap();
     assert_eq!(
-        get_mapping(MappingKind::OfflineToSpecificCsv, &offline_to_specific_path).unwrap(),
-        vec![
-            (
-                offline_uuid("CaveNightingale"),
-                Uuid::from_str("2d318504-1a7b-39dc-8c18-44df798a5c06").unwrap()
-            ),
-            (
-                offline_uuid("Notch"),
-                Uuid::from_str("069a79f4-44e9-4726-a5be-fca90e38aaf5").unwrap()
-            ),
-        ]
+        get_mapping(
+            MappingKind::OfflineToSpecificCsv,
+            &offline_to_specific_path,
+            &OfflineScheme::default(),
+            &OnlineLookup::default(),
+            &[]
+        )
+        .unwrap(),
+        // CaveNightingale is mapped to its own offline uuid, so the entry is removed
+        vec![(
+            offline_uuid("Notch"),
+            Uuid::from_str("069a79f4-44e9-4726-a5be-fca90e38aaf5").unwrap()
+        ),]
         .into_iter()
         .collect()
     );
     std::fs::remove_file(offline_to_specific_path).unwrap();
 }
+
+#[cfg(test)]
+#[test]
+fn test_chained() {
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    let a = Uuid::from_u128(1);
+    let b = Uuid::from_u128(2);
+    let c = Uuid::from_u128(3);
+    assert_eq!(
+        chained_entries(&HashMap::from([(a, b), (b, c)])),
+        vec![(a, b, c)]
+    );
+    assert_eq!(
+        chained_entries(&HashMap::from([(a, b), (b, a)])),
+        vec![(a, b, a), (b, a, b)]
+    );
+    assert!(chained_entries(&HashMap::from([(a, b), (c, b)])).is_empty());
+    // A chain of length 3 ends at its last uuid, a cycle has no end
+    let d = Uuid::from_u128(4);
+    assert_eq!(
+        compose_chains(&HashMap::from([(a, b), (b, c), (c, d)])).unwrap(),
+        HashMap::from([(a, d), (b, d), (c, d)])
+    );
+    let err = compose_chains(&HashMap::from([(a, b), (b, a), (c, a)])).unwrap_err();
+    assert!(
+        err.to_string()
+            .contains(&format!("   {} -> {} -> {}", a, b, a)),
+        "{}",
+        err
+    );
+    assert_eq!(err.to_string().lines().count(), 2);
+    assert_eq!(
+        compose_chains(&HashMap::from([(a, b), (b, b), (c, c)])).unwrap(),
+        HashMap::from([(a, b)])
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn test_usercache_lenient() {
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    let notch = Uuid::from_str("069a79f4-44e9-4726-a5be-fca90e38aaf5").unwrap();
+    // A BOM, trailing commas, a missing name, a bad uuid, a misspelled key and an empty name
+    let usercache = format!(
+        "\u{feff}[
+            {{\"name\":\"Notch\",\"uuid\":\"{}\",}},
+            {{\"uuid\":\"{}\"}},
+            {{\"name\":\"bad\",\"uuid\":\"not-a-uuid\"}},
+            {{\"name\":\"typo\",\"uudi\":\"{}\"}},
+            {{\"name\":\" \"}},
+            {{\"name\":\"comma, ]\"}},
+        ]",
+        notch, notch, notch
+    );
+    let entries = parse_usercache(&usercache).unwrap();
+    assert_eq!(
+        entries
+            .iter()
+            .map(|e| (e.name.as_str(), e.uuid))
+            .collect::<Vec<_>>(),
+        vec![("Notch", Some(notch)), ("typo", None), ("comma, ]", None)]
+    );
+    assert!(parse_usercache(r#"[{"uuid":"x"}, 1]"#).is_err());
+    assert!(parse_usercache(r#"{"name":"Notch"}"#).is_err());
+    assert!(parse_usercache("[]").unwrap().is_empty());
+
+    // Cached online uuids need no lookup, but the kind needs the online feature anyway
+    let path = std::env::temp_dir().join("test_usercache_lenient.json");
+    std::fs::write(
+        &path,
+        format!(r#"[{{"name":"Notch","uuid":"{}"}},]"#, notch),
+    )
+    .unwrap();
+    let loaded = get_mapping(
+        MappingKind::UsercacheToOnline,
+        &path,
+        &OfflineScheme::default(),
+        &OnlineLookup::default(),
+        &[],
+    );
+    #[cfg(feature = "online")]
+    assert_eq!(
+        loaded.unwrap(),
+        HashMap::from([(offline_uuid("Notch"), notch)])
+    );
+    #[cfg(not(feature = "online"))]
+    assert!(format!("{:#}", loaded.unwrap_err()).contains("without online support"));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(test)]
+#[test]
+fn test_usercache_duplicates() {
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    assert_eq!(parse_expires("1970-01-02 00:00:00 +0000"), Some(86400));
+    assert_eq!(parse_expires("1970-01-01 08:00:00 +0800"), Some(0));
+    assert_eq!(parse_expires("1970-01-01T00:00:00.250Z"), Some(0));
+    assert_eq!(parse_expires("1969-12-31 19:00 -05:00"), Some(0));
+    assert_eq!(parse_expires("2024-03-01"), Some(1709251200));
+    assert_eq!(parse_expires("2024-02-29 00:00:00"), Some(1709164800));
+    assert_eq!(parse_expires("Thu Jan 01 1970"), None);
+    assert_eq!(parse_expires("2024-13-01 00:00:00 +0000"), None);
+    assert_eq!(parse_expires("2024-01-01 00:00:00 +08"), Some(1704038400));
+    assert_eq!(parse_expires("2024-01-01 00:00:00 CEST"), None);
+
+    let old = Uuid::from_u128(1);
+    let new = Uuid::from_u128(2);
+    let other = Uuid::from_u128(3);
+    // `bob` renamed, then someone else took the name
+    let usercache = format!(
+        r#"[
+            {{"name":"bob","uuid":"{}","expiresOn":"2024-05-01 00:00:00 +0000"}},
+            {{"name":"alice","uuid":"{}","expiresOn":"2024-01-01 00:00:00 +0000"}},
+            {{"name":"bob","uuid":"{}","expiresOn":"2024-04-30 23:00:00 -0200"}}
+        ]"#,
+        old, other, new
+    );
+    let uuids = |entries: Vec<CacheEntry>| {
+        entries
+            .into_iter()
+            .map(|e| (e.name, e.uuid.unwrap()))
+            .collect::<Vec<_>>()
+    };
+    // The second bob expires an hour later
+    assert_eq!(
+        uuids(load_usercache(&usercache, &[]).unwrap()),
+        vec![("alice".to_string(), other), ("bob".to_string(), new)]
+    );
+    assert_eq!(
+        uuids(load_usercache(&usercache, &[old]).unwrap()),
+        vec![("bob".to_string(), old), ("alice".to_string(), other)]
+    );
+
+    // Undated entries lose, ties keep the last one
+    let entry = |uuid: u128, expires_on: Option<&str>| CacheEntry {
+        name: "bob".to_string(),
+        uuid: Some(Uuid::from_u128(uuid)),
+        expires_on: expires_on.map(str::to_string),
+    };
+    let (kept, discarded) = dedup_usercache(
+        vec![
+            entry(1, Some("2024-01-01")),
+            entry(2, None),
+            entry(3, Some("2024-01-01")),
+        ],
+        &[],
+    );
+    assert_eq!(kept, vec![entry(3, Some("2024-01-01"))]);
+    assert_eq!(
+        discarded,
+        vec![entry(1, Some("2024-01-01")), entry(2, None)]
+    );
+
+    assert_eq!(
+        cached_uuids(
+            &[CacheEntry {
+                name: "carol".to_string(),
+                uuid: None,
+                expires_on: None,
+            }],
+            &HashMap::from([("carol".to_string(), other)])
+        ),
+        HashMap::from([("carol".to_string(), other)])
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn test_usercache_pair() {
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    let dir = std::env::temp_dir().join("test_usercache_pair");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("lobby")).unwrap();
+    std::fs::create_dir_all(dir.join("survival")).unwrap();
+    let uuid = Uuid::from_u128;
+    let entry = |name: &str, uuid: Uuid, expires_on: &str| {
+        format!(
+            r#"{{"name":"{}","uuid":"{}","expiresOn":"{}"}}"#,
+            name, uuid, expires_on
+        )
+    };
+    // `bob` is cached twice on the old server, the newest entry is kept
+    let old = [
+        entry("alice", uuid(1), "2024-05-01 00:00:00 +0000"),
+        entry("bob", uuid(2), "2024-04-01 00:00:00 +0000"),
+        entry("bob", uuid(3), "2024-05-01 00:00:00 +0000"),
+        entry("carol", uuid(4), "2024-05-01 00:00:00 +0000"),
+    ];
+    let new = [
+        entry("bob", uuid(13), "2024-06-01 00:00:00 +0000"),
+        entry("alice", uuid(11), "2024-06-01 00:00:00 +0000"),
+        entry("dave", uuid(15), "2024-06-01 00:00:00 +0000"),
+    ];
+    std::fs::write(
+        dir.join("lobby").join("usercache.json"),
+        format!("[{}]", old.join(",")),
+    )
+    .unwrap();
+    std::fs::write(
+        dir.join("survival").join("usercache.json"),
+        format!("[{}]", new.join(",")),
+    )
+    .unwrap();
+    let pair = r#"{"from": "lobby/usercache.json", "to": "survival/usercache.json"}"#;
+    let path = dir.join("pair.json");
+    std::fs::write(&path, pair).unwrap();
+    assert_eq!(
+        get_mapping(
+            MappingKind::UsercacheToUsercache,
+            &path,
+            &OfflineScheme::default(),
+            &OnlineLookup::default(),
+            &[]
+        )
+        .unwrap(),
+        HashMap::from([(uuid(1), uuid(11)), (uuid(3), uuid(13))])
+    );
+    assert_eq!(guess_kinds(pair), vec![MappingKind::UsercacheToUsercache]);
+
+    let missing = r#"{"from": "lobby/usercache.json", "to": "missing.json"}"#;
+    let err = format!(
+        "{:#}",
+        load_usercache_pair(&path, missing, &[]).unwrap_err()
+    );
+    assert!(err.contains("missing.json"));
+    let incomplete = r#"{"from": "lobby/usercache.json"}"#;
+    assert!(load_usercache_pair(&path, incomplete, &[]).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(test)]
+#[test]
+fn test_playerdata_pair() {
+    use std::io::Write;
+
+    use flate2::{write::GzEncoder, Compression};
+    use valence_nbt::{binary::to_binary, snbt::from_snbt_str, Value};
+
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    let dir = std::env::temp_dir().join("test_playerdata_pair");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("old")).unwrap();
+    std::fs::create_dir_all(dir.join("new")).unwrap();
+    let uuid = Uuid::from_u128;
+    let write = |side: &str, uuid: Uuid, snbt: &str| {
+        let Value::Compound(nbt) = from_snbt_str(snbt).unwrap() else {
+            panic!()
+        };
+        let mut binary = vec![];
+        to_binary(&nbt, &mut binary, "").unwrap();
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(&binary).unwrap();
+        let path = dir.join(side).join(format!("{}.dat", uuid));
+        std::fs::write(path, encoder.finish().unwrap()).unwrap();
+    };
+    let player = |name: &str, last_played: i64| {
+        format!(
+            r#"{{DataVersion: 3700, bukkit: {{lastKnownName: "{}", lastPlayed: {}L}}}}"#,
+            name, last_played
+        )
+    };
+    write("old", uuid(1), &player("alice", 0));
+    // `bob` joined with two accounts, the one played last wins
+    write("old", uuid(2), &player("bob", 100));
+    write("old", uuid(3), &player("bob", 200));
+    write("old", uuid(4), &player("carol", 0));
+    // Vanilla
+    write("old", uuid(5), "{DataVersion: 3700}");
+    std::fs::write(dir.join("old").join(format!("{}.dat", uuid(6))), b"corrupt").unwrap();
+    std::fs::write(dir.join("old").join(format!("{}.dat_old", uuid(7))), b"").unwrap();
+    write("new", uuid(11), &player("alice", 0));
+    write("new", uuid(13), &player("bob", 0));
+    write("new", uuid(15), &player("dave", 0));
+    let pair = r#"{"from": "old", "to": "new"}"#;
+    let path = dir.join("pair.json");
+    std::fs::write(&path, pair).unwrap();
+    let scheme = OfflineScheme::default();
+    assert_eq!(
+        get_mapping(
+            MappingKind::PlayerdataToPlayerdata,
+            &path,
+            &scheme,
+            &OnlineLookup::default(),
+            &[]
+        )
+        .unwrap(),
+        HashMap::from([(uuid(1), uuid(11)), (uuid(3), uuid(13))])
+    );
+    assert_eq!(
+        get_mapping(
+            MappingKind::PlayerdataToPlayerdata,
+            &path,
+            &scheme,
+            &OnlineLookup::default(),
+            &[uuid(2)]
+        )
+        .unwrap(),
+        HashMap::from([(uuid(1), uuid(11)), (uuid(2), uuid(13))])
+    );
+    assert_eq!(guess_kinds(pair), vec![MappingKind::PlayerdataToPlayerdata]);
+
+    let missing = r#"{"from": "old", "to": "missing"}"#;
+    let err = format!(
+        "{:#}",
+        load_playerdata_pair(&path, missing, &[]).unwrap_err()
+    );
+    assert!(err.contains("missing"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(test)]
+#[test]
+fn test_inline() {
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    let a = Uuid::from_u128(1);
+    let b = Uuid::from_u128(2);
+    let c = Uuid::from_u128(3);
+    assert_eq!(
+        parse_inline("00000000-0000-0000-0000-000000000001=00000000000000000000000000000002"),
+        Ok((a, b))
+    );
+    assert_eq!(
+        parse_inline(
+            " 00000000-0000-0000-0000-000000000001 = 00000000-0000-0000-0000-000000000002"
+        ),
+        Ok((a, b))
+    );
+    assert!(parse_inline("00000000-0000-0000-0000-000000000001").is_err());
+    assert!(parse_inline("00000000-0000-0000-0000-000000000001=Notch").is_err());
+
+    let file = HashMap::from([(a, b)]);
+    assert_eq!(
+        merge_inline(HashMap::new(), &[(a, b), (b, b)], OnConflict::Error).unwrap(),
+        file
+    );
+    // The same entry in both is not a conflict
+    assert_eq!(
+        merge_inline(file.clone(), &[(a, b), (c, a)], OnConflict::Error).unwrap(),
+        HashMap::from([(a, b), (c, a)])
+    );
+    assert!(merge_inline(file.clone(), &[(a, c)], OnConflict::Error).is_err());
+    assert_eq!(
+        merge_inline(file.clone(), &[(a, c)], OnConflict::Inline).unwrap(),
+        HashMap::from([(a, c)])
+    );
+    assert_eq!(
+        merge_inline(file.clone(), &[(a, c)], OnConflict::File).unwrap(),
+        file
+    );
+    assert!(merge_inline(HashMap::new(), &[(a, b), (a, c)], OnConflict::Inline).is_err());
+}
+
+#[cfg(test)]
+#[test]
+fn test_freeze() {
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    let scheme = OfflineScheme::default();
+    let a = Uuid::from_u128(1);
+    let b = Uuid::from_u128(2);
+    let c = Uuid::from_u128(3);
+    let online = Uuid::from_u128(4);
+    assert_eq!(
+        parse_frozen(" 00000000-0000-0000-0000-000000000001 "),
+        Ok(Frozen::Uuid(a))
+    );
+    assert_eq!(parse_frozen("Notch"), Ok(Frozen::Name("Notch".to_string())));
+    assert!(parse_frozen(" ").is_err());
+
+    let mut mapping = HashMap::from([(a, b), (c, a), (scheme.uuid("Notch"), online)]);
+    let frozen = freeze(
+        &mut mapping,
+        &[Frozen::Uuid(a), Frozen::Name("Notch".to_string())],
+        &scheme,
+    );
+    assert!(mapping.is_empty());
+    // The other side of the removed entries is not frozen
+    let mut expected = vec![a, scheme.uuid("Notch")];
+    expected.sort();
+    assert_eq!(frozen, expected);
+    // A uuid not in the mapping is still frozen, for its files
+    let mut mapping = HashMap::from([(b, c)]);
+    assert_eq!(freeze(&mut mapping, &[Frozen::Uuid(a)], &scheme), [a]);
+    assert_eq!(mapping, HashMap::from([(b, c)]));
+}
+
+#[cfg(test)]
+#[test]
+fn test_check_resolved() {
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    let lookup = OnlineLookup::default();
+    let unresolved = (0..12).map(|i| format!("player{}", i)).collect::<Vec<_>>();
+    // Nothing looked up, or enough found
+    assert!(check_resolved(0, 0, &[], &lookup).is_ok());
+    assert!(check_resolved(12, 24, &unresolved, &lookup).is_ok());
+    // The network is down, nothing is found
+    let err = check_resolved(0, 12, &unresolved, &lookup).unwrap_err();
+    let message = err.to_string();
+    assert!(message.contains("Only 0 of the 12 names"));
+    assert!(message.contains("player9, and 2 more"));
+    assert!(message.contains("--allow-partial-mapping"));
+    assert!(check_resolved(11, 23, &unresolved, &lookup).is_err());
+    let partial = OnlineLookup {
+        allow_partial: true,
+        ..Default::default()
+    };
+    assert!(check_resolved(0, 12, &unresolved, &partial).is_ok());
+}
+
+#[cfg(all(test, feature = "online"))]
+#[test]
+fn test_online_rename() {
+    use std::time::SystemTime;
+
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    let dir = std::env::temp_dir().join("test_online_rename");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let (alt, main, other) = (Uuid::from_u128(1), Uuid::from_u128(2), Uuid::from_u128(3));
+    // Every name is in the cache, nothing is asked to the API
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let cache = serde_json::json!({
+        "alt": {"name": "Alt", "uuid": alt, "fetched": now},
+        "main": {"name": "Main", "uuid": main, "fetched": now},
+        "gone": {"name": "Gone", "uuid": null, "fetched": now},
+        "other": {"name": "Other", "uuid": other, "fetched": now},
+    });
+    std::fs::write(dir.join("cache.json"), cache.to_string()).unwrap();
+    let lookup = OnlineLookup {
+        cache: Some(dir.join("cache.json")),
+        ..Default::default()
+    };
+    // The names are looked up in any case, a row with a missing name is left out
+    let path = dir.join("renames.csv");
+    std::fs::write(&path, "old,new\nalt,\"Main\"\nGone,Other\nALT,main\n").unwrap();
+    let resolved = resolve_mapping(
+        MappingKind::OnlineRenameCsv,
+        &path,
+        &OfflineScheme::default(),
+        &lookup,
+        &[],
+    )
+    .unwrap();
+    assert_eq!(resolved.mapping, HashMap::from([(alt, main)]));
+    assert_eq!(resolved.unresolved, ["Gone"]);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(test)]
+#[test]
+fn test_missing_names() {
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    let scheme = OfflineScheme::default();
+    let names = ["Alice", "Bob", "Carol", "Alice"].map(String::from);
+    let cached = Uuid::from_u128(0x2d318504_1a7b_39dc_8c18_44df798a5c06);
+    let online = Uuid::from_u128(0xfb1ad51e_cf1f_41f7_8fd1_10dff164b17d);
+    // Alice is mapped by the offline uuid, Bob by the cached one, Carol is left out
+    let mapping = HashMap::from([(scheme.uuid("Alice"), online), (cached, Uuid::nil())]);
+    let names = names
+        .iter()
+        .map(|name| (name, (name == "Bob").then_some(cached)));
+    assert_eq!(missing_names(names.clone(), &mapping, &scheme), ["Carol"]);
+    // Either direction counts
+    let inverse = mapping.iter().map(|(&a, &b)| (b, a)).collect();
+    assert_eq!(missing_names(names, &inverse, &scheme), ["Carol"]);
+    let all = ["Bob", "Bob", "Dave"].map(String::from);
+    assert_eq!(
+        missing_names(
+            all.iter().map(|name| (name, None)),
+            &HashMap::new(),
+            &scheme
+        ),
+        ["Bob", "Dave"]
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn test_direction() {
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    let dir = std::env::temp_dir().join("test_direction");
+    let _ = std::fs::remove_dir_all(&dir);
+    let names = (0..10).map(|i| format!("player{}", i)).collect::<Vec<_>>();
+    // Online to offline, looked up with a fixed uuid per name
+    let mapping = names
+        .iter()
+        .enumerate()
+        .map(|(i, name)| (Uuid::from_u128(i as u128 + 1), offline_uuid(name)))
+        .collect::<HashMap<_, _>>();
+    let world = |players: &[Uuid]| {
+        let _ = std::fs::remove_dir_all(&dir);
+        let playerdata = dir.join("world").join("playerdata");
+        std::fs::create_dir_all(&playerdata).unwrap();
+        std::fs::write(dir.join("world").join("level.dat"), b"").unwrap();
+        for player in players {
+            std::fs::write(playerdata.join(format!("{}.dat", player)), b"").unwrap();
+            std::fs::write(playerdata.join(format!("{}.dat_old", player)), b"").unwrap();
+        }
+        std::fs::write(playerdata.join("notes.dat"), b"").unwrap();
+        Direction::of(&mapping, &playerdata_uuids(&dir))
+    };
+
+    // The world has the old uuids, as expected
+    let online = mapping.keys().copied().collect::<Vec<_>>();
+    let direction = world(&online);
+    assert_eq!(
+        direction,
+        Direction {
+            players: 10,
+            sources: 10,
+            targets: 0
+        }
+    );
+    assert!(direction.warning().is_none());
+
+    // The world is offline already, and two players without a mapping joined
+    let mut offline = mapping.values().copied().collect::<Vec<_>>();
+    offline.extend([Uuid::from_u128(100), Uuid::from_u128(101)]);
+    let direction = world(&offline);
+    assert_eq!(
+        direction,
+        Direction {
+            players: 12,
+            sources: 0,
+            targets: 10
+        }
+    );
+    let warning = direction.warning().unwrap();
+    assert!(warning.contains("10 of the 12 players"));
+    assert!(warning.contains("inverted"));
+
+    // Thresholds, half of the players are new uuids, and the old ones less than a tenth of them
+    let direction = |players, sources, targets| Direction {
+        players,
+        sources,
+        targets,
+    };
+    assert!(direction(20, 0, 10).looks_inverted());
+    assert!(!direction(21, 0, 10).looks_inverted());
+    assert!(!direction(20, 1, 10).looks_inverted());
+    assert!(direction(20, 1, 11).looks_inverted());
+    assert!(!direction(0, 0, 0).looks_inverted());
+    assert!(playerdata_uuids(&dir.join("missing")).is_empty());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(test)]
+#[test]
+fn test_convert() {
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    let dir = std::env::temp_dir().join("test_convert");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let mapping = (1..=5)
+        .map(|i| (Uuid::from_u128(i * 7919), Uuid::from_u128(i)))
+        .collect::<HashMap<_, _>>();
+    let formats = [
+        MappingFormat::Csv,
+        MappingFormat::Json,
+        MappingFormat::Yaml,
+        MappingFormat::Toml,
+    ];
+    for from in formats {
+        let input = dir.join(format!("in.{:?}", from));
+        std::fs::write(&input, write_mapping(from, &mapping)).unwrap();
+        for to in formats {
+            let output = dir.join(format!("{:?}.{:?}", from, to));
+            assert_eq!(convert_mapping(from, &input, to, &output).unwrap(), 5);
+            let converted = std::fs::read_to_string(&output).unwrap();
+            assert_eq!(converted, write_mapping(to, &mapping));
+            let loaded = get_mapping(
+                to.kind(),
+                &output,
+                &OfflineScheme::default(),
+                &OnlineLookup::default(),
+                &[],
+            )
+            .unwrap();
+            assert_eq!(loaded, mapping);
+        }
+    }
+    // Sorted by the old uuid
+    let csv = write_mapping(MappingFormat::Csv, &mapping);
+    let lines = csv.lines().collect::<Vec<_>>();
+    assert_eq!(lines[0], "old,new");
+    assert_eq!(
+        lines[1],
+        format!("{},{}", Uuid::from_u128(7919), Uuid::from_u128(1))
+    );
+    let mut sorted = lines[1..].to_vec();
+    sorted.sort();
+    assert_eq!(sorted, lines[1..]);
+
+    // An invalid line fails the conversion, rather than being dropped
+    let input = dir.join("messy.csv");
+    std::fs::write(
+        &input,
+        format!(
+            "from,to\n{},{}\nnot a uuid,{}\n",
+            Uuid::from_u128(1),
+            Uuid::from_u128(2),
+            Uuid::from_u128(3)
+        ),
+    )
+    .unwrap();
+    let output = dir.join("messy.json");
+    let err =
+        convert_mapping(MappingFormat::Csv, &input, MappingFormat::Json, &output).unwrap_err();
+    assert!(
+        format!("{:#}", err).contains("Invalid uuid \"not a uuid\" on line 3"),
+        "{:#}",
+        err
+    );
+    assert!(!output.exists());
+    assert!(convert_mapping(
+        MappingFormat::Json,
+        &input,
+        MappingFormat::Csv,
+        &dir.join("x.csv")
+    )
+    .is_err());
+
+    let mut mapping = mapping;
+    assert!(colliding_targets(&mapping).is_empty());
+    assert!(check_targets(&mapping).is_ok());
+    let inverted = invert(mapping.clone()).unwrap();
+    assert_eq!(inverted.len(), mapping.len());
+    assert!(mapping.iter().all(|(from, to)| inverted[to] == *from));
+    mapping.insert(Uuid::from_u128(99), Uuid::from_u128(3));
+    mapping.insert(Uuid::from_u128(98), Uuid::from_u128(3));
+    let err = check_targets(&mapping).unwrap_err().to_string();
+    assert!(err.contains(&format!(
+        "{} -> {}",
+        Uuid::from_u128(98),
+        Uuid::from_u128(3)
+    )));
+    assert!(err.contains(&format!(
+        "{} -> {}",
+        Uuid::from_u128(99),
+        Uuid::from_u128(3)
+    )));
+    assert!(err.contains("--allow-duplicate-targets"));
+    let err = invert(mapping.clone()).unwrap_err().to_string();
+    assert!(err.contains(&format!(
+        "{}, {}, {} -> {}",
+        Uuid::from_u128(98),
+        Uuid::from_u128(99),
+        Uuid::from_u128(3 * 7919),
+        Uuid::from_u128(3)
+    )));
+    assert_eq!(
+        colliding_targets(&mapping),
+        vec![(
+            Uuid::from_u128(3),
+            vec![
+                Uuid::from_u128(98),
+                Uuid::from_u128(99),
+                Uuid::from_u128(3 * 7919)
+            ]
+        )]
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(test)]
+#[test]
+fn test_yaml() {
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    let (a, b, c, d) = (
+        Uuid::from_u128(1),
+        Uuid::from_u128(2),
+        Uuid::from_u128(3),
+        Uuid::from_u128(4),
+    );
+    let expected = HashMap::from([(a, b), (c, d)]);
+    // A flat map, the uuids may be quoted or without dashes
+    let map = format!(
+        "# exported mapping\n---\n{}: \"{}\"\n\n'{}': {} # Alex\n",
+        a,
+        b,
+        c.simple(),
+        d
+    );
+    assert_eq!(parse_yaml(&map).unwrap(), expected);
+    // A list of entries, in block or flow style, the other fields are ignored
+    let list = format!(
+        "- from: {}\n  name: Steve\n  to: {}\n-\n  from: {}\n  to: {}\n",
+        a, b, c, d
+    );
+    assert_eq!(parse_yaml(&list).unwrap(), expected);
+    let flow = format!(
+        "- {{from: {}, to: {}}}\n- {{ to: {}, from: {} }}\n",
+        a, b, d, c
+    );
+    assert_eq!(parse_yaml(&flow).unwrap(), expected);
+    assert_eq!(guess_kinds(&map), vec![MappingKind::Yaml]);
+    assert_eq!(guess_kinds(&list), vec![MappingKind::Yaml]);
+
+    assert_eq!(parse_yaml("# nothing yet\n").unwrap(), HashMap::new());
+
+    let err = parse_yaml(&format!("{}: {}\nsteve: {}\n", a, b, c))
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("line 2"), "{}", err);
+    let err = parse_yaml(&format!("- from: {}\n  to: 1234\n", a))
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("line 2"), "{}", err);
+    let err = parse_yaml(&format!("- from: {}\n- from: {}\n  to: {}\n", a, c, d))
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("missing field `to`"), "{}", err);
+    assert!(parse_yaml(&format!("{}:\n  {}: {}\n", a, b, c)).is_err());
+    assert!(parse_yaml(&format!("- {{from: {}, to: {}\n", a, b)).is_err());
+}
+
+#[cfg(test)]
+#[test]
+fn test_toml() {
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    let (a, b, c, d) = (
+        Uuid::from_u128(1),
+        Uuid::from_u128(2),
+        Uuid::from_u128(3),
+        Uuid::from_u128(4),
+    );
+    let expected = HashMap::from([(a, b), (c, d)]);
+    let table = format!(
+        "# exported by the panel\n[mappings]\n\"{}\" = \"{}\"\n'{}' = '{}' # Alex\n",
+        a,
+        b,
+        c.simple(),
+        d
+    );
+    assert_eq!(parse_toml(&table).unwrap(), expected);
+    let tables = format!(
+        "[[mappings]]\nfrom = \"{}\"\nname = \"Steve #1\"\nto = \"{}\"\n\n[[mappings]]\nto = \"{}\"\nfrom = \"{}\"\n",
+        a, b, d, c
+    );
+    assert_eq!(parse_toml(&tables).unwrap(), expected);
+    assert_eq!(guess_kinds(&table), vec![MappingKind::Toml]);
+    assert_eq!(guess_kinds(&tables), vec![MappingKind::Toml]);
+
+    assert_eq!(parse_toml("# nothing yet\n").unwrap(), HashMap::new());
+
+    let err = parse_toml(&format!("[mappings]\n\"steve\" = \"{}\"\n", b))
+        .unwrap_err()
+        .to_string();
+    assert!(err.starts_with("Invalid uuid in the key \"steve\""));
+    let err = parse_toml(&format!("\"{}\" = \"alex\"\n", a))
+        .unwrap_err()
+        .to_string();
+    assert!(err.starts_with(&format!("Invalid uuid \"alex\" for the key {}", a)));
+    let err = parse_toml(&format!("\"{}\" = 1234\n", a))
+        .unwrap_err()
+        .to_string();
+    assert_eq!(err, format!("Expected a uuid for the key {}, got 1234", a));
+    let err = parse_toml(&format!("[[mappings]]\nfrom = \"{}\"\nto = \"1234\"\n", a)).unwrap_err();
+    assert_eq!(err.to_string(), "Invalid entry 1 of mappings");
+    let err = parse_toml(&format!("[[mappings]]\nfrom = \"{}\"\n[[mappings]]\n", a)).unwrap_err();
+    assert!(format!("{:#}", err).contains("missing field `to`"));
+    assert!(parse_toml("[mappings\n").is_err());
+}
+
+#[cfg(test)]
+#[test]
+fn test_kind_mismatch() {
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    let dir = std::env::temp_dir().join("test_kind_mismatch");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let (a, b) = (Uuid::from_u128(1), Uuid::from_u128(2));
+    let load = |file: &str, content: String, kind| {
+        let path = dir.join(file);
+        std::fs::write(&path, content).unwrap();
+        get_mapping(
+            kind,
+            &path,
+            &OfflineScheme::default(),
+            &OnlineLookup::default(),
+            &[],
+        )
+    };
+
+    let usercache = format!(
+        r#"[{{"name":"Steve","uuid":"{}","expiresOn":"2030-01-01 00:00:00 +0000"}}]"#,
+        a
+    );
+    let err = format!(
+        "{:#}",
+        load("usercache.json", usercache, MappingKind::Json).unwrap_err()
+    );
+    assert!(
+        err.contains("Expected a JSON object of old uuids to new uuids for --mapping-kind json")
+    );
+    assert!(err.contains(r#"the first line is "[{\"name\":\"Steve\""#));
+    assert!(err.contains("try --mapping-kind usercache-to-offline or usercache-to-online"));
+
+    let csv = format!("old,new\n{},{}\n", a, b);
+    let err = format!(
+        "{:#}",
+        load("mapping.csv", csv.clone(), MappingKind::Json).unwrap_err()
+    );
+    assert!(err.contains(r#"the first line is "old,new""#));
+    assert!(err.contains("try --mapping-kind csv"));
+    assert_eq!(
+        load("mapping.csv", csv, MappingKind::Csv).unwrap(),
+        HashMap::from([(a, b)])
+    );
+
+    assert_eq!(
+        guess_kinds("Steve\nAlex\n"),
+        vec![MappingKind::ListToOffline, MappingKind::ListToOnline]
+    );
+    assert_eq!(
+        guess_kinds("old,new\nSteve,Alex\n"),
+        vec![MappingKind::OfflineRenameCsv, MappingKind::OnlineRenameCsv]
+    );
+    assert_eq!(
+        guess_kinds(&format!("name,uuid\nSteve,{}\n", a)),
+        vec![MappingKind::OfflineToSpecificCsv]
+    );
+    assert_eq!(
+        guess_kinds(&format!("{{\"{}\": \"{}\"}}", a, b)),
+        vec![MappingKind::Json]
+    );
+    assert!(guess_kinds("not a mapping at all").is_empty());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
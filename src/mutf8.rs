@@ -0,0 +1,181 @@
+use std::ops::Range;
+
+// The Modified UTF-8 of Java, used by the NBT strings
+// NUL is written as `C0 80`, and characters outside the BMP as two 3-byte surrogates
+// Rewrites of the same length replace ASCII in place and never need it, as ASCII is the same
+// in both encodings, while a rewrite changing the length splices the new text into the raw bytes,
+// so the untouched sequences are kept exactly as written, even non-canonical ones
+
+/// The string is not valid Modified UTF-8
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Malformed {
+    pub offset: usize,
+}
+
+impl std::fmt::Display for Malformed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Malformed Modified UTF-8 at byte {}", self.offset)
+    }
+}
+
+impl std::error::Error for Malformed {}
+
+fn is_continuation(byte: u8) -> bool {
+    byte & 0xc0 == 0x80
+}
+
+/// Decode into UTF-16 code units, which may be lone surrogates
+pub fn decode_units(raw: &[u8]) -> Result<Vec<u16>, Malformed> {
+    let mut units = Vec::with_capacity(raw.len());
+    let mut i = 0;
+    while i < raw.len() {
+        let malformed = Malformed { offset: i };
+        let byte = raw[i];
+        let len = match byte {
+            0x00..=0x7f => 1,
+            0xc0..=0xdf => 2,
+            0xe0..=0xef => 3,
+            _ => return Err(malformed),
+        };
+        let Some(sequence) = raw.get(i..i + len) else {
+            return Err(malformed);
+        };
+        if !sequence[1..].iter().all(|&b| is_continuation(b)) {
+            return Err(malformed);
+        }
+        units.push(match len {
+            1 => byte as u16,
+            2 => ((byte as u16 & 0x1f) << 6) | (sequence[1] as u16 & 0x3f),
+            _ => {
+                ((byte as u16 & 0x0f) << 12)
+                    | ((sequence[1] as u16 & 0x3f) << 6)
+                    | (sequence[2] as u16 & 0x3f)
+            }
+        });
+        i += len;
+    }
+    Ok(units)
+}
+
+/// Decode for display, lone surrogates and malformed strings are shown with replacement characters
+pub fn decode_lossy(raw: &[u8]) -> String {
+    match decode_units(raw) {
+        Ok(units) => char::decode_utf16(units)
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect(),
+        Err(_) => String::from_utf8_lossy(raw).into_owned(),
+    }
+}
+
+/// Encode UTF-16 code units in the canonical form written by Java
+#[cfg_attr(not(test), allow(dead_code))]
+pub fn encode_units(units: impl IntoIterator<Item = u16>) -> Vec<u8> {
+    let mut raw = vec![];
+    for unit in units {
+        match unit {
+            0x01..=0x7f => raw.push(unit as u8),
+            // NUL included
+            0x00 | 0x80..=0x7ff => {
+                raw.push(0xc0 | (unit >> 6) as u8);
+                raw.push(0x80 | (unit & 0x3f) as u8);
+            }
+            _ => {
+                raw.push(0xe0 | (unit >> 12) as u8);
+                raw.push(0x80 | ((unit >> 6) & 0x3f) as u8);
+                raw.push(0x80 | (unit & 0x3f) as u8);
+            }
+        }
+    }
+    raw
+}
+
+#[cfg_attr(not(test), allow(dead_code))]
+pub fn encode(s: &str) -> Vec<u8> {
+    encode_units(s.encode_utf16())
+}
+
+/// Check that a rewrite may start or end at the byte, not inside a character or a surrogate pair
+fn is_boundary(raw: &[u8], at: usize) -> bool {
+    if at == 0 || at == raw.len() {
+        return true;
+    }
+    if at > raw.len() || is_continuation(raw[at]) {
+        return false;
+    }
+    let low_after = matches!(raw[at..], [0xed, 0xb0..=0xbf, ..]);
+    let high_before = at >= 3 && matches!(raw[at - 3..at], [0xed, 0xa0..=0xaf, _]);
+    !(low_after && high_before)
+}
+
+/// Replace the bytes in the range with the text, leaving the rest of the string as is
+/// The caller updates the length prefix of the NBT string, which must stay within `u16::MAX`
+#[cfg_attr(not(test), allow(dead_code))]
+pub fn splice(raw: &[u8], range: Range<usize>, text: &str) -> anyhow::Result<Vec<u8>> {
+    if range.start > range.end || !is_boundary(raw, range.start) || !is_boundary(raw, range.end) {
+        anyhow::bail!("{:?} is not on character boundaries", range);
+    }
+    let mut spliced = Vec::with_capacity(raw.len() + text.len());
+    spliced.extend_from_slice(&raw[..range.start]);
+    spliced.extend(encode(text));
+    spliced.extend_from_slice(&raw[range.end..]);
+    if spliced.len() > u16::MAX as usize {
+        anyhow::bail!("The string would be longer than {} bytes", u16::MAX);
+    }
+    Ok(spliced)
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    // U+1F600 as a surrogate pair, and an embedded NUL
+    let grin = [0xed, 0xa0, 0xbd, 0xed, 0xb8, 0x80];
+    let nul = [0xc0, 0x80];
+    assert_eq!(encode("\u{1f600}"), grin);
+    assert_eq!(encode("\0"), nul);
+    assert_eq!(encode("é"), "é".as_bytes());
+    assert_eq!(decode_lossy(&grin), "\u{1f600}");
+
+    let uuid = "2d318504-1a7b-39dc-8c18-44df798a5c06";
+    let mut raw = b"owner ".to_vec();
+    raw.extend(grin);
+    raw.extend(uuid.as_bytes());
+    raw.extend(nul);
+    raw.extend("ü".as_bytes());
+    // A lone surrogate survives the round trip too
+    raw.extend([0xed, 0xb0, 0x80]);
+    let units = decode_units(&raw).unwrap();
+    assert_eq!(encode_units(units.iter().copied()), raw);
+    assert_eq!(
+        decode_lossy(&raw),
+        format!("owner \u{1f600}{}\0ü\u{fffd}", uuid)
+    );
+
+    // A longer rewrite keeps every other byte as it was
+    let start = 6 + grin.len();
+    let spliced = splice(&raw, start..start + uuid.len(), "[I; 1, 2, 3, 4]").unwrap();
+    assert_eq!(spliced[..start], raw[..start]);
+    assert_eq!(spliced[start..start + 15], *b"[I; 1, 2, 3, 4]");
+    assert_eq!(spliced[start + 15..], raw[start + uuid.len()..]);
+    // A non-canonical sequence next to the rewrite is not fixed either
+    let overlong = [0xc1, 0x81, b'x'];
+    assert_eq!(
+        splice(&overlong, 2..3, "\0").unwrap(),
+        [0xc1, 0x81, 0xc0, 0x80]
+    );
+
+    // Inside a character or a surrogate pair
+    assert!(splice(&raw, 7..8, "").is_err());
+    assert!(splice(&raw, 9..9, "x").is_err());
+    assert!(splice(&raw, 6..12, "x").is_ok());
+    assert!(splice(&raw, 0..raw.len() + 1, "x").is_err());
+    assert!(splice(&[], 0..0, "x").is_ok());
+    assert!(splice(b"x", 0..1, &"x".repeat(u16::MAX as usize + 1)).is_err());
+
+    assert_eq!(decode_units(&[0xe0, 0x80]), Err(Malformed { offset: 0 }));
+    assert_eq!(decode_units(b"ab\xff"), Err(Malformed { offset: 2 }));
+    assert_eq!(decode_lossy(b"ab\xff"), "ab\u{fffd}");
+}
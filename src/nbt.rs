@@ -1,6 +1,9 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
-use crate::text::visit_text;
+use crate::{
+    source::{located, tagged, Location, Source},
+    text::visit_text,
+};
 
 use anyhow::Ok;
 use uuid::Uuid;
@@ -43,19 +46,110 @@ fn list_element_size(kind: u8) -> Option<usize> {
 
 type UuidBitLoc<'a> = Option<&'a mut [u8]>;
 
+/// Rules deciding which uuid-like fields are not uuids of players
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NbtRules {
+    /// Remap the `UUID` of the elements of `AttributeModifiers` lists, which identifies the
+    /// modifier, not a player
+    pub remap_attribute_modifiers: bool,
+    /// Also pair the longs written by versions before 1.13, `M` and `L` of `NBTUtil`,
+    /// and the `Most` and `Least` suffixes without `UUID`, e.g. `LoveCauseMost`
+    pub legacy_pairs: bool,
+}
+
+/// The field of the `M`/`L` pair, NBT names are modified UTF-8 so they never contain 0xff
+const ML_FIELD: &[u8] = b"\xffML";
+
+/// The field of a pre-1.13 long holding half of a uuid, and whether it is the most significant half
+fn legacy_half(name: &[u8]) -> Option<(&[u8], bool)> {
+    match name {
+        b"M" => Some((ML_FIELD, true)),
+        b"L" => Some((ML_FIELD, false)),
+        _ => name
+            .strip_suffix(b"Most")
+            .map(|field| (field, true))
+            .or_else(|| name.strip_suffix(b"Least").map(|field| (field, false)))
+            .filter(|(field, _)| !field.is_empty()),
+    }
+}
+
 enum VisitFrame<'a> {
-    Compound(HashMap<&'a [u8], (UuidBitLoc<'a>, UuidBitLoc<'a>)>),
-    List { kind: u8, index: usize, len: usize },
+    Compound {
+        /// The `Most`/`Least` halves waiting for the other half in the same compound,
+        /// a pair is remapped as soon as both are found, so an error later in the compound keeps it
+        fields: BTreeMap<&'a [u8], (UuidBitLoc<'a>, UuidBitLoc<'a>)>,
+        /// The compound is an element of an `AttributeModifiers` list
+        modifier: bool,
+        /// The key in the parent compound, empty for list elements and the root
+        name: &'a [u8],
+    },
+    List {
+        kind: u8,
+        index: usize,
+        len: usize,
+        /// The list is named `AttributeModifiers`
+        modifiers: bool,
+        name: &'a [u8],
+    },
+}
+
+impl<'a> VisitFrame<'a> {
+    fn name(&self) -> &'a [u8] {
+        match self {
+            VisitFrame::Compound { name, .. } | VisitFrame::List { name, .. } => *name,
+        }
+    }
+}
+
+/// The path of the value under `name` in the innermost frame, e.g. `Inventory[3].tag.Owner`
+fn nbt_path(stack: &[VisitFrame], name: &[u8]) -> String {
+    let mut path = String::new();
+    for (i, parent) in stack.iter().enumerate() {
+        let name = match stack.get(i + 1) {
+            Some(frame) => frame.name(),
+            None => name,
+        };
+        match parent {
+            VisitFrame::List { index, .. } => path.push_str(&format!("[{}]", index - 1)),
+            VisitFrame::Compound { .. } => {
+                if !path.is_empty() {
+                    path.push('.');
+                }
+                path.push_str(&String::from_utf8_lossy(name));
+            }
+        }
+    }
+    path
+}
+
+/// Narrow the location to the value, the path is only built inside `source::locate`
+fn at_path<'s>(stack: &'s [VisitFrame<'s>], name: &'s [u8]) -> impl FnOnce(&mut Location) + 's {
+    move |location| location.nbt_path = Some(nbt_path(stack, name))
 }
 
 struct NbtReader<'a, 'b, F: Fn(Uuid) -> Option<Uuid>> {
     nbt: &'a mut [u8],
     callback: &'b F,
+    rules: NbtRules,
 }
 
 impl<'a, 'b, F: Fn(Uuid) -> Option<Uuid>> NbtReader<'a, 'b, F> {
-    fn new(nbt: &'a mut [u8], callback: &'b F) -> Self {
-        Self { nbt, callback }
+    fn new(nbt: &'a mut [u8], callback: &'b F, rules: NbtRules) -> Self {
+        Self {
+            nbt,
+            callback,
+            rules,
+        }
+    }
+
+    /// Check if the uuid under the key in the innermost compound identifies an attribute modifier
+    fn is_modifier_uuid(&self, stack: &[VisitFrame<'a>], key: &[u8]) -> bool {
+        !self.rules.remap_attribute_modifiers
+            && key == b"UUID"
+            && matches!(
+                stack.last(),
+                Some(VisitFrame::Compound { modifier: true, .. })
+            )
     }
 
     fn take(&mut self, len: usize) -> anyhow::Result<&'a mut [u8]> {
@@ -72,16 +166,30 @@ impl<'a, 'b, F: Fn(Uuid) -> Option<Uuid>> NbtReader<'a, 'b, F> {
         self.take(len)
     }
 
-    fn visit_str(&mut self) -> anyhow::Result<()> {
-        visit_text(self.take_str()?, self.callback);
+    fn visit_str(&mut self, stack: &[VisitFrame<'a>], name: &[u8]) -> anyhow::Result<()> {
+        let text = self.take_str()?;
+        located(at_path(stack, name), || {
+            tagged(Source::NbtString, || visit_text(text, self.callback))
+        });
         Ok(())
     }
 
-    fn visit_uuid(&self, most: &mut [u8], least: &mut [u8]) -> anyhow::Result<()> {
+    /// `stack` and `name` locate the value, see `nbt_path`
+    fn visit_uuid(
+        &self,
+        most: &mut [u8],
+        least: &mut [u8],
+        source: Source,
+        stack: &[VisitFrame<'a>],
+        name: &[u8],
+    ) -> anyhow::Result<()> {
         let omost = u64::from_be_bytes(most.try_into().unwrap());
         let oleast = u64::from_be_bytes(least.try_into().unwrap());
         let uuid = Uuid::from_u64_pair(omost, oleast);
-        if let Some(new_uuid) = (self.callback)(uuid) {
+        let found = located(at_path(stack, name), || {
+            tagged(source, || (self.callback)(uuid))
+        });
+        if let Some(new_uuid) = found {
             let (nmost, nleast) = new_uuid.as_u64_pair();
             most.copy_from_slice(&nmost.to_be_bytes());
             least.copy_from_slice(&nleast.to_be_bytes());
@@ -89,13 +197,19 @@ impl<'a, 'b, F: Fn(Uuid) -> Option<Uuid>> NbtReader<'a, 'b, F> {
         Ok(())
     }
 
-    fn visit_value(&mut self, stack: &mut Vec<VisitFrame<'a>>, kind: u8) -> anyhow::Result<()> {
+    /// Visit a value, `name` is its key in the compound, or empty for list elements
+    fn visit_value(
+        &mut self,
+        stack: &mut Vec<VisitFrame<'a>>,
+        kind: u8,
+        name: &'a [u8],
+    ) -> anyhow::Result<()> {
         if kind == TAG_INT_ARRAY {
             let count = u32::from_be_bytes(self.take(4)?.try_into().unwrap()) as usize;
-            if count == 4 {
+            if count == 4 && !self.is_modifier_uuid(stack, name) {
                 let most = self.take(8)?;
                 let least = self.take(8)?;
-                self.visit_uuid(most, least)?;
+                self.visit_uuid(most, least, Source::NbtArray, stack, name)?;
             } else {
                 self.take(count * 4)?;
             }
@@ -105,7 +219,18 @@ impl<'a, 'b, F: Fn(Uuid) -> Option<Uuid>> NbtReader<'a, 'b, F> {
             let count = u32::from_be_bytes(self.take(4)?.try_into().unwrap()) as usize;
             self.take(count * element_size)?;
         } else if kind == TAG_COMPOUND {
-            stack.push(VisitFrame::Compound(HashMap::new()));
+            let modifier = matches!(
+                stack.last(),
+                Some(VisitFrame::List {
+                    modifiers: true,
+                    ..
+                })
+            );
+            stack.push(VisitFrame::Compound {
+                fields: BTreeMap::new(),
+                modifier,
+                name,
+            });
         } else if kind == TAG_LIST {
             let ele_kind = self.take(1)?[0];
             let count = u32::from_be_bytes(self.take(4)?.try_into().unwrap()) as usize;
@@ -116,10 +241,12 @@ impl<'a, 'b, F: Fn(Uuid) -> Option<Uuid>> NbtReader<'a, 'b, F> {
                     kind: ele_kind,
                     index: 0,
                     len: count,
+                    modifiers: name == b"AttributeModifiers",
+                    name,
                 });
             }
         } else if kind == TAG_STRING {
-            self.visit_str()?;
+            self.visit_str(stack, name)?;
         } else {
             anyhow::bail!("Malformed NBT: Unknown tag type {}", kind);
         }
@@ -142,47 +269,60 @@ impl<'a, 'b, F: Fn(Uuid) -> Option<Uuid>> NbtReader<'a, 'b, F> {
             return Ok(false);
         };
         match top {
-            VisitFrame::Compound(map) => {
+            VisitFrame::Compound {
+                fields: map,
+                modifier,
+                ..
+            } => {
                 let kind = self.take(1)?[0];
                 if kind == TAG_END {
-                    let Some(VisitFrame::Compound(map)) = stack.pop() else {
-                        unreachable!();
-                    };
-                    for uuid in map.into_values() {
-                        if let (Some(most_p), Some(least_p)) = uuid {
-                            self.visit_uuid(most_p, least_p)?;
-                        }
-                    }
+                    // The halves left are unpaired, they are not uuids
+                    stack.pop();
                 } else {
                     let name = self.take_str()?;
-                    if kind == TAG_LONG {
-                        if let Some(field) = strip_postfix!(name, b"UUIDMost") {
-                            if let Some((pos, _)) = map.get_mut(field) {
-                                *pos = Some(self.take(8)?);
-                            } else {
-                                map.insert(field, (Some(self.take(8)?), None));
-                            };
-                        } else if let Some(field) = strip_postfix!(name, b"UUIDLeast") {
-                            if let Some((_, pos)) = map.get_mut(field) {
-                                *pos = Some(self.take(8)?);
-                            } else {
-                                map.insert(field, (None, Some(self.take(8)?)));
-                            };
+                    let half = if kind != TAG_LONG {
+                        None
+                    } else if let Some(field) = strip_postfix!(name, b"UUIDMost") {
+                        Some((field, true))
+                    } else if let Some(field) = strip_postfix!(name, b"UUIDLeast") {
+                        Some((field, false))
+                    } else if self.rules.legacy_pairs {
+                        legacy_half(name)
+                    } else {
+                        None
+                    };
+                    if let Some((field, most)) = half {
+                        let value = Some(self.take(8)?);
+                        let (most_p, least_p) = map.entry(field).or_insert((None, None));
+                        if most {
+                            *most_p = value;
                         } else {
-                            self.visit_value(stack, kind)?;
+                            *least_p = value;
+                        }
+                        if most_p.is_some() && least_p.is_some() {
+                            let Some((Some(most_p), Some(least_p))) = map.remove(field) else {
+                                unreachable!();
+                            };
+                            // Legacy modifiers store the uuid as `UUIDMost` and `UUIDLeast`
+                            let skip_legacy = *modifier && !self.rules.remap_attribute_modifiers;
+                            if !(skip_legacy && field.is_empty()) {
+                                self.visit_uuid(most_p, least_p, Source::MostLeast, stack, name)?;
+                            }
                         }
                     } else {
-                        self.visit_value(stack, kind)?;
+                        self.visit_value(stack, kind, name)?;
                     }
                 }
             }
-            VisitFrame::List { kind, index, len } => {
+            VisitFrame::List {
+                kind, index, len, ..
+            } => {
                 if *index == *len {
                     stack.pop();
                 } else {
                     *index += 1;
                     let kind = *kind;
-                    self.visit_value(stack, kind)?;
+                    self.visit_value(stack, kind, b"")?;
                 }
             }
         }
@@ -193,7 +333,7 @@ impl<'a, 'b, F: Fn(Uuid) -> Option<Uuid>> NbtReader<'a, 'b, F> {
         let mut stack = Vec::with_capacity(32);
         let root_kind = self.take(1)?[0];
         self.take_str()?;
-        self.visit_value(&mut stack, root_kind)?;
+        self.visit_value(&mut stack, root_kind, b"")?;
         while self.step(&mut stack)? {}
         if !self.nbt.is_empty() {
             anyhow::bail!("Malformed NBT: Unexpected trailing data");
@@ -202,8 +342,158 @@ impl<'a, 'b, F: Fn(Uuid) -> Option<Uuid>> NbtReader<'a, 'b, F> {
     }
 }
 
+/// The root tag of a chunk is not a compound, e.g. chunks written by some ancient converters
+#[derive(Debug)]
+pub struct NonCompoundRoot {
+    pub tag: u8,
+}
+
+impl std::fmt::Display for NonCompoundRoot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Malformed NBT: Root tag type {} is not a compound",
+            self.tag
+        )
+    }
+}
+
+impl std::error::Error for NonCompoundRoot {}
+
+/// Check that the root tag is a compound, as it is for every chunk written by the game
+pub(crate) fn check_compound_root(nbt: &[u8]) -> Result<(), NonCompoundRoot> {
+    match nbt.first() {
+        Some(&tag) if tag != TAG_COMPOUND => Err(NonCompoundRoot { tag }),
+        _ => std::result::Result::Ok(()),
+    }
+}
+
 pub(crate) fn visit_nbt(nbt: &mut [u8], cb: &impl Fn(Uuid) -> Option<Uuid>) -> anyhow::Result<()> {
-    NbtReader::new(nbt, cb).process()
+    visit_nbt_with(nbt, cb, NbtRules::default())
+}
+
+pub(crate) fn visit_nbt_with(
+    nbt: &mut [u8],
+    cb: &impl Fn(Uuid) -> Option<Uuid>,
+    rules: NbtRules,
+) -> anyhow::Result<()> {
+    NbtReader::new(nbt, cb, rules).process()
+}
+
+/// Skip a value of the kind at the start of `nbt`, returning the rest
+fn skip_value(nbt: &[u8], kind: u8) -> anyhow::Result<&[u8]> {
+    fn take(nbt: &[u8], len: usize) -> anyhow::Result<(&[u8], &[u8])> {
+        if len > nbt.len() {
+            anyhow::bail!("Malformed NBT: Unexpected EOF");
+        }
+        Ok(nbt.split_at(len))
+    }
+    fn count(nbt: &[u8], len: usize) -> anyhow::Result<(usize, &[u8])> {
+        let (head, rest) = take(nbt, len)?;
+        let count = match len {
+            2 => u16::from_be_bytes(head.try_into().unwrap()) as usize,
+            _ => u32::from_be_bytes(head.try_into().unwrap()) as usize,
+        };
+        Ok((count, rest))
+    }
+    if let Some(size) = tag_size(kind) {
+        return Ok(take(nbt, size)?.1);
+    }
+    if let Some(element_size) = list_element_size(kind) {
+        let (len, rest) = count(nbt, 4)?;
+        return Ok(take(rest, len.saturating_mul(element_size))?.1);
+    }
+    match kind {
+        TAG_STRING => {
+            let (len, rest) = count(nbt, 2)?;
+            Ok(take(rest, len)?.1)
+        }
+        TAG_LIST => {
+            let (head, rest) = take(nbt, 1)?;
+            let (len, mut rest) = count(rest, 4)?;
+            for _ in 0..len {
+                rest = skip_value(rest, head[0])?;
+            }
+            Ok(rest)
+        }
+        TAG_COMPOUND => {
+            let mut rest = nbt;
+            loop {
+                let (head, tail) = take(rest, 1)?;
+                if head[0] == TAG_END {
+                    return Ok(tail);
+                }
+                let (len, tail) = count(tail, 2)?;
+                rest = skip_value(take(tail, len)?.1, head[0])?;
+            }
+        }
+        _ => anyhow::bail!("Malformed NBT: Unknown tag type {}", kind),
+    }
+}
+
+/// The type and the payload of the tag under the path of compound keys, read only
+fn find_tag<'a>(nbt: &'a [u8], path: &[&str]) -> anyhow::Result<Option<(u8, &'a [u8])>> {
+    let Some((&TAG_COMPOUND, rest)) = nbt.split_first() else {
+        return Ok(None);
+    };
+    // The name of the root
+    let mut rest = skip_value(rest, TAG_STRING)?;
+    let mut path = path.iter();
+    let Some(mut key) = path.next() else {
+        return Ok(None);
+    };
+    loop {
+        let Some((&kind, tail)) = rest.split_first() else {
+            anyhow::bail!("Malformed NBT: Unexpected EOF");
+        };
+        if kind == TAG_END {
+            return Ok(None);
+        }
+        let after_name = skip_value(tail, TAG_STRING)?;
+        let name = &tail[2..tail.len() - after_name.len()];
+        if name != key.as_bytes() {
+            rest = skip_value(after_name, kind)?;
+            continue;
+        }
+        match (path.next(), kind) {
+            (None, _) => {
+                let after_value = skip_value(after_name, kind)?;
+                return Ok(Some((
+                    kind,
+                    &after_name[..after_name.len() - after_value.len()],
+                )));
+            }
+            (Some(next), TAG_COMPOUND) => {
+                key = next;
+                rest = after_name;
+            }
+            _ => return Ok(None),
+        }
+    }
+}
+
+/// Read the int under the path of compound keys, e.g. `["Data", "DataVersion"]`
+pub(crate) fn find_int(nbt: &[u8], path: &[&str]) -> anyhow::Result<Option<i32>> {
+    Ok(match find_tag(nbt, path)? {
+        Some((TAG_INT, value)) => Some(i32::from_be_bytes(value.try_into().unwrap())),
+        _ => None,
+    })
+}
+
+/// Read the long under the path of compound keys, e.g. `["bukkit", "lastPlayed"]`
+pub(crate) fn find_long(nbt: &[u8], path: &[&str]) -> anyhow::Result<Option<i64>> {
+    Ok(match find_tag(nbt, path)? {
+        Some((TAG_LONG, value)) => Some(i64::from_be_bytes(value.try_into().unwrap())),
+        _ => None,
+    })
+}
+
+/// Read the string under the path of compound keys, e.g. `["bukkit", "lastKnownName"]`
+pub(crate) fn find_string(nbt: &[u8], path: &[&str]) -> anyhow::Result<Option<String>> {
+    let Some((TAG_STRING, value)) = find_tag(nbt, path)? else {
+        return Ok(None);
+    };
+    Ok(Some(crate::mutf8::decode_lossy(&value[2..])))
 }
 
 #[cfg(test)]
@@ -359,3 +649,288 @@ fn test_visit_nbt() {
     assert_eq!(de.get("UUIDMost"), Some(&Value::Int(7)));
     assert_eq!(de.get("UUIDLeast"), Some(&Value::Int(32))); // Should not be replaced
 }
+
+#[cfg(test)]
+#[test]
+fn test_attribute_modifiers() {
+    use valence_nbt::{binary::to_binary, from_binary, snbt::from_snbt_str, Value};
+
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    const FROM: Uuid = Uuid::from_u128(0x1234567890abcdef1234567890abcdef);
+    const TO: Uuid = Uuid::from_u128(0xabcdef1234567890abcdef1234567890);
+    let ints = |uuid: Uuid| {
+        let u = uuid.as_u128();
+        format!(
+            "[I; {}, {}, {}, {}]",
+            (u >> 96) as i32,
+            (u >> 64) as i32,
+            (u >> 32) as i32,
+            u as i32
+        )
+    };
+    let (most, least) = FROM.as_u64_pair();
+    // A modifier whose uuid happens to be a mapping key, next to a real owner
+    let snbt = |uuid: Uuid| {
+        format!(
+            r#"{{
+                Inventory: [{{
+                    id: "minecraft:diamond_sword",
+                    Count: 1b,
+                    tag: {{
+                        AttributeModifiers: [
+                            {{AttributeName: "generic.attack_damage", Amount: 5.0d, Operation: 0, UUID: {}}},
+                            {{AttributeName: "generic.armor", Amount: 1.0d, Operation: 0, UUIDMost: {}L, UUIDLeast: {}L}}
+                        ],
+                        Owner: {}
+                    }}
+                }}],
+                Pets: [{{UUID: {}}}]
+            }}"#,
+            ints(FROM),
+            most as i64,
+            least as i64,
+            ints(uuid),
+            ints(uuid)
+        )
+    };
+    let encode = |snbt: &str| {
+        let Value::Compound(nbt) = from_snbt_str(snbt).unwrap() else {
+            panic!()
+        };
+        let mut binary = vec![];
+        to_binary(&nbt, &mut binary, "").unwrap();
+        binary
+    };
+    let decode = |binary: &[u8]| from_binary::<String>(&mut &binary[..]).unwrap().0;
+    let cb = |uuid| (uuid == FROM).then_some(TO);
+
+    let mut nbt = encode(&snbt(FROM));
+    visit_nbt(&mut nbt, &cb).unwrap();
+    assert_eq!(decode(&nbt), decode(&encode(&snbt(TO))));
+
+    let mut nbt = encode(&snbt(FROM));
+    let rules = NbtRules {
+        remap_attribute_modifiers: true,
+        ..Default::default()
+    };
+    visit_nbt_with(&mut nbt, &cb, rules).unwrap();
+    let (most, least) = TO.as_u64_pair();
+    let remapped = snbt(TO)
+        .replacen(&ints(FROM), &ints(TO), 1)
+        .replace(
+            &format!("{}L", FROM.as_u64_pair().0 as i64),
+            &format!("{}L", most as i64),
+        )
+        .replace(
+            &format!("{}L", FROM.as_u64_pair().1 as i64),
+            &format!("{}L", least as i64),
+        );
+    assert_eq!(decode(&nbt), decode(&encode(&remapped)));
+}
+
+#[cfg(test)]
+#[test]
+fn test_legacy_pairs() {
+    use valence_nbt::{binary::to_binary, from_binary, snbt::from_snbt_str, Value};
+
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    const FROM: Uuid = Uuid::from_u128(0x1234567890abcdef1234567890abcdef);
+    const TO: Uuid = Uuid::from_u128(0xabcdef1234567890abcdef1234567890);
+    let halves = |uuid: Uuid| {
+        let (most, least) = uuid.as_u64_pair();
+        (most as i64, least as i64)
+    };
+    // A 1.12 shulker bullet and a cow in love, `bare` is under `Most`/`Least` without a prefix
+    let snbt = |owner: Uuid, love: Uuid, bare: Uuid| {
+        let ((om, ol), (lm, ll), (bm, bl)) = (halves(owner), halves(love), halves(bare));
+        format!(
+            r#"{{
+                Entities: [
+                    {{id: "minecraft:shulker_bullet", Owner: {{M: {}L, L: {}L, X: 0, Y: 64, Z: 0}}}},
+                    {{id: "minecraft:cow", LoveCauseMost: {}L, LoveCauseLeast: {}L, Most: {}L, Least: {}L}}
+                ],
+                M: {}L,
+                DataVersion: 1343
+            }}"#,
+            om,
+            ol,
+            lm,
+            ll,
+            bm,
+            bl,
+            halves(FROM).0
+        )
+    };
+    let encode = |snbt: &str| {
+        let Value::Compound(nbt) = from_snbt_str(snbt).unwrap() else {
+            panic!()
+        };
+        let mut binary = vec![];
+        to_binary(&nbt, &mut binary, "").unwrap();
+        binary
+    };
+    let decode = |binary: &[u8]| from_binary::<String>(&mut &binary[..]).unwrap().0;
+    let cb = |uuid| (uuid == FROM).then_some(TO);
+
+    // Not uuids in modern worlds
+    let mut nbt = encode(&snbt(FROM, FROM, FROM));
+    visit_nbt(&mut nbt, &cb).unwrap();
+    assert_eq!(decode(&nbt), decode(&encode(&snbt(FROM, FROM, FROM))));
+
+    // The unpaired `M` and the bare `Most`/`Least` are kept
+    let rules = NbtRules {
+        legacy_pairs: true,
+        ..Default::default()
+    };
+    let mut nbt = encode(&snbt(FROM, FROM, FROM));
+    visit_nbt_with(&mut nbt, &cb, rules).unwrap();
+    assert_eq!(decode(&nbt), decode(&encode(&snbt(TO, TO, FROM))));
+
+    let nbt = encode(&snbt(FROM, FROM, FROM));
+    assert_eq!(find_int(&nbt, &["DataVersion"]).unwrap(), Some(1343));
+    // Not an int
+    assert_eq!(find_int(&nbt, &["M"]).unwrap(), None);
+    assert_eq!(find_int(&nbt, &["Entities", "id"]).unwrap(), None);
+    assert_eq!(find_int(&nbt, &["Missing"]).unwrap(), None);
+    assert!(find_int(&nbt[..nbt.len() - 8], &["Missing"]).is_err());
+
+    let nbt = encode(r#"{bukkit: {lastKnownName: "alice", lastPlayed: 0L}, DataVersion: 3700}"#);
+    assert_eq!(
+        find_string(&nbt, &["bukkit", "lastKnownName"]).unwrap(),
+        Some("alice".to_string())
+    );
+    // Not a string
+    assert_eq!(find_string(&nbt, &["bukkit", "lastPlayed"]).unwrap(), None);
+    assert_eq!(find_string(&nbt, &["bukkit"]).unwrap(), None);
+    assert_eq!(find_long(&nbt, &["bukkit", "lastPlayed"]).unwrap(), Some(0));
+    assert_eq!(find_int(&nbt, &["DataVersion"]).unwrap(), Some(3700));
+}
+
+#[cfg(test)]
+#[test]
+fn test_pairs_flushed() {
+    use valence_nbt::{binary::to_binary, from_binary, Compound, Value};
+
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    const FROM: Uuid = Uuid::from_u128(0x1234567890abcdef1234567890abcdef);
+    const TO: Uuid = Uuid::from_u128(0xabcdef1234567890abcdef1234567890);
+    let (from_most, from_least) = FROM.as_u64_pair();
+    let (to_most, to_least) = TO.as_u64_pair();
+    // A cooldown map of a mod, keyed by the names of the players
+    let mut nbtc = Compound::<String>::new();
+    for i in 0..500 {
+        nbtc.insert(
+            format!("player{}UUIDMost", i),
+            Value::Long(from_most as i64),
+        );
+        nbtc.insert(
+            format!("player{}UUIDLeast", i),
+            Value::Long(from_least as i64),
+        );
+    }
+    // The other half comes after the error
+    nbtc.insert("lateUUIDMost".to_string(), Value::Long(from_most as i64));
+    let mut nbt = vec![];
+    to_binary(&nbtc, &mut nbt, "").unwrap();
+    let end = nbt.len() - 1;
+    assert_eq!(nbt[end], TAG_END);
+    // An illegal tag type after the pairs
+    nbt.truncate(end);
+    nbt.extend([255, 0, 3, b'b', b'a', b'd']);
+    nbt.extend([TAG_LONG, 0, 13]);
+    nbt.extend(b"lateUUIDLeast");
+    nbt.extend(from_least.to_be_bytes());
+    nbt.push(TAG_END);
+    let cb = |uuid| (uuid == FROM).then_some(TO);
+    assert!(visit_nbt(&mut nbt, &cb).is_err());
+
+    // Cut the broken tail to read the pairs back
+    nbt.truncate(end);
+    nbt.push(TAG_END);
+    let (de, _) = from_binary::<String>(&mut nbt.as_slice()).unwrap();
+    for i in 0..500 {
+        assert_eq!(
+            de.get(&format!("player{}UUIDMost", i)),
+            Some(&Value::Long(to_most as i64))
+        );
+        assert_eq!(
+            de.get(&format!("player{}UUIDLeast", i)),
+            Some(&Value::Long(to_least as i64))
+        );
+    }
+    assert_eq!(de.get("lateUUIDMost"), Some(&Value::Long(from_most as i64)));
+}
+
+#[cfg(test)]
+#[test]
+fn test_mod_sections() {
+    use valence_nbt::{binary::to_binary, from_binary, snbt::from_snbt_str, Value};
+
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    const FROM: Uuid = Uuid::from_u128(0x1234567890abcdef1234567890abcdef);
+    const TO: Uuid = Uuid::from_u128(0xabcdef1234567890abcdef1234567890);
+    let ints = |uuid: Uuid| {
+        let u = uuid.as_u128();
+        format!(
+            "[I; {}, {}, {}, {}]",
+            (u >> 96) as i32,
+            (u >> 64) as i32,
+            (u >> 32) as i32,
+            u as i32
+        )
+    };
+    // Claims of mods under keys of their own, the uuids are found whatever the names
+    let snbt = |uuid: Uuid| {
+        format!(
+            r#"{{Level: {{xPos: 0, zPos: 0, CustomData: {{ars_nouveau: {{warden: {}}},
+            ftbchunks: {{claims: [{{team: "{}", since: 0L}}]}}}}}}}}"#,
+            ints(uuid),
+            uuid.hyphenated()
+        )
+    };
+    let encode = |snbt: &str| {
+        let Value::Compound(nbt) = from_snbt_str(snbt).unwrap() else {
+            panic!()
+        };
+        let mut binary = vec![];
+        to_binary(&nbt, &mut binary, "").unwrap();
+        binary
+    };
+    let decode = |binary: &[u8]| from_binary::<String>(&mut &binary[..]).unwrap().0;
+    let cb = |uuid| (uuid == FROM).then_some(TO);
+    let mut nbt = encode(&snbt(FROM));
+    visit_nbt(&mut nbt, &cb).unwrap();
+    assert_eq!(decode(&nbt), decode(&encode(&snbt(TO))));
+
+    // Each uuid is located by its path, the string also by its offset
+    let found = std::cell::RefCell::new(vec![]);
+    let record = |_: Uuid| -> Option<Uuid> {
+        found.borrow_mut().push(crate::source::location().unwrap());
+        None
+    };
+    crate::source::locate(|| visit_nbt(&mut encode(&snbt(FROM)), &record)).unwrap();
+    let found = found
+        .into_inner()
+        .iter()
+        .map(|location| location.to_string())
+        .collect::<std::collections::BTreeSet<_>>();
+    assert_eq!(
+        found,
+        std::collections::BTreeSet::from([
+            "Level.CustomData.ars_nouveau.warden".to_string(),
+            "Level.CustomData.ftbchunks.claims[0].team, offset 0".to_string(),
+        ])
+    );
+}
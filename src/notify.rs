@@ -0,0 +1,170 @@
+use std::path::PathBuf;
+
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::report::Summary;
+
+/// How long a notification may take, it is sent once more after a failure
+#[cfg(feature = "online")]
+pub const TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// When the webhook is notified
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum NotifyOn {
+    Always,
+    /// Unless the run is done without any error
+    Errors,
+}
+
+impl NotifyOn {
+    pub fn wants(self, notification: &Notification) -> bool {
+        match self {
+            NotifyOn::Always => true,
+            NotifyOn::Errors => notification.outcome != "done" || notification.summary.errors > 0,
+        }
+    }
+}
+
+/// The payload posted to `--notify-webhook`
+#[derive(Debug, Serialize)]
+pub struct Notification {
+    /// `done`, `failed` or `cancelled`, like the exit code
+    pub outcome: &'static str,
+    pub world: PathBuf,
+    /// The same as in the report, zero if the run ends before remapping
+    pub summary: Summary,
+}
+
+/// Post the notification as JSON, retried once
+#[cfg(feature = "online")]
+pub fn post(url: &str, notification: &Notification) -> anyhow::Result<()> {
+    let client = reqwest::blocking::Client::builder()
+        .timeout(TIMEOUT)
+        .build()?;
+    let send = || -> anyhow::Result<()> {
+        client
+            .post(url)
+            .json(notification)
+            .send()?
+            .error_for_status()?;
+        Ok(())
+    };
+    send().or_else(|err| {
+        log::debug!("Retrying the notification after: {:#}", err);
+        send()
+    })
+}
+
+#[cfg(not(feature = "online"))]
+pub fn post(_url: &str, _notification: &Notification) -> anyhow::Result<()> {
+    Err(crate::online::unsupported("--notify-webhook"))
+}
+
+#[cfg(all(test, feature = "online"))]
+#[test]
+fn test() {
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+    };
+
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    // Fails the first request, to check the retry
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/hook", listener.local_addr().unwrap());
+    let server = std::thread::spawn(move || {
+        let mut bodies = vec![];
+        for status in ["500 Internal Server Error", "200 OK"] {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut len = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if let Some(value) = line.to_lowercase().strip_prefix("content-length:") {
+                    len = value.trim().parse().unwrap();
+                }
+                if line == "\r\n" {
+                    break;
+                }
+            }
+            let mut body = vec![0; len];
+            reader.read_exact(&mut body).unwrap();
+            bodies.push(serde_json::from_slice::<serde_json::Value>(&body).unwrap());
+            write!(
+                reader.get_mut(),
+                "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                status
+            )
+            .unwrap();
+        }
+        bodies
+    });
+
+    let notification = Notification {
+        outcome: "done",
+        world: PathBuf::from("/srv/world"),
+        summary: Summary {
+            files_scanned: 10,
+            files_modified: 3,
+            replacements: 42,
+            errors: 1,
+            ..Summary::default()
+        },
+    };
+    post(&url, &notification).unwrap();
+    let bodies = server.join().unwrap();
+    assert_eq!(bodies[0], bodies[1]);
+    assert_eq!(
+        bodies[1],
+        serde_json::json!({
+            "outcome": "done",
+            "world": "/srv/world",
+            "summary": {
+                "files_scanned": 10,
+                "files_modified": 3,
+                "files_skipped": 0,
+                "files_frozen": 0,
+                "files_failed": 0,
+                "replacements": 42,
+                "replacements_by_source": {
+                    "nbt_array": 0,
+                    "most_least": 0,
+                    "nbt_string": 0,
+                    "text_dashed": 0,
+                    "text_undashed": 0,
+                    "text_decimal": 0,
+                    "file_name": 0
+                },
+                "renames": 0,
+                "rename_conflicts": 0,
+                "region_anomalies": 0,
+                "errors": 1,
+                "prefilter_build_secs": 0.0,
+                "elapsed_secs": 0.0
+            }
+        })
+    );
+
+    assert!(NotifyOn::Errors.wants(&notification));
+    let clean = Notification {
+        summary: Summary {
+            errors: 0,
+            ..notification.summary
+        },
+        ..notification
+    };
+    assert!(!NotifyOn::Errors.wants(&clean));
+    assert!(NotifyOn::Always.wants(&clean));
+    let cancelled = Notification {
+        outcome: "cancelled",
+        ..clean
+    };
+    assert!(NotifyOn::Errors.wants(&cancelled));
+    // Nothing is listening anymore
+    assert!(post(&url, &cancelled).is_err());
+}
@@ -0,0 +1,1154 @@
+//! Everything that needs the network, only built with the `online` feature, which is enabled by
+//! default, build with `--no-default-features` for servers without access to the Mojang API
+
+use std::{path::PathBuf, time::Duration};
+
+use clap::ValueEnum;
+
+#[cfg(feature = "online")]
+use std::{
+    cell::Cell,
+    collections::{BTreeMap, HashMap},
+    path::Path,
+    time::SystemTime,
+};
+
+#[cfg(feature = "online")]
+use anyhow::Context;
+#[cfg(feature = "online")]
+use indicatif::ProgressBar;
+#[cfg(feature = "online")]
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "online")]
+use uuid::Uuid;
+
+#[cfg(feature = "online")]
+use crate::retry::RetryPolicy;
+
+/// The API the names are looked up with, with `--resolver`, all of them give the same uuids
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Resolver {
+    /// api.mojang.com, asked 10 names per request
+    #[default]
+    Mojang,
+    /// playerdb.co, a mirror of the Mojang API asked one name per request
+    #[value(name = "playerdb")]
+    PlayerDb,
+    /// api.ashcon.app, a mirror of the Mojang API asked one name per request
+    Ashcon,
+}
+
+/// How the online uuids are looked up
+#[derive(Debug, Clone)]
+#[cfg_attr(not(feature = "online"), allow(dead_code))]
+pub struct OnlineLookup {
+    /// Retry a failed request this many times
+    pub retries: u32,
+    /// The delay before the first retry, doubled for each further retry
+    pub retry_delay: Duration,
+    /// The file caching the looked up names, `None` to always ask the API
+    pub cache: Option<PathBuf>,
+    /// Cached names older than this are looked up again
+    pub cache_ttl: Duration,
+    pub resolver: Resolver,
+    /// Replaces `https://api.mojang.com` for the Mojang resolver, e.g. the Yggdrasil API of an
+    /// authlib-injector server, with `--api-base`
+    pub api_base: Option<String>,
+    /// Proceed when fewer than `mapping::MIN_RESOLVED` of the names are found, with `--allow-partial-mapping`
+    pub allow_partial: bool,
+}
+
+impl Default for OnlineLookup {
+    fn default() -> Self {
+        Self {
+            retries: 5,
+            retry_delay: Duration::from_secs(1),
+            cache: None,
+            cache_ttl: Duration::from_secs(24 * 60 * 60),
+            resolver: Resolver::Mojang,
+            api_base: None,
+            allow_partial: false,
+        }
+    }
+}
+
+/// `~/.cache/uuid-remapper/mojang.json`, or under `$XDG_CACHE_HOME` if it is set
+/// Another `--api-base` has other players, so it is cached in a file named after its host
+pub fn default_cache(api_base: Option<&str>) -> Option<PathBuf> {
+    let dir = std::env::var_os("XDG_CACHE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| crate::task::home_dir().map(|home| home.join(".cache")))?;
+    let name = match api_base {
+        Some(base) => {
+            let host = base.split_once("://").map_or(base, |(_, rest)| rest);
+            let host = host.split('/').next().unwrap_or_default();
+            host.replace(
+                |c: char| !c.is_ascii_alphanumeric() && c != '.' && c != '-',
+                "_",
+            )
+        }
+        None => "mojang".to_string(),
+    };
+    Some(dir.join("uuid-remapper").join(format!("{}.json", name)))
+}
+
+/// A looked up name, `uuid` is `None` if no player has it
+#[cfg(feature = "online")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CacheEntry {
+    /// The name as the API spells it
+    name: String,
+    uuid: Option<Uuid>,
+    /// Seconds since the Unix epoch
+    fetched: u64,
+}
+
+/// The names looked up before, keyed by the lowercase name, as the API ignores the case
+#[cfg(feature = "online")]
+#[derive(Debug, Default)]
+struct Cache {
+    entries: BTreeMap<String, CacheEntry>,
+    modified: bool,
+}
+
+#[cfg(feature = "online")]
+impl Cache {
+    /// A missing or unreadable cache is empty, it is only an optimization
+    fn load(path: &Path) -> Self {
+        let entries = match std::fs::read(path) {
+            Ok(content) => serde_json::from_slice(&content).unwrap_or_else(|err| {
+                log::warn!("Ignoring the corrupt cache {}: {}", path.display(), err);
+                BTreeMap::new()
+            }),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(err) => {
+                log::warn!("Failed to read the cache {}: {}", path.display(), err);
+                BTreeMap::new()
+            }
+        };
+        Self {
+            entries,
+            modified: false,
+        }
+    }
+
+    fn get(&self, name: &str, now: u64, ttl: Duration) -> Option<&CacheEntry> {
+        self.entries
+            .get(&name.to_lowercase())
+            .filter(|entry| now.saturating_sub(entry.fetched) < ttl.as_secs())
+    }
+
+    fn insert(&mut self, requested: &str, entry: CacheEntry) {
+        self.entries.insert(requested.to_lowercase(), entry);
+        self.modified = true;
+    }
+
+    /// Write the cache back without the expired entries, a failure is only warned
+    fn save(&mut self, path: &Path, now: u64, ttl: Duration) {
+        if !self.modified {
+            return;
+        }
+        self.entries
+            .retain(|_, entry| now.saturating_sub(entry.fetched) < ttl.as_secs());
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(".tmp");
+        let result = path
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|()| Ok(serde_json::to_vec_pretty(&self.entries)?))
+            .and_then(|content| std::fs::write(&temp, content))
+            .and_then(|()| std::fs::rename(&temp, path));
+        match result {
+            Ok(()) => self.modified = false,
+            Err(err) => log::warn!("Failed to write the cache {}: {}", path.display(), err),
+        }
+    }
+}
+
+#[cfg(feature = "online")]
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+/// Why a request failed
+#[cfg(feature = "online")]
+#[derive(Debug)]
+enum Failure {
+    /// 429, waiting for `Retry-After` if the API tells it
+    RateLimited(Option<Duration>),
+    /// A network error or a server error, likely to go away when retried
+    Transient(String),
+    /// Retrying would not help, e.g. a malformed request
+    Fatal(String),
+}
+
+#[cfg(feature = "online")]
+impl std::fmt::Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Failure::RateLimited(_) => write!(f, "rate limited (429)"),
+            Failure::Transient(err) | Failure::Fatal(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+/// The longest wait for `Retry-After`, in retry delays, so a bogus header can't stall the run
+#[cfg(feature = "online")]
+const RETRY_AFTER_CAP: u32 = 60;
+
+/// Run the request, retrying with an exponential backoff, the state is shown in the bar
+#[cfg(feature = "online")]
+fn with_retries<T>(
+    lookup: &OnlineLookup,
+    pg: &ProgressBar,
+    mut request: impl FnMut() -> Result<T, Failure>,
+    sleep: impl Fn(Duration),
+) -> anyhow::Result<T> {
+    let policy = RetryPolicy::new(lookup.retries, lookup.retry_delay);
+    let mut attempt = 0;
+    loop {
+        let failure = match request() {
+            Ok(result) => {
+                pg.set_message("");
+                return Ok(result);
+            }
+            Err(Failure::Fatal(err)) => anyhow::bail!("{}", err),
+            Err(failure) if attempt >= lookup.retries => {
+                anyhow::bail!("{} after {} attempts", failure, attempt + 1)
+            }
+            Err(failure) => failure,
+        };
+        let delay = match failure {
+            Failure::RateLimited(Some(retry_after)) => {
+                retry_after.min(lookup.retry_delay.saturating_mul(RETRY_AFTER_CAP))
+            }
+            _ => policy.backoff(attempt),
+        };
+        attempt += 1;
+        let state = format!(
+            "{}, retry {}/{} in {:.1}s",
+            failure,
+            attempt,
+            lookup.retries,
+            delay.as_secs_f64()
+        );
+        log::debug!("Looking up online uuids: {}", state);
+        pg.set_message(state);
+        sleep(delay);
+    }
+}
+
+/// A profile returned by the API
+#[cfg(feature = "online")]
+#[derive(Debug, Clone, Deserialize)]
+struct Profile {
+    id: Uuid,
+    name: String,
+}
+
+/// Looks up the profiles with the API, replaced in the tests
+#[cfg(feature = "online")]
+trait ProfileApi {
+    /// Whether `profiles` is supported, otherwise every name is asked with `profile`
+    fn bulk(&self) -> bool {
+        true
+    }
+    /// The profiles of at most `CHUNK` names, names without a profile are left out
+    /// Asks `profile` for each name unless the API has a bulk endpoint
+    fn profiles(&self, names: &[&String]) -> Result<Vec<Profile>, Failure> {
+        let mut profiles = vec![];
+        for name in names {
+            profiles.extend(self.profile(name)?);
+        }
+        Ok(profiles)
+    }
+    /// The profile of a single name, `None` if no player has it
+    fn profile(&self, name: &str) -> Result<Option<Profile>, Failure>;
+}
+
+/// The number of names in a request, the Mojang API limit
+#[cfg(feature = "online")]
+const CHUNK: usize = 10;
+
+#[cfg(feature = "online")]
+struct MojangApi {
+    client: reqwest::blocking::Client,
+    /// `https://api.mojang.com`, or the `--api-base`
+    base: String,
+    /// The base answers under `/api`, like the Yggdrasil root of Blessing Skin, e.g. LittleSkin,
+    /// found when the bulk endpoint is not found at the standard path
+    nested: Cell<bool>,
+}
+
+#[cfg(feature = "online")]
+impl MojangApi {
+    fn new(client: reqwest::blocking::Client, base: Option<&str>) -> Self {
+        Self {
+            client,
+            base: base
+                .unwrap_or("https://api.mojang.com")
+                .trim_end_matches('/')
+                .to_string(),
+            nested: Cell::new(false),
+        }
+    }
+
+    fn url(&self, path: &str) -> String {
+        let nested = if self.nested.get() { "/api" } else { "" };
+        format!("{}{}{}", self.base, nested, path)
+    }
+
+    fn post_names(&self, names: &[&String]) -> Result<reqwest::blocking::Response, Failure> {
+        self.client
+            .post(self.url("/profiles/minecraft"))
+            .json(names)
+            .send()
+            .map_err(|err| Failure::Transient(err.to_string()))
+    }
+}
+
+/// Get the url with the name appended as a path segment, escaped
+#[cfg(feature = "online")]
+fn get_name(
+    client: &reqwest::blocking::Client,
+    base: &str,
+    name: &str,
+) -> Result<reqwest::blocking::Response, Failure> {
+    let invalid =
+        |err: &dyn std::fmt::Display| Failure::Fatal(format!("Invalid url {}: {}", base, err));
+    let mut url = reqwest::Url::parse(base).map_err(|err| invalid(&err))?;
+    url.path_segments_mut()
+        .map_err(|()| invalid(&"it can't have a path"))?
+        .push(name);
+    client
+        .get(url)
+        .send()
+        .map_err(|err| Failure::Transient(err.to_string()))
+}
+
+/// Classify the errors of a response, a success is returned as is
+#[cfg(feature = "online")]
+fn check_status(
+    response: reqwest::blocking::Response,
+) -> Result<reqwest::blocking::Response, Failure> {
+    let status = response.status();
+    if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+        let retry_after = response
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|value| value.to_str().ok()?.parse::<u64>().ok())
+            .map(Duration::from_secs);
+        return Err(Failure::RateLimited(retry_after));
+    }
+    if status.is_server_error() {
+        return Err(Failure::Transient(format!("server error ({})", status)));
+    }
+    if !status.is_success() {
+        return Err(Failure::Fatal(format!("request refused ({})", status)));
+    }
+    Ok(response)
+}
+
+#[cfg(feature = "online")]
+impl ProfileApi for MojangApi {
+    /// Post the names to the bulk endpoint
+    fn profiles(&self, names: &[&String]) -> Result<Vec<Profile>, Failure> {
+        let mut response = self.post_names(names)?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND && !self.nested.get() {
+            self.nested.set(true);
+            log::info!(
+                "{} is not found, trying {}",
+                response.url(),
+                self.url("/profiles/minecraft")
+            );
+            response = self.post_names(names)?;
+        }
+        // A truncated body is a network error
+        check_status(response)?
+            .json()
+            .map_err(|err| Failure::Transient(err.to_string()))
+    }
+
+    /// Get the name from the single profile endpoint, which also knows some names the bulk one drops
+    fn profile(&self, name: &str) -> Result<Option<Profile>, Failure> {
+        let response = get_name(&self.client, &self.url("/users/profiles/minecraft"), name)?;
+        // No content, not found, or not a valid name
+        if matches!(
+            response.status(),
+            reqwest::StatusCode::NO_CONTENT
+                | reqwest::StatusCode::NOT_FOUND
+                | reqwest::StatusCode::BAD_REQUEST
+        ) {
+            return Ok(None);
+        }
+        check_status(response)?
+            .json()
+            .map(Some)
+            .map_err(|err| Failure::Transient(err.to_string()))
+    }
+}
+
+/// The response of playerdb.co, `data` is only filled when the player is found
+#[cfg(feature = "online")]
+#[derive(Debug, Deserialize)]
+struct PlayerDbResponse {
+    code: String,
+    #[serde(default)]
+    data: Option<PlayerDbData>,
+}
+
+#[cfg(feature = "online")]
+#[derive(Debug, Deserialize)]
+struct PlayerDbData {
+    player: Option<PlayerDbPlayer>,
+}
+
+#[cfg(feature = "online")]
+#[derive(Debug, Deserialize)]
+struct PlayerDbPlayer {
+    id: Uuid,
+    username: String,
+}
+
+/// Parse a response of playerdb.co, which answers errors with a code in the body, often with 400
+#[cfg(feature = "online")]
+fn parse_playerdb(body: &[u8]) -> Result<Option<Profile>, Failure> {
+    let response = serde_json::from_slice::<PlayerDbResponse>(body)
+        .map_err(|err| Failure::Transient(format!("invalid response: {}", err)))?;
+    match response.code.as_str() {
+        "player.found" => {
+            let player = response
+                .data
+                .and_then(|data| data.player)
+                .ok_or_else(|| Failure::Transient("player.found without a player".into()))?;
+            Ok(Some(Profile {
+                id: player.id,
+                name: player.username,
+            }))
+        }
+        // Mojang failed behind the mirror
+        "minecraft.api_failure" => Err(Failure::Transient(response.code)),
+        // minecraft.invalid_username, or an unknown name
+        _ => Ok(None),
+    }
+}
+
+#[cfg(feature = "online")]
+struct PlayerDbApi {
+    client: reqwest::blocking::Client,
+}
+
+#[cfg(feature = "online")]
+impl ProfileApi for PlayerDbApi {
+    fn bulk(&self) -> bool {
+        false
+    }
+
+    fn profile(&self, name: &str) -> Result<Option<Profile>, Failure> {
+        let response = get_name(
+            &self.client,
+            "https://playerdb.co/api/player/minecraft",
+            name,
+        )?;
+        let response = match response.status() {
+            reqwest::StatusCode::BAD_REQUEST | reqwest::StatusCode::NOT_FOUND => response,
+            _ => check_status(response)?,
+        };
+        let body = response
+            .bytes()
+            .map_err(|err| Failure::Transient(err.to_string()))?;
+        parse_playerdb(&body)
+    }
+}
+
+/// A profile of api.ashcon.app
+#[cfg(feature = "online")]
+#[derive(Debug, Deserialize)]
+struct AshconUser {
+    uuid: Uuid,
+    username: String,
+}
+
+/// Parse a successful response of api.ashcon.app, unknown names are answered with 404
+#[cfg(feature = "online")]
+fn parse_ashcon(body: &[u8]) -> Result<Profile, Failure> {
+    let user = serde_json::from_slice::<AshconUser>(body)
+        .map_err(|err| Failure::Transient(format!("invalid response: {}", err)))?;
+    Ok(Profile {
+        id: user.uuid,
+        name: user.username,
+    })
+}
+
+#[cfg(feature = "online")]
+struct AshconApi {
+    client: reqwest::blocking::Client,
+}
+
+#[cfg(feature = "online")]
+impl ProfileApi for AshconApi {
+    fn bulk(&self) -> bool {
+        false
+    }
+
+    fn profile(&self, name: &str) -> Result<Option<Profile>, Failure> {
+        let response = get_name(&self.client, "https://api.ashcon.app/mojang/v2/user", name)?;
+        if matches!(
+            response.status(),
+            reqwest::StatusCode::NOT_FOUND | reqwest::StatusCode::BAD_REQUEST
+        ) {
+            return Ok(None);
+        }
+        let body = check_status(response)?
+            .bytes()
+            .map_err(|err| Failure::Transient(err.to_string()))?;
+        parse_ashcon(&body).map(Some)
+    }
+}
+
+/// The online uuids of the names, keyed by the names as they were requested
+#[cfg(feature = "online")]
+#[derive(Debug, Default)]
+pub struct OnlineUuids {
+    pub found: HashMap<String, Uuid>,
+    /// The names no player has, even when asked one by one, as they were given
+    pub unresolved: Vec<String>,
+}
+
+/// Look up the online uuids of the players with the API of `lookup.resolver`
+/// The names the bulk endpoint leaves out are asked one by one, the ones still missing are returned
+/// A request failing after the retries is an error, rather than leaving its names out
+#[cfg(feature = "online")]
+pub fn online_uuids<'a>(
+    name: impl IntoIterator<Item = &'a String>,
+    lookup: &OnlineLookup,
+) -> anyhow::Result<OnlineUuids> {
+    if let Some(base) = &lookup.api_base {
+        check_api_base(base).with_context(|| format!("Invalid --api-base {}", base))?;
+    }
+    let client = reqwest::blocking::Client::new();
+    let api: Box<dyn ProfileApi> = match lookup.resolver {
+        Resolver::Mojang => Box::new(MojangApi::new(client, lookup.api_base.as_deref())),
+        Resolver::PlayerDb => Box::new(PlayerDbApi { client }),
+        Resolver::Ashcon => Box::new(AshconApi { client }),
+    };
+    lookup_with(name, lookup, api.as_ref(), std::thread::sleep)
+}
+
+/// An http or https url the paths of the API are appended to, e.g. not `localhost:8080`
+#[cfg(feature = "online")]
+fn check_api_base(base: &str) -> anyhow::Result<()> {
+    let url = reqwest::Url::parse(base)?;
+    if !matches!(url.scheme(), "http" | "https") || url.cannot_be_a_base() {
+        anyhow::bail!("Expected an http or https url, e.g. http://localhost:8080");
+    }
+    Ok(())
+}
+
+/// Look up the names missing from the cache with the API, writing them back to the cache
+#[cfg(feature = "online")]
+fn lookup_with<'a>(
+    name: impl IntoIterator<Item = &'a String>,
+    lookup: &OnlineLookup,
+    api: &dyn ProfileApi,
+    sleep: impl Fn(Duration) + Copy,
+) -> anyhow::Result<OnlineUuids> {
+    let now = unix_now();
+    let mut cache = lookup.cache.as_deref().map(Cache::load).unwrap_or_default();
+    let mut ret = OnlineUuids::default();
+    let mut missing = vec![];
+    let mut cached = 0;
+    for name in name {
+        match cache.get(name, now, lookup.cache_ttl) {
+            Some(entry) => {
+                cached += 1;
+                match entry.uuid {
+                    Some(uuid) => {
+                        ret.found.insert(name.clone(), uuid);
+                    }
+                    None => ret.unresolved.push(name.clone()),
+                }
+            }
+            None => missing.push(name),
+        }
+    }
+    if lookup.cache.is_some() {
+        log::info!(
+            "{} names found in the cache, {} to look up",
+            cached,
+            missing.len()
+        );
+    }
+    if missing.is_empty() {
+        return Ok(ret);
+    }
+    // Without a bulk endpoint, every name is asked one by one
+    let bulk = api.bulk();
+    let (chunks, mut dropped) = if bulk {
+        (missing.chunks(CHUNK).collect::<Vec<_>>(), vec![])
+    } else {
+        (vec![], missing.clone())
+    };
+    let pg = crate::style::progress_bar(
+        (chunks.len() + dropped.len()) as u64,
+        "Receiving online uuids: ",
+        "{pos} requests / {len} requests {msg}",
+    );
+    let mut record = |requested: &String, profile: Option<&Profile>| {
+        cache.insert(
+            requested,
+            CacheEntry {
+                name: profile.map_or_else(|| requested.to_string(), |p| p.name.clone()),
+                uuid: profile.map(|profile| profile.id),
+                fetched: now,
+            },
+        );
+    };
+    let names = |names: &[&String]| {
+        names
+            .iter()
+            .map(|name| name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let mut result = Ok(());
+    for chunk in chunks {
+        let found = match with_retries(lookup, &pg, || api.profiles(chunk), sleep) {
+            Ok(found) => found,
+            Err(err) => {
+                result = Err(anyhow::anyhow!(
+                    "Failed to look up the online uuids of {}: {}",
+                    names(chunk),
+                    err
+                ));
+                break;
+            }
+        };
+        for &requested in chunk {
+            match found
+                .iter()
+                .find(|profile| profile.name.eq_ignore_ascii_case(requested))
+            {
+                Some(profile) => {
+                    record(requested, Some(profile));
+                    ret.found.insert(requested.clone(), profile.id);
+                }
+                None => dropped.push(requested),
+            }
+        }
+        pg.inc(1);
+    }
+    // Invalid and recently renamed names are silently dropped by the bulk endpoint
+    if result.is_ok() && bulk && !dropped.is_empty() {
+        log::info!(
+            "{} names are not in the bulk response, asking them one by one",
+            dropped.len()
+        );
+        pg.inc_length(dropped.len() as u64);
+    }
+    for &requested in dropped.iter().take_while(|_| result.is_ok()) {
+        match with_retries(lookup, &pg, || api.profile(requested), sleep) {
+            Ok(Some(profile)) => {
+                record(requested, Some(&profile));
+                ret.found.insert(requested.clone(), profile.id);
+            }
+            Ok(None) => {
+                record(requested, None);
+                ret.unresolved.push(requested.clone());
+            }
+            Err(err) => {
+                result = Err(anyhow::anyhow!(
+                    "Failed to look up the online uuid of {}: {}",
+                    requested,
+                    err
+                ))
+            }
+        }
+        pg.inc(1);
+    }
+    // The names looked up before a failure are kept for the next run
+    if let Some(path) = &lookup.cache {
+        cache.save(path, now, lookup.cache_ttl);
+    }
+    if result.is_ok() && !ret.unresolved.is_empty() {
+        log::warn!(
+            "No online player has the names {}",
+            ret.unresolved.join(", ")
+        );
+    }
+    result.map(|()| ret)
+}
+
+/// The error of an option that needs the network, in a binary built without the `online` feature
+#[cfg(not(feature = "online"))]
+pub fn unsupported(what: &str) -> anyhow::Error {
+    anyhow::anyhow!(
+        "{} needs the network, but this binary is built without online support, \
+        rebuild it with the default features",
+        what
+    )
+}
+
+#[cfg(all(test, feature = "online"))]
+#[test]
+fn test() {
+    use std::str::FromStr;
+
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    let online = online_uuids(
+        vec![
+            "CaveNightingale".to_string(),
+            "Notch".to_string(),
+            "Dinnerbone".to_string(),
+        ]
+        .iter(),
+        &OnlineLookup::default(),
+    )
+    .unwrap()
+    .found;
+    assert_eq!(
+        online.get("CaveNightingale").unwrap(),
+        &Uuid::from_str("fb1ad51e-cf1f-41f7-8fd1-10dff164b17d").unwrap()
+    );
+    assert_eq!(
+        online.get("Notch").unwrap(),
+        &Uuid::from_str("069a79f4-44e9-4726-a5be-fca90e38aaf5").unwrap()
+    );
+    assert_eq!(
+        online.get("Dinnerbone").unwrap(),
+        &Uuid::from_str("61699b2e-d327-4a01-9f1e-0ea8c3f06bc6").unwrap()
+    );
+}
+
+#[cfg(all(test, not(feature = "online")))]
+#[test]
+fn test() {
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    assert!(unsupported("--online")
+        .to_string()
+        .starts_with("--online needs the network"));
+}
+
+#[cfg(all(test, feature = "online"))]
+#[test]
+fn test_retries() {
+    use std::cell::RefCell;
+
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    let lookup = OnlineLookup {
+        retries: 3,
+        retry_delay: Duration::from_millis(100),
+        ..Default::default()
+    };
+    let pg = ProgressBar::hidden();
+    let sleeps = RefCell::new(vec![]);
+    let sleep = |delay| sleeps.borrow_mut().push(delay);
+
+    // Rate limited, then a server error, then it succeeds
+    let attempts = Cell::new(0);
+    let result = with_retries(
+        &lookup,
+        &pg,
+        || {
+            attempts.set(attempts.get() + 1);
+            match attempts.get() {
+                1 => Err(Failure::RateLimited(Some(Duration::from_secs(3)))),
+                2 => Err(Failure::RateLimited(None)),
+                3 => Err(Failure::Transient("server error (502)".to_string())),
+                _ => Ok(42),
+            }
+        },
+        sleep,
+    );
+    assert_eq!(result.unwrap(), 42);
+    // Retry-After wins over the backoff
+    assert_eq!(
+        sleeps.take(),
+        [
+            Duration::from_secs(3),
+            Duration::from_millis(200),
+            Duration::from_millis(400)
+        ]
+    );
+
+    // Exhausted, an error rather than an empty result
+    let err = with_retries(
+        &lookup,
+        &pg,
+        || Err::<(), _>(Failure::RateLimited(None)),
+        sleep,
+    )
+    .unwrap_err();
+    assert_eq!(err.to_string(), "rate limited (429) after 4 attempts");
+    assert_eq!(sleeps.take().len(), 3);
+
+    // Capped, however long the API asks to wait
+    let attempts = Cell::new(0);
+    let result = with_retries(
+        &lookup,
+        &pg,
+        || {
+            attempts.set(attempts.get() + 1);
+            match attempts.get() {
+                1 => Err(Failure::RateLimited(Some(Duration::from_secs(86400)))),
+                _ => Ok(()),
+            }
+        },
+        sleep,
+    );
+    assert!(result.is_ok());
+    assert_eq!(sleeps.take(), [Duration::from_secs(6)]);
+
+    // Not retried
+    let attempts = Cell::new(0);
+    let err = with_retries(
+        &lookup,
+        &pg,
+        || {
+            attempts.set(attempts.get() + 1);
+            Err::<(), _>(Failure::Fatal(
+                "request refused (400 Bad Request)".to_string(),
+            ))
+        },
+        sleep,
+    )
+    .unwrap_err();
+    assert_eq!(err.to_string(), "request refused (400 Bad Request)");
+    assert_eq!(attempts.get(), 1);
+    assert!(sleeps.take().is_empty());
+}
+
+#[cfg(all(test, feature = "online"))]
+#[test]
+fn test_cache() {
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    struct Counting {
+        requests: Cell<usize>,
+        fail: Cell<bool>,
+    }
+    impl ProfileApi for Counting {
+        fn profiles(&self, names: &[&String]) -> Result<Vec<Profile>, Failure> {
+            self.requests.set(self.requests.get() + 1);
+            if self.fail.get() {
+                return Err(Failure::Fatal(
+                    "request refused (403 Forbidden)".to_string(),
+                ));
+            }
+            // Like the Mojang API, the renamed names are only known by the single profile endpoint
+            Ok(names
+                .iter()
+                .filter(|name| !name.starts_with("nobody") && !name.starts_with("renamed"))
+                .map(|name| profile(name))
+                .collect())
+        }
+        fn profile(&self, name: &str) -> Result<Option<Profile>, Failure> {
+            self.requests.set(self.requests.get() + 1);
+            Ok(name.starts_with("renamed").then(|| profile(name)))
+        }
+    }
+    fn profile(name: &str) -> Profile {
+        Profile {
+            id: Uuid::new_v3(&Uuid::NAMESPACE_OID, name.to_lowercase().as_bytes()),
+            name: name.to_uppercase(),
+        }
+    }
+
+    let dir = std::env::temp_dir().join("test_online_cache");
+    let _ = std::fs::remove_dir_all(&dir);
+    let lookup = OnlineLookup {
+        retries: 0,
+        cache: Some(dir.join("nested").join("mojang.json")),
+        ..Default::default()
+    };
+    let api = Counting {
+        requests: Cell::new(0),
+        fail: Cell::new(false),
+    };
+    let names = (0..12)
+        .map(|i| format!("player{}", i))
+        .chain(["renamed0".to_string(), "nobody".to_string()])
+        .collect::<Vec<_>>();
+    // Two chunks, then the two names missing from them one by one
+    let first = lookup_with(&names, &lookup, &api, |_| ()).unwrap();
+    assert_eq!(api.requests.get(), 4);
+    assert_eq!(first.found.len(), 13);
+    assert!(first.found.contains_key("player0"));
+    assert!(first.found.contains_key("renamed0"));
+    assert_eq!(first.unresolved, ["nobody"]);
+
+    // Nothing is requested again, not even the unknown name
+    let second = lookup_with(&names, &lookup, &api, |_| ()).unwrap();
+    assert_eq!(api.requests.get(), 4);
+    assert_eq!(second.found, first.found);
+    assert_eq!(second.unresolved, ["nobody"]);
+    let case = lookup_with(&["Player3".to_string()], &lookup, &api, |_| ()).unwrap();
+    assert_eq!(api.requests.get(), 4);
+    assert_eq!(case.found["Player3"], first.found["player3"]);
+
+    // Only the new name is looked up, and the failure is not cached
+    api.fail.set(true);
+    let more = [names[0].clone(), "player12".to_string()];
+    assert!(lookup_with(&more, &lookup, &api, |_| ()).is_err());
+    assert_eq!(api.requests.get(), 5);
+    api.fail.set(false);
+    let found = lookup_with(&more, &lookup, &api, |_| ()).unwrap();
+    assert_eq!(found.found.len(), 2);
+    assert_eq!(api.requests.get(), 6);
+
+    // Expired entries are looked up again
+    let expired = OnlineLookup {
+        cache_ttl: Duration::ZERO,
+        ..lookup.clone()
+    };
+    lookup_with(&names, &expired, &api, |_| ()).unwrap();
+    assert_eq!(api.requests.get(), 10);
+
+    // Without the cache, every name is asked
+    let uncached = OnlineLookup {
+        cache: None,
+        ..lookup
+    };
+    lookup_with(&names, &uncached, &api, |_| ()).unwrap();
+    assert_eq!(api.requests.get(), 14);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(all(test, feature = "online"))]
+#[test]
+fn test_resolvers() {
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    // Recorded responses, the textures are cut
+    const MOJANG: &str = r#"[
+        {"id":"069a79f444e94726a5befca90e38aaf5","name":"Notch"},
+        {"id":"61699b2ed3274a019f1e0ea8c3f06bc6","name":"Dinnerbone"}
+    ]"#;
+    const PLAYERDB: [&str; 2] = [
+        r#"{"code":"player.found","message":"Successfully found player by given ID.","data":{"player":{"meta":{"cached_at":1700000000},"username":"Notch","id":"069a79f4-44e9-4726-a5be-fca90e38aaf5","raw_id":"069a79f444e94726a5befca90e38aaf5","avatar":"https://crafthead.net/avatar/069a79f444e94726a5befca90e38aaf5","skin_texture":"http://textures.minecraft.net/texture/292009a4925b58f02c77dadc3ecef07ea4c7472f64e0fdc32ce5522489362680","properties":[],"name_history":[]}},"success":true}"#,
+        r#"{"code":"player.found","message":"Successfully found player by given ID.","data":{"player":{"meta":{"cached_at":1700000000},"username":"Dinnerbone","id":"61699b2e-d327-4a01-9f1e-0ea8c3f06bc6","raw_id":"61699b2ed3274a019f1e0ea8c3f06bc6","avatar":"https://crafthead.net/avatar/61699b2ed3274a019f1e0ea8c3f06bc6","skin_texture":"http://textures.minecraft.net/texture/50c410fad8d9d8825ad56b0e443e2777a6b46bfa20dacd1d2f55edc71fbeb06d","properties":[],"name_history":[]}},"success":true}"#,
+    ];
+    const PLAYERDB_UNKNOWN: &str = r#"{"code":"minecraft.invalid_username","message":"Mojang API lookup failed.","data":{},"success":false}"#;
+    const PLAYERDB_FAILURE: &str = r#"{"code":"minecraft.api_failure","message":"Mojang API lookup failed.","data":{},"success":false}"#;
+    const ASHCON: [&str; 2] = [
+        r#"{"uuid":"069a79f4-44e9-4726-a5be-fca90e38aaf5","username":"Notch","username_history":[{"username":"Notch"}],"textures":{"custom":false,"slim":false},"created_at":null}"#,
+        r#"{"uuid":"61699b2e-d327-4a01-9f1e-0ea8c3f06bc6","username":"Dinnerbone","username_history":[{"username":"Dinnerbone"}],"textures":{"custom":true,"slim":false},"created_at":null}"#,
+    ];
+
+    // Replays the parsed responses, the other names are unknown
+    struct Recorded {
+        bulk: bool,
+        profiles: Vec<Profile>,
+    }
+    impl ProfileApi for Recorded {
+        fn bulk(&self) -> bool {
+            self.bulk
+        }
+        fn profiles(&self, names: &[&String]) -> Result<Vec<Profile>, Failure> {
+            assert!(self.bulk);
+            Ok(self
+                .profiles
+                .iter()
+                .filter(|p| names.iter().any(|name| p.name.eq_ignore_ascii_case(name)))
+                .cloned()
+                .collect())
+        }
+        fn profile(&self, name: &str) -> Result<Option<Profile>, Failure> {
+            Ok(self
+                .profiles
+                .iter()
+                .find(|p| p.name.eq_ignore_ascii_case(name))
+                .cloned())
+        }
+    }
+
+    let apis = [
+        Recorded {
+            bulk: true,
+            profiles: serde_json::from_str(MOJANG).unwrap(),
+        },
+        Recorded {
+            bulk: false,
+            profiles: PLAYERDB
+                .iter()
+                .map(|body| parse_playerdb(body.as_bytes()).unwrap().unwrap())
+                .collect(),
+        },
+        Recorded {
+            bulk: false,
+            profiles: ASHCON
+                .iter()
+                .map(|body| parse_ashcon(body.as_bytes()).unwrap())
+                .collect(),
+        },
+    ];
+    let names = ["notch", "Dinnerbone", "nobody"].map(String::from);
+    // Without a bulk endpoint, the profiles are asked one by one
+    struct Single;
+    impl ProfileApi for Single {
+        fn profile(&self, name: &str) -> Result<Option<Profile>, Failure> {
+            Ok((name != "nobody").then(|| Profile {
+                id: Uuid::nil(),
+                name: name.to_string(),
+            }))
+        }
+    }
+    let found = Single.profiles(&names.iter().collect::<Vec<_>>()).unwrap();
+    assert_eq!(
+        found.iter().map(|p| p.name.as_str()).collect::<Vec<_>>(),
+        ["notch", "Dinnerbone"]
+    );
+    let lookup = OnlineLookup {
+        retries: 0,
+        ..Default::default()
+    };
+    let results = apis
+        .iter()
+        .map(|api| lookup_with(&names, &lookup, api, |_| ()).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        results[0].found["notch"],
+        Uuid::from_u128(0x069a79f4_44e9_4726_a5be_fca90e38aaf5)
+    );
+    assert_eq!(results[0].found.len(), 2);
+    for result in &results {
+        assert_eq!(result.found, results[0].found);
+        assert_eq!(result.unresolved, ["nobody"]);
+    }
+
+    assert!(parse_playerdb(PLAYERDB_UNKNOWN.as_bytes())
+        .unwrap()
+        .is_none());
+    assert!(matches!(
+        parse_playerdb(PLAYERDB_FAILURE.as_bytes()),
+        Err(Failure::Transient(_))
+    ));
+    assert!(parse_ashcon(b"<html>Bad Gateway</html>").is_err());
+}
+
+#[cfg(all(test, feature = "online"))]
+#[test]
+fn test_api_base() {
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+    };
+
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    // A Blessing Skin server, answering under `/api` of the given root
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let base = format!("http://{}/api/yggdrasil/", listener.local_addr().unwrap());
+    let server = std::thread::spawn(move || {
+        let mut paths = vec![];
+        for _ in 0..3 {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut request = String::new();
+            reader.read_line(&mut request).unwrap();
+            let mut len = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if let Some(value) = line.to_lowercase().strip_prefix("content-length:") {
+                    len = value.trim().parse().unwrap();
+                }
+                if line == "\r\n" {
+                    break;
+                }
+            }
+            let mut body = vec![0; len];
+            reader.read_exact(&mut body).unwrap();
+            let path = request.split(' ').nth(1).unwrap().to_string();
+            let (status, body) = match path.as_str() {
+                "/api/yggdrasil/api/profiles/minecraft" => (
+                    "200 OK",
+                    r#"[{"id":"069a79f444e94726a5befca90e38aaf5","name":"Notch"}]"#,
+                ),
+                "/api/yggdrasil/api/users/profiles/minecraft/nobody" => ("204 No Content", ""),
+                _ => ("404 Not Found", ""),
+            };
+            write!(
+                reader.get_mut(),
+                "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            )
+            .unwrap();
+            paths.push(path);
+        }
+        paths
+    });
+
+    let lookup = OnlineLookup {
+        retries: 0,
+        ..Default::default()
+    };
+    // The server is local, a proxy of the environment would never reach it
+    let client = reqwest::blocking::Client::builder()
+        .no_proxy()
+        .build()
+        .unwrap();
+    let api = MojangApi::new(client, Some(base.as_str()));
+    let names = ["notch", "nobody"].map(String::from);
+    let online = lookup_with(&names, &lookup, &api, |_| ()).unwrap();
+    assert_eq!(
+        online.found["notch"],
+        Uuid::from_u128(0x069a79f4_44e9_4726_a5be_fca90e38aaf5)
+    );
+    assert_eq!(online.unresolved, ["nobody"]);
+    assert_eq!(
+        server.join().unwrap(),
+        [
+            "/api/yggdrasil/profiles/minecraft",
+            "/api/yggdrasil/api/profiles/minecraft",
+            "/api/yggdrasil/api/users/profiles/minecraft/nobody",
+        ]
+    );
+
+    for base in [
+        "not a url",
+        "localhost:8080",
+        "mailto:admin@example.com",
+        "ftp://example.com",
+    ] {
+        let invalid = OnlineLookup {
+            api_base: Some(base.to_string()),
+            ..Default::default()
+        };
+        assert!(online_uuids(&names, &invalid).is_err(), "{}", base);
+    }
+    assert!(check_api_base("http://localhost:8080/api/yggdrasil").is_ok());
+    let client = reqwest::blocking::Client::new();
+    assert!(matches!(
+        get_name(&client, "localhost:8080", "Notch"),
+        Err(Failure::Fatal(_))
+    ));
+    assert!(default_cache(Some("https://authserver.ely.by/api/"))
+        .unwrap()
+        .ends_with("uuid-remapper/authserver.ely.by.json"));
+    assert!(default_cache(None)
+        .unwrap()
+        .ends_with("uuid-remapper/mojang.json"));
+}
@@ -0,0 +1,180 @@
+//! Running as root leaves the files it writes owned by root, which the server can no longer write
+//!
+//! With `--allow-root`, the owner of every file is captured before it is modified and given back
+//! once all writes are done.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+/// The user and the group of a file
+pub type Owner = (u32, u32);
+
+/// Whether the effective user is root
+#[cfg(unix)]
+pub fn is_root() -> bool {
+    // SAFETY: geteuid has no preconditions and never fails
+    unsafe { libc::geteuid() == 0 }
+}
+
+#[cfg(not(unix))]
+pub fn is_root() -> bool {
+    false
+}
+
+#[cfg(unix)]
+fn owner_of(path: &Path) -> Option<Owner> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.uid(), metadata.gid()))
+}
+
+#[cfg(not(unix))]
+fn owner_of(_path: &Path) -> Option<Owner> {
+    None
+}
+
+#[cfg(unix)]
+pub fn chown(path: &Path, (uid, gid): Owner) -> std::io::Result<()> {
+    std::os::unix::fs::chown(path, Some(uid), Some(gid))
+}
+
+#[cfg(not(unix))]
+pub fn chown(_path: &Path, _owner: Owner) -> std::io::Result<()> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+/// The owners of the modified files, as they were before the first modification
+#[derive(Debug, Default)]
+pub struct Owners {
+    owners: Mutex<BTreeMap<PathBuf, Owner>>,
+}
+
+impl Owners {
+    /// Record the owner of the file about to be modified, a new file belongs to the owner of its
+    /// directory
+    pub fn capture(&self, path: &Path) {
+        let mut owners = self.owners.lock().unwrap();
+        if owners.contains_key(path) {
+            return;
+        }
+        let owner = owner_of(path).or_else(|| owner_of(path.parent()?));
+        if let Some(owner) = owner {
+            owners.insert(path.to_path_buf(), owner);
+        }
+    }
+
+    /// Record the owner of `to` about to be replaced by `from`, a new `to` belongs to the owner of
+    /// `from`
+    pub fn capture_rename(&self, from: &Path, to: &Path) {
+        let mut owners = self.owners.lock().unwrap();
+        if owners.contains_key(to) {
+            return;
+        }
+        let owner = owner_of(to)
+            .or_else(|| owners.get(from).copied())
+            .or_else(|| owner_of(from));
+        if let Some(owner) = owner {
+            owners.insert(to.to_path_buf(), owner);
+        }
+    }
+
+    /// Number of files captured
+    pub fn captured(&self) -> usize {
+        self.owners.lock().unwrap().len()
+    }
+
+    /// Give the files still there back to their owners, the number of files restored and the
+    /// failures are returned
+    pub fn restore(
+        &self,
+        chown: impl Fn(&Path, Owner) -> std::io::Result<()>,
+    ) -> (usize, Vec<(PathBuf, std::io::Error)>) {
+        let owners = std::mem::take(&mut *self.owners.lock().unwrap());
+        let mut restored = 0;
+        let mut failed = Vec::new();
+        for (path, owner) in owners {
+            if !path.exists() {
+                continue;
+            }
+            match chown(&path, owner) {
+                Ok(()) => restored += 1,
+                Err(err) => failed.push((path, err)),
+            }
+        }
+        (restored, failed)
+    }
+}
+
+#[cfg(all(test, unix))]
+#[test]
+fn test() {
+    use std::{cell::RefCell, sync::Arc};
+
+    use crate::{guard::WriteGuard, setup_test_logger};
+
+    setup_test_logger();
+
+    let dir = std::env::temp_dir().join("test_owners");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let owner = owner_of(&dir).unwrap();
+    let (a, b, c) = (dir.join("a.mca"), dir.join("b.mca"), dir.join("c.mca"));
+    std::fs::write(&a, b"region").unwrap();
+    // The files created belong to the effective user
+    assert_eq!(is_root(), owner_of(&a).unwrap().0 == 0);
+
+    // The first owner seen is kept, renamed and new files get one as well
+    let owners = Arc::new(Owners::default());
+    let guard = WriteGuard::default().with_owners(owners.clone());
+    guard.write(&a, b"remapped").unwrap();
+    guard.write(&a, b"remapped again").unwrap();
+    guard.write(&b, b"new").unwrap();
+    guard.rename(&b, &c).unwrap();
+    drop(guard.create(&dir.join("gone.tmp")).unwrap());
+    std::fs::remove_file(dir.join("gone.tmp")).unwrap();
+    assert_eq!(owners.captured(), 4);
+    {
+        let captured = owners.owners.lock().unwrap();
+        assert!(captured.values().all(|&captured| captured == owner));
+    }
+
+    // Nothing is captured in dry-run mode
+    let dry_run = Arc::new(Owners::default());
+    let guard = WriteGuard::dry_run().with_owners(dry_run.clone());
+    guard.write(&a, b"discarded").unwrap();
+    assert_eq!(dry_run.captured(), 0);
+
+    // The files gone are skipped, the failures are reported
+    let calls = RefCell::new(Vec::new());
+    let (restored, failed) = owners.restore(|path, owner| {
+        calls.borrow_mut().push((path.to_path_buf(), owner));
+        if path == c {
+            Err(std::io::ErrorKind::PermissionDenied.into())
+        } else {
+            Ok(())
+        }
+    });
+    assert_eq!(restored, 1);
+    assert_eq!(failed.len(), 1);
+    assert_eq!(failed[0].0, c);
+    assert_eq!(
+        calls.into_inner(),
+        vec![(a.clone(), owner), (c.clone(), owner)]
+    );
+    assert_eq!(owners.captured(), 0);
+
+    // Only root can give files away
+    if is_root() {
+        chown(&a, (65534, 65534)).unwrap();
+        let owners = Owners::default();
+        owners.capture(&a);
+        chown(&a, (0, 0)).unwrap();
+        assert_eq!(owners.restore(chown).0, 1);
+        assert_eq!(owner_of(&a), Some((65534, 65534)));
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
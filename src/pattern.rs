@@ -0,0 +1,166 @@
+use std::{ops::Range, str::FromStr};
+
+use regex::bytes::Regex;
+use uuid::Uuid;
+
+use crate::source::{located, tagged, Location, Source};
+
+/// The capture holding the uuid in a `--text-pattern`
+const CAPTURE: &str = "uuid";
+
+/// Compile a `--text-pattern`, which must have a capture named `uuid`
+pub fn parse_pattern(s: &str) -> Result<Regex, String> {
+    let regex = Regex::new(s).map_err(|err| format!("Invalid regex {:?}: {}", s, err))?;
+    if !regex.capture_names().any(|name| name == Some(CAPTURE)) {
+        return Err(format!(
+            "The regex {:?} has no capture named uuid, like (?P<uuid>[0-9a-f]{{32}})",
+            s
+        ));
+    }
+    Ok(regex)
+}
+
+/// Parse the captured text as a uuid, dashed or not, in any case
+fn parse_captured(captured: &[u8]) -> Option<Uuid> {
+    let captured = std::str::from_utf8(captured).ok()?.trim();
+    match captured.len() {
+        32 | 36 => Uuid::from_str(captured).ok(),
+        _ => None,
+    }
+}
+
+/// Format the uuid like the captured text, dashed by its length, uppercase if it has no lowercase letter
+fn format_like(uuid: Uuid, captured: &[u8]) -> String {
+    let captured = std::str::from_utf8(captured).unwrap_or_default().trim();
+    let formatted = if captured.len() == 36 {
+        uuid.hyphenated().to_string()
+    } else {
+        uuid.simple().to_string()
+    };
+    if captured.bytes().any(|c| c.is_ascii_uppercase())
+        && !captured.bytes().any(|c| c.is_ascii_lowercase())
+    {
+        formatted.to_ascii_uppercase()
+    } else {
+        formatted
+    }
+}
+
+/// The replacements of the uuids captured by the patterns, sorted by their ranges
+/// Every pattern is matched on the same text, a capture overlapping a range in `skip`, which
+/// the text pass already replaced, or the replacement of an earlier pattern is left alone, so
+/// each uuid is mapped once
+pub fn visit_patterns(
+    text: &[u8],
+    patterns: &[Regex],
+    skip: &[Range<usize>],
+    cb: &impl Fn(Uuid) -> Option<Uuid>,
+) -> Vec<(Range<usize>, String)> {
+    let overlaps = |a: &Range<usize>, b: &Range<usize>| a.start < b.end && b.start < a.end;
+    let mut replacements: Vec<(Range<usize>, String)> = vec![];
+    for pattern in patterns {
+        for captures in pattern.captures_iter(text) {
+            let Some(captured) = captures.name(CAPTURE) else {
+                continue;
+            };
+            let range = captured.range();
+            if skip.iter().any(|skipped| overlaps(skipped, &range))
+                || replacements
+                    .iter()
+                    .any(|(replaced, _)| overlaps(replaced, &range))
+            {
+                continue;
+            }
+            let Some(uuid) = parse_captured(captured.as_bytes()) else {
+                continue;
+            };
+            let source = if captured.len() == 36 {
+                Source::TextDashed
+            } else {
+                Source::TextUndashed
+            };
+            let offset = |location: &mut Location| location.offset = Some(range.start);
+            if let Some(new_uuid) = located(offset, || tagged(source, || cb(uuid))) {
+                replacements.push((range, format_like(new_uuid, captured.as_bytes())));
+            }
+        }
+    }
+    replacements.sort_by_key(|(range, _)| range.start);
+    replacements
+}
+
+/// Apply the replacements of `visit_patterns`, the captured text may be padded, so the length
+/// can change
+pub fn splice(text: &[u8], replacements: &[(Range<usize>, String)]) -> Vec<u8> {
+    let mut rewritten = Vec::with_capacity(text.len());
+    let mut last = 0;
+    for (range, value) in replacements {
+        rewritten.extend_from_slice(&text[last..range.start]);
+        rewritten.extend_from_slice(value.as_bytes());
+        last = range.end;
+    }
+    rewritten.extend_from_slice(&text[last..]);
+    rewritten
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    assert!(parse_pattern(r"\[(?P<uuid>[0-9A-F]{32})\]").is_ok());
+    let err = parse_pattern(r"\[([0-9A-F]{32})\]").unwrap_err();
+    assert!(err.contains("no capture named uuid"));
+    assert!(parse_pattern(r"\[(?P<uuid>[0-9A-F]{32}\]").is_err());
+
+    let from = Uuid::from_u128(0x2d318504_1a7b_39dc_8c18_44df798a5c06);
+    let to = Uuid::from_u128(0xfb1ad51e_cf1f_41f7_8fd1_10dff164b17d);
+    let patterns = [
+        parse_pattern(r"\[#(?P<uuid>[0-9A-F]{32})\]").unwrap(),
+        parse_pattern(r"id=(?P<uuid>[0-9a-fA-F-]{36})\b").unwrap(),
+    ];
+    let cb = |uuid| (uuid == from).then_some(to);
+    let text = format!(
+        "[#{}] [#{}] id={}",
+        from.simple().to_string().to_uppercase(),
+        Uuid::nil().simple(),
+        from
+    );
+    let replacements = visit_patterns(text.as_bytes(), &patterns, &[], &cb);
+    assert_eq!(replacements.len(), 2);
+    assert_eq!(
+        String::from_utf8(splice(text.as_bytes(), &replacements)).unwrap(),
+        format!(
+            "[#{}] [#{}] id={}",
+            to.simple().to_string().to_uppercase(),
+            Uuid::nil().simple(),
+            to
+        )
+    );
+    assert!(visit_patterns(b"[#nothing]", &patterns, &[], &cb).is_empty());
+
+    // A swap maps each capture once, even if a later pattern or the text pass matches it too
+    let swap = |uuid| match uuid {
+        uuid if uuid == from => Some(to),
+        uuid if uuid == to => Some(from),
+        _ => None,
+    };
+    let patterns = [
+        parse_pattern(r"\[#(?P<uuid>[0-9A-Fa-f]{32})\]").unwrap(),
+        parse_pattern(r"#(?P<uuid>[0-9A-Fa-f]{32})").unwrap(),
+    ];
+    let text = format!("[#{}] #{}", from.simple(), to.simple());
+    let replacements = visit_patterns(text.as_bytes(), &patterns, &[], &swap);
+    assert_eq!(
+        String::from_utf8(splice(text.as_bytes(), &replacements)).unwrap(),
+        format!("[#{}] #{}", to.simple(), from.simple())
+    );
+    let skip = [2..34];
+    let replacements = visit_patterns(text.as_bytes(), &patterns, &skip, &swap);
+    assert_eq!(
+        String::from_utf8(splice(text.as_bytes(), &replacements)).unwrap(),
+        format!("[#{}] #{}", from.simple(), from.simple())
+    );
+}
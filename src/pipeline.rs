@@ -0,0 +1,370 @@
+use std::{
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{sync_channel, Receiver, RecvTimeoutError, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use indicatif::ProgressBar;
+
+use crate::{
+    anvil::LOW_MEMORY_THRESHOLD,
+    budget::{self, BudgetPermit},
+    handler::{Registry, RemapOptions},
+    mapping::Mapping,
+    progress::{Progress, Totals},
+    stage::{self, Op},
+    task::{remap_task, Task, WorkerResult},
+};
+
+/// How long an IO worker waits for results before checking the other side again
+const POLL: Duration = Duration::from_millis(10);
+
+/// The threads of the pipeline
+pub struct Stages {
+    /// Number of threads reading and writing the files
+    pub io_threads: usize,
+    /// One CPU worker for each bar
+    pub cpu_bars: Vec<ProgressBar>,
+}
+
+/// A file read by the IO stage, without the content if the CPU worker should read it itself
+struct Loaded<'a> {
+    task: &'a Task,
+    content: Option<Vec<u8>>,
+    /// The budget of remapping the file, held until the CPU worker is done with it
+    permit: BudgetPermit<'a>,
+}
+
+/// The modifications of a remapped file, in order
+struct Remapped<'a> {
+    task: &'a Task,
+    ops: Vec<Op>,
+}
+
+/// Read the file for the CPU stage
+/// Large files are left to the handler, it may stream them instead of loading them
+fn prefetch(path: &Path, options: &RemapOptions) -> Option<Vec<u8>> {
+    let retry = options.guard.retry();
+    let meta = retry.run(path, || std::fs::metadata(path)).ok()?;
+    let streamed = options.low_memory && path.extension().is_some_and(|ext| ext == "mca");
+    if !meta.is_file() || meta.len() > LOW_MEMORY_THRESHOLD || streamed {
+        return None;
+    }
+    // Errors are reported when the CPU worker reads the file again
+    retry.read(path).ok()
+}
+
+/// Apply the modifications of a file, stopping at the first failure
+fn write(remapped: Remapped, options: &RemapOptions, totals: &Totals, result: &mut WorkerResult) {
+    let start = Instant::now();
+    for op in &remapped.ops {
+        if let Err(err) = op.apply(&options.guard) {
+            log::error!(
+                "Failed to write file {}: {:#?}",
+                remapped.task.path.display(),
+                err
+            );
+            result
+                .dimensions
+                .entry(remapped.task.dimension.clone())
+                .or_default()
+                .errors += 1;
+            totals.add_errors(1);
+            result.failed.push(remapped.task.path.clone());
+            break;
+        }
+    }
+    result.io_busy += start.elapsed();
+}
+
+/// Read the files one by one until there is none left, then write the rest of the results
+/// Results are written whenever the CPU stage is full, so neither side waits for the other forever
+fn io_worker<'a>(
+    world: &Path,
+    tasks: &'a [Task],
+    next: &AtomicUsize,
+    loaded: SyncSender<Loaded<'a>>,
+    remapped: &Mutex<Receiver<Remapped<'a>>>,
+    options: &'a RemapOptions,
+    totals: &Totals,
+) -> WorkerResult {
+    let mut result = WorkerResult::default();
+    'read: while let Some(task) = tasks.get(next.fetch_add(1, Ordering::Relaxed)) {
+        let path = world.join(&task.path);
+        // Acquired before reading, so the files read ahead are within the budget as well
+        let len = std::fs::metadata(&path).map_or(0, |meta| meta.len() as usize);
+        let permit = options.budget.acquire(budget::estimate(&path, len));
+        let start = Instant::now();
+        let mut file = Loaded {
+            task,
+            content: prefetch(&path, options),
+            permit,
+        };
+        result.io_busy += start.elapsed();
+        loop {
+            match loaded.try_send(file) {
+                Ok(()) => break,
+                Err(TrySendError::Full(back)) => {
+                    file = back;
+                    // Another worker is writing the results already
+                    let Ok(receiver) = remapped.try_lock() else {
+                        std::thread::sleep(POLL);
+                        continue;
+                    };
+                    let received = receiver.recv_timeout(POLL);
+                    drop(receiver);
+                    if let Ok(received) = received {
+                        write(received, options, totals, &mut result);
+                    }
+                }
+                Err(TrySendError::Disconnected(_)) => break 'read,
+            }
+        }
+    }
+    drop(loaded);
+    loop {
+        let received = remapped.lock().unwrap().recv_timeout(POLL);
+        match received {
+            Ok(received) => write(received, options, totals, &mut result),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+    result
+}
+
+/// Remap the tasks with an IO stage reading and writing the files, and a CPU stage remapping them
+/// The stages are connected by bounded channels, so only a few files are held in memory at a time
+pub fn run_pipeline(
+    world: &Path,
+    tasks: &[Task],
+    stages: Stages,
+    totals: Arc<Totals>,
+    mapping: &dyn Mapping,
+    registry: &Registry,
+    options: &RemapOptions,
+) -> WorkerResult {
+    let next = AtomicUsize::new(0);
+    let (loaded_tx, loaded_rx) = sync_channel::<Loaded>(stages.cpu_bars.len());
+    let (remapped_tx, remapped_rx) = sync_channel::<Remapped>(stages.io_threads);
+    let (loaded_rx, remapped_rx) = (Mutex::new(loaded_rx), Mutex::new(remapped_rx));
+    std::thread::scope(|s| {
+        let mut handles = vec![];
+        for pg in stages.cpu_bars {
+            let remapped_tx = remapped_tx.clone();
+            let (loaded_rx, totals) = (&loaded_rx, totals.clone());
+            handles.push(s.spawn(move || {
+                let mut result = WorkerResult::default();
+                let progress = Progress::new(pg.clone(), totals);
+                loop {
+                    let received = loaded_rx.lock().unwrap().recv();
+                    let Ok(Loaded {
+                        task,
+                        content,
+                        permit,
+                    }) = received
+                    else {
+                        break;
+                    };
+                    let start = Instant::now();
+                    let input = content.map(|content| (world.join(&task.path), content));
+                    let (remapped, ops) = stage::staged(input, || {
+                        remap_task(
+                            world,
+                            task,
+                            mapping,
+                            registry,
+                            options,
+                            &progress,
+                            &mut result,
+                        )
+                    });
+                    drop(permit);
+                    result.cpu_busy += start.elapsed();
+                    if let Err(err) = remapped {
+                        log::error!("Failed to remap file {}: {:#?}", task.path.display(), err);
+                    }
+                    pg.inc(1);
+                    if remapped_tx.send(Remapped { task, ops }).is_err() {
+                        break;
+                    }
+                }
+                result
+            }));
+        }
+        drop(remapped_tx);
+        for _ in 0..stages.io_threads {
+            let loaded_tx = loaded_tx.clone();
+            let (next, remapped_rx, totals) = (&next, &remapped_rx, &totals);
+            handles.push(s.spawn(move || {
+                io_worker(world, tasks, next, loaded_tx, remapped_rx, options, totals)
+            }));
+        }
+        drop(loaded_tx);
+        let mut total = WorkerResult::default();
+        for handle in handles {
+            match handle.join() {
+                Ok(result) => total.merge(result),
+                Err(err) => {
+                    log::error!("Thread panicked: {:#?}", err);
+                    panic!("Thread panicked");
+                }
+            }
+        }
+        total
+    })
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    use std::{collections::HashMap, str::FromStr};
+
+    use uuid::Uuid;
+
+    use crate::{anvil::Anvil, anvil::Chunk, setup_test_logger, task::scan_world};
+
+    setup_test_logger();
+
+    let from = Uuid::from_str("2d318504-1a7b-39dc-8c18-44df798a5c06").unwrap();
+    let to = Uuid::from_str("fb1ad51e-cf1f-41f7-8fd1-10dff164b17d").unwrap();
+    let make_world = |world: &Path| {
+        std::fs::create_dir_all(world.join("region")).unwrap();
+        std::fs::create_dir_all(world.join("stats")).unwrap();
+        for i in 0..20 {
+            std::fs::write(
+                world.join(format!("config-{}.yml", i)),
+                format!("owner: {}\n", from),
+            )
+            .unwrap();
+        }
+        std::fs::write(world.join("stats").join(format!("{}.json", from)), "{}").unwrap();
+        std::fs::write(world.join("bad.dat"), [10, 0, 30]).unwrap();
+        let mut nbt = vec![10, 0, 0, 8, 0, 4, b'u', b'u', b'i', b'd', 0, 36];
+        nbt.extend_from_slice(from.to_string().as_bytes());
+        nbt.push(0);
+        let mut anvil = Anvil::new(&world.join("region").join("r.0.0.mca"));
+        for (x, uncompressed) in [nbt, vec![10, 0, 0, 255, 0]].into_iter().enumerate() {
+            anvil
+                .write(&Chunk {
+                    external: false,
+                    location: (x as i32, 0),
+                    timestamp: 0,
+                    uncompressed,
+                    lz4: None,
+                })
+                .unwrap();
+        }
+        anvil.save().unwrap();
+    };
+    let read_world = |world: &Path| {
+        let mut files = std::collections::BTreeMap::new();
+        let mut dirs = vec![world.to_path_buf()];
+        while let Some(dir) = dirs.pop() {
+            for entry in std::fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    dirs.push(path);
+                } else {
+                    let relative = path.strip_prefix(world).unwrap().to_path_buf();
+                    files.insert(relative, std::fs::read(&path).unwrap());
+                }
+            }
+        }
+        files
+    };
+
+    let mut registry = Registry::builtin();
+    let options = RemapOptions::default();
+    let mapping = HashMap::from([(from, to)]);
+    let temp = std::env::temp_dir();
+    let (single, piped) = (
+        temp.join("test_pipeline_single"),
+        temp.join("test_pipeline"),
+    );
+    let _ = std::fs::remove_dir_all(&single);
+    let _ = std::fs::remove_dir_all(&piped);
+    make_world(&single);
+    make_world(&piped);
+
+    // The same tasks remapped on a single thread
+    let tasks = scan_world(&single, &mut registry, &options).unwrap().tasks;
+    let progress = Progress::hidden();
+    let mut expected = WorkerResult::default();
+    for task in &tasks {
+        let _ = remap_task(
+            &single,
+            task,
+            &mapping,
+            &registry,
+            &options,
+            &progress,
+            &mut expected,
+        );
+    }
+
+    let totals = Arc::new(Totals::default());
+    let tasks = scan_world(&piped, &mut registry, &options).unwrap().tasks;
+    let stages = Stages {
+        io_threads: 3,
+        cpu_bars: vec![ProgressBar::hidden(), ProgressBar::hidden()],
+    };
+    let result = run_pipeline(
+        &piped,
+        &tasks,
+        stages,
+        totals.clone(),
+        &mapping,
+        &registry,
+        &options,
+    );
+    assert_eq!(result.replacements, expected.replacements);
+    assert_eq!(result.replacements, 22);
+    assert_eq!(result.files_modified, expected.files_modified);
+    assert_eq!(result.stats.chunk_errors, 1);
+    assert_eq!(
+        result.dimensions.values().map(|d| d.errors).sum::<usize>(),
+        1
+    );
+    assert_eq!(totals.errors(), 2);
+    assert_eq!(result.renamed.len(), 1);
+    assert!(result.cpu_busy > Duration::ZERO);
+    assert!(result.io_busy > Duration::ZERO);
+    assert_eq!(read_world(&piped), read_world(&single));
+    assert!(piped.join("stats").join(format!("{}.json", to)).exists());
+
+    // Nothing is written in dry-run mode, the files read ahead are within the budget
+    let options = RemapOptions {
+        guard: crate::guard::WriteGuard::dry_run(),
+        budget: crate::budget::MemoryBudget::new(Some(64)),
+        ..Default::default()
+    };
+    let before = read_world(&piped);
+    let tasks = scan_world(&piped, &mut registry, &options).unwrap().tasks;
+    let stages = Stages {
+        io_threads: 1,
+        cpu_bars: vec![ProgressBar::hidden()],
+    };
+    let mapping = HashMap::from([(to, from)]);
+    let result = run_pipeline(
+        &piped,
+        &tasks,
+        stages,
+        Arc::new(Totals::default()),
+        &mapping,
+        &registry,
+        &options,
+    );
+    assert_eq!(result.replacements, 22);
+    assert_eq!(read_world(&piped), before);
+    assert_eq!(options.guard.writes(), 0);
+    assert!(options.guard.discarded() > 0);
+    assert_eq!(options.budget.peak(), 64);
+    assert_eq!(options.budget.in_use(), 0);
+    std::fs::remove_dir_all(&single).unwrap();
+    std::fs::remove_dir_all(&piped).unwrap();
+}
@@ -0,0 +1,431 @@
+use std::{
+    fmt::Write,
+    path::Path,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::text::{lines, LineEnding};
+
+/// Changed fields shown for a single file
+pub const FILE_LIMIT: usize = 16;
+/// Changed fields shown for the whole run
+pub const TOTAL_LIMIT: usize = 256;
+/// Deeper NBT is malformed or malicious, the game refuses it as well
+const MAX_DEPTH: usize = 512;
+
+/// A decoded NBT value, only used for previews, the remapping never decodes NBT
+#[derive(Debug, Clone, PartialEq)]
+enum Tag {
+    Byte(i8),
+    Short(i16),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    ByteArray(Vec<i8>),
+    String(String),
+    List(Vec<Tag>),
+    Compound(Vec<(String, Tag)>),
+    IntArray(Vec<i32>),
+    LongArray(Vec<i64>),
+}
+
+struct Decoder<'a> {
+    nbt: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, len: usize) -> anyhow::Result<&'a [u8]> {
+        if len > self.nbt.len() {
+            anyhow::bail!("Malformed NBT: Unexpected EOF");
+        }
+        let (head, tail) = self.nbt.split_at(len);
+        self.nbt = tail;
+        Ok(head)
+    }
+
+    fn take_array<const N: usize>(&mut self) -> anyhow::Result<[u8; N]> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn take_len(&mut self) -> anyhow::Result<usize> {
+        let len = i32::from_be_bytes(self.take_array()?);
+        Ok(len.max(0) as usize)
+    }
+
+    fn take_str(&mut self) -> anyhow::Result<String> {
+        let len = u16::from_be_bytes(self.take_array()?) as usize;
+        Ok(crate::mutf8::decode_lossy(self.take(len)?))
+    }
+
+    fn value(&mut self, kind: u8, depth: usize) -> anyhow::Result<Tag> {
+        if depth > MAX_DEPTH {
+            anyhow::bail!("Malformed NBT: Nested too deep");
+        }
+        Ok(match kind {
+            1 => Tag::Byte(i8::from_be_bytes(self.take_array()?)),
+            2 => Tag::Short(i16::from_be_bytes(self.take_array()?)),
+            3 => Tag::Int(i32::from_be_bytes(self.take_array()?)),
+            4 => Tag::Long(i64::from_be_bytes(self.take_array()?)),
+            5 => Tag::Float(f32::from_be_bytes(self.take_array()?)),
+            6 => Tag::Double(f64::from_be_bytes(self.take_array()?)),
+            7 => {
+                let len = self.take_len()?;
+                Tag::ByteArray(self.take(len)?.iter().map(|&b| b as i8).collect())
+            }
+            8 => Tag::String(self.take_str()?),
+            9 => {
+                let kind = self.take_array::<1>()?[0];
+                let len = self.take_len()?;
+                let mut list = Vec::with_capacity(len.min(self.nbt.len()));
+                for _ in 0..len {
+                    list.push(self.value(kind, depth + 1)?);
+                }
+                Tag::List(list)
+            }
+            10 => {
+                let mut compound = vec![];
+                loop {
+                    let kind = self.take_array::<1>()?[0];
+                    if kind == 0 {
+                        break;
+                    }
+                    let name = self.take_str()?;
+                    compound.push((name, self.value(kind, depth + 1)?));
+                }
+                Tag::Compound(compound)
+            }
+            11 => {
+                let len = self.take_len()?;
+                let mut array = Vec::with_capacity(len.min(self.nbt.len() / 4));
+                for _ in 0..len {
+                    array.push(i32::from_be_bytes(self.take_array()?));
+                }
+                Tag::IntArray(array)
+            }
+            12 => {
+                let len = self.take_len()?;
+                let mut array = Vec::with_capacity(len.min(self.nbt.len() / 8));
+                for _ in 0..len {
+                    array.push(i64::from_be_bytes(self.take_array()?));
+                }
+                Tag::LongArray(array)
+            }
+            _ => anyhow::bail!("Malformed NBT: Unknown tag type {}", kind),
+        })
+    }
+}
+
+/// Decode the root tag, its name is dropped
+fn decode(nbt: &[u8]) -> anyhow::Result<Tag> {
+    let mut decoder = Decoder { nbt };
+    let kind = decoder.take_array::<1>()?[0];
+    decoder.take_str()?;
+    decoder.value(kind, 0)
+}
+
+fn write_str(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        if c == '"' || c == '\\' {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out.push('"');
+}
+
+fn write_key(out: &mut String, key: &str) {
+    if !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.' | '+'))
+    {
+        out.push_str(key);
+    } else {
+        write_str(out, key);
+    }
+}
+
+fn write_array<T: std::fmt::Display>(out: &mut String, prefix: &str, array: &[T], suffix: &str) {
+    write!(out, "[{};", prefix).unwrap();
+    for (i, v) in array.iter().enumerate() {
+        let sep = if i == 0 { " " } else { ", " };
+        write!(out, "{}{}{}", sep, v, suffix).unwrap();
+    }
+    out.push(']');
+}
+
+/// Print the tag as SNBT, on a single line
+fn write_snbt(out: &mut String, tag: &Tag) {
+    match tag {
+        Tag::Byte(v) => write!(out, "{}b", v).unwrap(),
+        Tag::Short(v) => write!(out, "{}s", v).unwrap(),
+        Tag::Int(v) => write!(out, "{}", v).unwrap(),
+        Tag::Long(v) => write!(out, "{}L", v).unwrap(),
+        Tag::Float(v) => write!(out, "{}f", v).unwrap(),
+        Tag::Double(v) => write!(out, "{}d", v).unwrap(),
+        Tag::ByteArray(v) => write_array(out, "B", v, "b"),
+        Tag::String(v) => write_str(out, v),
+        Tag::List(list) => {
+            out.push('[');
+            for (i, v) in list.iter().enumerate() {
+                if i != 0 {
+                    out.push_str(", ");
+                }
+                write_snbt(out, v);
+            }
+            out.push(']');
+        }
+        Tag::Compound(compound) => {
+            out.push('{');
+            for (i, (k, v)) in compound.iter().enumerate() {
+                if i != 0 {
+                    out.push_str(", ");
+                }
+                write_key(out, k);
+                out.push_str(": ");
+                write_snbt(out, v);
+            }
+            out.push('}');
+        }
+        Tag::IntArray(v) => write_array(out, "I", v, ""),
+        Tag::LongArray(v) => write_array(out, "L", v, "L"),
+    }
+}
+
+fn snbt(tag: &Tag) -> String {
+    let mut out = String::new();
+    write_snbt(&mut out, tag);
+    out
+}
+
+/// A changed field, with its full NBT path and the old and new values as SNBT
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub path: String,
+    pub old: String,
+    pub new: String,
+}
+
+fn diff_tag(path: &mut String, old: &Tag, new: &Tag, changes: &mut Vec<Change>) {
+    if old == new {
+        return;
+    }
+    let len = path.len();
+    match (old, new) {
+        (Tag::Compound(old), Tag::Compound(new))
+            if old.len() == new.len() && old.iter().zip(new).all(|(a, b)| a.0 == b.0) =>
+        {
+            for ((key, a), (_, b)) in old.iter().zip(new) {
+                if len != 0 {
+                    path.push('.');
+                }
+                write_key(path, key);
+                diff_tag(path, a, b, changes);
+                path.truncate(len);
+            }
+        }
+        (Tag::List(old), Tag::List(new)) if old.len() == new.len() => {
+            for (i, (a, b)) in old.iter().zip(new).enumerate() {
+                write!(path, "[{}]", i).unwrap();
+                diff_tag(path, a, b, changes);
+                path.truncate(len);
+            }
+        }
+        _ => {
+            // NaN is never equal to itself
+            let (old, new) = (snbt(old), snbt(new));
+            if old != new {
+                changes.push(Change {
+                    path: path.clone(),
+                    old,
+                    new,
+                });
+            }
+        }
+    }
+}
+
+/// The fields changed by the remapping of uncompressed NBT, in the order they appear
+pub fn diff_nbt(old: &[u8], new: &[u8]) -> anyhow::Result<Vec<Change>> {
+    let mut changes = vec![];
+    diff_tag(
+        &mut String::new(),
+        &decode(old)?,
+        &decode(new)?,
+        &mut changes,
+    );
+    Ok(changes)
+}
+
+/// Show the changes of a dry run, capped per file and for the whole run
+#[derive(Debug, Default)]
+pub struct Preview {
+    shown: AtomicUsize,
+}
+
+impl Preview {
+    /// The lines shown for the file, the file is not shown if the cap of the run is reached
+    pub fn lines(&self, path: &Path, changes: &[Change]) -> Vec<String> {
+        if changes.is_empty() {
+            return vec![];
+        }
+        let wanted = changes.len().min(FILE_LIMIT);
+        let before = self.shown.fetch_add(wanted, Ordering::Relaxed);
+        let shown = wanted.min(TOTAL_LIMIT.saturating_sub(before));
+        if shown == 0 {
+            return vec![];
+        }
+        let mut lines = vec![format!("--- {}", path.display())];
+        for change in &changes[..shown] {
+            lines.push(format!(
+                "  {}: {} -> {}",
+                change.path, change.old, change.new
+            ));
+        }
+        if shown < changes.len() {
+            lines.push(format!("  ... and {} more", changes.len() - shown));
+        }
+        if before + wanted >= TOTAL_LIMIT && before < TOTAL_LIMIT {
+            lines.push(format!(
+                "Preview limit of {} changes reached, further changes are not shown",
+                TOTAL_LIMIT
+            ));
+        }
+        lines
+    }
+
+    /// The changed lines of a text file, the header tells the line endings, which are not shown
+    pub fn text_lines(&self, path: &Path, old: &[u8], new: &[u8]) -> Vec<String> {
+        // The rewrites keep the line endings, so the lines correspond
+        let changes = lines(old)
+            .into_iter()
+            .zip(lines(new))
+            .enumerate()
+            .filter(|(_, (o, n))| old[o.clone()] != new[n.clone()])
+            .map(|(i, (o, n))| Change {
+                path: format!("line {}", i + 1),
+                old: String::from_utf8_lossy(&old[o]).into_owned(),
+                new: String::from_utf8_lossy(&new[n]).into_owned(),
+            })
+            .collect::<Vec<_>>();
+        let mut lines = self.lines(path, &changes);
+        if let Some(header) = lines.first_mut() {
+            header.push_str(&format!(" ({})", LineEnding::detect(old)));
+        }
+        lines
+    }
+
+    /// Show the changed lines of the text file
+    pub fn text(&self, path: &Path, old: &[u8], new: &[u8]) {
+        for line in self.text_lines(path, old, new) {
+            log::info!("{}", line);
+        }
+    }
+
+    /// Show the changes of the uncompressed NBT of the file
+    pub fn nbt(&self, path: &Path, old: &[u8], new: &[u8]) {
+        match diff_nbt(old, new) {
+            Ok(changes) => {
+                for line in self.lines(path, &changes) {
+                    log::info!("{}", line);
+                }
+            }
+            Err(err) => log::warn!("Failed to preview {}: {:#}", path.display(), err),
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    use std::collections::HashMap;
+
+    use uuid::Uuid;
+    use valence_nbt::{binary::to_binary, snbt::from_snbt_str, Value};
+
+    use crate::{nbt::visit_nbt, setup_test_logger};
+
+    setup_test_logger();
+
+    let from = Uuid::from_u128(0x00000001_00000002_00000003_00000004);
+    let to = Uuid::from_u128(0x0000000a_0000000b_0000000c_0000000d);
+    let mapping = HashMap::from([(from, to)]);
+    let snbt_text = r#"{
+        UUID: [I; 1, 2, 3, 4],
+        Health: 20.0f,
+        Inventory: [{id: "minecraft:stone", Count: 1b}],
+        Brain: {memories: {"minecraft:angry_at": {value: [I; 1, 2, 3, 4]}}},
+        Owner: "00000001-0000-0002-0000-000300000004",
+        Other: [I; 5, 6, 7, 8],
+    }"#;
+    let Value::Compound(compound) = from_snbt_str(snbt_text).unwrap() else {
+        panic!()
+    };
+    let mut old = vec![];
+    to_binary(&compound, &mut old, "").unwrap();
+    let mut new = old.clone();
+    visit_nbt(&mut new, &|uuid| mapping.get(&uuid).copied()).unwrap();
+
+    // valence_nbt sorts the keys
+    let changes = diff_nbt(&old, &new).unwrap();
+    assert_eq!(
+        changes,
+        vec![
+            Change {
+                path: "Brain.memories.\"minecraft:angry_at\".value".to_string(),
+                old: "[I; 1, 2, 3, 4]".to_string(),
+                new: "[I; 10, 11, 12, 13]".to_string(),
+            },
+            Change {
+                path: "Owner".to_string(),
+                old: "\"00000001-0000-0002-0000-000300000004\"".to_string(),
+                new: "\"0000000a-0000-000b-0000-000c0000000d\"".to_string(),
+            },
+            Change {
+                path: "UUID".to_string(),
+                old: "[I; 1, 2, 3, 4]".to_string(),
+                new: "[I; 10, 11, 12, 13]".to_string(),
+            },
+        ]
+    );
+    assert!(diff_nbt(&old, &old).unwrap().is_empty());
+    assert!(diff_nbt(&old[..old.len() - 1], &new).is_err());
+
+    let preview = Preview::default();
+    let path = Path::new("playerdata/00000001-0000-0002-0000-000300000004.dat");
+    let lines = preview.lines(path, &changes);
+    assert_eq!(
+        lines[0],
+        "--- playerdata/00000001-0000-0002-0000-000300000004.dat"
+    );
+    assert_eq!(lines.len(), 4);
+    assert_eq!(lines[3], "  UUID: [I; 1, 2, 3, 4] -> [I; 10, 11, 12, 13]");
+
+    // Capped per file and for the whole run
+    let many = vec![changes[0].clone(); FILE_LIMIT + 3];
+    let lines = preview.lines(path, &many);
+    assert_eq!(lines.len(), FILE_LIMIT + 2);
+    assert_eq!(lines.last().unwrap(), "  ... and 3 more");
+    let mut files = 0;
+    while !preview.lines(path, &many).is_empty() {
+        files += 1;
+        assert!(files <= TOTAL_LIMIT / FILE_LIMIT);
+    }
+    assert!(preview.lines(path, &many).is_empty());
+
+    let preview = Preview::default();
+    let path = Path::new("plugins/Claims/claims.yml");
+    let old = b"claims:\r\n  owner: 00000001-0000-0002-0000-000300000004\r\n  size: 4\r\n";
+    let new = b"claims:\r\n  owner: 0000000a-0000-000b-0000-000c0000000d\r\n  size: 4\r\n";
+    assert_eq!(
+        preview.text_lines(path, old, new),
+        [
+            "--- plugins/Claims/claims.yml (CRLF)",
+            "  line 2:   owner: 00000001-0000-0002-0000-000300000004 -> \
+            \x20 owner: 0000000a-0000-000b-0000-000c0000000d",
+        ]
+    );
+    assert!(preview.text_lines(path, old, old).is_empty());
+}
@@ -0,0 +1,241 @@
+use std::{
+    collections::BTreeMap,
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use clap::{parser::ValueSource, Arg, ArgMatches, Command};
+use serde::Deserialize;
+
+/// The config file read without `--config`, in the working directory, ignored if missing
+pub const DEFAULT_CONFIG: &str = "uuid-remapper.json";
+
+/// The options of a profile, keyed by the long name of the flag without the dashes,
+/// e.g. `{"threads": 8, "yes": true, "chunk": ["0,0", "1,0"]}`
+pub type ProfileOptions = BTreeMap<String, serde_json::Value>;
+
+/// The content of the config file
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub profiles: BTreeMap<String, ProfileOptions>,
+}
+
+impl Config {
+    /// Read the config file, or the default one if it exists
+    pub fn load(path: Option<&Path>) -> anyhow::Result<Self> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => {
+                let path = PathBuf::from(DEFAULT_CONFIG);
+                if !path.exists() {
+                    return Ok(Self::default());
+                }
+                path
+            }
+        };
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        serde_json::from_str(&content).with_context(|| format!("Invalid config {}", path.display()))
+    }
+
+    /// The profile, an unknown name is an error listing the profiles
+    pub fn profile(&self, name: &str) -> anyhow::Result<&ProfileOptions> {
+        self.profiles.get(name).with_context(|| {
+            if self.profiles.is_empty() {
+                format!("Unknown profile {}, no profile is defined", name)
+            } else {
+                let names = self.profiles.keys().cloned().collect::<Vec<_>>();
+                format!(
+                    "Unknown profile {}, available profiles: {}",
+                    name,
+                    names.join(", ")
+                )
+            }
+        })
+    }
+}
+
+/// The options of `--legacy-behavior`, the quirks of older versions scripts depend on:
+/// - every file and chunk is visited and rewritten, even without an old uuid
+/// - the exit code is 0, even if the run fails or is cancelled
+/// - an empty mapping is accepted
+///
+/// The other checks added since still apply, e.g. to a partial or chained mapping, the names left
+/// out of it, a running server or root, each with its own flag, and files are still renamed once
+/// every content is remapped, unless `--single-phase`
+pub fn legacy_behavior() -> ProfileOptions {
+    ["no-prefilter", "exit-zero", "assume-empty-ok"]
+        .into_iter()
+        .map(|name| (name.to_string(), serde_json::Value::Bool(true)))
+        .collect()
+}
+
+/// The arguments of the option, e.g. `["--chunk", "0,0", "--chunk", "1,0"]`
+pub fn arguments(name: &str, value: &serde_json::Value) -> anyhow::Result<Vec<String>> {
+    let flag = format!("--{}", name);
+    let scalar = |value: &serde_json::Value| match value {
+        serde_json::Value::String(s) => Ok(s.clone()),
+        serde_json::Value::Number(n) => Ok(n.to_string()),
+        _ => anyhow::bail!("Invalid value {} of {} in the profile", value, flag),
+    };
+    Ok(match value {
+        serde_json::Value::Bool(true) => vec![flag],
+        serde_json::Value::Bool(false) => vec![],
+        serde_json::Value::Array(values) => {
+            let mut args = vec![];
+            for value in values {
+                args.push(flag.clone());
+                args.push(scalar(value)?);
+            }
+            args
+        }
+        value => vec![flag.clone(), scalar(value)?],
+    })
+}
+
+/// Insert the options of the profile not given on the command line, which is parsed as `matches`,
+/// nor conflicting with an option given on it, after the program name
+/// Returns the arguments and the options taken from the profile, as they are inserted
+pub fn apply(
+    command: &Command,
+    mut args: Vec<OsString>,
+    matches: &ArgMatches,
+    profile: &ProfileOptions,
+) -> anyhow::Result<(Vec<OsString>, Vec<String>)> {
+    let given =
+        |arg: &Arg| matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine);
+    let mut applied = vec![];
+    let mut inserted = vec![];
+    for (name, value) in profile {
+        if matches!(name.as_str(), "profile" | "config") {
+            anyhow::bail!("--{} can't be set in a profile", name);
+        }
+        let Some(arg) = command
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(name.as_str()))
+        else {
+            anyhow::bail!("Unknown option --{} in the profile", name);
+        };
+        // The command line wins over the profile
+        let conflicting = command
+            .get_arguments()
+            .filter(|other| given(other))
+            .any(|other| {
+                command.get_arg_conflicts_with(arg).contains(&other)
+                    || command.get_arg_conflicts_with(other).contains(&arg)
+            });
+        if given(arg) || conflicting {
+            continue;
+        }
+        let option = arguments(name, value)?;
+        if !option.is_empty() {
+            applied.push(option.join(" "));
+            inserted.extend(option.into_iter().map(OsString::from));
+        }
+    }
+    let at = args.len().min(1);
+    args.splice(at..at, inserted);
+    Ok((args, applied))
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    use clap::ArgAction;
+
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    let command = Command::new("uuid-remapper")
+        .arg(Arg::new("path"))
+        .arg(Arg::new("threads").long("threads").default_value("24"))
+        .arg(Arg::new("yes").long("yes").action(ArgAction::SetTrue))
+        .arg(Arg::new("chunks").long("chunk").action(ArgAction::Append))
+        .arg(
+            Arg::new("dry_run")
+                .long("dry-run")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("yes"),
+        )
+        .arg(Arg::new("profile").long("profile"));
+    let args = [
+        "uuid-remapper",
+        "world",
+        "--threads",
+        "2",
+        "--profile",
+        "audit",
+    ]
+    .map(OsString::from)
+    .to_vec();
+    let matches = command.clone().get_matches_from(&args);
+    let config: Config = serde_json::from_str(
+        r#"{"profiles": {"audit": {"threads": 8, "yes": true, "chunk": ["0,0", "1,0"]}}}"#,
+    )
+    .unwrap();
+    let (merged, applied) = apply(
+        &command,
+        args.clone(),
+        &matches,
+        config.profile("audit").unwrap(),
+    )
+    .unwrap();
+    // The threads on the command line win
+    assert_eq!(applied, ["--chunk 0,0 --chunk 1,0", "--yes"]);
+    let merged = command.clone().get_matches_from(merged);
+    assert_eq!(merged.get_one::<String>("threads").unwrap(), "2");
+    assert!(merged.get_flag("yes"));
+    assert_eq!(
+        merged
+            .get_many::<String>("chunks")
+            .unwrap()
+            .collect::<Vec<_>>(),
+        ["0,0", "1,0"]
+    );
+    // The defaults are not given on the command line
+    let defaults = command.clone().get_matches_from(&args[..2]);
+    let (_, applied) = apply(
+        &command,
+        args[..2].to_vec(),
+        &defaults,
+        &ProfileOptions::from([("threads".to_string(), serde_json::json!(8))]),
+    )
+    .unwrap();
+    assert_eq!(applied, ["--threads 8"]);
+    // The options conflicting with the command line are not taken, in either direction
+    for (given, option) in [("--dry-run", "yes"), ("--yes", "dry-run")] {
+        let args = ["uuid-remapper", "world", given]
+            .map(OsString::from)
+            .to_vec();
+        let matches = command.clone().get_matches_from(&args);
+        let profile = ProfileOptions::from([
+            (option.to_string(), serde_json::json!(true)),
+            ("threads".to_string(), serde_json::json!(8)),
+        ]);
+        let (merged, applied) = apply(&command, args.clone(), &matches, &profile).unwrap();
+        assert_eq!(applied, ["--threads 8"]);
+        assert!(command.clone().try_get_matches_from(merged).is_ok());
+    }
+    // Inserted before a `--` on the command line
+    let args = ["uuid-remapper", "--", "world"]
+        .map(OsString::from)
+        .to_vec();
+    let matches = command.clone().get_matches_from(&args);
+    let (merged, _) = apply(&command, args, &matches, config.profile("audit").unwrap()).unwrap();
+    let merged = command.clone().get_matches_from(merged);
+    assert_eq!(merged.get_one::<String>("path").unwrap(), "world");
+    assert!(merged.get_flag("yes"));
+
+    let err = config.profile("cleanup").unwrap_err().to_string();
+    assert_eq!(err, "Unknown profile cleanup, available profiles: audit");
+    let unknown = ProfileOptions::from([("thread".to_string(), serde_json::json!(8))]);
+    assert!(apply(&command, args.clone(), &matches, &unknown).is_err());
+    let nested = ProfileOptions::from([("profile".to_string(), serde_json::json!("audit"))]);
+    assert!(apply(&command, args.clone(), &matches, &nested).is_err());
+    assert!(arguments("area", &serde_json::json!({})).is_err());
+    assert!(serde_json::from_str::<Config>(r#"{"profile": {}}"#).is_err());
+}
@@ -0,0 +1,236 @@
+use std::{
+    cell::{Cell, RefCell},
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use indicatif::ProgressBar;
+
+use crate::source::BySource;
+
+/// Minimum interval between two updates of the message, redrawing is not free
+const THROTTLE: Duration = Duration::from_millis(250);
+
+/// Totals of all the workers, shown in the aggregate bar
+#[derive(Default)]
+pub struct Totals {
+    bar: Option<ProgressBar>,
+    errors: AtomicUsize,
+    files: AtomicUsize,
+    replacements: AtomicUsize,
+    /// The sizes of the files done, as they were before the remapping
+    bytes: AtomicU64,
+    by_source: Mutex<BySource>,
+    /// The file each worker is remapping, `None` between two files
+    current: Mutex<Vec<Option<String>>>,
+}
+
+impl Totals {
+    pub fn new(bar: ProgressBar) -> Self {
+        bar.set_message("total errors: 0");
+        Self {
+            bar: Some(bar),
+            ..Default::default()
+        }
+    }
+
+    /// Files and chunks failed so far
+    pub fn errors(&self) -> usize {
+        self.errors.load(Ordering::Relaxed)
+    }
+
+    /// Files done so far, including the failed ones
+    pub fn files(&self) -> usize {
+        self.files.load(Ordering::Relaxed)
+    }
+
+    /// Uuids replaced so far
+    pub fn replacements(&self) -> usize {
+        self.replacements.load(Ordering::Relaxed)
+    }
+
+    /// Bytes of the files done so far
+    pub fn bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    /// Uuids replaced so far, by where they were found
+    pub fn by_source(&self) -> BySource {
+        *self.by_source.lock().unwrap()
+    }
+
+    /// The file each worker is remapping
+    pub fn current(&self) -> Vec<Option<String>> {
+        self.current.lock().unwrap().clone()
+    }
+
+    /// A new worker, returning its slot in `current`
+    fn register(&self) -> usize {
+        let mut current = self.current.lock().unwrap();
+        current.push(None);
+        current.len() - 1
+    }
+
+    fn set_current(&self, slot: usize, file: Option<String>) {
+        self.current.lock().unwrap()[slot] = file;
+    }
+
+    pub fn add_errors(&self, count: usize) {
+        let before = self.errors.fetch_add(count, Ordering::Relaxed);
+        if before == 0 {
+            log::warn!("Some files failed to remap, check the log for the errors");
+        }
+        if let Some(bar) = &self.bar {
+            bar.set_message(format!("total errors: {}", before + count));
+        }
+    }
+
+    fn file_done(&self, replacements: usize) {
+        self.files.fetch_add(1, Ordering::Relaxed);
+        self.replacements.fetch_add(replacements, Ordering::Relaxed);
+        if let Some(bar) = &self.bar {
+            bar.inc(1);
+        }
+    }
+}
+
+/// Progress within the file being remapped, shown in the message of the worker bar
+#[derive(Default)]
+pub struct Progress {
+    bar: Option<ProgressBar>,
+    totals: Option<Arc<Totals>>,
+    /// The slot of the worker in the totals
+    slot: usize,
+    file: RefCell<String>,
+    last: Cell<Option<Instant>>,
+    /// Files and chunks failed in the worker
+    errors: Cell<usize>,
+}
+
+impl Progress {
+    /// Report nothing
+    pub fn hidden() -> Self {
+        Self::default()
+    }
+
+    pub fn new(bar: ProgressBar, totals: Arc<Totals>) -> Self {
+        Self {
+            bar: Some(bar),
+            slot: totals.register(),
+            totals: Some(totals),
+            ..Default::default()
+        }
+    }
+
+    fn set_message(&self, bar: &ProgressBar, text: String) {
+        bar.set_message(format!("errs: {} {}", self.errors.get(), text));
+    }
+
+    /// A new file is started
+    pub fn start(&self, file: &Path) {
+        *self.file.borrow_mut() = file.display().to_string();
+        self.last.set(None);
+        if let Some(totals) = &self.totals {
+            totals.set_current(self.slot, Some(self.file.borrow().clone()));
+        }
+        if let Some(bar) = &self.bar {
+            self.set_message(bar, self.file.borrow().clone());
+        }
+    }
+
+    /// The file is done with the errors, counting both the failed file and its failed chunks
+    pub fn finish(&self, errors: usize, replacements: usize) {
+        if errors > 0 {
+            self.errors.set(self.errors.get() + errors);
+            if let Some(totals) = &self.totals {
+                totals.add_errors(errors);
+            }
+            if let Some(bar) = &self.bar {
+                self.set_message(bar, self.file.borrow().clone());
+            }
+        }
+        if let Some(totals) = &self.totals {
+            totals.set_current(self.slot, None);
+            totals.file_done(replacements);
+        }
+    }
+
+    /// The size of the file and its replacements by source, before it is finished
+    pub fn record(&self, bytes: u64, by_source: &BySource) {
+        if let Some(totals) = &self.totals {
+            totals.bytes.fetch_add(bytes, Ordering::Relaxed);
+            totals.by_source.lock().unwrap().merge(by_source);
+        }
+    }
+
+    fn update(&self, detail: impl FnOnce() -> String) {
+        let Some(bar) = &self.bar else {
+            return;
+        };
+        let now = Instant::now();
+        if self.last.get().is_some_and(|last| now - last < THROTTLE) {
+            return;
+        }
+        self.last.set(Some(now));
+        self.set_message(bar, format!("{} ({})", self.file.borrow(), detail()));
+    }
+
+    /// The `index`-th chunk of `count` in the region is being remapped, 1-based
+    pub fn chunk(&self, index: usize, count: usize) {
+        self.update(|| format!("chunk {}/{}", index, count));
+    }
+
+    /// `done` bytes of `total` are processed
+    pub fn bytes(&self, done: usize, total: usize) {
+        self.update(|| format!("{}/{} bytes", done, total));
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    let bar = ProgressBar::hidden();
+    let total_bar = ProgressBar::hidden();
+    let totals = Arc::new(Totals::new(total_bar.clone()));
+    let progress = Progress::new(bar.clone(), totals.clone());
+    progress.start(Path::new("region/r.0.0.mca"));
+    assert_eq!(bar.message(), "errs: 0 region/r.0.0.mca");
+    progress.chunk(1, 10);
+    assert_eq!(bar.message(), "errs: 0 region/r.0.0.mca (chunk 1/10)");
+    // Throttled
+    progress.chunk(2, 10);
+    assert_eq!(bar.message(), "errs: 0 region/r.0.0.mca (chunk 1/10)");
+    std::thread::sleep(THROTTLE);
+    progress.chunk(3, 10);
+    assert_eq!(bar.message(), "errs: 0 region/r.0.0.mca (chunk 3/10)");
+    progress.finish(2, 5);
+    assert_eq!(bar.message(), "errs: 2 region/r.0.0.mca");
+    // A new file is shown at once
+    progress.start(Path::new("config.yml"));
+    progress.bytes(0, 100);
+    assert_eq!(bar.message(), "errs: 2 config.yml (0/100 bytes)");
+    progress.finish(0, 0);
+
+    // The totals are shared by the workers
+    let other = Progress::new(ProgressBar::hidden(), totals.clone());
+    other.start(Path::new("level.dat"));
+    assert_eq!(totals.current(), vec![None, Some("level.dat".to_string())]);
+    other.finish(1, 7);
+    assert_eq!(totals.errors(), 3);
+    assert_eq!(total_bar.message(), "total errors: 3");
+    assert_eq!(total_bar.position(), 3);
+    assert_eq!(totals.files(), 3);
+    assert_eq!(totals.replacements(), 12);
+    assert_eq!(totals.current(), vec![None, None]);
+
+    Progress::hidden().chunk(1, 1);
+    Progress::hidden().finish(1, 1);
+}
@@ -7,56 +7,446 @@ use std::os::unix::ffi::{OsStrExt, OsStringExt};
 use std::os::windows::ffi::{OsStrExt, OsStringExt};
 #[cfg(target_family = "windows")]
 use std::str::FromStr;
-use std::{io::Read, path::Path};
+use std::{
+    collections::HashMap,
+    io::Read,
+    path::{Path, PathBuf},
+};
 
 use std::io::Write;
 
-use flate2::read::GzDecoder;
-use flate2::write::GzEncoder;
+use anyhow::Context;
+use flate2::read::{GzDecoder, ZlibDecoder};
+use flate2::write::{GzEncoder, ZlibEncoder};
 use uuid::Uuid;
 
-use crate::{anvil::Anvil, nbt::visit_nbt, text::visit_text};
+use crate::{
+    anvil::{
+        external_chunk_coords, region_coords, renamed_externals, Anvil, AnvilError, AnvilReader,
+        AnvilWriter, Region, RegionWriter, COMPRESSION_LEVEL, LOW_MEMORY_THRESHOLD, MAX_CHUNK_SIZE,
+    },
+    budget,
+    conflict::{self, Prompter},
+    decimal::visit_decimal,
+    filter::world_chunk,
+    format::{is_poi, Profile},
+    guard::WriteGuard,
+    handler::{sniff, FileHandler, FileStats, Registry, RemapContext, RemapOptions, SNIFF_LEN},
+    nbt::{check_compound_root, visit_nbt_with, NonCompoundRoot},
+    pattern::{splice, visit_patterns},
+    progress::Progress,
+    source::{located, Location},
+    stage,
+    text::{visit_file_name, visit_text_replaced, LineEnding},
+};
 
-fn remap_mca(path: &Path, cb: &impl Fn(Uuid) -> Option<Uuid>) -> anyhow::Result<()> {
-    let input = Anvil::open(path)?;
-    let mut output = Anvil::new(path);
-    for block in input.iter() {
+/// The external chunks are written under the current name, they are renamed with the region
+fn remap_mca(
+    path: &Path,
+    options: &RemapOptions,
+    progress: &Progress,
+    cb: &impl Fn(Uuid) -> Option<Uuid>,
+) -> anyhow::Result<FileStats> {
+    let len = options
+        .guard
+        .retry()
+        .run(path, || std::fs::metadata(path))?
+        .len();
+    let limit = options.max_chunk_size.unwrap_or(MAX_CHUNK_SIZE);
+    if options.low_memory || len > LOW_MEMORY_THRESHOLD {
+        log::debug!("Remapping {} in low-memory mode", path.display());
+        let input =
+            AnvilReader::open_with_guard(path, options.guard.clone())?.with_chunk_limit(limit);
+        let output = AnvilWriter::create(path, options.guard.clone())?;
+        remap_region(input, output, len, options, progress, cb)
+    } else {
+        let input = Anvil::open_with_guard(path, options.guard.clone())?.with_chunk_limit(limit);
+        let output = Anvil::new(path).with_guard(options.guard.clone());
+        remap_region(input, output, len, options, progress, cb)
+    }
+}
+
+/// Whether the chunk replaces the first one with the same timestamp pointing at the same sectors,
+/// only if the user chooses so, the first one is kept otherwise
+fn keep_other(
+    conflicts: Option<&Prompter>,
+    path: &Path,
+    first: (i32, i32),
+    other: (i32, i32),
+    offset: usize,
+) -> bool {
+    let Some(conflicts) = conflicts else {
+        return false;
+    };
+    let keep_first = format!("keep chunk ({}, {})", first.0, first.1);
+    let keep_other = format!("keep chunk ({}, {})", other.0, other.1);
+    let details = vec![
+        conflict::describe(path),
+        format!(
+            "Chunks ({}, {}) and ({}, {}) point at sector {} with the same timestamp",
+            first.0, first.1, other.0, other.1, offset
+        ),
+    ];
+    conflicts.ask(
+        "duplicate_chunk",
+        details,
+        &[("f", &keep_first), ("o", &keep_other)],
+    ) == 1
+}
+
+/// `len` is the length of the input file, compared with the output for the anomalies along with
+/// the external chunk files on both sides, as rewriting moves chunks in and out of them
+fn remap_region(
+    input: impl Region,
+    mut output: impl RegionWriter,
+    len: u64,
+    options: &RemapOptions,
+    progress: &Progress,
+    cb: &impl Fn(Uuid) -> Option<Uuid>,
+) -> anyhow::Result<FileStats> {
+    let path = input.path();
+    let len = len + input.externals_len();
+    let mut stats = FileStats::default();
+    let region = region_coords(path);
+    // Corrupted headers may have several entries pointing at the same sectors,
+    // writing all of them would duplicate the chunk, so only the newest one is kept
+    let mut owners = HashMap::<usize, (i32, i32)>::new();
+    for location in input.locations() {
+        let offset = input.sector_offset(location)?;
+        match owners.get(&offset) {
+            Some(&owner) if input.timestamp(owner)? > input.timestamp(location)? => {}
+            Some(&owner) if input.timestamp(owner)? == input.timestamp(location)? => {
+                if keep_other(options.conflicts.as_ref(), path, owner, location, offset) {
+                    owners.insert(offset, location);
+                }
+            }
+            _ => {
+                owners.insert(offset, location);
+            }
+        }
+    }
+    let locations = input.locations();
+    for (i, &location) in locations.iter().enumerate() {
+        progress.chunk(i + 1, locations.len());
+        let owner = owners[&input.sector_offset(location)?];
+        if owner != location {
+            stats.duplicate_chunks += 1;
+            log::warn!(
+                "Chunk ({}, {}) in file {} points at the same sectors as chunk ({}, {}), \
+                keeping the newer one (timestamp {} over {})",
+                location.0,
+                location.1,
+                path.display(),
+                owner.0,
+                owner.1,
+                input.timestamp(owner)?,
+                input.timestamp(location)?
+            );
+            continue;
+        }
+        if let Some(filter) = &options.chunk_filter {
+            match region {
+                Some(region) if filter.contains(region, location) => {
+                    stats.selected_chunks.push(world_chunk(region, location));
+                }
+                _ => {
+                    if let Err(err) = output.copy_chunk(&input, location) {
+                        stats.chunk_errors += 1;
+                        log::error!(
+                            "Failed to copy chunk ({}, {}) in file {}: {}",
+                            location.0,
+                            location.1,
+                            path.display(),
+                            err
+                        );
+                    }
+                    continue;
+                }
+            }
+        }
+        stats.chunks += 1;
         if let Err(err) = (|| -> anyhow::Result<()> {
-            let mut chunk = block?;
-            visit_nbt(&mut chunk.uncompressed, cb)?;
+            // Held while the chunk is uncompressed, the pipeline charged the whole file instead
+            let _permit = (!stage::is_staged()).then(|| {
+                let stored = input.stored_len(location);
+                options.budget.acquire(budget::estimate_chunk(stored))
+            });
+            let mut chunk = input.read(location).with_context(|| {
+                format!(
+                    "Failed to read chunk ({}, {}) in file {}",
+                    location.0,
+                    location.1,
+                    path.display()
+                )
+            })?;
+            // The visitor modifies the chunk in place, so the input is kept for the capture
+            let capture = options.capture.as_ref().filter(|c| c.all_nbt_errors());
+            let original = capture.map(|_| chunk.uncompressed.clone());
+            let context = || {
+                format!(
+                    "Failed to visit chunk ({}, {}) in file {}",
+                    location.0,
+                    location.1,
+                    path.display()
+                )
+            };
+            check_compound_root(&chunk.uncompressed).with_context(context)?;
+            if options
+                .prefilter
+                .as_ref()
+                .is_some_and(|engine| !engine.prefilter(&chunk.uncompressed))
+            {
+                // Nothing to remap, the chunk is not compressed again
+                output.copy_chunk(&input, location)?;
+                return Ok(());
+            }
+            let chunk_location = |at: &mut Location| at.chunk = Some(location);
+            let visited = located(chunk_location, || {
+                visit_nbt_with(&mut chunk.uncompressed, cb, options.nbt_rules)
+            });
+            if let Err(err) = visited {
+                if let (Some(capture), Some(original)) = (capture, &original) {
+                    capture.chunk(path, location, original, &err, options.nbt_rules);
+                }
+                return Err(err.context(context()));
+            }
             output.write(&chunk)?;
             Ok(())
         })() {
-            log::error!("Failed to visit chunk {:#?}", err);
+            let anvil_error = err.downcast_ref::<AnvilError>();
+            let oversized = matches!(anvil_error, Some(AnvilError::Oversized(_)));
+            if oversized || err.downcast_ref::<NonCompoundRoot>().is_some() {
+                if oversized {
+                    stats.oversized_chunks += 1;
+                    log::warn!("Oversized, skipped, passing through chunk as is: {:#}", err);
+                } else {
+                    stats.non_compound_roots += 1;
+                    log::warn!("Passing through chunk as is: {:#}", err);
+                }
+                if let Err(err) = output.copy_chunk(&input, location) {
+                    stats.chunk_errors += 1;
+                    log::error!(
+                        "Failed to copy chunk ({}, {}) in file {}: {}",
+                        location.0,
+                        location.1,
+                        path.display(),
+                        err
+                    );
+                }
+            } else if matches!(anvil_error, Some(AnvilError::LengthMismatch(_))) {
+                // Still an error, the chunk is likely cut, but its bytes are kept for a repair
+                stats.chunk_errors += 1;
+                log::error!("Passing through chunk as is: {:#}", err);
+                if let Err(err) = output.copy_chunk(&input, location) {
+                    log::error!(
+                        "Failed to copy chunk ({}, {}) in file {}: {}",
+                        location.0,
+                        location.1,
+                        path.display(),
+                        err
+                    );
+                }
+            } else {
+                stats.chunk_errors += 1;
+                log::error!("Failed to visit chunk {:#?}", err);
+            }
+        }
+    }
+    let written = output.written_len() as u64;
+    stats.region_size = Some((len, written));
+    if let Some(check) = &options.anomalies {
+        if check.is_anomaly(len, written) {
+            stats.anomaly = true;
+            log::warn!(
+                "{} grows from {} to {} bytes ({:.2}x) when rewritten, over the anomaly factor {}",
+                path.display(),
+                len,
+                written,
+                written as f64 / len.max(1) as f64,
+                check.factor
+            );
+            match check.keep(path) {
+                Ok(Some(copy)) => log::warn!("   The original is kept as {}", copy.display()),
+                Ok(None) => {}
+                Err(err) => log::error!(
+                    "Failed to keep the original of {}: {:#}",
+                    path.display(),
+                    err
+                ),
+            }
         }
     }
-    output.save()?;
+    // The input is closed before the output replaces it
+    drop(input);
+    output.finish()?;
+    Ok(stats)
+}
+
+/// Remap the NBT, showing the changed fields if previewing
+fn visit_nbt_previewed(
+    nbt: &mut [u8],
+    path: &Path,
+    options: &RemapOptions,
+    cb: &impl Fn(Uuid) -> Option<Uuid>,
+) -> anyhow::Result<()> {
+    let Some(preview) = &options.preview else {
+        return visit_nbt_with(nbt, cb, options.nbt_rules);
+    };
+    let old = nbt.to_vec();
+    visit_nbt_with(nbt, cb, options.nbt_rules)?;
+    if old != nbt {
+        preview.nbt(path, &old, nbt);
+    }
     Ok(())
 }
 
-fn remap_dat(path: &Path, cb: &impl Fn(Uuid) -> Option<Uuid>) -> anyhow::Result<()> {
-    let mut chunk = std::fs::read(path)?;
-    let mut decoder = GzDecoder::<&[u8]>::new(&chunk);
-    let mut uncompressed = Vec::new();
-    if decoder.read_to_end(&mut uncompressed).is_err() {
+/// Decompress every gzip member, some tools append to a file as another member
+/// Returns the members and the bytes after the last one, an error if the data is not gzip
+fn gzip_members(mut data: &[u8]) -> std::io::Result<(Vec<Vec<u8>>, &[u8])> {
+    let mut members = vec![];
+    loop {
+        // Reading from the slice itself, so it is left at the end of the member
+        let mut decoder = flate2::bufread::GzDecoder::new(data);
+        let mut member = Vec::new();
+        decoder.read_to_end(&mut member)?;
+        data = decoder.into_inner();
+        members.push(member);
+        if !data.starts_with(&[0x1f, 0x8b]) {
+            return Ok((members, data));
+        }
+    }
+}
+
+/// Remap an NBT file, `gzip` forces the compression, otherwise it is detected
+/// Each gzip member is remapped and compressed separately, so none of them is lost
+fn remap_dat(
+    path: &Path,
+    options: &RemapOptions,
+    gzip: Option<bool>,
+    cb: &impl Fn(Uuid) -> Option<Uuid>,
+) -> anyhow::Result<()> {
+    let guard = &options.guard;
+    let mut chunk = guard.retry().read(path)?;
+    let decoded = match gzip {
+        Some(false) => None,
+        _ => gzip_members(&chunk)
+            .ok()
+            .map(|(members, trailing)| (members, trailing.to_vec())),
+    };
+    let Some((mut members, trailing)) = decoded else {
+        if gzip == Some(true) {
+            anyhow::bail!("{} is not gzipped NBT", path.display());
+        }
         // Not a Gzip file? try raw nbt
-        visit_nbt(&mut chunk, cb)?;
-        std::fs::write(path, &chunk)?;
+        visit_nbt_previewed(&mut chunk, path, options, cb)?;
+        guard.write(path, &chunk)?;
         return Ok(());
     };
+    let count = members.len();
+    if count > 1 {
+        log::info!(
+            "{} has {} gzip members, each of them is remapped",
+            path.display(),
+            count
+        );
+    }
+    // A member that is not a whole NBT document fails the file before anything is written
+    for (i, member) in members.iter_mut().enumerate() {
+        visit_nbt_previewed(member, path, options, cb)
+            .with_context(|| format!("In gzip member {} of {}", i + 1, count))?;
+    }
     chunk.clear();
-    visit_nbt(&mut uncompressed, cb)?;
-    let mut encoder = GzEncoder::new(&mut chunk, flate2::Compression::default());
-    encoder.write_all(&uncompressed)?;
-    encoder.finish()?;
-    std::fs::write(path, &chunk)?;
+    for member in &members {
+        let mut encoder = GzEncoder::new(&mut chunk, COMPRESSION_LEVEL);
+        encoder.write_all(member)?;
+        encoder.finish()?;
+    }
+    // E.g. padding, kept as is
+    chunk.extend(trailing);
+    guard.write(path, &chunk)?;
     Ok(())
 }
 
-fn remap_text(path: &Path, cb: &impl Fn(Uuid) -> Option<Uuid>) -> anyhow::Result<()> {
-    let mut text = std::fs::read(path)?;
-    visit_text(&mut text, cb);
-    std::fs::write(path, &text)?;
+fn remap_text(
+    path: &Path,
+    options: &RemapOptions,
+    progress: &Progress,
+    cb: &impl Fn(Uuid) -> Option<Uuid>,
+) -> anyhow::Result<()> {
+    let mut text = options.guard.retry().read(path)?;
+    progress.bytes(0, text.len());
+    if options
+        .prefilter
+        .as_ref()
+        .is_some_and(|engine| !engine.prefilter(&text))
+    {
+        progress.bytes(text.len(), text.len());
+        return Ok(());
+    }
+    let old = options.preview.is_some().then(|| text.clone());
+    let ending = LineEnding::detect(&text);
+    // The patterns are matched on the original text, skipping the uuids the text pass replaced
+    let original = (!options.text_patterns.is_empty()).then(|| text.clone());
+    let replaced = visit_text_replaced(&mut text, cb);
+    if let Some(original) = original {
+        let replacements = visit_patterns(&original, &options.text_patterns, &replaced, cb);
+        if !replacements.is_empty() {
+            text = splice(&text, &replacements);
+        }
+    }
+    if let Some(keys) = &options.decimal_uuid_keys {
+        if matches!(
+            extension(path),
+            "json" | "json5" | "properties" | "yml" | "yaml"
+        ) {
+            if let Some(rewritten) = visit_decimal(&text, keys, path, cb) {
+                text = rewritten;
+            }
+        }
+    }
+    // A rewrite must never normalize the line endings as a side effect
+    if LineEnding::detect(&text) != ending {
+        anyhow::bail!("The remapping would change the line endings ({})", ending);
+    }
+    if let (Some(preview), Some(old)) = (&options.preview, old) {
+        preview.text(path, &old, &text);
+    }
+    progress.bytes(text.len(), text.len());
+    options.guard.write(path, &text)?;
+    Ok(())
+}
+
+/// Remap an external chunk file whose region is missing
+/// The compression is recorded in the region, so it is guessed from the content and kept
+fn remap_mcc(
+    path: &Path,
+    options: &RemapOptions,
+    cb: &impl Fn(Uuid) -> Option<Uuid>,
+) -> anyhow::Result<()> {
+    let guard = &options.guard;
+    let rules = options.nbt_rules;
+    let mut chunk = guard.retry().read(path)?;
+    match chunk.first() {
+        Some(0x1f) => {
+            let mut uncompressed = Vec::new();
+            GzDecoder::new(chunk.as_slice()).read_to_end(&mut uncompressed)?;
+            visit_nbt_with(&mut uncompressed, cb, rules)?;
+            let mut encoder = GzEncoder::new(Vec::new(), COMPRESSION_LEVEL);
+            encoder.write_all(&uncompressed)?;
+            chunk = encoder.finish()?;
+        }
+        Some(0x78) => {
+            let mut uncompressed = Vec::new();
+            ZlibDecoder::new(chunk.as_slice()).read_to_end(&mut uncompressed)?;
+            visit_nbt_with(&mut uncompressed, cb, rules)?;
+            let mut encoder = ZlibEncoder::new(Vec::new(), COMPRESSION_LEVEL);
+            encoder.write_all(&uncompressed)?;
+            chunk = encoder.finish()?;
+        }
+        Some(10) => visit_nbt_with(&mut chunk, cb, rules)?,
+        _ => anyhow::bail!("Unknown compression of external chunk {}", path.display()),
+    }
+    guard.write(path, &chunk)?;
     Ok(())
 }
 
@@ -66,66 +456,495 @@ macro_rules! text_ext {
     };
 }
 
+fn extension(path: &Path) -> &str {
+    path.extension().and_then(|s| s.to_str()).unwrap_or("")
+}
+
+/// Region files
+pub struct McaHandler;
+
+impl FileHandler for McaHandler {
+    fn name(&self) -> &'static str {
+        "mca"
+    }
+
+    fn matches(&self, path: &Path, _sniff: &[u8]) -> bool {
+        extension(path) == "mca"
+    }
+
+    fn sniffs(&self) -> bool {
+        false
+    }
+
+    fn remap(
+        &self,
+        path: &Path,
+        ctx: &RemapContext,
+        cb: &dyn Fn(Uuid) -> Option<Uuid>,
+    ) -> anyhow::Result<FileStats> {
+        remap_mca(path, ctx.options, ctx.progress, &cb)
+    }
+}
+
+/// External chunk files, only scheduled when the owning region is not
+pub struct MccHandler;
+
+impl FileHandler for MccHandler {
+    fn name(&self) -> &'static str {
+        "mcc"
+    }
+
+    fn matches(&self, path: &Path, _sniff: &[u8]) -> bool {
+        extension(path) == "mcc"
+    }
+
+    fn sniffs(&self) -> bool {
+        false
+    }
+
+    fn remap(
+        &self,
+        path: &Path,
+        ctx: &RemapContext,
+        cb: &dyn Fn(Uuid) -> Option<Uuid>,
+    ) -> anyhow::Result<FileStats> {
+        remap_mcc(path, ctx.options, &cb)?;
+        Ok(FileStats {
+            chunks: 1,
+            ..Default::default()
+        })
+    }
+}
+
+/// Gzipped or raw NBT files
+pub struct NbtHandler;
+
+impl FileHandler for NbtHandler {
+    fn name(&self) -> &'static str {
+        "nbt"
+    }
+
+    fn matches(&self, path: &Path, _sniff: &[u8]) -> bool {
+        matches!(extension(path), "dat" | "nbt")
+    }
+
+    fn sniffs(&self) -> bool {
+        false
+    }
+
+    fn remap(
+        &self,
+        path: &Path,
+        ctx: &RemapContext,
+        cb: &dyn Fn(Uuid) -> Option<Uuid>,
+    ) -> anyhow::Result<FileStats> {
+        remap_dat(path, ctx.options, None, &cb)?;
+        Ok(FileStats::default())
+    }
+}
+
+/// NBT files with a known compression, only used for the extensions overridden with `--handler`
+pub struct ForcedNbtHandler {
+    pub gzip: bool,
+}
+
+impl FileHandler for ForcedNbtHandler {
+    fn name(&self) -> &'static str {
+        if self.gzip {
+            "nbt-gzip"
+        } else {
+            "nbt-raw"
+        }
+    }
+
+    fn matches(&self, _path: &Path, _sniff: &[u8]) -> bool {
+        true
+    }
+
+    fn sniffs(&self) -> bool {
+        false
+    }
+
+    fn remap(
+        &self,
+        path: &Path,
+        ctx: &RemapContext,
+        cb: &dyn Fn(Uuid) -> Option<Uuid>,
+    ) -> anyhow::Result<FileStats> {
+        remap_dat(path, ctx.options, Some(self.gzip), &cb)?;
+        Ok(FileStats::default())
+    }
+}
+
+/// Plain text files
+pub struct TextHandler;
+
+impl FileHandler for TextHandler {
+    fn name(&self) -> &'static str {
+        "text"
+    }
+
+    fn matches(&self, path: &Path, _sniff: &[u8]) -> bool {
+        matches!(extension(path), text_ext!())
+    }
+
+    fn sniffs(&self) -> bool {
+        false
+    }
+
+    fn remap(
+        &self,
+        path: &Path,
+        ctx: &RemapContext,
+        cb: &dyn Fn(Uuid) -> Option<Uuid>,
+    ) -> anyhow::Result<FileStats> {
+        remap_text(path, ctx.options, ctx.progress, &cb)?;
+        Ok(FileStats::default())
+    }
+}
+
+/// Rename the file within its directory, a uuid in the name of a parent directory is not renamed
+/// A case-only change goes through a temporary name, as a case-insensitive filesystem sees the new
+/// name taken by the file itself
+fn rename_in_place(guard: &WriteGuard, from: &Path, to: &Path) -> anyhow::Result<()> {
+    check_same_directory(from, to)?;
+    let (Some(old_name), Some(new_name)) = (from.file_name(), to.file_name()) else {
+        anyhow::bail!("Invalid file name {}", to.display());
+    };
+    let case_only = old_name != new_name && old_name.eq_ignore_ascii_case(new_name) && to.exists();
+    if case_only {
+        let mut temp_name = OsString::from(".");
+        temp_name.push(new_name);
+        temp_name.push(".rename");
+        let temp = from.with_file_name(temp_name);
+        guard.rename(from, &temp)?;
+        guard.rename(&temp, to)?;
+    } else {
+        guard.rename(from, to)?;
+    }
+    Ok(())
+}
+
+/// Refuse a new name in another directory, e.g. with a uuid in the name of a parent directory
+fn check_same_directory(from: &Path, to: &Path) -> anyhow::Result<()> {
+    if from.parent() != to.parent() {
+        anyhow::bail!(
+            "Renaming {} to {} would move it to another directory",
+            from.display(),
+            to.display()
+        );
+    }
+    Ok(())
+}
+
+/// Rename the file, a region named after a uuid with the external chunk files named after it
+/// The region is renamed first, its external files stay with it if that fails
+fn rename_with_externals(guard: &WriteGuard, from: &Path, to: &Path) -> anyhow::Result<()> {
+    let externals = renamed_externals(from, to);
+    rename_in_place(guard, from, to)?;
+    for (old, new) in externals {
+        rename_in_place(guard, &old, &new).with_context(|| {
+            format!(
+                "{} is renamed to {}, but not its external chunk file {}",
+                from.display(),
+                to.display(),
+                old.display()
+            )
+        })?;
+    }
+    Ok(())
+}
+
+/// Whether the file is renamed over an existing file, only if the user chooses so when asked,
+/// it is without `--interactive-conflicts` unless `keep_taken`, as old uuids sharing a new uuid
+/// are renamed onto the same file
+fn overwrite(conflicts: Option<&Prompter>, keep_taken: bool, from: &Path, to: &Path) -> bool {
+    let taken = match (from.file_name(), to.file_name()) {
+        (Some(old_name), Some(new_name)) => !old_name.eq_ignore_ascii_case(new_name) && to.exists(),
+        _ => false,
+    };
+    if !taken {
+        return true;
+    }
+    let Some(conflicts) = conflicts else {
+        if keep_taken {
+            log::warn!(
+                "{} keeps its name, {} exists, merge them by hand",
+                from.display(),
+                to.display()
+            );
+        }
+        return !keep_taken;
+    };
+    let details = vec![
+        format!("Renaming {} to an existing file", from.display()),
+        conflict::describe(from),
+        conflict::describe(to),
+    ];
+    conflicts.ask(
+        "rename",
+        details,
+        &[
+            ("o", "overwrite the existing file"),
+            ("k", "keep the old name"),
+        ],
+    ) == 0
+}
+
+/// Rename the files once every content is remapped, the renames are relative to the world
+/// A file renamed to the old name of another one, e.g. when two players are swapped, is moved
+/// aside first, so it never replaces a file not renamed yet
+/// Returns the result of each rename, `false` if the old name is kept after a conflict
+pub fn rename_files(
+    world: &Path,
+    renames: &[(PathBuf, PathBuf)],
+    guard: &WriteGuard,
+    conflicts: Option<&Prompter>,
+    keep_taken: bool,
+) -> Vec<anyhow::Result<bool>> {
+    let sources = renames
+        .iter()
+        .map(|(from, _)| from)
+        .collect::<std::collections::HashSet<_>>();
+    let mut aside = vec![];
+    let mut results = renames
+        .iter()
+        .enumerate()
+        .map(|(i, (from, to))| {
+            let swapped = sources.contains(to);
+            let (from, to) = (world.join(from), world.join(to));
+            if !swapped {
+                if !overwrite(conflicts, keep_taken, &from, &to) {
+                    return Ok(false);
+                }
+                return rename_with_externals(guard, &from, &to).map(|()| true);
+            }
+            // The external chunk files of a region go aside with it
+            let externals = renamed_externals(&from, &to);
+            let mut moved = vec![];
+            for (from, to) in std::iter::once((from, to)).chain(externals) {
+                let mut temp_name = OsString::from(".");
+                temp_name.push(from.file_name().unwrap_or_default());
+                temp_name.push(".swap");
+                let temp = from.with_file_name(temp_name);
+                if let Err(err) = guard.rename(&from, &temp) {
+                    // The files already aside still get their new names
+                    aside.push((i, moved));
+                    return Err(err.into());
+                }
+                moved.push((temp, to));
+            }
+            aside.push((i, moved));
+            Ok(true)
+        })
+        .collect::<Vec<_>>();
+    for (i, moved) in aside {
+        let renamed = moved
+            .iter()
+            .try_for_each(|(temp, to)| rename_in_place(guard, temp, to));
+        if results[i].is_ok() {
+            results[i] = renamed.map(|()| true);
+        }
+    }
+    results
+}
+
+/// Remap the content and then the name of the file
+/// The content and the name are separate bytes, and each byte is visited once, so a uuid is never
+/// mapped twice within a run, even if the mapping is chained like `A -> B` and `B -> C`
 pub fn remap_file(
     world: &Path,
     path: &Path,
+    registry: &Registry,
+    options: &RemapOptions,
+    progress: &Progress,
     cb: &impl Fn(Uuid) -> Option<Uuid>,
-) -> anyhow::Result<()> {
+) -> anyhow::Result<FileStats> {
     let concated = world.join(path);
-    if concated.is_file() {
-        // Remap the file content
-        match path.extension().and_then(|s| s.to_str()).unwrap_or("") {
-            "mca" => remap_mca(&concated, cb)?,
-            "dat" | "nbt" => remap_dat(&concated, cb)?,
-            text_ext!() => remap_text(&concated, cb)?,
-            _ => log::warn!("Unsupported file type: {}", concated.display()),
-        }
+    let mut stats = FileStats::default();
+    // The IO stage of the pipeline may have read the file already
+    let staged = stage::peek_input(&concated, |content| {
+        (
+            content.len(),
+            content[..content.len().min(SNIFF_LEN)].to_vec(),
+        )
+    });
+    if staged.is_some() || concated.is_file() {
+        let retry = options.guard.retry();
+        let (len, head) = match staged {
+            Some(staged) => staged,
+            None => (
+                retry.run(&concated, || std::fs::metadata(&concated))?.len() as usize,
+                match registry.needs_sniff(path) {
+                    true => retry.run(&concated, || sniff(&concated))?,
+                    false => vec![],
+                },
+            ),
+        };
+        // The pipeline acquires the budget of the file before reading it, regions are charged per
+        // chunk
+        let charged = !stage::is_staged() && extension(&concated) != "mca";
+        let _permit = charged.then(|| options.budget.acquire(budget::estimate(&concated, len)));
 
-        // Remap the file name
-        let path = path.as_os_str().to_os_string();
+        // The new name is known first, a clone is remapped under it
+        let relative = path;
+        let new_path = remapped_path(path, cb)?;
+        let new_concated = world.join(&new_path);
 
-        #[cfg(not(target_family = "windows"))]
-        let mut new_path = path.into_vec();
-        #[cfg(target_family = "windows")]
-        let mut new_path = if let Some(path) = path.to_str() {
-            path.as_bytes().to_vec()
-        } else {
-            anyhow::bail!("Illegal character in file name {}", path.to_string_lossy())
-        };
+        if options.clone_files {
+            if new_concated == concated {
+                return Ok(stats);
+            }
+            check_same_directory(&concated, &new_concated)?;
+            if new_concated.exists() {
+                anyhow::bail!(
+                    "Unable to clone {} to {}, it exists",
+                    concated.display(),
+                    new_concated.display()
+                );
+            }
+            // A copy failing to be remapped would still hold the old uuids, it is removed
+            let mut copied = vec![];
+            let mut clone = || -> anyhow::Result<FileStats> {
+                let externals = renamed_externals(&concated, &new_concated);
+                for (old, new) in
+                    std::iter::once((concated.clone(), new_concated.clone())).chain(externals)
+                {
+                    options.guard.copy(&old, &new)?;
+                    copied.push(new);
+                }
+                match registry.find(&new_path, &head) {
+                    Some(handler) => {
+                        let ctx = RemapContext {
+                            world,
+                            relative: &new_path,
+                            options,
+                            progress,
+                        };
+                        handler.remap(&new_concated, &ctx, cb)
+                    }
+                    None => {
+                        log::warn!("Unsupported file type: {}", concated.display());
+                        Ok(FileStats::default())
+                    }
+                }
+            };
+            return match clone() {
+                Ok(mut stats) => {
+                    stats.cloned = Some(new_path);
+                    Ok(stats)
+                }
+                Err(err) => {
+                    for path in copied.iter().rev() {
+                        if let Err(err) = options.guard.discard(path) {
+                            log::error!("Failed to remove the copy {}: {}", path.display(), err);
+                        }
+                    }
+                    Err(err)
+                }
+            };
+        }
 
-        visit_text(&mut new_path, cb);
-        #[cfg(not(target_family = "windows"))]
-        let new_concated = world.join(OsString::from_vec(new_path));
-        #[cfg(target_family = "windows")]
-        let new_concated = world.join(OsString::from_str(&String::from_utf8(new_path)?)?);
-        let new_concated = Path::new(&new_concated);
+        // Remap the file content
+        match registry.find(relative, &head) {
+            Some(handler) => {
+                let ctx = RemapContext {
+                    world,
+                    relative,
+                    options,
+                    progress,
+                };
+                stats = handler.remap(&concated, &ctx, cb)?;
+            }
+            None => log::warn!("Unsupported file type: {}", concated.display()),
+        }
         if new_concated != concated {
-            std::fs::rename(&concated, new_concated)?;
+            check_same_directory(&concated, &new_concated)?;
+            if !options.defer_renames {
+                if !overwrite(
+                    options.conflicts.as_ref(),
+                    options.duplicate_targets,
+                    &concated,
+                    &new_concated,
+                ) {
+                    return Ok(stats);
+                }
+                rename_with_externals(&options.guard, &concated, &new_concated)?;
+            }
+            stats.renamed = Some(new_path);
         }
     } else {
         log::warn!("Unsupported file type: {}", concated.display());
     }
-    Ok(())
+    Ok(stats)
+}
+
+/// The path with the uuids in the file name remapped, relative paths stay relative
+/// Only the last component is remapped, a file under a directory named after a uuid stays in it
+pub fn remapped_path(path: &Path, cb: &impl Fn(Uuid) -> Option<Uuid>) -> anyhow::Result<PathBuf> {
+    let Some(name) = path.file_name() else {
+        return Ok(path.to_path_buf());
+    };
+    let name = name.to_os_string();
+
+    #[cfg(not(target_family = "windows"))]
+    let mut new_name = name.into_vec();
+    #[cfg(target_family = "windows")]
+    let mut new_name = if let Some(name) = name.to_str() {
+        name.as_bytes().to_vec()
+    } else {
+        anyhow::bail!("Illegal character in file name {}", path.display())
+    };
+
+    visit_file_name(&mut new_name, cb);
+    #[cfg(not(target_family = "windows"))]
+    let new_name = OsString::from_vec(new_name);
+    #[cfg(target_family = "windows")]
+    let new_name = OsString::from_str(&String::from_utf8(new_name)?)?;
+    Ok(path.with_file_name(new_name))
 }
 
 /// Check if the file requires remapping
-pub fn require_remapping(path: &Path) -> bool {
-    matches!(
-        path.extension().and_then(|s| s.to_str()),
-        Some("mca" | "dat" | "nbt" | text_ext!())
-    ) && std::fs::metadata(path)
+pub fn require_remapping(path: &Path, registry: &Registry, options: &RemapOptions) -> bool {
+    std::fs::metadata(path)
         .map(|m| m.is_file() && m.len() > 0 && !m.permissions().readonly())
         .unwrap_or(false)
+        && !excluded_by_chunk_filter(path, options)
+        && !(options.profile == Profile::Modern && is_poi(path))
+        && head(path, registry, options).is_ok_and(|head| registry.find(path, &head).is_some())
+}
+
+/// The head of the file for finding its handler, empty without sniffing or if no handler needs it
+pub fn head(path: &Path, registry: &Registry, options: &RemapOptions) -> std::io::Result<Vec<u8>> {
+    if options.no_sniff || !registry.needs_sniff(path) {
+        Ok(vec![])
+    } else {
+        sniff(path)
+    }
+}
+
+/// Region files without any selected chunk need no remapping
+fn excluded_by_chunk_filter(path: &Path, options: &RemapOptions) -> bool {
+    let Some(filter) = &options.chunk_filter else {
+        return false;
+    };
+    match extension(path) {
+        "mca" => !region_coords(path).is_some_and(|r| filter.contains_region(r)),
+        "mcc" => !external_chunk_coords(path)
+            .is_some_and(|(x, z)| filter.contains((x >> 5, z >> 5), (x & 31, z & 31))),
+        _ => false,
+    }
 }
 
 #[cfg(test)]
 #[test]
 fn test() {
-    use std::{path::PathBuf, str::FromStr};
+    use std::str::FromStr;
 
     use valence_nbt::{to_binary, Compound, Value};
 
     use crate::anvil::Chunk;
+    use crate::filter::ChunkFilter;
 
     use crate::setup_test_logger;
 
@@ -152,12 +971,146 @@ fn test() {
             uncompressed: buffer.clone(),
             external: false,
             timestamp: 0,
+            lz4: None,
         })
         .unwrap();
     anvil.save().unwrap();
 
     let path = test.join("r.0.0.mca");
-    remap_mca(&path, &|_| None).unwrap();
+    let stats = remap_mca(
+        &path,
+        &RemapOptions::default(),
+        &Progress::hidden(),
+        &|_| None,
+    )
+    .unwrap();
+    assert_eq!(stats.chunks, 1);
+    assert_eq!(stats.chunk_errors, 0);
+
+    // Chunk filter, only the selected chunk is remapped, the other is copied as is
+    let from = Uuid::from_str("2d318504-1a7b-39dc-8c18-44df798a5c06").unwrap();
+    let to = Uuid::from_str("00000000-0000-0000-0000-000000000000").unwrap();
+    let path = test.join("r.1.0.mca");
+    let mut anvil = Anvil::new(&path);
+    for location in [(0, 0), (1, 0)] {
+        anvil
+            .write(&Chunk {
+                location,
+                uncompressed: buffer.clone(),
+                external: false,
+                timestamp: 1,
+                lz4: None,
+            })
+            .unwrap();
+    }
+    anvil.save().unwrap();
+    let untouched = anvil.raw((1, 0)).unwrap().to_vec();
+    let options = RemapOptions {
+        chunk_filter: Some(ChunkFilter::new([(32, 0), (100, 100)])),
+        ..Default::default()
+    };
+    let stats = remap_mca(&path, &options, &Progress::hidden(), &|uuid| {
+        (uuid == from).then_some(to)
+    })
+    .unwrap();
+    assert_eq!(stats.chunks, 1);
+    assert_eq!(stats.selected_chunks, vec![(32, 0)]);
+    let anvil = Anvil::open(&path).unwrap();
+    assert_eq!(anvil.raw((1, 0)).unwrap(), &untouched[..]);
+    let (de, _) = valence_nbt::from_binary::<String>(
+        &mut anvil.read((0, 0)).unwrap().uncompressed.as_slice(),
+    )
+    .unwrap();
+    assert_eq!(de.get("uuid"), Some(&Value::String(to.to_string())));
+    assert!(!excluded_by_chunk_filter(&path, &options));
+
+    // A chunk with a list root is passed through, and counted apart from errors
+    let path = test.join("r.2.0.mca");
+    let mut anvil = Anvil::new(&path);
+    let list_root = vec![9, 0, 0, 8, 0, 0, 0, 1, 0, 0];
+    for (location, uncompressed) in [((0, 0), buffer.clone()), ((1, 0), list_root.clone())] {
+        anvil
+            .write(&Chunk {
+                location,
+                uncompressed,
+                external: false,
+                timestamp: 2,
+                lz4: None,
+            })
+            .unwrap();
+    }
+    anvil.save().unwrap();
+    let untouched = anvil.raw((1, 0)).unwrap().to_vec();
+    let stats = remap_mca(
+        &path,
+        &RemapOptions::default(),
+        &Progress::hidden(),
+        &|uuid| (uuid == from).then_some(to),
+    )
+    .unwrap();
+    assert_eq!(stats.chunks, 2);
+    assert_eq!(stats.chunk_errors, 0);
+    assert_eq!(stats.non_compound_roots, 1);
+    let anvil = Anvil::open(&path).unwrap();
+    assert_eq!(anvil.raw((1, 0)).unwrap(), &untouched[..]);
+    assert_eq!(anvil.read((1, 0)).unwrap().uncompressed, list_root);
+
+    // Two header entries pointing at the same sectors, only the newer one survives
+    let path = test.join("r.3.0.mca");
+    let mut anvil = Anvil::new(&path);
+    for (location, timestamp) in [((0, 0), 1), ((1, 0), 5)] {
+        anvil
+            .write(&Chunk {
+                location,
+                uncompressed: buffer.clone(),
+                external: false,
+                timestamp,
+                lz4: None,
+            })
+            .unwrap();
+    }
+    anvil.save().unwrap();
+    let mut content = std::fs::read(&path).unwrap();
+    content.copy_within(4..8, 0);
+    std::fs::write(&path, &content).unwrap();
+    let stats = remap_mca(
+        &path,
+        &RemapOptions::default(),
+        &Progress::hidden(),
+        &|_| None,
+    )
+    .unwrap();
+    assert_eq!(stats.duplicate_chunks, 1);
+    assert_eq!(stats.chunks, 1);
+    let anvil = Anvil::open(&path).unwrap();
+    assert_eq!(anvil.locations(), vec![(1, 0)]);
+    assert_eq!(anvil.timestamp((1, 0)).unwrap(), 5);
+
+    // The low-memory path writes the same bytes
+    let rebuilt = std::fs::read(&path).unwrap();
+    std::fs::write(&path, &content).unwrap();
+    let low_memory = RemapOptions {
+        low_memory: true,
+        ..Default::default()
+    };
+    let stats = remap_mca(&path, &low_memory, &Progress::hidden(), &|_| None).unwrap();
+    assert_eq!(stats.duplicate_chunks, 1);
+    assert_eq!(stats.chunks, 1);
+    assert_eq!(std::fs::read(&path).unwrap(), rebuilt);
+    assert!(!test.join("r.3.0.mca.tmp").exists());
+
+    let err = check_compound_root(&list_root)
+        .with_context(|| "chunk (1, 0)")
+        .unwrap_err();
+    assert!(err.downcast_ref::<NonCompoundRoot>().is_some());
+    assert!(format!("{:#}", err).contains("Root tag type 9"));
+    assert!(excluded_by_chunk_filter(&test.join("r.5.5.mca"), &options));
+    assert!(!excluded_by_chunk_filter(&test.join("level.dat"), &options));
+    assert!(!excluded_by_chunk_filter(
+        &test.join("c.32.0.mcc"),
+        &options
+    ));
+    assert!(excluded_by_chunk_filter(&test.join("c.33.0.mcc"), &options));
 
     std::fs::write(
         &test.join("2d318504-1a7b-39dc-8c18-44df798a5c06.json"),
@@ -174,9 +1127,13 @@ fn test() {
     )
     .unwrap();
 
+    let registry = Registry::builtin();
     remap_file(
         &test,
         &PathBuf::from("2d318504-1a7b-39dc-8c18-44df798a5c06.json"),
+        &registry,
+        &RemapOptions::default(),
+        &Progress::hidden(),
         &|uuid| {
             if uuid == Uuid::from_str("2d318504-1a7b-39dc-8c18-44df798a5c06").unwrap() {
                 Some(Uuid::from_str("00000000-0000-0000-0000-000000000000").unwrap())
@@ -190,6 +1147,9 @@ fn test() {
     remap_file(
         &test,
         &PathBuf::from("2d318504-1a7b-39dc-8c18-44df798a5c06.dat"),
+        &registry,
+        &RemapOptions::default(),
+        &Progress::hidden(),
         &|uuid| {
             if uuid == Uuid::from_str("2d318504-1a7b-39dc-8c18-44df798a5c06").unwrap() {
                 Some(Uuid::from_str("00000000-0000-0000-0000-000000000000").unwrap())
@@ -201,3 +1161,901 @@ fn test() {
     .unwrap();
     std::fs::remove_dir_all(&test).unwrap();
 }
+
+#[cfg(test)]
+#[test]
+fn test_handler_override() {
+    use valence_nbt::{from_binary, to_binary, Compound, Value};
+
+    use crate::{handler::HandlerKind, setup_test_logger};
+
+    setup_test_logger();
+
+    let test = std::env::temp_dir().join("test_handler_override");
+    std::fs::create_dir_all(&test).unwrap();
+    let from = Uuid::from_u128(0x00000001_00000002_00000003_00000004);
+    let to = Uuid::from_u128(0x0000000a_0000000b_0000000c_0000000d);
+    let content =
+        Compound::<String>::from_iter([("UUID".to_string(), Value::IntArray(vec![1, 2, 3, 4]))]);
+    let mut nbt = vec![];
+    to_binary(&content, &mut nbt, "").unwrap();
+    let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(&nbt).unwrap();
+    let gzipped = encoder.finish().unwrap();
+    let path = test.join("data.json");
+    let decode = |path: &Path| {
+        let mut uncompressed = vec![];
+        GzDecoder::new(std::fs::read(path).unwrap().as_slice())
+            .read_to_end(&mut uncompressed)
+            .unwrap();
+        let (compound, _) = from_binary::<String>(&mut uncompressed.as_slice()).unwrap();
+        compound.get("UUID").cloned()
+    };
+    let cb = |uuid| (uuid == from).then_some(to);
+
+    // Scanned as text, the uuid is not found
+    std::fs::write(&path, &gzipped).unwrap();
+    let mut registry = Registry::builtin();
+    let options = RemapOptions::default();
+    assert_eq!(
+        registry.find(&path, &gzipped).map(|h| h.name()),
+        Some("text")
+    );
+    remap_file(
+        &test,
+        Path::new("data.json"),
+        &registry,
+        &options,
+        &Progress::hidden(),
+        &cb,
+    )
+    .unwrap();
+    assert_eq!(decode(&path), Some(Value::IntArray(vec![1, 2, 3, 4])));
+
+    // Structurally remapped as NBT
+    registry.set_override("json", HandlerKind::NbtGzip);
+    assert!(require_remapping(&path, &registry, &options));
+    remap_file(
+        &test,
+        Path::new("data.json"),
+        &registry,
+        &options,
+        &Progress::hidden(),
+        &cb,
+    )
+    .unwrap();
+    assert_eq!(decode(&path), Some(Value::IntArray(vec![10, 11, 12, 13])));
+
+    // A plain json file is not gzipped NBT
+    std::fs::write(&path, "{}").unwrap();
+    assert!(remap_file(
+        &test,
+        Path::new("data.json"),
+        &registry,
+        &options,
+        &Progress::hidden(),
+        &cb,
+    )
+    .is_err());
+    registry.set_override("json", HandlerKind::Skip);
+    assert!(!require_remapping(&path, &registry, &options));
+    std::fs::remove_dir_all(&test).unwrap();
+}
+
+#[cfg(test)]
+#[test]
+fn test_line_endings() {
+    use crate::{decimal::parse_keys, setup_test_logger};
+
+    setup_test_logger();
+
+    let dir = std::env::temp_dir().join("test_line_endings");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let from = Uuid::from_u128(0x2d318504_1a7b_39dc_8c18_44df798a5c06);
+    let to = Uuid::from_u128(0xfb1ad51e_cf1f_41f7_8fd1_10dff164b17d);
+    let decimal = |uuid: Uuid| {
+        let (most, least) = uuid.as_u64_pair();
+        (most as i64, least as i64)
+    };
+    let yaml = |uuid: Uuid| {
+        let (most, least) = decimal(uuid);
+        format!(
+            "owner: {}\r\nhome:\r\n  most: {}\r\n  least: {}\r\n",
+            uuid, most, least
+        )
+    };
+    let path = dir.join("claims.yml");
+    // In place, the decimals are left as they are
+    let in_place = format!(
+        "owner: {}\r\nhome:\r\n  most: {}\r\n  least: {}\r\n",
+        to,
+        decimal(from).0,
+        decimal(from).1
+    );
+    // Rebuilt, as the length of the decimals changes
+    let growable = yaml(to);
+    for (keys, expected) in [(None, in_place), (Some("most,least"), growable)] {
+        std::fs::write(&path, yaml(from)).unwrap();
+        let options = RemapOptions {
+            decimal_uuid_keys: keys.map(|keys| parse_keys(keys).unwrap()),
+            ..Default::default()
+        };
+        remap_file(
+            &dir,
+            Path::new("claims.yml"),
+            &Registry::builtin(),
+            &options,
+            &Progress::hidden(),
+            &|uuid| (uuid == from).then_some(to),
+        )
+        .unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), expected);
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(test)]
+#[test]
+fn test_text_patterns() {
+    use crate::{pattern::parse_pattern, setup_test_logger};
+
+    setup_test_logger();
+
+    let dir = std::env::temp_dir().join("test_text_patterns");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("plugins")).unwrap();
+    let from = Uuid::from_u128(0x2d318504_1a7b_39dc_8c18_44df798a5c06);
+    let to = Uuid::from_u128(0xfb1ad51e_cf1f_41f7_8fd1_10dff164b17d);
+    let upper = |uuid: Uuid| uuid.simple().to_string().to_uppercase();
+    // Uppercase uuids are not found by the built-in scan of the contents
+    let path = dir.join("plugins").join("Bank.txt");
+    let content = |uuid: Uuid| format!("owner [#{}] {}\n", upper(uuid), upper(uuid));
+    std::fs::write(&path, content(from)).unwrap();
+    let options = RemapOptions {
+        text_patterns: vec![parse_pattern(r"\[#(?P<uuid>[0-9A-F]{32})\]").unwrap()],
+        ..Default::default()
+    };
+    remap_file(
+        &dir,
+        &Path::new("plugins").join("Bank.txt"),
+        &Registry::builtin(),
+        &options,
+        &Progress::hidden(),
+        &|uuid| (uuid == from).then_some(to),
+    )
+    .unwrap();
+    assert_eq!(
+        std::fs::read_to_string(&path).unwrap(),
+        format!("owner [#{}] {}\n", upper(to), upper(from))
+    );
+
+    // With a swap, a uuid the text pass replaced is not mapped back by a pattern matching it too
+    std::fs::write(
+        &path,
+        format!("owner [#{}] [#{}]\n", from.simple(), upper(to)),
+    )
+    .unwrap();
+    let options = RemapOptions {
+        text_patterns: vec![parse_pattern(r"\[#(?P<uuid>[0-9A-Fa-f]{32})\]").unwrap()],
+        ..Default::default()
+    };
+    remap_file(
+        &dir,
+        &Path::new("plugins").join("Bank.txt"),
+        &Registry::builtin(),
+        &options,
+        &Progress::hidden(),
+        &|uuid| match uuid {
+            uuid if uuid == from => Some(to),
+            uuid if uuid == to => Some(from),
+            _ => None,
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        std::fs::read_to_string(&path).unwrap(),
+        format!("owner [#{}] [#{}]\n", to.simple(), upper(from))
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(test)]
+#[test]
+fn test_multi_member_gzip() {
+    use valence_nbt::{to_binary, Compound, Value};
+
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    let test = std::env::temp_dir().join("test_multi_member_gzip");
+    let _ = std::fs::remove_dir_all(&test);
+    std::fs::create_dir_all(&test).unwrap();
+    let from = Uuid::from_u128(0x00000001_00000002_00000003_00000004);
+    let to = Uuid::from_u128(0x0000000a_0000000b_0000000c_0000000d);
+    let cb = |uuid| (uuid == from).then_some(to);
+    let nbt = |key: &str| {
+        let content =
+            Compound::<String>::from_iter([(key.to_string(), Value::IntArray(vec![1, 2, 3, 4]))]);
+        let mut nbt = vec![];
+        to_binary(&content, &mut nbt, "").unwrap();
+        nbt
+    };
+    let gzip = |data: &[u8]| {
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    };
+    let remap = |name: &str| {
+        remap_file(
+            &test,
+            Path::new(name),
+            &Registry::builtin(),
+            &RemapOptions::default(),
+            &Progress::hidden(),
+            &cb,
+        )
+    };
+
+    // Two documents appended as members, and some padding
+    let mut data = gzip(&nbt("Owner"));
+    data.extend(gzip(&nbt("Target")));
+    data.extend([0; 3]);
+    std::fs::write(test.join("appended.dat"), &data).unwrap();
+    remap("appended.dat").unwrap();
+    let remapped = std::fs::read(test.join("appended.dat")).unwrap();
+    let (members, trailing) = gzip_members(&remapped).unwrap();
+    assert_eq!(members.len(), 2);
+    assert_eq!(trailing, [0; 3]);
+    for (member, key) in members.iter().zip(["Owner", "Target"]) {
+        let mut expected = nbt(key);
+        visit_nbt_with(&mut expected, &cb, Default::default()).unwrap();
+        assert_eq!(*member, expected);
+        assert_ne!(*member, nbt(key));
+    }
+
+    // One document split across members is refused, the file is kept as is
+    let whole = nbt("Owner");
+    let (head, tail) = whole.split_at(whole.len() / 2);
+    let mut data = gzip(head);
+    data.extend(gzip(tail));
+    std::fs::write(test.join("split.dat"), &data).unwrap();
+    assert!(remap("split.dat").is_err());
+    assert_eq!(std::fs::read(test.join("split.dat")).unwrap(), data);
+    std::fs::remove_dir_all(&test).unwrap();
+}
+
+#[cfg(test)]
+#[test]
+fn test_rename_in_place() {
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    let dir = std::env::temp_dir().join("test_rename_in_place");
+    let _ = std::fs::remove_dir_all(&dir);
+    let from = "2d318504-1a7b-39dc-8c18-44df798a5c06";
+    let to = "fb1ad51e-cf1f-41f7-8fd1-10dff164b17d";
+    std::fs::create_dir_all(dir.join("plugins").join(from)).unwrap();
+    let data = dir.join("plugins").join(from).join(format!("{}.yml", from));
+    let content = format!("owner: {}\n", from);
+    std::fs::write(&data, &content).unwrap();
+    let guard = WriteGuard::default();
+
+    // A uuid in a directory name is left as it is, the file is remapped and renamed in it
+    let renamed = data.with_file_name(format!("{}.yml", to));
+    let relative = renamed.strip_prefix(&dir).unwrap().to_path_buf();
+    let remapped = format!("owner: {}\n", to);
+    for (clone_files, defer_renames) in [(false, false), (false, true), (true, false)] {
+        std::fs::write(&data, &content).unwrap();
+        let options = RemapOptions {
+            clone_files,
+            defer_renames,
+            ..Default::default()
+        };
+        let stats = remap_file(
+            &dir,
+            data.strip_prefix(&dir).unwrap(),
+            &Registry::builtin(),
+            &options,
+            &Progress::hidden(),
+            &|uuid: Uuid| (uuid.to_string() == from).then(|| Uuid::parse_str(to).unwrap()),
+        )
+        .unwrap();
+        if clone_files {
+            assert_eq!(stats.cloned.as_ref(), Some(&relative));
+            assert_eq!(std::fs::read_to_string(&data).unwrap(), content);
+            assert_eq!(std::fs::read_to_string(&renamed).unwrap(), remapped);
+        } else if defer_renames {
+            assert_eq!(stats.renamed.as_ref(), Some(&relative));
+            assert_eq!(std::fs::read_to_string(&data).unwrap(), remapped);
+            assert!(!renamed.exists());
+        } else {
+            assert_eq!(stats.renamed.as_ref(), Some(&relative));
+            assert!(!data.exists());
+            assert_eq!(std::fs::read_to_string(&renamed).unwrap(), remapped);
+        }
+        let _ = std::fs::remove_file(&renamed);
+    }
+    std::fs::write(&data, &content).unwrap();
+    assert!(rename_in_place(&guard, &data, &dir.join("other.yml")).is_err());
+
+    rename_in_place(&guard, &data, &renamed).unwrap();
+    assert!(!data.exists());
+    assert!(renamed.exists());
+
+    // The new name is taken by the file itself on a case-insensitive filesystem
+    #[cfg(any(target_os = "macos", target_os = "windows"))]
+    {
+        let upper = renamed.with_file_name(format!("{}.yml", to.to_uppercase()));
+        rename_in_place(&guard, &renamed, &upper).unwrap();
+        let names = std::fs::read_dir(upper.parent().unwrap())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec![upper.file_name().unwrap().to_os_string()]);
+    }
+
+    // Two files swapping their names, and one moved to another directory
+    let (a, b) = (PathBuf::from("a.yml"), PathBuf::from("b.yml"));
+    std::fs::write(dir.join(&a), "a").unwrap();
+    std::fs::write(dir.join(&b), "b").unwrap();
+    let results = rename_files(
+        &dir,
+        &[
+            (a.clone(), b.clone()),
+            (b.clone(), a.clone()),
+            (b.clone(), PathBuf::from("plugins").join("b.yml")),
+        ],
+        &guard,
+        None,
+        false,
+    );
+    assert!(results[0].is_ok() && results[1].is_ok() && results[2].is_err());
+    assert_eq!(std::fs::read_to_string(dir.join(&a)).unwrap(), "b");
+    assert_eq!(std::fs::read_to_string(dir.join(&b)).unwrap(), "a");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(test)]
+#[test]
+fn test_rename_conflict() {
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    let dir = std::env::temp_dir().join("test_rename_conflict");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let guard = WriteGuard::default();
+    let (a, b, c) = (
+        PathBuf::from("a.yml"),
+        PathBuf::from("b.yml"),
+        PathBuf::from("c.yml"),
+    );
+    for name in [&a, &b, &c] {
+        std::fs::write(dir.join(name), name.to_str().unwrap()).unwrap();
+    }
+    let renames = [(a.clone(), c.clone()), (b.clone(), c.clone())];
+    let prompter = Prompter::new(std::io::Cursor::new("k\no\n"));
+    let results = rename_files(&dir, &renames, &guard, Some(&prompter), false);
+    assert_eq!(
+        results.into_iter().map(|r| r.unwrap()).collect::<Vec<_>>(),
+        [false, true]
+    );
+    assert_eq!(std::fs::read_to_string(dir.join(&a)).unwrap(), "a.yml");
+    assert!(!dir.join(&b).exists());
+    assert_eq!(std::fs::read_to_string(dir.join(&c)).unwrap(), "b.yml");
+    let decisions = prompter.decisions();
+    assert_eq!(decisions.len(), 2);
+    assert_eq!(decisions[0].resolution, "keep the old name");
+    assert_eq!(decisions[1].resolution, "overwrite the existing file");
+
+    // Two old uuids sharing a new uuid, the second file keeps its name
+    std::fs::write(dir.join(&a), "a.yml").unwrap();
+    std::fs::write(dir.join(&b), "b.yml").unwrap();
+    std::fs::remove_file(dir.join(&c)).unwrap();
+    let results = rename_files(&dir, &renames, &guard, None, true);
+    assert_eq!(
+        results.into_iter().map(|r| r.unwrap()).collect::<Vec<_>>(),
+        [true, false]
+    );
+    assert_eq!(std::fs::read_to_string(dir.join(&c)).unwrap(), "a.yml");
+    assert_eq!(std::fs::read_to_string(dir.join(&b)).unwrap(), "b.yml");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(test)]
+#[test]
+fn test_uppercase_file_name() {
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    let dir = std::env::temp_dir().join("test_uppercase_file_name");
+    let _ = std::fs::remove_dir_all(&dir);
+    let from = "2d318504-1a7b-39dc-8c18-44df798a5c06";
+    let to = "fb1ad51e-cf1f-41f7-8fd1-10dff164b17d";
+    let userdata = dir.join("plugins").join("Essentials").join("userdata");
+    std::fs::create_dir_all(&userdata).unwrap();
+    let upper = userdata.join(format!("{}.yml", from.to_uppercase()));
+    let lower = dir.join(format!("{}.json", from));
+    std::fs::write(&upper, "money: 1\n").unwrap();
+    std::fs::write(&lower, "{}").unwrap();
+    let remap = |path: &Path| {
+        remap_file(
+            &dir,
+            path.strip_prefix(&dir).unwrap(),
+            &Registry::builtin(),
+            &RemapOptions::default(),
+            &Progress::hidden(),
+            &|uuid: Uuid| (uuid.to_string() == from).then(|| Uuid::parse_str(to).unwrap()),
+        )
+        .unwrap()
+    };
+
+    // Each name keeps its case, as other files of the plugin may be looked up by it
+    let renamed = userdata.join(format!("{}.yml", to.to_uppercase()));
+    assert_eq!(
+        remap(&upper).renamed,
+        Some(renamed.strip_prefix(&dir).unwrap().to_path_buf())
+    );
+    assert!(!upper.exists());
+    assert_eq!(std::fs::read_to_string(&renamed).unwrap(), "money: 1\n");
+    remap(&lower);
+    assert!(dir.join(format!("{}.json", to)).exists());
+
+    // Both names are the same file on a case-insensitive filesystem, simulated with a symbolic
+    // link, which also stops resolving once the file is moved aside
+    let guard = WriteGuard::default();
+    let lowercase = userdata.join(format!("{}.yml", to));
+    #[cfg(all(unix, not(target_os = "macos")))]
+    std::os::unix::fs::symlink(renamed.file_name().unwrap(), &lowercase).unwrap();
+    rename_in_place(&guard, &renamed, &lowercase).unwrap();
+    let names = std::fs::read_dir(&userdata)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect::<Vec<_>>();
+    assert_eq!(names, vec![lowercase.file_name().unwrap().to_os_string()]);
+    assert_eq!(std::fs::read_to_string(&lowercase).unwrap(), "money: 1\n");
+    // And a plain rename on a case-sensitive one
+    rename_in_place(&guard, &lowercase, &renamed).unwrap();
+    assert!(renamed.exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(test)]
+#[test]
+fn test_chunk_budget() {
+    use crate::{anvil::Chunk, budget::MemoryBudget, setup_test_logger};
+
+    setup_test_logger();
+
+    let dir = std::env::temp_dir().join("test_chunk_budget");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("region")).unwrap();
+    let path = PathBuf::from("region").join("r.0.0.mca");
+    for low_memory in [false, true] {
+        let mut anvil = Anvil::new(&dir.join(&path));
+        for (x, padding) in [(0, 100), (1, 10000)] {
+            let mut uncompressed = vec![10, 0, 0, 7, 0, 1, b'p'];
+            uncompressed.extend_from_slice(&(padding as i32).to_be_bytes());
+            uncompressed.extend((0..padding).map(|i: usize| (i * 7) as u8));
+            uncompressed.push(0);
+            anvil
+                .write(&Chunk {
+                    external: false,
+                    location: (x, 0),
+                    timestamp: 1,
+                    uncompressed,
+                    lz4: None,
+                })
+                .unwrap();
+        }
+        anvil.save().unwrap();
+        let largest = anvil.stored_len((1, 0));
+        assert!(largest > anvil.stored_len((0, 0)));
+        let options = RemapOptions {
+            budget: MemoryBudget::new(Some(64 * 1024 * 1024)),
+            low_memory,
+            ..Default::default()
+        };
+        remap_file(
+            &dir,
+            &path,
+            &Registry::builtin(),
+            &options,
+            &Progress::hidden(),
+            &|_| None,
+        )
+        .unwrap();
+        // Charged for one chunk at a time, not for the whole region
+        assert_eq!(options.budget.peak(), budget::estimate_chunk(largest));
+        assert_eq!(options.budget.in_use(), 0);
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(test)]
+#[test]
+fn test_clone_failure() {
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    let dir = std::env::temp_dir().join("test_clone_failure");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("playerdata")).unwrap();
+    let from = Uuid::from_u128(1);
+    let to = Uuid::from_u128(2);
+    let path = PathBuf::from("playerdata").join(format!("{}.dat", from));
+    // An unknown tag, the copy fails to be remapped
+    std::fs::write(dir.join(&path), [10, 0, 0, 99]).unwrap();
+    let options = RemapOptions {
+        clone_files: true,
+        ..Default::default()
+    };
+    assert!(remap_file(
+        &dir,
+        &path,
+        &Registry::builtin(),
+        &options,
+        &Progress::hidden(),
+        &|uuid| (uuid == from).then_some(to),
+    )
+    .is_err());
+    // The copy holding the old uuids is removed, the original is kept
+    assert!(!dir.join("playerdata").join(format!("{}.dat", to)).exists());
+    assert_eq!(std::fs::read(dir.join(&path)).unwrap(), [10, 0, 0, 99]);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(test)]
+#[test]
+fn test_oversized_chunk() {
+    use crate::{anvil::Chunk, setup_test_logger};
+
+    setup_test_logger();
+
+    let dir = std::env::temp_dir().join("test_oversized_chunk");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("region")).unwrap();
+    let path = dir.join("region").join("r.0.0.mca");
+    let (from, to) = (Uuid::from_u128(1), Uuid::from_u128(2));
+    // A byte array of the padding, then the uuid at the end
+    let nbt = |padding: usize| {
+        let mut nbt = vec![10, 0, 0, 7, 0, 4];
+        nbt.extend(b"Data");
+        nbt.extend((padding as i32).to_be_bytes());
+        nbt.extend(vec![0; padding]);
+        nbt.extend([11, 0, 4]);
+        nbt.extend(b"UUID");
+        nbt.extend(4i32.to_be_bytes());
+        nbt.extend(from.as_bytes());
+        nbt.push(0);
+        nbt
+    };
+    let large = nbt(2 << 20);
+    let write_region = || {
+        let mut anvil = Anvil::new(&path);
+        for (location, uncompressed) in [((0, 0), nbt(16)), ((1, 0), large.clone())] {
+            anvil
+                .write(&Chunk {
+                    external: false,
+                    location,
+                    timestamp: 1700000000,
+                    uncompressed,
+                    lz4: None,
+                })
+                .unwrap();
+        }
+        anvil.save().unwrap();
+        Anvil::open(&path).unwrap().raw((1, 0)).unwrap().to_vec()
+    };
+    let remap = |max_chunk_size, low_memory| {
+        let options = RemapOptions {
+            max_chunk_size: Some(max_chunk_size),
+            low_memory,
+            ..Default::default()
+        };
+        remap_file(
+            &dir,
+            Path::new("region/r.0.0.mca"),
+            &Registry::builtin(),
+            &options,
+            &Progress::hidden(),
+            &|uuid| (uuid == from).then_some(to),
+        )
+        .unwrap()
+    };
+    let ends_with = |location, uuid: Uuid| {
+        let chunk = Anvil::open(&path).unwrap().read(location).unwrap();
+        let len = chunk.uncompressed.len();
+        chunk.uncompressed[len - 17..len - 1] == *uuid.as_bytes()
+    };
+
+    for low_memory in [false, true] {
+        // Passed through byte for byte, the other chunks are remapped
+        let raw = write_region();
+        let stats = remap(large.len() - 1, low_memory);
+        assert_eq!(stats.oversized_chunks, 1);
+        assert_eq!(stats.chunk_errors, 0);
+        assert_eq!(Anvil::open(&path).unwrap().raw((1, 0)).unwrap(), raw);
+        assert!(ends_with((0, 0), to));
+        assert!(ends_with((1, 0), from));
+        // A chunk of exactly the limit is remapped
+        write_region();
+        let stats = remap(large.len(), low_memory);
+        assert_eq!(stats.oversized_chunks, 0);
+        assert!(ends_with((1, 0), to));
+    }
+
+    // A corrupted length field is refused before anything is allocated for it
+    write_region();
+    let mut content = std::fs::read(&path).unwrap();
+    let start = (u32::from_be_bytes(content[..4].try_into().unwrap()) >> 8) as usize * 4096;
+    content[start..start + 4].copy_from_slice(&0x7fff_ffffu32.to_be_bytes());
+    std::fs::write(&path, content).unwrap();
+    let err = Anvil::open(&path).unwrap().read((0, 0)).unwrap_err();
+    assert!(format!("{:#}", err).contains("Invalid chunk length"));
+    let reader = AnvilReader::open_with_guard(&path, WriteGuard::default()).unwrap();
+    assert!(reader.read((0, 0)).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(test)]
+#[test]
+fn test_uuid_named_region() {
+    use rand::Rng;
+
+    use crate::{anvil::Chunk, setup_test_logger};
+
+    setup_test_logger();
+
+    let dir = std::env::temp_dir().join("test_uuid_named_region");
+    let _ = std::fs::remove_dir_all(&dir);
+    let plot = dir.join("plugins").join("Plots");
+    std::fs::create_dir_all(&plot).unwrap();
+    let (from, to) = (
+        Uuid::from_u128(0x2d318504_1a7b_39dc_8c18_44df798a5c06),
+        Uuid::from_u128(0xfb1ad51e_cf1f_41f7_8fd1_10dff164b17d),
+    );
+    let (old, new) = (
+        plot.join(format!("{}.mca", from)),
+        plot.join(format!("{}.mca", to)),
+    );
+    // Random bytes do not compress, so the chunk is too large for the region
+    let mut padding = vec![0; 2 << 20];
+    rand::thread_rng().fill(&mut padding[..]);
+    let mut large = vec![10, 0, 0, 7, 0, 4];
+    large.extend(b"Data");
+    large.extend((padding.len() as i32).to_be_bytes());
+    large.extend(padding);
+    large.extend([11, 0, 4]);
+    large.extend(b"UUID");
+    large.extend(4i32.to_be_bytes());
+    large.extend(from.as_bytes());
+    large.push(0);
+    let ends_with = |uuid: Uuid| {
+        let chunk = Anvil::open(&new).unwrap().read((3, 4)).unwrap();
+        assert!(chunk.external);
+        let len = chunk.uncompressed.len();
+        chunk.uncompressed[len - 17..len - 1] == *uuid.as_bytes()
+    };
+
+    // Remapped, then passed through as an oversized chunk
+    for (max_chunk_size, remapped) in [(None, true), (Some(1 << 20), false)] {
+        for low_memory in [false, true] {
+            let _ = std::fs::remove_file(&new);
+            let mut anvil = Anvil::new(&old);
+            anvil
+                .write(&Chunk {
+                    external: false,
+                    location: (3, 4),
+                    timestamp: 1700000000,
+                    uncompressed: large.clone(),
+                    lz4: None,
+                })
+                .unwrap();
+            anvil.save().unwrap();
+            assert!(plot.join(format!("{}.3.4.mcc", from)).exists());
+            let options = RemapOptions {
+                max_chunk_size,
+                low_memory,
+                ..Default::default()
+            };
+            let stats = remap_file(
+                &dir,
+                &Path::new("plugins")
+                    .join("Plots")
+                    .join(format!("{}.mca", from)),
+                &Registry::builtin(),
+                &options,
+                &Progress::hidden(),
+                &|uuid| (uuid == from).then_some(to),
+            )
+            .unwrap();
+            assert_eq!(stats.chunk_errors, 0);
+            assert!(stats.renamed.is_some());
+            let mut files = std::fs::read_dir(&plot)
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+                .collect::<Vec<_>>();
+            files.sort();
+            assert_eq!(
+                files,
+                vec![format!("{}.3.4.mcc", to), format!("{}.mca", to)]
+            );
+            assert_eq!(ends_with(to), remapped);
+            // The scan remaps the external file through its region
+            assert_eq!(
+                crate::anvil::owning_region(&plot.join(format!("{}.3.4.mcc", to))),
+                Some(new.clone())
+            );
+        }
+    }
+
+    // With the renames deferred, the external file keeps the old name until the region is renamed
+    let relative = |uuid: Uuid| {
+        Path::new("plugins")
+            .join("Plots")
+            .join(format!("{}.mca", uuid))
+    };
+    let files = || {
+        let mut files = std::fs::read_dir(&plot)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        files.sort();
+        files
+    };
+    std::fs::rename(&new, &old).unwrap();
+    std::fs::rename(
+        plot.join(format!("{}.3.4.mcc", to)),
+        plot.join(format!("{}.3.4.mcc", from)),
+    )
+    .unwrap();
+    let options = RemapOptions {
+        defer_renames: true,
+        ..Default::default()
+    };
+    let stats = remap_file(
+        &dir,
+        &relative(from),
+        &Registry::builtin(),
+        &options,
+        &Progress::hidden(),
+        &|uuid| (uuid == from).then_some(to),
+    )
+    .unwrap();
+    assert_eq!(stats.renamed, Some(relative(to)));
+    let old_files = vec![format!("{}.3.4.mcc", from), format!("{}.mca", from)];
+    assert_eq!(files(), old_files);
+    // A rename not done leaves both files under the old name
+    std::fs::write(&new, b"taken").unwrap();
+    let renames = [(relative(from), relative(to))];
+    let results = rename_files(&dir, &renames, &options.guard, None, true);
+    assert!(!*results[0].as_ref().unwrap());
+    std::fs::remove_file(&new).unwrap();
+    assert_eq!(files(), old_files);
+    let results = rename_files(&dir, &renames, &options.guard, None, true);
+    assert!(*results[0].as_ref().unwrap());
+    assert_eq!(
+        files(),
+        vec![format!("{}.3.4.mcc", to), format!("{}.mca", to)]
+    );
+    assert!(ends_with(to));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(test)]
+#[test]
+fn test_region_anomaly() {
+    use rand::Rng;
+
+    use crate::{handler::AnomalyCheck, setup_test_logger};
+
+    setup_test_logger();
+
+    let dir = std::env::temp_dir().join("test_region_anomaly");
+    let _ = std::fs::remove_dir_all(&dir);
+    let (world, kept) = (dir.join("world"), dir.join("kept"));
+    let region = world.join("region");
+    std::fs::create_dir_all(&region).unwrap();
+    let (from, to) = (
+        Uuid::from_u128(0x2d318504_1a7b_39dc_8c18_44df798a5c06),
+        Uuid::from_u128(0xfb1ad51e_cf1f_41f7_8fd1_10dff164b17d),
+    );
+    let uuid_nbt = |data: &[u8]| {
+        let mut nbt = vec![10, 0, 0, 7, 0, 4];
+        nbt.extend(b"Data");
+        nbt.extend((data.len() as i32).to_be_bytes());
+        nbt.extend(data);
+        nbt.extend([11, 0, 4]);
+        nbt.extend(b"UUID");
+        nbt.extend(4i32.to_be_bytes());
+        nbt.extend(from.as_bytes());
+        nbt.push(0);
+        nbt
+    };
+
+    // A broken header: a chunk of 64 sectors of random bytes, which do not compress, stored
+    // uncompressed, and entries of other chunks pointing inside it, each at an empty chunk,
+    // rewriting gives each of them its own sector
+    let sectors = 64;
+    let mut data = vec![0; sectors * 4096 - 64];
+    rand::thread_rng().fill(&mut data[..]);
+    // The length field, the compression byte and the NBT before the bytes of the array
+    let data_start = 2 * 4096 + 5 + 14;
+    let empty = [0, 0, 0, 5, 3, 10, 0, 0, 0];
+    for k in 1..sectors {
+        let at = (2 + k) * 4096 - data_start;
+        data[at..at + empty.len()].copy_from_slice(&empty);
+    }
+    let nbt = uuid_nbt(&data);
+    let mut content = vec![0; 2 * 4096];
+    content[..4].copy_from_slice(&[0, 0, 2, sectors as u8]);
+    for k in 1..sectors {
+        content[k * 4..k * 4 + 4].copy_from_slice(&[0, 0, 2 + k as u8, 1]);
+    }
+    content.extend((nbt.len() as u32 + 1).to_be_bytes());
+    content.push(3);
+    content.extend(&nbt);
+    content.resize(content.len().div_ceil(4096) * 4096, 0);
+    let broken = region.join("r.0.0.mca");
+    std::fs::write(&broken, &content).unwrap();
+
+    let options = RemapOptions {
+        anomalies: Some(AnomalyCheck::new(1.5, Some(&kept), &world).unwrap()),
+        ..Default::default()
+    };
+    let cb = |uuid| (uuid == from).then_some(to);
+    let stats = remap_mca(&broken, &options, &Progress::hidden(), &cb).unwrap();
+    assert_eq!(stats.chunk_errors, 0);
+    assert_eq!(stats.chunks, sectors);
+    let (input, output) = stats.region_size.unwrap();
+    assert_eq!(input, content.len() as u64);
+    assert_eq!(output, std::fs::metadata(&broken).unwrap().len());
+    assert!(output as f64 > input as f64 * 1.9);
+    assert!(stats.anomaly);
+    assert_eq!(
+        std::fs::read(kept.join("region").join("r.0.0.mca")).unwrap(),
+        content
+    );
+
+    // The chunk is stored in an external file, the region is only the header and one sector,
+    // rewriting moves the chunk in the region, which is no growth with the external file
+    let mut padding = vec![0; 512 << 10];
+    rand::thread_rng().fill(&mut padding[..]);
+    let nbt = uuid_nbt(&padding);
+    let external = region.join("r.1.0.mca");
+    let mut content = vec![0; 3 * 4096];
+    content[..4].copy_from_slice(&[0, 0, 2, 1]);
+    content[8192..8197].copy_from_slice(&[0, 0, 0, 1, 0x82]);
+    let mut encoder = ZlibEncoder::new(vec![], COMPRESSION_LEVEL);
+    encoder.write_all(&nbt).unwrap();
+    let mcc = encoder.finish().unwrap();
+    for low_memory in [false, true] {
+        std::fs::write(&external, &content).unwrap();
+        std::fs::write(region.join("c.32.0.mcc"), &mcc).unwrap();
+        let options = RemapOptions {
+            anomalies: Some(AnomalyCheck::new(1.5, Some(&kept), &world).unwrap()),
+            low_memory,
+            ..Default::default()
+        };
+        let stats = remap_mca(&external, &options, &Progress::hidden(), &cb).unwrap();
+        assert_eq!(stats.chunk_errors, 0);
+        let (input, output) = stats.region_size.unwrap();
+        assert_eq!(input, (content.len() + mcc.len()) as u64);
+        assert!(output.abs_diff(input) <= 2 * 4096);
+        assert!(!stats.anomaly);
+        assert!(!region.join("c.32.0.mcc").exists());
+    }
+    assert!(!kept.join("region").join("r.1.0.mca").exists());
+
+    // The directory must be outside the world
+    assert!(AnomalyCheck::new(1.5, Some(&world.join("kept")), &world).is_err());
+    assert!(AnomalyCheck::new(0.0, None, &world).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,290 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use colored::Colorize;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{conflict::Decision, dimension::DimensionStats, source::BySource, style::paint};
+
+/// A hardlink skipped during the scan, the physical file is remapped through `original`
+#[derive(Debug, Serialize)]
+pub struct Alias {
+    pub alias: PathBuf,
+    pub original: PathBuf,
+}
+
+/// When a file is renamed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RenamePhase {
+    /// Right after its content, with `--single-phase`
+    Contents,
+    /// After every content is remapped
+    Renames,
+}
+
+/// A file renamed because its name contains a remapped uuid
+#[derive(Debug, Serialize)]
+pub struct RenamedFile {
+    pub from: PathBuf,
+    pub to: PathBuf,
+    pub phase: RenamePhase,
+}
+
+/// A file copied to the name with the new uuid by `--clone`, only the copy is remapped
+#[derive(Debug, Serialize)]
+pub struct ClonedFile {
+    pub from: PathBuf,
+    pub to: PathBuf,
+}
+
+/// The length of a region file before and after remapping
+#[derive(Debug, Clone, Serialize)]
+pub struct RegionSize {
+    pub path: PathBuf,
+    pub input_bytes: u64,
+    pub output_bytes: u64,
+    /// The output length over the input length
+    pub ratio: f64,
+    /// The output exceeds the input by more than `--anomaly-factor`
+    pub anomaly: bool,
+}
+
+impl RegionSize {
+    pub fn new(path: PathBuf, (input_bytes, output_bytes): (u64, u64), anomaly: bool) -> Self {
+        Self {
+            path,
+            input_bytes,
+            output_bytes,
+            ratio: output_bytes as f64 / input_bytes.max(1) as f64,
+            anomaly,
+        }
+    }
+}
+
+/// The totals of a run, the `RESULT` line, the final table and the report all show these
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize)]
+pub struct Summary {
+    pub files_scanned: usize,
+    pub files_modified: usize,
+    /// Left as they are by `--limit`
+    pub files_skipped: usize,
+    /// Left out because their path names a player of `--freeze`
+    pub files_frozen: usize,
+    /// Failed to be remapped, counted in `errors`
+    pub files_failed: usize,
+    pub replacements: usize,
+    pub replacements_by_source: BySource,
+    pub renames: usize,
+    /// Renames failed after the contents are remapped, counted in `errors`
+    pub rename_conflicts: usize,
+    /// Region files growing by more than `--anomaly-factor`, not counted in `errors`
+    pub region_anomalies: usize,
+    pub errors: usize,
+    /// The time spent building the prefilter of the mapping, before remapping
+    pub prefilter_build_secs: f64,
+    /// The time spent remapping and renaming the files, without the confirmation
+    pub elapsed_secs: f64,
+}
+
+impl Summary {
+    /// The final table, aligned, the labels are styled unless `--no-color` is given
+    pub fn table(&self) -> Vec<String> {
+        let row = |label: &str, value: String, alert: bool| {
+            let label = format!("{:<24}", label);
+            let value = format!("{:>16}", value);
+            if alert {
+                format!(
+                    "{}{}",
+                    paint(label, |s| s.red()),
+                    paint(value, |s| s.red().bold())
+                )
+            } else {
+                format!("{}{}", paint(label, |s| s.yellow()), value)
+            }
+        };
+        let mut lines = vec![
+            paint("Summary", |s| s.bold().underline()),
+            row("Files scanned", self.files_scanned.to_string(), false),
+            row("Files modified", self.files_modified.to_string(), false),
+            row("Files skipped", self.files_skipped.to_string(), false),
+            row("Files frozen", self.files_frozen.to_string(), false),
+            row(
+                "Files failed",
+                self.files_failed.to_string(),
+                self.files_failed > 0,
+            ),
+            row("Replacements", self.replacements.to_string(), false),
+        ];
+        for (label, count) in self.replacements_by_source.rows() {
+            lines.push(row(&format!("  {}", label), count.to_string(), false));
+        }
+        lines.extend([
+            row("Renames", self.renames.to_string(), false),
+            row(
+                "Rename conflicts",
+                self.rename_conflicts.to_string(),
+                self.rename_conflicts > 0,
+            ),
+            row(
+                "Region anomalies",
+                self.region_anomalies.to_string(),
+                self.region_anomalies > 0,
+            ),
+            row("Errors", self.errors.to_string(), self.errors > 0),
+            row(
+                "Prefilter build",
+                format!("{:.1}s", self.prefilter_build_secs),
+                false,
+            ),
+            row("Elapsed", format!("{:.1}s", self.elapsed_secs), false),
+            row(
+                "Throughput",
+                format!(
+                    "{:.1} files/s",
+                    self.files_scanned as f64 / self.elapsed_secs.max(f64::EPSILON)
+                ),
+                false,
+            ),
+        ]);
+        lines
+    }
+}
+
+/// A run with `--limit`, which left some files as they are
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Partial {
+    pub limit: usize,
+    pub files_skipped: usize,
+}
+
+/// Machine-readable report of a run, written to the path given by `--report`
+#[derive(Debug, Default, Serialize)]
+pub struct Report {
+    /// The seed used to shuffle the files, pass it to `--seed` to replay the run
+    pub seed: u64,
+    /// Number of uuid fields modified
+    pub replacements: usize,
+    pub summary: Summary,
+    pub skipped_aliases: Vec<Alias>,
+    pub renamed_files: Vec<RenamedFile>,
+    /// Files copied by `--clone`, the originals are kept as they are
+    pub cloned_files: Vec<ClonedFile>,
+    /// The region files rewritten, with their length before and after
+    pub regions: Vec<RegionSize>,
+    /// Chunks selected by `--chunk`, in world chunk coordinates
+    pub selected_chunks_found: Vec<(i32, i32)>,
+    pub selected_chunks_missing: Vec<(i32, i32)>,
+    /// Chunks whose NBT root is not a compound, copied as is
+    pub non_compound_roots: usize,
+    /// Chunks larger than `--max-chunk-size` uncompressed, copied as is
+    pub oversized_chunks: usize,
+    /// Chunks discarded because a newer chunk points at the same sectors
+    pub duplicate_chunks: usize,
+    /// Statistics of the files in each dimension
+    pub dimensions: BTreeMap<String, DimensionStats>,
+    /// Uuids computed by `--derive`, the inverse can be used as a json mapping to undo the run
+    pub derived: BTreeMap<Uuid, Uuid>,
+    /// Some derived uuids are not recorded because there are too many
+    pub derived_truncated: bool,
+    /// Files that needed retries after transient filesystem errors
+    pub retried_files: Vec<PathBuf>,
+    /// Time spent remapping the files, summed over the workers
+    pub cpu_busy_secs: f64,
+    /// Time spent reading and writing the files, only with `--threads-io`
+    pub io_busy_secs: Option<f64>,
+    /// Set with `--limit`, `None` if every file is remapped
+    pub partial: Option<Partial>,
+    /// Files left out by `--modified-after` and `--modified-before`
+    pub files_excluded_by_time: usize,
+    /// The uuids of the players of `--freeze`, removed from the mapping with their files left out
+    pub frozen_players: Vec<Uuid>,
+    /// Conflicts resolved with `--interactive-conflicts`, in the order they were asked
+    pub conflict_decisions: Vec<Decision>,
+}
+
+impl Report {
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    // Other tests may enable the style, so the escapes are stripped
+    let strip = |line: &String| {
+        let mut plain = String::new();
+        let mut chars = line.chars();
+        while let Some(c) = chars.next() {
+            if c == '\x1b' {
+                chars.by_ref().find(|c| *c == 'm');
+            } else {
+                plain.push(c);
+            }
+        }
+        plain
+    };
+    let summary = Summary {
+        files_scanned: 120,
+        files_modified: 30,
+        files_skipped: 4,
+        files_frozen: 2,
+        files_failed: 1,
+        replacements: 58,
+        replacements_by_source: BySource {
+            nbt_array: 40,
+            most_least: 2,
+            nbt_string: 5,
+            text_dashed: 8,
+            text_undashed: 1,
+            text_decimal: 0,
+            file_name: 2,
+        },
+        renames: 2,
+        rename_conflicts: 0,
+        region_anomalies: 1,
+        errors: 3,
+        prefilter_build_secs: 0.3,
+        elapsed_secs: 2.5,
+    };
+    assert_eq!(summary.replacements_by_source.total(), summary.replacements);
+    let table = summary.table().iter().map(strip).collect::<Vec<_>>();
+    assert_eq!(
+        table,
+        [
+            "Summary",
+            "Files scanned                        120",
+            "Files modified                        30",
+            "Files skipped                          4",
+            "Files frozen                           2",
+            "Files failed                           1",
+            "Replacements                          58",
+            "  NBT int arrays                      40",
+            "  NBT Most/Least pairs                 2",
+            "  NBT strings                          5",
+            "  Text, dashed                         8",
+            "  Text, undashed                       1",
+            "  Text, decimal pairs                  0",
+            "  File names                           2",
+            "Renames                                2",
+            "Rename conflicts                       0",
+            "Region anomalies                       1",
+            "Errors                                 3",
+            "Prefilter build                      0.3s",
+            "Elapsed                             2.5s",
+            "Throughput                  48.0 files/s",
+        ]
+    );
+    let json = serde_json::to_value(summary).unwrap();
+    assert_eq!(json["replacements_by_source"]["nbt_array"], 40);
+    assert_eq!(json["files_failed"], 1);
+}
@@ -0,0 +1,182 @@
+use std::{
+    sync::{Arc, Condvar, Mutex},
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use crate::progress::Totals;
+
+/// Print the progress as plain lines, for output piped to a file or `tee` where no bar is drawn
+/// Stopped when dropped, after the workers finish
+pub struct Reporter {
+    stop: Arc<(Mutex<bool>, Condvar)>,
+    handle: Option<JoinHandle<()>>,
+}
+
+/// A count for humans, e.g. `1.2M`
+fn human_count(n: usize) -> String {
+    match n {
+        0..=9_999 => n.to_string(),
+        10_000..=999_999 => format!("{:.1}k", n as f64 / 1e3),
+        1_000_000..=999_999_999 => format!("{:.1}M", n as f64 / 1e6),
+        _ => format!("{:.1}G", n as f64 / 1e9),
+    }
+}
+
+/// A duration for humans, e.g. `3h12m`
+pub fn human_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}h{:02}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+/// The remaining time, assuming the remaining files take as long as the done ones
+pub fn eta(done: usize, total: usize, elapsed: Duration) -> Option<Duration> {
+    (done > 0).then(|| elapsed.mul_f64(total.saturating_sub(done) as f64 / done as f64))
+}
+
+/// The progress line, the ETA assumes the remaining files take as long as the done ones
+pub fn progress_line(
+    done: usize,
+    total: usize,
+    replacements: usize,
+    errors: usize,
+    elapsed: Duration,
+) -> String {
+    let eta = eta(done, total, elapsed).map_or("unknown".to_string(), human_duration);
+    format!(
+        "processed {}/{} files, {} replacements, {} errors, ETA {}",
+        done,
+        total,
+        human_count(replacements),
+        errors,
+        eta
+    )
+}
+
+impl Reporter {
+    /// Log a progress line of the `total` files every `interval`
+    pub fn start(totals: Arc<Totals>, total: usize, interval: Duration) -> Self {
+        Self::start_with(totals, total, interval, |line| log::info!("{}", line))
+    }
+
+    fn start_with(
+        totals: Arc<Totals>,
+        total: usize,
+        interval: Duration,
+        sink: impl Fn(String) + Send + 'static,
+    ) -> Self {
+        let stop = Arc::new((Mutex::new(false), Condvar::new()));
+        let handle = {
+            let stop = stop.clone();
+            std::thread::spawn(move || {
+                let start = Instant::now();
+                let (stopped, cvar) = &*stop;
+                let mut stopped = stopped.lock().unwrap();
+                loop {
+                    let deadline = Instant::now() + interval;
+                    // Woken up early when stopped, or spuriously
+                    while !*stopped && Instant::now() < deadline {
+                        stopped = cvar
+                            .wait_timeout(
+                                stopped,
+                                deadline.saturating_duration_since(Instant::now()),
+                            )
+                            .unwrap()
+                            .0;
+                    }
+                    if *stopped {
+                        break;
+                    }
+                    sink(progress_line(
+                        totals.files(),
+                        total,
+                        totals.replacements(),
+                        totals.errors(),
+                        start.elapsed(),
+                    ));
+                }
+            })
+        };
+        Self {
+            stop,
+            handle: Some(handle),
+        }
+    }
+}
+
+impl Drop for Reporter {
+    fn drop(&mut self) {
+        let (stopped, cvar) = &*self.stop;
+        *stopped.lock().unwrap() = true;
+        cvar.notify_all();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    use std::path::Path;
+
+    use crate::{progress::Progress, setup_test_logger};
+
+    setup_test_logger();
+
+    assert_eq!(
+        progress_line(
+            1523,
+            80000,
+            1_234_567,
+            14,
+            Duration::from_secs(1523 * 3 / 20)
+        ),
+        "processed 1523/80000 files, 1.2M replacements, 14 errors, ETA 3h15m"
+    );
+    assert_eq!(
+        progress_line(0, 10, 0, 0, Duration::from_secs(5)),
+        "processed 0/10 files, 0 replacements, 0 errors, ETA unknown"
+    );
+    assert_eq!(
+        progress_line(5, 10, 12_345, 0, Duration::from_secs(65)),
+        "processed 5/10 files, 12.3k replacements, 0 errors, ETA 1m05s"
+    );
+    assert_eq!(human_duration(Duration::from_secs(42)), "42s");
+    assert_eq!(human_count(9_999), "9999");
+
+    let totals = Arc::new(Totals::default());
+    let lines = Arc::new(Mutex::new(vec![]));
+    let reporter = {
+        let lines = lines.clone();
+        Reporter::start_with(totals.clone(), 2, Duration::from_millis(20), move |line| {
+            lines.lock().unwrap().push(line)
+        })
+    };
+    let progress = Progress::new(indicatif::ProgressBar::hidden(), totals.clone());
+    progress.start(Path::new("config.yml"));
+    progress.finish(0, 3);
+    std::thread::sleep(Duration::from_millis(200));
+    // Stops at once, not after the interval
+    let start = Instant::now();
+    drop(reporter);
+    assert!(start.elapsed() < Duration::from_secs(1));
+    let lines = lines.lock().unwrap().clone();
+    assert!(!lines.is_empty());
+    assert!(lines
+        .last()
+        .unwrap()
+        .starts_with("processed 1/2 files, 3 replacements, 0 errors, ETA "));
+
+    // A long interval does not delay the shutdown either
+    let reporter = Reporter::start_with(totals, 2, Duration::from_secs(3600), |_| {
+        panic!("No line before the interval")
+    });
+    let start = Instant::now();
+    drop(reporter);
+    assert!(start.elapsed() < Duration::from_secs(1));
+}
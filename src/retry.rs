@@ -0,0 +1,172 @@
+use std::{
+    collections::BTreeSet,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// `ESTALE`, `ErrorKind::StaleNetworkFileHandle` is not stable yet
+#[cfg(target_os = "linux")]
+const ESTALE: Option<i32> = Some(116);
+#[cfg(target_os = "macos")]
+const ESTALE: Option<i32> = Some(70);
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+const ESTALE: Option<i32> = None;
+
+/// Check if the error is likely to go away when retried, e.g. on network storage
+pub fn is_transient(err: &std::io::Error) -> bool {
+    matches!(
+        err.kind(),
+        ErrorKind::Interrupted | ErrorKind::WouldBlock | ErrorKind::TimedOut
+    ) || (ESTALE.is_some() && err.raw_os_error() == ESTALE)
+}
+
+/// Retry transient filesystem errors with an exponential backoff
+/// Clones share the record of the retried files
+#[derive(Debug, Clone, Default)]
+pub struct RetryPolicy {
+    retries: u32,
+    delay: Duration,
+    retried: Arc<Mutex<BTreeSet<PathBuf>>>,
+}
+
+impl RetryPolicy {
+    pub fn new(retries: u32, delay: Duration) -> Self {
+        Self {
+            retries,
+            delay,
+            ..Default::default()
+        }
+    }
+
+    /// The delay before the `attempt`-th retry, 0-based
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        self.delay.saturating_mul(1 << attempt.min(16))
+    }
+
+    /// Run the operation on the path, retrying transient errors
+    pub fn run<T>(
+        &self,
+        path: &Path,
+        op: impl FnMut() -> std::io::Result<T>,
+    ) -> std::io::Result<T> {
+        self.run_with(path, op, std::thread::sleep)
+    }
+
+    fn run_with<T>(
+        &self,
+        path: &Path,
+        mut op: impl FnMut() -> std::io::Result<T>,
+        sleep: impl Fn(Duration),
+    ) -> std::io::Result<T> {
+        let mut attempt = 0;
+        loop {
+            match op() {
+                Err(err) if attempt < self.retries && is_transient(&err) => {
+                    let delay = self.backoff(attempt);
+                    log::debug!(
+                        "Retrying {} in {:?} after a transient error: {}",
+                        path.display(),
+                        delay,
+                        err
+                    );
+                    self.retried.lock().unwrap().insert(path.to_path_buf());
+                    sleep(delay);
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Read the file, unless the IO stage has read it already
+    pub fn read(&self, path: &Path) -> std::io::Result<Vec<u8>> {
+        if let Some(content) = crate::stage::take_input(path) {
+            return Ok(content);
+        }
+        self.run(path, || std::fs::read(path))
+    }
+
+    /// The files that needed at least one retry
+    pub fn retried(&self) -> Vec<PathBuf> {
+        self.retried.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    use std::{cell::RefCell, io::Error};
+
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    assert!(is_transient(&Error::from(ErrorKind::Interrupted)));
+    assert!(is_transient(&Error::from(ErrorKind::WouldBlock)));
+    assert!(is_transient(&Error::from(ErrorKind::TimedOut)));
+    assert!(!is_transient(&Error::from(ErrorKind::NotFound)));
+    assert!(!is_transient(&Error::from(ErrorKind::PermissionDenied)));
+    if let Some(estale) = ESTALE {
+        assert!(is_transient(&Error::from_raw_os_error(estale)));
+    }
+
+    let policy = RetryPolicy::new(3, Duration::from_millis(10));
+    assert_eq!(policy.backoff(0), Duration::from_millis(10));
+    assert_eq!(policy.backoff(2), Duration::from_millis(40));
+    assert_eq!(
+        RetryPolicy::new(100, Duration::MAX).backoff(99),
+        Duration::MAX
+    );
+
+    // A writer failing the first few times
+    let failing = |failures: usize, kind: ErrorKind| {
+        let calls = RefCell::new(0);
+        move || {
+            *calls.borrow_mut() += 1;
+            if *calls.borrow() <= failures {
+                Err(Error::from(kind))
+            } else {
+                Ok(*calls.borrow())
+            }
+        }
+    };
+    let sleeps = RefCell::new(vec![]);
+    let sleep = |d| sleeps.borrow_mut().push(d);
+    let a = Path::new("region/r.0.0.mca");
+    let b = Path::new("level.dat");
+    let c = Path::new("config.yml");
+    assert_eq!(
+        policy
+            .run_with(a, failing(2, ErrorKind::Interrupted), sleep)
+            .unwrap(),
+        3
+    );
+    assert_eq!(
+        sleeps.take(),
+        vec![Duration::from_millis(10), Duration::from_millis(20)]
+    );
+    // Gives up after the retries
+    let err = policy
+        .run_with(b, failing(4, ErrorKind::TimedOut), sleep)
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::TimedOut);
+    assert_eq!(sleeps.take().len(), 3);
+    // Permanent errors are not retried
+    let err = policy
+        .run_with(c, failing(1, ErrorKind::NotFound), sleep)
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+    assert!(sleeps.take().is_empty());
+    assert_eq!(
+        policy.clone().retried(),
+        vec![b.to_path_buf(), a.to_path_buf()]
+    );
+
+    let no_retry = RetryPolicy::default();
+    assert!(no_retry
+        .run_with(a, failing(1, ErrorKind::Interrupted), sleep)
+        .is_err());
+    assert!(no_retry.retried().is_empty());
+}
@@ -0,0 +1,115 @@
+use std::{collections::BTreeMap, path::Path, time::Duration};
+
+use crate::{
+    handler::{Registry, RemapOptions},
+    remap::head,
+    task::Task,
+};
+
+/// The files of a kind in a directory
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Count {
+    pub files: usize,
+    pub bytes: u64,
+}
+
+/// The files to remap, counted by the top-level directory and the handler, from the metadata only
+pub type Breakdown = BTreeMap<(String, &'static str), Count>;
+
+/// The first directory of the path, `.` for the files directly in the world
+fn top_level(path: &Path) -> String {
+    let mut components = path.components();
+    match (components.next(), components.next()) {
+        (Some(first), Some(_)) => first.as_os_str().to_string_lossy().into_owned(),
+        _ => ".".to_string(),
+    }
+}
+
+/// Count the scanned files, sniffing them again unless disabled
+pub fn breakdown(
+    world: &Path,
+    tasks: &[Task],
+    registry: &Registry,
+    options: &RemapOptions,
+) -> Breakdown {
+    let mut breakdown = Breakdown::new();
+    for task in tasks {
+        let path = world.join(&task.path);
+        let handler = head(&path, registry, options)
+            .ok()
+            .and_then(|head| registry.find(&path, &head))
+            .map_or("unknown", |handler| handler.name());
+        let count = breakdown
+            .entry((top_level(&task.path), handler))
+            .or_default();
+        count.files += 1;
+        count.bytes += std::fs::metadata(&path).map_or(0, |meta| meta.len());
+    }
+    breakdown
+}
+
+/// The time to remap the bytes at the throughput, in MiB per second
+pub fn estimate(bytes: u64, throughput: f64) -> Duration {
+    Duration::from_secs_f64(bytes as f64 / 1024.0 / 1024.0 / throughput)
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    use std::path::PathBuf;
+
+    use crate::{setup_test_logger, task::scan_world};
+
+    setup_test_logger();
+
+    let world = std::env::temp_dir().join("test_scan");
+    let _ = std::fs::remove_dir_all(&world);
+    std::fs::create_dir_all(world.join("region")).unwrap();
+    std::fs::create_dir_all(world.join("plugins").join("Shop")).unwrap();
+    std::fs::write(world.join("region").join("r.0.0.mca"), [0; 8192]).unwrap();
+    std::fs::write(world.join("region").join("r.0.1.mca"), [0; 4096]).unwrap();
+    std::fs::write(
+        world.join("plugins").join("Shop").join("config.yml"),
+        "a: b\n",
+    )
+    .unwrap();
+    std::fs::write(world.join("level.dat"), [10, 0, 0, 0]).unwrap();
+    let structures = world.join("generated").join("minecraft").join("structures");
+    std::fs::create_dir_all(&structures).unwrap();
+    std::fs::write(structures.join("house.nbt"), [10, 0, 0, 0, 0]).unwrap();
+    // Not handled by anything
+    std::fs::write(world.join("region").join("notes.bin"), [1, 2, 3]).unwrap();
+
+    let mut registry = Registry::builtin();
+    for no_sniff in [false, true] {
+        let options = RemapOptions {
+            no_sniff,
+            ..Default::default()
+        };
+        let world = PathBuf::from(&world);
+        let tasks = scan_world(&world, &mut registry, &options).unwrap().tasks;
+        assert_eq!(
+            breakdown(&world, &tasks, &registry, &options),
+            Breakdown::from([
+                ((".".to_string(), "nbt"), Count { files: 1, bytes: 4 }),
+                (
+                    ("generated".to_string(), "nbt"),
+                    Count { files: 1, bytes: 5 }
+                ),
+                (
+                    ("plugins".to_string(), "text"),
+                    Count { files: 1, bytes: 5 }
+                ),
+                (
+                    ("region".to_string(), "mca"),
+                    Count {
+                        files: 2,
+                        bytes: 12288
+                    }
+                ),
+            ])
+        );
+    }
+    assert_eq!(estimate(100 * 1024 * 1024, 50.0), Duration::from_secs(2));
+    std::fs::remove_dir_all(&world).unwrap();
+}
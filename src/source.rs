@@ -0,0 +1,231 @@
+use std::cell::{Cell, RefCell};
+
+use serde::Serialize;
+
+/// Where a uuid is found, attributed to the outermost visitor, e.g. a uuid in an NBT string
+/// is an NBT string even though the string is visited as text
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Source {
+    NbtArray,
+    MostLeast,
+    NbtString,
+    TextDashed,
+    TextUndashed,
+    /// Two decimals under `--decimal-uuid-keys`
+    TextDecimal,
+    FileName,
+}
+
+impl Source {
+    pub fn label(self) -> &'static str {
+        match self {
+            Source::NbtArray => "NBT int array",
+            Source::MostLeast => "NBT Most/Least pair",
+            Source::NbtString => "NBT string",
+            Source::TextDashed => "dashed text",
+            Source::TextUndashed => "undashed text",
+            Source::TextDecimal => "decimal pair",
+            Source::FileName => "file name",
+        }
+    }
+}
+
+/// Where in its file a uuid is found, as far as the visitors know
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Location {
+    /// The chunk of a region file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunk: Option<(i32, i32)>,
+    /// The NBT value, e.g. `Inventory[3].tag.Owner`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nbt_path: Option<String>,
+    /// The byte offset in the text, or in the NBT string
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offset: Option<usize>,
+}
+
+impl std::fmt::Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut parts = vec![];
+        if let Some((x, z)) = self.chunk {
+            parts.push(format!("chunk ({}, {})", x, z));
+        }
+        if let Some(path) = self.nbt_path.as_ref().filter(|path| !path.is_empty()) {
+            parts.push(path.clone());
+        }
+        if let Some(offset) = self.offset {
+            parts.push(format!("offset {}", offset));
+        }
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+thread_local! {
+    static CURRENT: Cell<Option<Source>> = const { Cell::new(None) };
+    static LOCATION: RefCell<Option<Location>> = const { RefCell::new(None) };
+}
+
+/// Run `f` with the uuids looked up inside it attributed to the source,
+/// unless an outer visitor has attributed them already
+pub fn tagged<T>(source: Source, f: impl FnOnce() -> T) -> T {
+    let outer = CURRENT.get();
+    if outer.is_some() {
+        return f();
+    }
+    CURRENT.set(Some(source));
+    let ret = f();
+    CURRENT.set(outer);
+    ret
+}
+
+/// The source of the uuid being looked up on this thread
+pub fn current() -> Option<Source> {
+    CURRENT.get()
+}
+
+/// Run `f` recording where the uuids looked up inside it are found, read with `location`
+/// Nothing is recorded outside, so the remapping doesn't pay for it
+pub fn locate<T>(f: impl FnOnce() -> T) -> T {
+    let outer = LOCATION.replace(Some(Location::default()));
+    let ret = f();
+    LOCATION.set(outer);
+    ret
+}
+
+/// Run `f` with the location narrowed by `narrow`, only called inside `locate`
+pub fn located<T>(narrow: impl FnOnce(&mut Location), f: impl FnOnce() -> T) -> T {
+    let outer = LOCATION.with_borrow_mut(|location| {
+        let location = location.as_mut()?;
+        let outer = location.clone();
+        narrow(location);
+        Some(outer)
+    });
+    let ret = f();
+    if outer.is_some() {
+        LOCATION.set(outer);
+    }
+    ret
+}
+
+/// Whether the location is recorded on this thread, to skip computing it otherwise
+pub fn locating() -> bool {
+    LOCATION.with_borrow(Option::is_some)
+}
+
+/// The location of the uuid being looked up on this thread, inside `locate`
+pub fn location() -> Option<Location> {
+    LOCATION.with_borrow(Clone::clone)
+}
+
+/// Replacements counted by source, their sum is the total number of replacements
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct BySource {
+    pub nbt_array: usize,
+    pub most_least: usize,
+    pub nbt_string: usize,
+    pub text_dashed: usize,
+    pub text_undashed: usize,
+    pub text_decimal: usize,
+    pub file_name: usize,
+}
+
+impl BySource {
+    pub fn add(&mut self, source: Source) {
+        *match source {
+            Source::NbtArray => &mut self.nbt_array,
+            Source::MostLeast => &mut self.most_least,
+            Source::NbtString => &mut self.nbt_string,
+            Source::TextDashed => &mut self.text_dashed,
+            Source::TextUndashed => &mut self.text_undashed,
+            Source::TextDecimal => &mut self.text_decimal,
+            Source::FileName => &mut self.file_name,
+        } += 1;
+    }
+
+    pub fn merge(&mut self, other: &BySource) {
+        self.nbt_array += other.nbt_array;
+        self.most_least += other.most_least;
+        self.nbt_string += other.nbt_string;
+        self.text_dashed += other.text_dashed;
+        self.text_undashed += other.text_undashed;
+        self.text_decimal += other.text_decimal;
+        self.file_name += other.file_name;
+    }
+
+    /// The label and the count of each source, in the order of the summary
+    pub fn rows(&self) -> [(&'static str, usize); 7] {
+        [
+            ("NBT int arrays", self.nbt_array),
+            ("NBT Most/Least pairs", self.most_least),
+            ("NBT strings", self.nbt_string),
+            ("Text, dashed", self.text_dashed),
+            ("Text, undashed", self.text_undashed),
+            ("Text, decimal pairs", self.text_decimal),
+            ("File names", self.file_name),
+        ]
+    }
+
+    pub fn total(&self) -> usize {
+        self.rows().iter().map(|(_, count)| count).sum()
+    }
+
+    /// The replacements in NBT values, a wrong one may break a chunk
+    pub fn nbt(&self) -> usize {
+        self.nbt_array + self.most_least + self.nbt_string
+    }
+
+    /// The replacements in text files, including the strings of JSON and YAML files
+    pub fn text(&self) -> usize {
+        self.text_dashed + self.text_undashed + self.text_decimal
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    assert_eq!(current(), None);
+    let outer = tagged(Source::NbtString, || tagged(Source::TextDashed, current));
+    assert_eq!(outer, Some(Source::NbtString));
+    assert_eq!(
+        tagged(Source::TextUndashed, current),
+        Some(Source::TextUndashed)
+    );
+    assert_eq!(current(), None);
+
+    let mut counts = BySource::default();
+    counts.add(Source::NbtArray);
+    counts.add(Source::FileName);
+    counts.add(Source::FileName);
+    let mut total = BySource::default();
+    total.merge(&counts);
+    total.merge(&counts);
+    assert_eq!(total.nbt_array, 2);
+    assert_eq!(total.file_name, 4);
+    assert_eq!(total.total(), 6);
+    assert_eq!((total.nbt(), total.text()), (2, 0));
+
+    let narrow = |location: &mut Location| location.chunk = Some((1, 2));
+    assert_eq!(located(narrow, location), None);
+    let (inner, outer) = locate(|| {
+        let inner = located(narrow, || {
+            located(|location| location.offset = Some(3), location)
+        });
+        (inner, location())
+    });
+    assert_eq!(
+        inner,
+        Some(Location {
+            chunk: Some((1, 2)),
+            nbt_path: None,
+            offset: Some(3),
+        })
+    );
+    assert_eq!(outer, Some(Location::default()));
+    assert_eq!(inner.unwrap().to_string(), "chunk (1, 2), offset 3");
+    assert!(!locating());
+}
@@ -0,0 +1,137 @@
+use std::{
+    cell::RefCell,
+    path::{Path, PathBuf},
+};
+
+use crate::guard::WriteGuard;
+
+/// A modification deferred from a CPU worker to the IO stage of the pipeline
+#[derive(Debug, PartialEq, Eq)]
+pub enum Op {
+    Write(PathBuf, Vec<u8>),
+    Rename(PathBuf, PathBuf),
+    Replace(PathBuf, PathBuf),
+    Remove(PathBuf),
+}
+
+impl Op {
+    /// Perform the modification through the guard
+    pub fn apply(&self, guard: &WriteGuard) -> std::io::Result<()> {
+        match self {
+            Op::Write(path, contents) => guard.write(path, contents),
+            Op::Rename(from, to) => guard.rename(from, to),
+            Op::Replace(temp, target) => guard.replace(temp, target),
+            Op::Remove(path) => guard.remove_file(path),
+        }
+    }
+}
+
+/// The file being remapped on the current thread
+struct Stage {
+    /// The path and the content read by the IO stage, until the handler reads it
+    input: Option<(PathBuf, Vec<u8>)>,
+    ops: Vec<Op>,
+}
+
+thread_local! {
+    static STAGE: RefCell<Option<Stage>> = const { RefCell::new(None) };
+}
+
+/// Run `f` with the file already read, deferring the modifications made through `WriteGuard`
+/// The modifications are returned in order, to be applied on another thread with `Op::apply`
+pub fn staged<T>(input: Option<(PathBuf, Vec<u8>)>, f: impl FnOnce() -> T) -> (T, Vec<Op>) {
+    STAGE.with(|stage| *stage.borrow_mut() = Some(Stage { input, ops: vec![] }));
+    let ret = f();
+    let stage = STAGE.with(|stage| stage.borrow_mut().take());
+    (ret, stage.map(|stage| stage.ops).unwrap_or_default())
+}
+
+/// Defer the modification if running staged, returning whether it is deferred
+pub fn defer(op: impl FnOnce() -> Op) -> bool {
+    STAGE.with(|stage| match &mut *stage.borrow_mut() {
+        Some(stage) => {
+            stage.ops.push(op());
+            true
+        }
+        None => false,
+    })
+}
+
+/// Whether the current thread remaps a file of the pipeline, which holds its memory budget
+pub fn is_staged() -> bool {
+    STAGE.with(|stage| stage.borrow().is_some())
+}
+
+/// Inspect the content of the file read by the IO stage
+pub fn peek_input<T>(path: &Path, f: impl FnOnce(&[u8]) -> T) -> Option<T> {
+    STAGE.with(|stage| match &*stage.borrow() {
+        Some(Stage {
+            input: Some((input, content)),
+            ..
+        }) if input == path => Some(f(content)),
+        _ => None,
+    })
+}
+
+/// Take the content of the file read by the IO stage, it can only be taken once
+pub fn take_input(path: &Path) -> Option<Vec<u8>> {
+    STAGE.with(|stage| {
+        let mut stage = stage.borrow_mut();
+        let input = &mut stage.as_mut()?.input;
+        if input.as_ref().is_some_and(|(input, _)| input == path) {
+            input.take().map(|(_, content)| content)
+        } else {
+            None
+        }
+    })
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    let dir = std::env::temp_dir().join("test_stage");
+    std::fs::create_dir_all(&dir).unwrap();
+    let a = dir.join("a.txt");
+    let b = dir.join("b.txt");
+    std::fs::write(&a, "on disk").unwrap();
+
+    let guard = WriteGuard::default();
+    let (len, ops) = staged(Some((a.clone(), b"prefetched".to_vec())), || {
+        assert!(is_staged());
+        assert_eq!(peek_input(&b, |c| c.len()), None);
+        let len = peek_input(&a, |c| c.len()).unwrap();
+        assert_eq!(guard.retry().read(&a).unwrap(), b"prefetched");
+        // Read again from the disk
+        assert_eq!(guard.retry().read(&a).unwrap(), b"on disk");
+        guard.write(&a, "remapped").unwrap();
+        guard.rename(&a, &b).unwrap();
+        len
+    });
+    assert_eq!(len, 10);
+    // Nothing is modified until the modifications are applied
+    assert_eq!(std::fs::read(&a).unwrap(), b"on disk");
+    assert!(!b.exists());
+    assert_eq!(
+        ops,
+        vec![
+            Op::Write(a.clone(), b"remapped".to_vec()),
+            Op::Rename(a.clone(), b.clone())
+        ]
+    );
+    assert_eq!(guard.writes(), 0);
+    for op in &ops {
+        op.apply(&guard).unwrap();
+    }
+    assert_eq!(std::fs::read(&b).unwrap(), b"remapped");
+    assert_eq!(guard.writes(), 2);
+
+    // Not staged outside
+    assert!(!is_staged());
+    assert!(!defer(|| Op::Remove(b.clone())));
+    assert_eq!(take_input(&a), None);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
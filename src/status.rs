@@ -0,0 +1,421 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Condvar, Mutex},
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+use crate::{progress::Totals, reporter::eta, source::BySource};
+
+/// How often the status file is rewritten during a run
+pub const INTERVAL: Duration = Duration::from_secs(2);
+
+/// How a status file is written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// `--progress-file`
+    Json,
+    /// `--metrics-textfile`, the text exposition format read by the textfile collector of node_exporter
+    Metrics,
+}
+
+/// What the run is doing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Phase {
+    Scanning,
+    Confirming,
+    Remapping,
+    /// Renaming the files after all the contents are remapped
+    Renaming,
+    /// Writing the summary and the report
+    Finishing,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+/// The content of the status file
+#[derive(Debug, Serialize)]
+pub struct Status {
+    pub phase: Phase,
+    pub elapsed_secs: f64,
+    pub files_total: usize,
+    pub files_done: usize,
+    pub replacements: usize,
+    pub replacements_by_source: BySource,
+    pub errors: usize,
+    /// The sizes of the files done, as they were before the remapping
+    pub bytes_processed: u64,
+    /// `None` until a file is done
+    pub eta_secs: Option<f64>,
+    /// The file each worker is remapping, `None` between two files
+    pub workers: Vec<Option<String>>,
+}
+
+struct Shared {
+    phase: Phase,
+    /// The counters of the bars, once the remapping starts
+    totals: Option<(Arc<Totals>, usize)>,
+    stopped: bool,
+}
+
+/// Rewrite the status files periodically for wrappers, e.g. web panels, until dropped
+/// They read the same counters as the bars, so all of them always agree
+pub struct StatusFile {
+    outputs: Vec<(PathBuf, Format)>,
+    start: Instant,
+    shared: Arc<(Mutex<Shared>, Condvar)>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl StatusFile {
+    pub fn start(outputs: Vec<(PathBuf, Format)>, interval: Duration) -> Self {
+        let start = Instant::now();
+        let shared = Arc::new((
+            Mutex::new(Shared {
+                phase: Phase::Scanning,
+                totals: None,
+                stopped: false,
+            }),
+            Condvar::new(),
+        ));
+        let handle = {
+            let (outputs, shared) = (outputs.clone(), shared.clone());
+            std::thread::spawn(move || {
+                let (lock, cvar) = &*shared;
+                let mut state = lock.lock().unwrap();
+                while !state.stopped {
+                    write_all(&outputs, &status(&state, start));
+                    state = cvar.wait_timeout(state, interval).unwrap().0;
+                }
+            })
+        };
+        Self {
+            outputs,
+            start,
+            shared,
+            handle: Some(handle),
+        }
+    }
+
+    /// Enter the phase, written at once
+    pub fn set_phase(&self, phase: Phase) {
+        let (lock, cvar) = &*self.shared;
+        lock.lock().unwrap().phase = phase;
+        cvar.notify_all();
+    }
+
+    /// Report the counters of the remapping of `total` files
+    pub fn attach(&self, totals: Arc<Totals>, total: usize) {
+        let (lock, cvar) = &*self.shared;
+        let mut state = lock.lock().unwrap();
+        state.totals = Some((totals, total));
+        state.phase = Phase::Remapping;
+        cvar.notify_all();
+    }
+
+    /// Stop rewriting the file after writing the final phase
+    pub fn finish(mut self, phase: Phase) {
+        self.stop();
+        let (lock, _) = &*self.shared;
+        let mut state = lock.lock().unwrap();
+        state.phase = phase;
+        write_all(&self.outputs, &status(&state, self.start));
+    }
+
+    fn stop(&mut self) {
+        let (lock, cvar) = &*self.shared;
+        lock.lock().unwrap().stopped = true;
+        cvar.notify_all();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for StatusFile {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn status(state: &Shared, start: Instant) -> Status {
+    let elapsed = start.elapsed();
+    let Some((totals, files_total)) = &state.totals else {
+        return Status {
+            phase: state.phase,
+            elapsed_secs: elapsed.as_secs_f64(),
+            files_total: 0,
+            files_done: 0,
+            replacements: 0,
+            replacements_by_source: BySource::default(),
+            errors: 0,
+            bytes_processed: 0,
+            eta_secs: None,
+            workers: vec![],
+        };
+    };
+    let (files_total, files_done) = (*files_total, totals.files());
+    Status {
+        phase: state.phase,
+        elapsed_secs: elapsed.as_secs_f64(),
+        files_total,
+        files_done,
+        replacements: totals.replacements(),
+        replacements_by_source: totals.by_source(),
+        errors: totals.errors(),
+        bytes_processed: totals.bytes(),
+        eta_secs: eta(files_done, files_total, elapsed).map(|eta| eta.as_secs_f64()),
+        workers: totals.current(),
+    }
+}
+
+/// The status in the text exposition format, each sample is prefixed with `uuid_remapper_`
+/// `finished` is set once the run is over, the file is left in place after it
+pub fn metrics(status: &Status) -> String {
+    let finished = matches!(status.phase, Phase::Done | Phase::Failed | Phase::Cancelled);
+    let mut out = String::new();
+    let mut family = |name: &str, kind: &str, help: &str, samples: Vec<(String, f64)>| {
+        out.push_str(&format!("# HELP uuid_remapper_{} {}\n", name, help));
+        out.push_str(&format!("# TYPE uuid_remapper_{} {}\n", name, kind));
+        for (labels, value) in samples {
+            out.push_str(&format!("uuid_remapper_{}{} {}\n", name, labels, value));
+        }
+    };
+    let single = |value: f64| vec![(String::new(), value)];
+    let label = |name: &str, value: &serde_json::Value| {
+        format!("{{{}=\"{}\"}}", name, value.as_str().unwrap_or_default())
+    };
+    let phase = serde_json::to_value(status.phase).unwrap();
+    let phases = [
+        Phase::Scanning,
+        Phase::Confirming,
+        Phase::Remapping,
+        Phase::Renaming,
+        Phase::Finishing,
+        Phase::Done,
+        Phase::Failed,
+        Phase::Cancelled,
+    ];
+    family(
+        "phase",
+        "gauge",
+        "1 for the phase the run is in",
+        phases
+            .iter()
+            .map(|p| {
+                let p = serde_json::to_value(p).unwrap();
+                (label("phase", &p), if p == phase { 1.0 } else { 0.0 })
+            })
+            .collect(),
+    );
+    family(
+        "files_total",
+        "gauge",
+        "Files to remap",
+        single(status.files_total as f64),
+    );
+    family(
+        "files_done",
+        "counter",
+        "Files remapped, including the failed ones",
+        single(status.files_done as f64),
+    );
+    family(
+        "replacements_total",
+        "counter",
+        "Uuids replaced",
+        single(status.replacements as f64),
+    );
+    let by_source = serde_json::to_value(status.replacements_by_source).unwrap();
+    family(
+        "replacements_by_source_total",
+        "counter",
+        "Uuids replaced, by where they were found",
+        by_source
+            .as_object()
+            .unwrap()
+            .iter()
+            .map(|(source, count)| {
+                let source = serde_json::Value::String(source.clone());
+                (label("source", &source), count.as_f64().unwrap_or_default())
+            })
+            .collect(),
+    );
+    family(
+        "errors_total",
+        "counter",
+        "Files and chunks failed",
+        single(status.errors as f64),
+    );
+    family(
+        "bytes_processed_total",
+        "counter",
+        "Bytes of the files remapped",
+        single(status.bytes_processed as f64),
+    );
+    family(
+        "elapsed_seconds",
+        "gauge",
+        "Time since the start of the run",
+        single(status.elapsed_secs),
+    );
+    family(
+        "finished",
+        "gauge",
+        "1 once the run is over",
+        single(if finished { 1.0 } else { 0.0 }),
+    );
+    out
+}
+
+/// The file written before replacing the status file
+pub fn temp_path(path: &Path) -> PathBuf {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    PathBuf::from(temp)
+}
+
+fn write_all(outputs: &[(PathBuf, Format)], status: &Status) {
+    for (path, format) in outputs {
+        write(path, *format, status);
+    }
+}
+
+/// Replace the file at once, so readers never see a partial file
+fn write(path: &Path, format: Format, status: &Status) {
+    let temp = temp_path(path);
+    let content = match format {
+        Format::Json => serde_json::to_vec_pretty(status).map_err(std::io::Error::from),
+        Format::Metrics => Ok(metrics(status).into_bytes()),
+    };
+    let result = content
+        .and_then(|content| std::fs::write(&temp, content))
+        .and_then(|()| std::fs::rename(&temp, path));
+    if let Err(err) = result {
+        log::warn!(
+            "Failed to write the status to {}: {:#}",
+            path.display(),
+            err
+        );
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    use std::collections::BTreeMap;
+
+    use crate::{progress::Progress, setup_test_logger};
+
+    setup_test_logger();
+
+    let dir = std::env::temp_dir().join("test_status");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("status.json");
+    let read = || -> Option<serde_json::Value> {
+        serde_json::from_slice(&std::fs::read(&path).ok()?).ok()
+    };
+    let wait_for = |phase: &str| {
+        let start = Instant::now();
+        while !read().is_some_and(|json| json["phase"] == phase) {
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "{} never written",
+                phase
+            );
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    };
+
+    let metrics_path = dir.join("uuid_remapper.prom");
+    let status = StatusFile::start(
+        vec![
+            (path.clone(), Format::Json),
+            (metrics_path.clone(), Format::Metrics),
+        ],
+        Duration::from_secs(3600),
+    );
+    wait_for("scanning");
+    // Written at once, not after the interval
+    status.set_phase(Phase::Confirming);
+    wait_for("confirming");
+    let totals = Arc::new(Totals::default());
+    let progress = Progress::new(indicatif::ProgressBar::hidden(), totals.clone());
+    progress.start(Path::new("region/r.0.0.mca"));
+    status.attach(totals.clone(), 4);
+    wait_for("remapping");
+    let json = read().unwrap();
+    assert_eq!(json["files_total"], 4);
+    assert_eq!(json["files_done"], 0);
+    assert_eq!(json["eta_secs"], serde_json::Value::Null);
+    assert_eq!(json["workers"], serde_json::json!(["region/r.0.0.mca"]));
+    // The counters of two snapshots never go down
+    let parse = |text: &str| {
+        let (mut kinds, mut samples) = (BTreeMap::new(), BTreeMap::new());
+        for line in text.lines() {
+            if let Some(family) = line.strip_prefix("# TYPE ") {
+                let (name, kind) = family.split_once(' ').unwrap();
+                kinds.insert(name.to_string(), kind.to_string());
+            } else if !line.starts_with('#') {
+                let (name, value) = line.rsplit_once(' ').unwrap();
+                let family = name.split('{').next().unwrap();
+                assert!(kinds.contains_key(family), "{} has no type", family);
+                samples.insert(name.to_string(), value.parse::<f64>().unwrap());
+            }
+        }
+        (kinds, samples)
+    };
+    let snapshot = || {
+        let shared = Shared {
+            phase: Phase::Remapping,
+            totals: Some((totals.clone(), 4)),
+            stopped: false,
+        };
+        parse(&metrics(&status(&shared, Instant::now())))
+    };
+    let (kinds, before) = snapshot();
+    assert_eq!(before["uuid_remapper_files_total"], 4.0);
+    assert_eq!(before["uuid_remapper_phase{phase=\"remapping\"}"], 1.0);
+    assert_eq!(before["uuid_remapper_phase{phase=\"done\"}"], 0.0);
+    assert_eq!(before["uuid_remapper_finished"], 0.0);
+    let by_source = BySource {
+        nbt_array: 2,
+        text_dashed: 1,
+        ..Default::default()
+    };
+    progress.record(4096, &by_source);
+    progress.finish(1, 3);
+    let (_, after) = snapshot();
+    for (name, value) in &before {
+        let family = name.split('{').next().unwrap();
+        if kinds[family] == "counter" {
+            assert!(after[name] >= *value, "{} went down", name);
+        }
+    }
+    assert_eq!(after["uuid_remapper_bytes_processed_total"], 4096.0);
+    assert_eq!(
+        after["uuid_remapper_replacements_by_source_total{source=\"nbt_array\"}"],
+        2.0
+    );
+    status.finish(Phase::Done);
+    let (_, finished) = parse(&std::fs::read_to_string(&metrics_path).unwrap());
+    assert_eq!(finished["uuid_remapper_finished"], 1.0);
+    assert_eq!(finished["uuid_remapper_files_done"], 1.0);
+    assert_eq!(finished["uuid_remapper_phase{phase=\"done\"}"], 1.0);
+    let json = read().unwrap();
+    assert_eq!(json["phase"], "done");
+    assert_eq!(json["files_done"], 1);
+    assert_eq!(json["replacements"], 3);
+    assert_eq!(json["errors"], 1);
+    assert!(json["eta_secs"].as_f64().unwrap() >= 0.0);
+    assert_eq!(json["workers"], serde_json::json!([null]));
+    assert_eq!(json["bytes_processed"], 4096);
+    assert!(!dir.join("status.json.tmp").exists());
+    assert!(!temp_path(&metrics_path).exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
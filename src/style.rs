@@ -0,0 +1,157 @@
+use std::{
+    fmt::Display,
+    io::IsTerminal,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use colored::{ColoredString, Colorize};
+use indicatif::{ProgressBar, ProgressStyle};
+
+/// Whether the output is styled, decided once at startup
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// Decide whether to style the output, following the `NO_COLOR` and `CLICOLOR` conventions
+fn decide(no_color: bool, var: impl Fn(&str) -> Option<String>, terminal: bool) -> bool {
+    if no_color || var("NO_COLOR").is_some_and(|v| !v.is_empty()) {
+        return false;
+    }
+    if var("CLICOLOR_FORCE").is_some_and(|v| !v.is_empty() && v != "0") {
+        return true;
+    }
+    if var("CLICOLOR").is_some_and(|v| v == "0") {
+        return false;
+    }
+    terminal
+}
+
+/// Decide and set the global style flag, returning it
+pub fn init(no_color: bool) -> bool {
+    let enabled = decide(
+        no_color,
+        |name| std::env::var(name).ok(),
+        std::io::stderr().is_terminal(),
+    );
+    set_enabled(enabled);
+    enabled
+}
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+    colored::control::set_override(enabled);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Style the text if styling is enabled, all colored output goes through here
+pub fn paint(text: impl Display, style: fn(ColoredString) -> ColoredString) -> String {
+    if enabled() {
+        style(text.to_string().normal()).to_string()
+    } else {
+        text.to_string()
+    }
+}
+
+/// The template of a progress bar, without colors if styling is disabled
+pub fn bar_template(prefix: &str, suffix: &str) -> String {
+    let bar = if enabled() {
+        "{bar:60.cyan/blue}"
+    } else {
+        "{bar:60}"
+    };
+    format!("{}[{}] {}", prefix, bar, suffix)
+}
+
+/// Whether the bars can be drawn, not if the log is not a terminal or on `TERM=dumb`,
+/// which can't move the cursor, the plain progress lines of the reporter are logged instead
+fn can_draw(var: impl Fn(&str) -> Option<String>, terminal: bool) -> bool {
+    terminal && !var("TERM").is_some_and(|term| term == "dumb")
+}
+
+pub fn bars_drawn() -> bool {
+    can_draw(
+        |name| std::env::var(name).ok(),
+        std::io::stderr().is_terminal(),
+    )
+}
+
+/// A progress bar, every bar is made here so they all follow `bars_drawn`
+pub fn progress_bar(len: u64, prefix: &str, suffix: &str) -> ProgressBar {
+    make_bar(len, prefix, suffix, bars_drawn())
+}
+
+/// A hidden bar if it is not drawn, otherwise added to the bars drawn together,
+/// with a plain style if the template is rejected
+fn make_bar(len: u64, prefix: &str, suffix: &str, drawn: bool) -> ProgressBar {
+    if !drawn {
+        let pg = ProgressBar::hidden();
+        pg.set_length(len);
+        return pg;
+    }
+    let style = match ProgressStyle::default_bar().template(&bar_template(prefix, suffix)) {
+        Ok(style) => style.progress_chars("#>-"),
+        Err(err) => {
+            log::debug!("Falling back to the plain bar style: {}", err);
+            ProgressStyle::default_bar()
+        }
+    };
+    crate::MULTI.add(ProgressBar::new(len).with_style(style))
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    use std::collections::HashMap;
+
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    let env = |vars: &[(&str, &str)]| {
+        let vars = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect::<HashMap<_, _>>();
+        move |name: &str| vars.get(name).cloned()
+    };
+    assert!(decide(false, env(&[]), true));
+    assert!(!decide(false, env(&[]), false));
+    assert!(!decide(true, env(&[]), true));
+    assert!(!decide(false, env(&[("NO_COLOR", "1")]), true));
+    assert!(decide(false, env(&[("NO_COLOR", "")]), true));
+    assert!(!decide(false, env(&[("CLICOLOR", "0")]), true));
+    assert!(decide(false, env(&[("CLICOLOR_FORCE", "1")]), false));
+    assert!(!decide(true, env(&[("CLICOLOR_FORCE", "1")]), true));
+
+    let previous = enabled();
+    set_enabled(false);
+    let output = [
+        paint("Done!", |s| s.green().bold()),
+        paint("Task Summary", |s| s.bold().underline()),
+        "plain".red().to_string(),
+        bar_template("worker-00: ", "{pos}/{len}"),
+    ];
+    for line in &output {
+        assert!(!line.as_bytes().contains(&0x1b), "{:?}", line);
+    }
+    assert_eq!(output[0], "Done!");
+    set_enabled(previous);
+
+    assert!(can_draw(env(&[("TERM", "xterm-256color")]), true));
+    assert!(can_draw(env(&[]), true));
+    assert!(!can_draw(env(&[("TERM", "dumb")]), true));
+    assert!(!can_draw(env(&[("TERM", "xterm-256color")]), false));
+    // A dumb terminal, the bar is hidden but still counts
+    let pg = make_bar(10, "total:     ", "{pos}/{len} {msg} ", false);
+    pg.inc(3);
+    pg.set_message("waiting");
+    assert!(pg.is_hidden());
+    assert_eq!((pg.position(), pg.length()), (3, Some(10)));
+    // A rejected template falls back to the plain style
+    let pg = make_bar(10, "{broken ", "{pos:>}}", true);
+    pg.inc(1);
+    assert_eq!(pg.position(), 1);
+    pg.finish_and_clear();
+    crate::MULTI.remove(&pg);
+}
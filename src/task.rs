@@ -1,41 +1,204 @@
 use std::{
     cell::Cell,
-    collections::HashMap,
+    collections::{BTreeMap, HashMap, HashSet},
     panic::catch_unwind,
     path::{Path, PathBuf},
+    sync::Arc,
     thread::JoinHandle,
+    time::{Duration, Instant},
 };
 
+use anyhow::Context;
 use indicatif::ProgressBar;
+use rand::{seq::SliceRandom, Rng};
 use uuid::Uuid;
 
-use crate::remap::{remap_file, require_remapping};
+use crate::{
+    anvil::{owning_region, region_coords, AnvilReader, Region},
+    dimension::DimensionStats,
+    filter::{world_chunk, Area},
+    guard::WriteGuard,
+    handler::{parse_hints, FileStats, Registry, RemapOptions, HINT_FILE},
+    mapping::Mapping,
+    progress::{Progress, Totals},
+    remap::{head, remap_file, require_remapping},
+    report::RegionSize,
+    source::{self, BySource},
+};
+
+/// The result of a worker thread
+#[derive(Debug, Default)]
+pub struct WorkerResult {
+    /// Number of uuid fields modified
+    pub replacements: usize,
+    /// The replacements by where the uuids are found, summing up to `replacements`
+    pub by_source: BySource,
+    /// Number of files with any uuid modified
+    pub files_modified: usize,
+    /// Statistics of all the files processed by the worker
+    pub stats: FileStats,
+    /// Renamed files, `(from, to)`, relative to the world
+    pub renamed: Vec<(PathBuf, PathBuf)>,
+    /// Files copied by `--clone`, `(from, to)`, relative to the world
+    pub cloned: Vec<(PathBuf, PathBuf)>,
+    /// The length of the rewritten region files, relative to the world
+    pub regions: Vec<RegionSize>,
+    /// Statistics of the files in each dimension
+    pub dimensions: BTreeMap<String, DimensionStats>,
+    /// Time spent remapping the files, including reading and writing them without an IO stage
+    pub cpu_busy: Duration,
+    /// Time spent reading and writing the files in the IO stage of the pipeline
+    pub io_busy: Duration,
+    /// The files failed to remap or write, relative to the world, not recorded as done by a pilot run
+    pub failed: Vec<PathBuf>,
+}
+
+impl WorkerResult {
+    pub fn merge(&mut self, other: WorkerResult) {
+        self.replacements += other.replacements;
+        self.by_source.merge(&other.by_source);
+        self.files_modified += other.files_modified;
+        self.stats.merge(&other.stats);
+        self.renamed.extend(other.renamed);
+        self.cloned.extend(other.cloned);
+        self.regions.extend(other.regions);
+        self.failed.extend(other.failed);
+        for (dimension, stats) in &other.dimensions {
+            self.dimensions
+                .entry(dimension.clone())
+                .or_default()
+                .merge(stats);
+        }
+        self.cpu_busy += other.cpu_busy;
+        self.io_busy += other.io_busy;
+    }
+}
+
+/// A file to remap
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Task {
+    /// The path relative to the world
+    pub path: PathBuf,
+    /// The dimension the file belongs to
+    pub dimension: String,
+}
+
+/// Remap a file, and add its statistics to the result
+pub fn remap_task(
+    world: &Path,
+    task: &Task,
+    mapping: &dyn Mapping,
+    registry: &Registry,
+    options: &RemapOptions,
+    progress: &Progress,
+    result: &mut WorkerResult,
+) -> anyhow::Result<()> {
+    let stat = Cell::new(0);
+    let by_source = Cell::new(BySource::default());
+    let cb = |uuid| {
+        let ret = mapping.get(uuid);
+        debug_assert_ne!(
+            ret,
+            Some(uuid),
+            "identity entries are removed from the mapping"
+        );
+        if ret.is_some() {
+            stat.set(stat.get() + 1);
+            let source = source::current();
+            debug_assert!(source.is_some(), "every lookup is tagged by its visitor");
+            if let Some(source) = source {
+                let mut counts = by_source.get();
+                counts.add(source);
+                by_source.set(counts);
+            }
+        }
+        ret
+    };
+    progress.start(&task.path);
+    let bytes = std::fs::metadata(world.join(&task.path)).map_or(0, |metadata| metadata.len());
+    let file_result = remap_file(world, &task.path, registry, options, progress, &cb);
+    progress.record(bytes, &by_source.get());
+    result.replacements += stat.get();
+    result.by_source.merge(&by_source.get());
+    let dimension = result.dimensions.entry(task.dimension.clone()).or_default();
+    dimension.files += 1;
+    dimension.replacements += stat.get();
+    progress.finish(
+        match &file_result {
+            Ok(file_stats) => file_stats.chunk_errors,
+            Err(_) => 1,
+        },
+        stat.get(),
+    );
+    match file_result {
+        Ok(mut file_stats) => {
+            file_stats.replacements = by_source.get();
+            if stat.get() > 0 {
+                result.files_modified += 1;
+                log::debug!(
+                    "{}: {} replacements, {} in NBT and {} in text",
+                    task.path.display(),
+                    stat.get(),
+                    file_stats.replacements.nbt(),
+                    file_stats.replacements.text()
+                );
+            }
+            result.stats.merge(&file_stats);
+            if let Some(new_path) = file_stats.renamed {
+                result.renamed.push((task.path.clone(), new_path));
+            }
+            if let Some(copy) = file_stats.cloned {
+                result.cloned.push((task.path.clone(), copy));
+            }
+            if let Some(size) = file_stats.region_size {
+                let region = RegionSize::new(task.path.clone(), size, file_stats.anomaly);
+                result.regions.push(region);
+            }
+            Ok(())
+        }
+        Err(err) => {
+            dimension.errors += 1;
+            result.failed.push(task.path.clone());
+            if let Some(capture) = &options.capture {
+                capture.file(&task.path, &err, options.nbt_rules);
+            }
+            Err(err)
+        }
+    }
+}
 
 pub fn run_tasks(
     world: PathBuf,
-    tasks: &'static [PathBuf],
+    tasks: &'static [Task],
     pg: ProgressBar,
-    mapping: &'static HashMap<Uuid, Uuid>,
-) -> JoinHandle<usize> {
+    totals: Arc<Totals>,
+    mapping: &'static dyn Mapping,
+    registry: &'static Registry,
+    options: &'static RemapOptions,
+) -> JoinHandle<WorkerResult> {
     std::thread::spawn(move || {
         let result = catch_unwind(move || {
             pg.set_length(tasks.len() as u64);
-            let stat = Cell::new(0);
+            let mut result = WorkerResult::default();
+            let progress = Progress::new(pg.clone(), totals);
             for task in tasks {
-                pg.set_message(task.display().to_string());
-                let cb = |uuid| {
-                    let ret = mapping.get(&uuid).copied();
-                    if ret.is_some() {
-                        stat.set(stat.get() + 1);
-                    }
-                    ret
-                };
-                if let Err(err) = remap_file(&world, task, &cb) {
-                    log::error!("Failed to remap file {}: {:#?}", task.display(), err);
-                };
+                let start = Instant::now();
+                let remapped = remap_task(
+                    &world,
+                    task,
+                    mapping,
+                    registry,
+                    options,
+                    &progress,
+                    &mut result,
+                );
+                result.cpu_busy += start.elapsed();
+                if let Err(err) = remapped {
+                    log::error!("Failed to remap file {}: {:#?}", task.path.display(), err);
+                }
                 pg.inc(1);
             }
-            stat.get()
+            result
         });
         match result {
             Err(err) => {
@@ -47,47 +210,260 @@ pub fn run_tasks(
     })
 }
 
-pub fn scan_world(world: &PathBuf) -> anyhow::Result<Vec<PathBuf>> {
+/// `level.dat` and its backup, remapped before all the other files
+pub fn is_level_dat(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|name| name == "level.dat" || name == "level.dat_old")
+}
+
+/// The result of scanning a world
+#[derive(Debug, Default)]
+pub struct Scan {
+    /// Files to remap
+    pub tasks: Vec<Task>,
+    /// Hardlinks to a file already in `tasks`, `(alias, original)`
+    /// The physical file is only remapped once through the original path
+    pub aliases: Vec<(PathBuf, PathBuf)>,
+    /// Files to remap left out by `--modified-after` and `--modified-before`
+    pub excluded_by_time: usize,
+}
+
+/// Identify the physical file, `None` if the file has no other hardlinks
+#[cfg(target_family = "unix")]
+fn file_id(path: &Path) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+
+    let meta = std::fs::metadata(path).ok()?;
+    (meta.nlink() > 1).then(|| (meta.dev(), meta.ino()))
+}
+
+/// Identify the physical file, `None` if the file has no other hardlinks
+// The file index on Windows is not available in stable Rust, so hardlinks are not detected there
+#[cfg(not(target_family = "unix"))]
+fn file_id(_path: &Path) -> Option<(u64, u64)> {
+    None
+}
+
+/// Expand a leading `~` to the home directory, for shells that do not do it
+fn expand_home(path: &Path, home: Option<PathBuf>) -> PathBuf {
+    match path.strip_prefix("~") {
+        Ok(rest) => match home {
+            Some(home) => home.join(rest),
+            None => path.to_path_buf(),
+        },
+        Err(_) => path.to_path_buf(),
+    }
+}
+
+pub fn home_dir() -> Option<PathBuf> {
+    std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .filter(|home| !home.is_empty())
+        .map(PathBuf::from)
+}
+
+/// Normalize the world path argument, so the relative paths do not depend on how it is spelled
+pub fn normalize_world(path: &Path) -> anyhow::Result<PathBuf> {
+    let expanded = expand_home(path, home_dir());
+    let world = expanded
+        .canonicalize()
+        .with_context(|| format!("The world {} does not exist", expanded.display()))?;
+    if !world.is_dir() {
+        anyhow::bail!("The world {} is not a directory", world.display());
+    }
+    Ok(world)
+}
+
+/// The path relative to the world, both are under the same normalized root
+pub fn relative_path(world: &Path, path: &Path) -> PathBuf {
+    path.strip_prefix(world)
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// The hint files found are added to the registry, their rules apply to the rest of the run
+pub fn scan_world(
+    world: &PathBuf,
+    registry: &mut Registry,
+    options: &RemapOptions,
+) -> anyhow::Result<Scan> {
     fn dfs_scan(
         world: &PathBuf,
         buf: &mut PathBuf,
-        tasks: &mut Vec<PathBuf>,
+        scan: &mut Scan,
+        inodes: &mut HashMap<(u64, u64), PathBuf>,
+        registry: &mut Registry,
+        options: &RemapOptions,
         depth: usize,
     ) -> anyhow::Result<()> {
         if depth > 20 {
             return Ok(());
         }
+        // The rules apply to the directory before any of its files is looked at
+        let hints = buf.join(HINT_FILE);
+        if hints.is_file() {
+            let relative = relative_path(world, buf);
+            let rules = std::fs::read_to_string(&hints)
+                .map_err(anyhow::Error::from)
+                .and_then(|content| parse_hints(&content))
+                .with_context(|| {
+                    format!(
+                        "Invalid hint file {}",
+                        relative_path(world, &hints).display()
+                    )
+                })?;
+            log::info!(
+                "{} handler rules from {}",
+                rules.len(),
+                relative_path(world, &hints).display()
+            );
+            registry.add_hints(world, &relative, rules);
+        }
         for entry in std::fs::read_dir(&*buf)? {
             let entry = entry?;
             let path = entry.path();
+            if path == hints {
+                continue;
+            }
             if path.is_dir() {
                 buf.push(path.file_name().unwrap());
-                dfs_scan(world, buf, tasks, depth + 1)?;
+                dfs_scan(world, buf, scan, inodes, registry, options, depth + 1)?;
                 buf.pop();
             } else {
-                fn relative_path(world: &Path, path: &Path) -> PathBuf {
-                    let p = path.strip_prefix(world).unwrap().to_path_buf();
-                    if p.starts_with(std::path::MAIN_SEPARATOR.to_string()) {
-                        p.strip_prefix(std::path::MAIN_SEPARATOR.to_string())
-                            .unwrap()
-                            .to_path_buf()
-                    } else {
-                        p
-                    }
+                if options.is_excluded(&path) {
+                    log::info!(
+                        "Excluding {}, it is used by the run itself",
+                        relative_path(world, &path).display()
+                    );
+                    continue;
                 }
-                if require_remapping(&path) {
-                    tasks.push(relative_path(world, &path));
+                if require_remapping(&path, registry, options) {
+                    let relative = relative_path(world, &path);
+                    if log::log_enabled!(log::Level::Debug) {
+                        if let Ok(head) = head(&path, registry, options) {
+                            if let (Some(handler), origin) = registry.resolve(&path, &head) {
+                                log::debug!(
+                                    "{}: {} ({})",
+                                    relative.display(),
+                                    handler.name(),
+                                    origin
+                                );
+                            }
+                        }
+                    }
+                    if options.modified.excludes(&path) {
+                        log::debug!(
+                            "Excluding {}, it is not modified in the time range",
+                            relative.display()
+                        );
+                        scan.excluded_by_time += 1;
+                        continue;
+                    }
+                    if let Some(id) = file_id(&path) {
+                        if let Some(original) = inodes.get(&id) {
+                            log::warn!(
+                                "{} is a hardlink of {}, it will be remapped only once",
+                                relative.display(),
+                                original.display()
+                            );
+                            scan.aliases.push((relative, original.clone()));
+                            continue;
+                        }
+                        inodes.insert(id, relative.clone());
+                    }
+                    scan.tasks.push(Task {
+                        dimension: options.dimension_rules.classify(&relative),
+                        path: relative,
+                    });
                 }
             }
         }
         Ok(())
     }
-    let mut tasks = Vec::new();
-    dfs_scan(world, &mut world.clone(), &mut tasks, 0)?;
-    Ok(tasks)
+    let mut scan = Scan::default();
+    dfs_scan(
+        world,
+        &mut world.clone(),
+        &mut scan,
+        &mut HashMap::new(),
+        registry,
+        options,
+        0,
+    )?;
+    // External chunks are remapped through their region, and only standalone if the region is missing
+    let scheduled = scan
+        .tasks
+        .iter()
+        .map(|task| task.path.clone())
+        .collect::<HashSet<_>>();
+    scan.tasks.retain(|task| match owning_region(&task.path) {
+        Some(region) if scheduled.contains(&region) => {
+            log::debug!(
+                "{} is remapped through {}",
+                task.path.display(),
+                region.display()
+            );
+            false
+        }
+        _ => true,
+    });
+    Ok(scan)
 }
 
-pub fn split_tasks(tasks: &[PathBuf], count: usize) -> Vec<&[PathBuf]> {
+/// Shuffle the tasks so that the workers get similar amount of work
+/// With `deterministic`, the order only depends on the set of tasks and the rng, not on the scan order
+pub fn shuffle_tasks<T: Ord>(tasks: &mut [T], rng: &mut impl Rng, deterministic: bool) {
+    if deterministic {
+        tasks.sort();
+    }
+    tasks.shuffle(rng);
+}
+
+/// Keep the first `limit` files in path order, `level.dat` first as it is remapped first,
+/// so a pilot run always picks the same files whatever the scan order
+/// Returns the number of files left out
+pub fn limit_tasks(tasks: &mut Vec<Task>, limit: usize) -> usize {
+    tasks.sort_by(|a, b| (!is_level_dat(&a.path), &a.path).cmp(&(!is_level_dat(&b.path), &b.path)));
+    let skipped = tasks.len().saturating_sub(limit);
+    tasks.truncate(limit);
+    skipped
+}
+
+/// Whether the path names one of the uuids, dashed or not, in any case
+pub fn names_uuid(path: &Path, uuids: &[Uuid]) -> bool {
+    let path = path.to_string_lossy().to_ascii_lowercase();
+    uuids.iter().any(|uuid| {
+        path.contains(&uuid.hyphenated().to_string()) || path.contains(&uuid.simple().to_string())
+    })
+}
+
+/// Count the regions intersecting the area and the chunks inside it, only reading the headers
+pub fn count_area(world: &Path, tasks: &[Task], area: &Area, guard: &WriteGuard) -> (usize, usize) {
+    let mut regions = 0;
+    let mut chunks = 0;
+    for task in tasks {
+        if task.path.extension().and_then(|s| s.to_str()) != Some("mca") {
+            continue;
+        }
+        let Some(region) = region_coords(&task.path).filter(|&r| area.intersects_region(r)) else {
+            continue;
+        };
+        regions += 1;
+        match AnvilReader::open_with_guard(&world.join(&task.path), guard.clone()) {
+            Ok(reader) => {
+                chunks += reader
+                    .locations()
+                    .into_iter()
+                    .filter(|&local| area.contains(world_chunk(region, local)))
+                    .count();
+            }
+            Err(err) => log::warn!("Unable to read {}: {:#}", task.path.display(), err),
+        }
+    }
+    (regions, chunks)
+}
+
+pub fn split_tasks<T>(tasks: &[T], count: usize) -> Vec<&[T]> {
     let mut ret = vec![];
     let block_size = tasks.len() / count;
     let block_remain = tasks.len() % count;
@@ -103,8 +479,6 @@ pub fn split_tasks(tasks: &[PathBuf], count: usize) -> Vec<&[PathBuf]> {
 #[cfg(test)]
 #[test]
 fn test() {
-    use std::collections::HashSet;
-
     use crate::setup_test_logger;
 
     setup_test_logger();
@@ -136,6 +510,38 @@ fn test() {
         vec![3, 3, 2, 2]
     );
 
+    // Same seed, same assignment, regardless of the scan order
+    use rand::{rngs::StdRng, SeedableRng};
+    let mut a = tasks.clone();
+    let mut b = tasks.iter().rev().cloned().collect::<Vec<_>>();
+    shuffle_tasks(&mut a, &mut StdRng::seed_from_u64(42), true);
+    shuffle_tasks(&mut b, &mut StdRng::seed_from_u64(42), true);
+    assert_eq!(a, b);
+    assert_eq!(split_tasks(&a, 3), split_tasks(&b, 3));
+
+    let task = |path: &str| Task {
+        path: PathBuf::from(path),
+        dimension: String::new(),
+    };
+    let mut limited = vec![
+        task("region/r.0.0.mca"),
+        task("b.yml"),
+        task("level.dat"),
+        task("a.yml"),
+    ];
+    assert_eq!(limit_tasks(&mut limited, 3), 1);
+    assert_eq!(
+        limited,
+        vec![task("level.dat"), task("a.yml"), task("b.yml")]
+    );
+    assert_eq!(limit_tasks(&mut limited, 10), 0);
+    assert_eq!(limited.len(), 3);
+
+    assert!(is_level_dat(Path::new("level.dat")));
+    assert!(is_level_dat(Path::new("world/level.dat_old")));
+    assert!(!is_level_dat(Path::new("level.dat/region.mca")));
+    assert!(!is_level_dat(Path::new("playerdata/level.dat.json")));
+
     #[cfg(target_family = "windows")]
     return;
 
@@ -180,11 +586,17 @@ fn test() {
     std::fs::write(&r, &pesudo_content).unwrap();
     let r = config.join("config.nbt");
     std::fs::write(&r, &pesudo_content).unwrap();
-    let tasks = scan_world(&temp_dir).unwrap();
+    let tasks = scan_world(
+        &temp_dir,
+        &mut Registry::builtin(),
+        &RemapOptions::default(),
+    )
+    .unwrap()
+    .tasks;
     assert_eq!(
         tasks
             .iter()
-            .map(|x| x.to_str().unwrap())
+            .map(|x| x.path.to_str().unwrap())
             .collect::<HashSet<_>>(),
         vec![
             "world/region/r.1.1.mca",
@@ -204,5 +616,600 @@ fn test() {
         .into_iter()
         .collect::<HashSet<_>>()
     );
+    for task in &tasks {
+        let expected = if task.path.starts_with("world/region") {
+            "overworld"
+        } else {
+            "other"
+        };
+        assert_eq!(task.dimension, expected);
+    }
     std::fs::remove_dir_all(&temp_dir).unwrap();
 }
+
+#[cfg(test)]
+#[cfg(target_family = "unix")]
+#[test]
+fn test_hardlink() {
+    use std::str::FromStr;
+
+    use uuid::Uuid;
+
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    let from = Uuid::from_str("2d318504-1a7b-39dc-8c18-44df798a5c06").unwrap();
+    let to = Uuid::from_str("00000000-0000-0000-0000-000000000000").unwrap();
+    let temp_dir = std::env::temp_dir().join("test_scan_hardlink");
+    let _ = std::fs::remove_dir_all(&temp_dir);
+    std::fs::create_dir_all(temp_dir.join("a")).unwrap();
+    std::fs::create_dir_all(temp_dir.join("b")).unwrap();
+    std::fs::write(temp_dir.join("a/data.json"), from.to_string()).unwrap();
+    std::fs::hard_link(temp_dir.join("a/data.json"), temp_dir.join("b/data.json")).unwrap();
+
+    let mut registry = Registry::builtin();
+    let options = RemapOptions::default();
+    let scan = scan_world(&temp_dir, &mut registry, &options).unwrap();
+    assert_eq!(scan.tasks.len(), 1);
+    assert_eq!(scan.aliases.len(), 1);
+    assert_eq!(scan.aliases[0].1, scan.tasks[0].path);
+
+    let calls = Cell::new(0);
+    for task in &scan.tasks {
+        remap_file(
+            &temp_dir,
+            &task.path,
+            &registry,
+            &options,
+            &Progress::hidden(),
+            &|uuid| {
+                calls.set(calls.get() + 1);
+                (uuid == from).then_some(to)
+            },
+        )
+        .unwrap();
+    }
+    assert_eq!(calls.get(), 1);
+    for file in ["a/data.json", "b/data.json"] {
+        assert_eq!(
+            std::fs::read_to_string(temp_dir.join(file)).unwrap(),
+            to.to_string()
+        );
+    }
+    std::fs::remove_dir_all(&temp_dir).unwrap();
+}
+
+#[cfg(test)]
+#[test]
+fn test_modified_filter() {
+    use std::{fs::File, time::SystemTime};
+
+    use crate::{filter::TimeFilter, setup_test_logger};
+
+    setup_test_logger();
+
+    let world = std::env::temp_dir().join("test_scan_modified");
+    let _ = std::fs::remove_dir_all(&world);
+    std::fs::create_dir_all(&world).unwrap();
+    let now = SystemTime::now();
+    let day = Duration::from_secs(86400);
+    for (file, age) in [("old.json", 10), ("edge.json", 7), ("new.json", 1)] {
+        let path = world.join(file);
+        std::fs::write(&path, "{}").unwrap();
+        File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(now - day * age)
+            .unwrap();
+    }
+    let mut registry = Registry::builtin();
+    let mut scan = |after: Option<u32>, before: Option<u32>| {
+        let options = RemapOptions {
+            modified: TimeFilter {
+                after: after.map(|age| now - day * age),
+                before: before.map(|age| now - day * age),
+            },
+            ..Default::default()
+        };
+        let scan = scan_world(&world, &mut registry, &options).unwrap();
+        let mut files = scan
+            .tasks
+            .into_iter()
+            .map(|task| task.path.to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        files.sort();
+        (files, scan.excluded_by_time)
+    };
+    assert_eq!(scan(None, None).1, 0);
+    // After is inclusive, before is exclusive
+    assert_eq!(
+        scan(Some(7), None),
+        (vec!["edge.json".to_string(), "new.json".to_string()], 1)
+    );
+    assert_eq!(scan(None, Some(7)), (vec!["old.json".to_string()], 2));
+    assert_eq!(scan(Some(8), Some(2)), (vec!["edge.json".to_string()], 2));
+    std::fs::remove_dir_all(&world).unwrap();
+}
+
+#[cfg(test)]
+#[test]
+fn test_external_chunk() {
+    use std::{
+        io::{Read, Write},
+        str::FromStr,
+    };
+
+    use rand::RngCore;
+    use uuid::Uuid;
+
+    use crate::{
+        anvil::{Anvil, Chunk},
+        setup_test_logger,
+    };
+
+    setup_test_logger();
+
+    let from = Uuid::from_str("2d318504-1a7b-39dc-8c18-44df798a5c06").unwrap();
+    let to = Uuid::from_str("00000000-0000-0000-0000-000000000000").unwrap();
+    let nbt = |padding: usize| {
+        let mut nbt = vec![10, 0, 0, 8, 0, 1, b'u', 0, 36];
+        nbt.extend_from_slice(from.to_string().as_bytes());
+        nbt.extend_from_slice(&[7, 0, 1, b'p']);
+        nbt.extend_from_slice(&(padding as i32).to_be_bytes());
+        let mut random = vec![0; padding];
+        rand::thread_rng().fill_bytes(&mut random);
+        nbt.extend_from_slice(&random);
+        nbt.push(0);
+        nbt
+    };
+    let world = std::env::temp_dir().join("test_scan_external_chunk");
+    let _ = std::fs::remove_dir_all(&world);
+    let region = world.join("region");
+    std::fs::create_dir_all(&region).unwrap();
+    // Too large to be stored in the region, so it is moved to c.5.10.mcc
+    let mut anvil = Anvil::new(&region.join("r.0.0.mca"));
+    anvil
+        .write(&Chunk {
+            external: false,
+            location: (5, 10),
+            timestamp: 0,
+            uncompressed: nbt(2 * 1024 * 1024),
+            lz4: None,
+        })
+        .unwrap();
+    anvil.save().unwrap();
+    assert!(region.join("c.5.10.mcc").exists());
+    // The region of c.40.0.mcc is missing
+    let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(&nbt(16)).unwrap();
+    std::fs::write(region.join("c.40.0.mcc"), encoder.finish().unwrap()).unwrap();
+
+    let mut registry = Registry::builtin();
+    let options = RemapOptions::default();
+    let scan = scan_world(&world, &mut registry, &options).unwrap();
+    let mut paths = scan
+        .tasks
+        .iter()
+        .map(|task| task.path.clone())
+        .collect::<Vec<_>>();
+    paths.sort();
+    assert_eq!(
+        paths,
+        vec![
+            PathBuf::from("region/c.40.0.mcc"),
+            PathBuf::from("region/r.0.0.mca")
+        ]
+    );
+
+    let calls = Cell::new(0);
+    for task in &scan.tasks {
+        remap_file(
+            &world,
+            &task.path,
+            &registry,
+            &options,
+            &Progress::hidden(),
+            &|uuid| {
+                calls.set(calls.get() + 1);
+                (uuid == from).then_some(to)
+            },
+        )
+        .unwrap();
+    }
+    // Once for the owned external chunk, once for the orphaned one
+    assert_eq!(calls.get(), 2);
+    let anvil = Anvil::open(&region.join("r.0.0.mca")).unwrap();
+    let chunk = anvil.read((5, 10)).unwrap();
+    assert!(chunk.external);
+    assert_eq!(&chunk.uncompressed[9..45], to.to_string().as_bytes());
+    let mut uncompressed = vec![];
+    flate2::read::ZlibDecoder::new(std::fs::read(region.join("c.40.0.mcc")).unwrap().as_slice())
+        .read_to_end(&mut uncompressed)
+        .unwrap();
+    assert_eq!(&uncompressed[9..45], to.to_string().as_bytes());
+    std::fs::remove_dir_all(&world).unwrap();
+}
+
+#[cfg(test)]
+#[test]
+fn test_world_path() {
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    // Relative to the working directory, like the main test
+    let root = PathBuf::from("test_world_path");
+    let _ = std::fs::remove_dir_all(&root);
+    let world = root.join("world");
+    std::fs::create_dir_all(world.join("stats")).unwrap();
+    std::fs::write(
+        world
+            .join("stats")
+            .join("2d318504-1a7b-39dc-8c18-44df798a5c06.json"),
+        "{}",
+    )
+    .unwrap();
+    std::fs::write(world.join("config.yml"), "a: b\n").unwrap();
+    let canonical = world.canonicalize().unwrap();
+    let mut registry = Registry::builtin();
+    let options = RemapOptions::default();
+    let mut scan = |spelling: &Path| {
+        let world = normalize_world(spelling).unwrap();
+        assert_eq!(world, canonical);
+        let mut tasks = scan_world(&world, &mut registry, &options)
+            .unwrap()
+            .tasks
+            .into_iter()
+            .map(|t| t.path)
+            .collect::<Vec<_>>();
+        tasks.sort();
+        tasks
+    };
+    let expected = vec![
+        PathBuf::from("config.yml"),
+        PathBuf::from("stats").join("2d318504-1a7b-39dc-8c18-44df798a5c06.json"),
+    ];
+    assert_eq!(scan(&world), expected);
+    let mut trailing = world.clone().into_os_string();
+    trailing.push(std::path::MAIN_SEPARATOR_STR);
+    assert_eq!(scan(Path::new(&trailing)), expected);
+    assert_eq!(scan(&Path::new(".").join(&world)), expected);
+    assert_eq!(scan(&canonical), expected);
+
+    // `~` is expanded
+    let home = canonical.parent().unwrap().to_path_buf();
+    assert_eq!(
+        expand_home(Path::new("~/world"), Some(home.clone())),
+        home.join("world")
+    );
+    assert_eq!(
+        expand_home(Path::new("~"), Some(home.clone())),
+        home.clone()
+    );
+    assert_eq!(
+        expand_home(Path::new("~/world"), None),
+        PathBuf::from("~/world")
+    );
+    assert_eq!(
+        expand_home(Path::new("a/~/world"), Some(home)),
+        PathBuf::from("a/~/world")
+    );
+
+    #[cfg(target_family = "unix")]
+    {
+        let link = root.join("link");
+        std::os::unix::fs::symlink(canonical.clone(), &link).unwrap();
+        assert_eq!(scan(&link), expected);
+    }
+
+    assert!(normalize_world(&root.join("missing")).is_err());
+    assert!(normalize_world(&world.join("config.yml")).is_err());
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[cfg(test)]
+#[test]
+fn test_error_counts() {
+    use uuid::Uuid;
+
+    use crate::{
+        anvil::{Anvil, Chunk},
+        setup_test_logger,
+    };
+
+    setup_test_logger();
+
+    let world = std::env::temp_dir().join("test_error_counts");
+    let _ = std::fs::remove_dir_all(&world);
+    let region = world.join("region");
+    std::fs::create_dir_all(&region).unwrap();
+    // Two failing files and a region with two failing chunks
+    std::fs::write(world.join("bad.dat"), [10, 0, 30]).unwrap();
+    std::fs::write(world.join("bad.nbt"), [10, 0, 0, 255, 0]).unwrap();
+    std::fs::write(world.join("config.yml"), "a: b\n").unwrap();
+    let mut anvil = Anvil::new(&region.join("r.0.0.mca"));
+    for (x, uncompressed) in [
+        vec![10, 0, 0, 255, 0],
+        vec![10, 0, 0, 0],
+        vec![10, 0, 0, 8, 0],
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        anvil
+            .write(&Chunk {
+                external: false,
+                location: (x as i32, 0),
+                timestamp: 0,
+                uncompressed,
+                lz4: None,
+            })
+            .unwrap();
+    }
+    anvil.save().unwrap();
+
+    let registry: &'static mut Registry = Box::leak(Box::new(Registry::builtin()));
+    let options: &'static RemapOptions = Box::leak(Box::new(RemapOptions::default()));
+    let mapping: &'static HashMap<Uuid, Uuid> = Box::leak(Box::new(HashMap::new()));
+    let tasks: &'static [Task] = Box::leak(
+        scan_world(&world, registry, options)
+            .unwrap()
+            .tasks
+            .into_boxed_slice(),
+    );
+    let registry: &'static Registry = registry;
+    assert_eq!(tasks.len(), 4);
+    let totals = Arc::new(Totals::default());
+    let handles = split_tasks(tasks, 3)
+        .into_iter()
+        .map(|tasks| {
+            run_tasks(
+                world.clone(),
+                tasks,
+                ProgressBar::hidden(),
+                totals.clone(),
+                mapping,
+                registry,
+                options,
+            )
+        })
+        .collect::<Vec<_>>();
+    let mut total = WorkerResult::default();
+    for handle in handles {
+        total.merge(handle.join().unwrap());
+    }
+    let errors =
+        total.dimensions.values().map(|d| d.errors).sum::<usize>() + total.stats.chunk_errors;
+    assert_eq!(total.stats.chunk_errors, 2);
+    assert_eq!(errors, 4);
+    assert_eq!(totals.errors(), errors);
+    std::fs::remove_dir_all(&world).unwrap();
+}
+
+#[cfg(test)]
+#[test]
+fn test_area() {
+    use std::str::FromStr;
+
+    use uuid::Uuid;
+
+    use crate::{
+        anvil::{Anvil, Chunk},
+        filter::{parse_area, ChunkFilter},
+        setup_test_logger,
+    };
+
+    setup_test_logger();
+
+    let from = Uuid::from_str("2d318504-1a7b-39dc-8c18-44df798a5c06").unwrap();
+    let to = Uuid::from_str("fb1ad51e-cf1f-41f7-8fd1-10dff164b17d").unwrap();
+    let nbt = |uuid: Uuid| {
+        let mut nbt = vec![10, 0, 0, 8, 0, 4, b'u', b'u', b'i', b'd', 0, 36];
+        nbt.extend_from_slice(uuid.to_string().as_bytes());
+        nbt.push(0);
+        nbt
+    };
+    let world = std::env::temp_dir().join("test_area");
+    let _ = std::fs::remove_dir_all(&world);
+    std::fs::create_dir_all(world.join("region")).unwrap();
+    let locals = [(0, 0), (15, 0), (16, 0), (31, 31)];
+    for region in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+        let path = world
+            .join("region")
+            .join(format!("r.{}.{}.mca", region.0, region.1));
+        let mut anvil = Anvil::new(&path);
+        for location in locals {
+            anvil
+                .write(&Chunk {
+                    external: false,
+                    location,
+                    timestamp: 0,
+                    uncompressed: nbt(from),
+                    lz4: None,
+                })
+                .unwrap();
+        }
+        anvil.save().unwrap();
+    }
+    std::fs::write(world.join("config.yml"), format!("owner: {}\n", from)).unwrap();
+
+    // One and a half regions
+    let area = parse_area("0,0,767,511").unwrap();
+    let options = RemapOptions {
+        chunk_filter: Some(ChunkFilter::default().with_area(area)),
+        ..Default::default()
+    };
+    let mut registry = Registry::builtin();
+    let mut tasks = scan_world(&world, &mut registry, &options).unwrap().tasks;
+    tasks.sort();
+    assert_eq!(
+        tasks.iter().map(|t| t.path.clone()).collect::<Vec<_>>(),
+        vec![
+            PathBuf::from("config.yml"),
+            PathBuf::from("region/r.0.0.mca"),
+            PathBuf::from("region/r.1.0.mca"),
+        ]
+    );
+    assert_eq!(count_area(&world, &tasks, &area, &options.guard), (2, 6));
+
+    let mapping = HashMap::from([(from, to)]);
+    let mut result = WorkerResult::default();
+    for task in &tasks {
+        remap_task(
+            &world,
+            task,
+            &mapping,
+            &registry,
+            &options,
+            &Progress::hidden(),
+            &mut result,
+        )
+        .unwrap();
+    }
+    let mut selected = result.stats.selected_chunks.clone();
+    selected.sort();
+    assert_eq!(
+        selected,
+        vec![(0, 0), (15, 0), (16, 0), (31, 31), (32, 0), (47, 0)]
+    );
+    let mut visited = vec![];
+    for region in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+        let path = world
+            .join("region")
+            .join(format!("r.{}.{}.mca", region.0, region.1));
+        let anvil = Anvil::open(&path).unwrap();
+        for location in locals {
+            if anvil.read(location).unwrap().uncompressed == nbt(to) {
+                visited.push(world_chunk(region, location));
+            }
+        }
+    }
+    visited.sort();
+    assert_eq!(visited, selected);
+    // The chunks and the config are counted apart
+    assert_eq!(result.stats.replacements, result.by_source);
+    assert_eq!(result.stats.replacements.nbt(), selected.len());
+    assert_eq!(result.stats.replacements.text(), 1);
+    assert_eq!(result.replacements, result.by_source.total());
+    // Other files are unaffected by the area
+    assert_eq!(
+        std::fs::read_to_string(world.join("config.yml")).unwrap(),
+        format!("owner: {}\n", to)
+    );
+    std::fs::remove_dir_all(&world).unwrap();
+}
+
+#[cfg(test)]
+#[test]
+fn test_hint_files() {
+    use crate::{
+        handler::{HandlerKind, Origin},
+        setup_test_logger,
+    };
+
+    setup_test_logger();
+
+    let world = std::env::temp_dir().join("test_scan_hint_files");
+    let _ = std::fs::remove_dir_all(&world);
+    let (plugins, nested) = (world.join("plugins"), world.join("plugins/Mod"));
+    std::fs::create_dir_all(nested.join("Deep")).unwrap();
+    std::fs::write(
+        plugins.join(HINT_FILE),
+        "\"*.pdata\" = \"nbt-gzip\"\n\"*.cache\" = \"skip\"\n\"*.log\" = \"text\"\n",
+    )
+    .unwrap();
+    // The nested hint file wins for its subtree
+    std::fs::write(nested.join(HINT_FILE), "\"*.pdata\" = \"text\"\n").unwrap();
+    for file in [
+        "top.pdata",
+        "plugins/a.pdata",
+        "plugins/notes.cache",
+        "plugins/notes.json",
+        "plugins/latest.log",
+        "plugins/Mod/b.pdata",
+        "plugins/Mod/Deep/c.pdata",
+    ] {
+        std::fs::write(
+            world.join(file),
+            "owner: 2d318504-1a7b-39dc-8c18-44df798a5c06\n",
+        )
+        .unwrap();
+    }
+
+    let mut registry = Registry::builtin();
+    // The global overrides win over the hint files
+    registry.set_override("log", HandlerKind::Skip);
+    let options = RemapOptions::default();
+    let mut tasks = scan_world(&world, &mut registry, &options)
+        .unwrap()
+        .tasks
+        .into_iter()
+        .map(|task| task.path)
+        .collect::<Vec<_>>();
+    tasks.sort();
+    assert_eq!(
+        tasks,
+        [
+            "plugins/Mod/Deep/c.pdata",
+            "plugins/Mod/b.pdata",
+            "plugins/a.pdata",
+            "plugins/notes.json",
+        ]
+        .map(PathBuf::from)
+    );
+    let resolve = |path: &str| {
+        let (handler, origin) = registry.resolve(Path::new(path), b"");
+        (handler.map(|h| h.name()), origin)
+    };
+    let hint = |dir: &str| Origin::Hint(PathBuf::from(dir));
+    assert_eq!(
+        resolve("plugins/a.pdata"),
+        (Some("nbt-gzip"), hint("plugins"))
+    );
+    assert_eq!(
+        resolve("plugins/Mod/Deep/c.pdata"),
+        (Some("text"), hint("plugins/Mod"))
+    );
+    assert_eq!(resolve("plugins/notes.cache"), (None, hint("plugins")));
+    assert_eq!(resolve("plugins/latest.log"), (None, Origin::Override));
+    assert_eq!(
+        resolve("plugins/notes.json"),
+        (Some("text"), Origin::Detected)
+    );
+    assert_eq!(resolve("top.pdata"), (None, Origin::Detected));
+    assert_eq!(
+        hint("plugins/Mod").to_string(),
+        Path::new("plugins/Mod")
+            .join(HINT_FILE)
+            .display()
+            .to_string()
+    );
+
+    // The rules apply when remapping as well
+    let to = Uuid::nil();
+    let stats = remap_file(
+        &world,
+        Path::new("plugins/Mod/b.pdata"),
+        &registry,
+        &options,
+        &Progress::hidden(),
+        &|_| Some(to),
+    )
+    .unwrap();
+    assert!(stats.renamed.is_none());
+    assert_eq!(
+        std::fs::read_to_string(nested.join("b.pdata")).unwrap(),
+        format!("owner: {}\n", to)
+    );
+
+    std::fs::write(nested.join(HINT_FILE), "\"*.pdata\" = \"yaml\"\n").unwrap();
+    let err = scan_world(&world, &mut Registry::builtin(), &options).unwrap_err();
+    assert!(
+        format!("{:#}", err).contains("Unknown handler yaml"),
+        "{:#}",
+        err
+    );
+    std::fs::remove_dir_all(&world).unwrap();
+}
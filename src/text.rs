@@ -1,25 +1,126 @@
+use std::ops::Range;
+
 use uuid::Uuid;
 
+use crate::source::{located, tagged, Location, Source};
+
+/// The line endings of a text, the rewrites keep them as they are
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineEnding {
+    /// A single line
+    None,
+    Lf,
+    Crlf,
+    /// A lone `\r`, e.g. old plugin exports
+    Cr,
+    Mixed,
+}
+
+impl LineEnding {
+    pub fn detect(text: &[u8]) -> Self {
+        let (mut lf, mut crlf, mut cr) = (false, false, false);
+        for (_, ending) in split_lines(text) {
+            match ending {
+                b"\n" => lf = true,
+                b"\r\n" => crlf = true,
+                b"\r" => cr = true,
+                _ => {}
+            }
+        }
+        match (lf, crlf, cr) {
+            (false, false, false) => LineEnding::None,
+            (true, false, false) => LineEnding::Lf,
+            (false, true, false) => LineEnding::Crlf,
+            (false, false, true) => LineEnding::Cr,
+            _ => LineEnding::Mixed,
+        }
+    }
+}
+
+impl std::fmt::Display for LineEnding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            LineEnding::None => "no line ending",
+            LineEnding::Lf => "LF",
+            LineEnding::Crlf => "CRLF",
+            LineEnding::Cr => "CR",
+            LineEnding::Mixed => "mixed line endings",
+        })
+    }
+}
+
+/// The ranges of the lines without their endings, and the endings, which are `\n`, `\r\n` or a lone `\r`
+fn split_lines(text: &[u8]) -> Vec<(Range<usize>, &'static [u8])> {
+    let mut lines = vec![];
+    let (mut start, mut i) = (0, 0);
+    while i < text.len() {
+        let ending: &'static [u8] = match (text[i], text.get(i + 1)) {
+            (b'\n', _) => b"\n",
+            (b'\r', Some(b'\n')) => b"\r\n",
+            (b'\r', _) => b"\r",
+            _ => {
+                i += 1;
+                continue;
+            }
+        };
+        lines.push((start..i, ending));
+        i += ending.len();
+        start = i;
+    }
+    if start < text.len() {
+        lines.push((start..text.len(), b""));
+    }
+    lines
+}
+
+/// The ranges of the lines without their endings, for the rewrites working on lines
+pub fn lines(text: &[u8]) -> Vec<Range<usize>> {
+    split_lines(text)
+        .into_iter()
+        .map(|(range, _)| range)
+        .collect()
+}
+
 // Remap UUIDs in a text buffer
 // Don't use &str since performance is critical here
-#[allow(clippy::manual_is_ascii_check)]
 pub fn visit_text(text: &mut [u8], cb: &impl Fn(Uuid) -> Option<Uuid>) {
-    #[inline]
-    fn is_digit(c: u8) -> bool {
-        (b'0'..=b'9').contains(&c) || (b'a'..=b'f').contains(&c)
-    }
-    #[inline]
-    fn from_hex_char(c: u8) -> u32 {
+    visit::<false>(text, cb);
+}
+
+/// Like `visit_text`, also returning the sorted ranges replaced, which the passes after it skip
+pub fn visit_text_replaced(
+    text: &mut [u8],
+    cb: &impl Fn(Uuid) -> Option<Uuid>,
+) -> Vec<Range<usize>> {
+    visit::<false>(text, cb)
+}
+
+// Remap UUIDs in a file name, also uppercase ones, e.g. written by plugins on Windows
+// An uppercase uuid is replaced with an uppercase one, so the name keeps its style
+pub fn visit_file_name(name: &mut [u8], cb: &impl Fn(Uuid) -> Option<Uuid>) {
+    tagged(Source::FileName, || visit::<true>(name, cb));
+}
+
+#[allow(clippy::manual_is_ascii_check)]
+fn visit<const ANY_CASE: bool>(
+    text: &mut [u8],
+    cb: &impl Fn(Uuid) -> Option<Uuid>,
+) -> Vec<Range<usize>> {
+    // Closures, as nested functions can't use the const parameter
+    let is_upper = |c: u8| ANY_CASE && (b'A'..=b'F').contains(&c);
+    let is_digit = |c: u8| (b'0'..=b'9').contains(&c) || (b'a'..=b'f').contains(&c) || is_upper(c);
+    let from_hex_char = |c: u8| {
         if (b'0'..=b'9').contains(&c) {
             (c - b'0') as u32
         } else if (b'a'..=b'f').contains(&c) {
             (c - b'a' + 10) as u32
+        } else if is_upper(c) {
+            (c - b'A' + 10) as u32
         } else {
             u32::MAX
         }
-    }
-    #[inline]
-    fn from_hex(str: &[u8]) -> u128 {
+    };
+    let from_hex = |str: &[u8]| {
         let mut ret = 0;
         for c in str {
             if is_digit(*c) {
@@ -27,18 +128,29 @@ pub fn visit_text(text: &mut [u8], cb: &impl Fn(Uuid) -> Option<Uuid>) {
             }
         }
         ret
-    }
+    };
     #[inline]
-    fn to_hex_char(c: u32) -> u8 {
+    fn to_hex_char(c: u32, upper: bool) -> u8 {
         if c < 10 {
             b'0' + c as u8
+        } else if upper {
+            b'A' + c as u8 - 10
         } else {
             b'a' + c as u8 - 10
         }
     }
+    // One scan of the original text for both patterns, a replacement is never matched again
     // Pattern: xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx
-    let mut matched = 0;
+    let mut dashed = vec![];
+    // Pattern: xxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxx
+    let mut undashed = vec![];
+    let (mut matched, mut run) = (0, 0);
     for i in 0..text.len() {
+        run = if is_digit(text[i]) { run + 1 } else { 0 };
+        if run == 32 {
+            run = 0;
+            undashed.push(i - 31..i + 1);
+        }
         if !is_digit(text[i]) && text[i] != b'-' {
             matched = 0;
             continue;
@@ -68,48 +180,57 @@ pub fn visit_text(text: &mut [u8], cb: &impl Fn(Uuid) -> Option<Uuid>) {
         };
         if matched == 36 {
             matched = 0;
-            let uuid = Uuid::from_u128(from_hex(&text[i - 35..i + 1]));
-            if let Some(new_uuid) = cb(uuid) {
-                let new_uuid = new_uuid.as_bytes();
-                let mut ptr = 0;
-                for c in text[i - 35..i + 1].iter_mut() {
-                    if *c == b'-' {
-                        continue;
-                    }
-                    if (ptr & 1) == 0 {
-                        *c = to_hex_char((new_uuid[ptr >> 1] >> 4) as u32);
-                    } else {
-                        *c = to_hex_char((new_uuid[ptr >> 1] & 0xF) as u32);
-                    }
-                    ptr += 1;
-                }
-            }
+            dashed.push(i - 35..i + 1);
         }
     }
 
-    // Pattern: xxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxx
-    let mut matched = 0;
-    for i in 0..text.len() {
-        if !is_digit(text[i]) {
-            matched = 0;
-            continue;
-        }
-        matched += 1;
-        if matched == 32 {
-            matched = 0;
-            let uuid = Uuid::from_u128(from_hex(&text[i - 31..i + 1]));
-            if let Some(new_uuid) = cb(uuid) {
-                let new_uuid = new_uuid.as_bytes();
-                for (ptr, c) in text[i - 31..i + 1].iter_mut().enumerate() {
-                    if (ptr & 1) == 0 {
-                        *c = to_hex_char((new_uuid[ptr >> 1] >> 4) as u32);
-                    } else {
-                        *c = to_hex_char((new_uuid[ptr >> 1] & 0xF) as u32);
-                    }
-                }
+    let mut replace = |range: Range<usize>, source: Source| {
+        let uuid = Uuid::from_u128(from_hex(&text[range.clone()]));
+        let offset = |location: &mut Location| location.offset = Some(range.start);
+        let Some(new_uuid) = located(offset, || tagged(source, || cb(uuid))) else {
+            return false;
+        };
+        let new_uuid = new_uuid.as_bytes();
+        let upper = text[range.clone()].iter().any(|c| is_upper(*c));
+        let mut ptr = 0;
+        for c in text[range].iter_mut() {
+            if *c == b'-' {
+                continue;
+            }
+            if (ptr & 1) == 0 {
+                *c = to_hex_char((new_uuid[ptr >> 1] >> 4) as u32, upper);
+            } else {
+                *c = to_hex_char((new_uuid[ptr >> 1] & 0xF) as u32, upper);
             }
+            ptr += 1;
+        }
+        true
+    };
+    // The dashed form wins, an undashed run overlapping a replaced dashed uuid is left alone
+    let mut replaced = vec![];
+    for range in dashed {
+        if replace(range.clone(), Source::TextDashed) {
+            replaced.push(range);
+        }
+    }
+    let mut undashed_replaced = vec![];
+    let mut dashed_replaced = replaced.iter().peekable();
+    for range in undashed {
+        while dashed_replaced
+            .next_if(|dashed| dashed.end <= range.start)
+            .is_some()
+        {}
+        if dashed_replaced
+            .peek()
+            .is_none_or(|dashed| dashed.start >= range.end)
+            && replace(range.clone(), Source::TextUndashed)
+        {
+            undashed_replaced.push(range);
         }
     }
+    replaced.extend(undashed_replaced);
+    replaced.sort_by_key(|range| range.start);
+    replaced
 }
 
 #[cfg(test)]
@@ -150,3 +271,205 @@ fn test_visit_text() {
         br#"{"name":"CaveNightingale", "uuid":"00000000-0000-0000-0000-000000000000"}"#
     );
 }
+
+#[cfg(test)]
+#[test]
+fn test_visit_text_boundaries() {
+    use std::{cell::Cell, str::FromStr};
+
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    let from = Uuid::from_str("2d318504-1a7b-39dc-8c18-44df798a5c06").unwrap();
+    let to = Uuid::from_str("fb1ad51e-cf1f-41f7-8fd1-10dff164b17d").unwrap();
+    let calls = Cell::new(0);
+    let cb = |uuid| {
+        calls.set(calls.get() + 1);
+        (uuid == from).then_some(to)
+    };
+    let forms = [
+        (from.hyphenated().to_string(), to.hyphenated().to_string()),
+        (from.simple().to_string(), to.simple().to_string()),
+    ];
+    // Every offset from the start and distance from the end, with separators that never match
+    for (old, new) in &forms {
+        for before in 0..40 {
+            for after in 0..40 {
+                for pad in [b' ', b'"', b'z', b'A', b'\n'] {
+                    let surround = |uuid: &str| {
+                        let mut text = vec![pad; before];
+                        text.extend_from_slice(uuid.as_bytes());
+                        text.extend(std::iter::repeat_n(pad, after));
+                        text
+                    };
+                    let mut text = surround(old);
+                    calls.set(0);
+                    visit_text(&mut text, &cb);
+                    assert_eq!(text, surround(new), "{} at {}+{}", old, before, after);
+                    assert_eq!(calls.get(), 1);
+                }
+            }
+        }
+    }
+
+    // Adjacent occurrences, separated by a single byte
+    let mut text = format!("{}:{}", from, from).into_bytes();
+    visit_text(&mut text, &cb);
+    assert_eq!(text, format!("{}:{}", to, to).into_bytes());
+    let mut text = format!("{},{}", from.simple(), from.simple()).into_bytes();
+    visit_text(&mut text, &cb);
+    assert_eq!(
+        text,
+        format!("{},{}", to.simple(), to.simple()).into_bytes()
+    );
+
+    // Every truncation of a uuid at either end is not a uuid
+    for (old, _) in &forms {
+        for len in 0..old.len() {
+            for part in [&old[..len], &old[old.len() - len..]] {
+                let mut text = part.as_bytes().to_vec();
+                calls.set(0);
+                visit_text(&mut text, &cb);
+                assert_eq!(text, part.as_bytes());
+                assert_eq!(calls.get(), 0, "{}", part);
+            }
+        }
+    }
+
+    // Extra hex digits before the first group are not part of the uuid
+    let mut text = format!("0{}", from).into_bytes();
+    visit_text(&mut text, &cb);
+    assert_eq!(text, format!("0{}", to).into_bytes());
+    // Empty and short buffers
+    for len in 0..4 {
+        let mut text = vec![b'-'; len];
+        visit_text(&mut text, &cb);
+        assert_eq!(text, vec![b'-'; len]);
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_visit_text_single_application() {
+    use std::{cell::Cell, collections::HashMap, str::FromStr};
+
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    let a = Uuid::from_str("2d318504-1a7b-39dc-8c18-44df798a5c06").unwrap();
+    let b = Uuid::from_str("fb1ad51e-cf1f-41f7-8fd1-10dff164b17d").unwrap();
+    let c = Uuid::from_str("069a79f4-44e9-4726-a5be-fca90e38aaf5").unwrap();
+    let hex = |digits: String| Uuid::from_str(&digits).unwrap();
+    // Hex digits right before and after a dashed uuid make undashed runs with its first and last
+    // groups, both before and after it is replaced
+    let before = "0123456789abcdef01234567";
+    let after = "0123456789abcdef0123";
+    let first = |uuid: Uuid| hex(format!("{}{}", before, &uuid.simple().to_string()[..8]));
+    let last = |uuid: Uuid| hex(format!("{}{}", &uuid.simple().to_string()[20..], after));
+    let calls = Cell::new(0);
+    let visit = |text: String, mapping: &HashMap<Uuid, Uuid>| {
+        let mut text = text.into_bytes();
+        calls.set(0);
+        visit_text(&mut text, &|uuid| {
+            calls.set(calls.get() + 1);
+            mapping.get(&uuid).copied()
+        });
+        String::from_utf8(text).unwrap()
+    };
+
+    // A -> B, B -> C, and every run around A or B mapped too: only A is replaced, once
+    let mapping = HashMap::from([
+        (a, b),
+        (b, c),
+        (first(a), c),
+        (first(b), c),
+        (last(a), c),
+        (last(b), c),
+    ]);
+    for (old, new) in [
+        (format!("{}{}", before, a), format!("{}{}", before, b)),
+        (format!("{}{}", a, after), format!("{}{}", b, after)),
+        (
+            format!("{}{}{}", before, a, after),
+            format!("{}{}{}", before, b, after),
+        ),
+    ] {
+        assert_eq!(visit(old.clone(), &mapping), new, "{}", old);
+        assert_eq!(calls.get(), 1, "{}", old);
+    }
+    // B -> C in the dashed form, A -> B in the undashed form, each applied once
+    let text = format!("{} {}", b, a.simple());
+    assert_eq!(visit(text, &mapping), format!("{} {}", c, b.simple()));
+    assert_eq!(calls.get(), 2);
+
+    // A dashed uuid left as is doesn't hide the run overlapping it
+    let mapping = HashMap::from([(first(a), c)]);
+    let text = format!("{}{}", before, a);
+    assert_eq!(
+        visit(text.clone(), &mapping),
+        format!("{}{}", c.simple(), &text[32..])
+    );
+    assert_eq!(calls.get(), 2);
+}
+
+#[cfg(test)]
+#[test]
+fn test_visit_file_name() {
+    use std::str::FromStr;
+
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    let from = Uuid::from_str("2d318504-1a7b-39dc-8c18-44df798a5c06").unwrap();
+    let to = Uuid::from_str("fb1ad51e-cf1f-41f7-8fd1-10dff164b17d").unwrap();
+    let cb = |uuid| (uuid == from).then_some(to);
+    let upper = |uuid: String| uuid.to_uppercase();
+    // The contents only have lowercase uuids
+    let mut text = upper(from.to_string()).into_bytes();
+    visit_text(&mut text, &cb);
+    assert_eq!(text, upper(from.to_string()).into_bytes());
+
+    for (old, new) in [
+        (from.to_string(), to.to_string()),
+        (upper(from.to_string()), upper(to.to_string())),
+        (
+            upper(from.simple().to_string()),
+            upper(to.simple().to_string()),
+        ),
+        // Mixed case is written in uppercase
+        (
+            "2D318504-1a7b-39dc-8c18-44df798a5c06".to_string(),
+            upper(to.to_string()),
+        ),
+    ] {
+        let mut name = format!("userdata/{}.yml", old).into_bytes();
+        visit_file_name(&mut name, &cb);
+        assert_eq!(name, format!("userdata/{}.yml", new).into_bytes());
+    }
+    // Uppercase letters outside the hex digits are not a uuid
+    let mut name = b"2G318504-1A7B-39DC-8C18-44DF798A5C06.yml".to_vec();
+    visit_file_name(&mut name, &cb);
+    assert_eq!(name, b"2G318504-1A7B-39DC-8C18-44DF798A5C06.yml");
+}
+
+#[cfg(test)]
+#[test]
+fn test_line_endings() {
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    let text = b"a: 1\r\nb: 2\r\n\r\nc";
+    assert_eq!(lines(text), vec![0..4, 6..10, 12..12, 14..15]);
+    assert_eq!(LineEnding::detect(text), LineEnding::Crlf);
+    assert_eq!(lines(b"a\rb\r"), vec![0..1, 2..3]);
+    assert_eq!(LineEnding::detect(b"a\rb\r"), LineEnding::Cr);
+    assert_eq!(LineEnding::detect(b"a\nb\n"), LineEnding::Lf);
+    assert_eq!(LineEnding::detect(b"a\nb\r\nc\r"), LineEnding::Mixed);
+    assert_eq!(LineEnding::detect(b"a"), LineEnding::None);
+    assert_eq!(lines(b""), Vec::<Range<usize>>::new());
+    assert_eq!(LineEnding::Crlf.to_string(), "CRLF");
+}
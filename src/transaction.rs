@@ -0,0 +1,555 @@
+use std::{
+    ffi::OsString,
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::guard::hard_links;
+
+/// The steps of the commit, written before the first one
+pub const JOURNAL: &str = "journal.json";
+
+/// The indices of the completed steps, one per line, appended after each of them
+pub const DONE: &str = "journal.done";
+
+/// A modification recorded while staging, applied in order by `Staging::commit`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum Step {
+    /// Move the target to the backup if it exists, then move the staged file over it with the
+    /// permissions of the target; a target with other hard links is copied to the backup and
+    /// overwritten in place instead, like outside a transaction
+    Replace {
+        staged: PathBuf,
+        target: PathBuf,
+        backup: PathBuf,
+    },
+    /// Move the target to the backup if it exists, e.g. created since it was staged, then move
+    /// the file over it
+    Rename {
+        from: PathBuf,
+        to: PathBuf,
+        backup: PathBuf,
+    },
+    /// Move the target to the backup, deleted with the staging directory
+    Remove { target: PathBuf, backup: PathBuf },
+}
+
+impl Step {
+    fn apply(&self) -> std::io::Result<()> {
+        match self {
+            Step::Replace {
+                staged,
+                target,
+                backup,
+            } => {
+                match std::fs::metadata(target) {
+                    Ok(meta) if hard_links(&meta) > 1 => {
+                        // Complete before it is named, so a revert never restores part of it
+                        let partial = backup.with_extension("partial");
+                        std::fs::copy(target, &partial)?;
+                        std::fs::rename(partial, backup)?;
+                        std::fs::set_permissions(staged, meta.permissions())?;
+                        std::fs::copy(staged, target)?;
+                        return Ok(());
+                    }
+                    Ok(meta) => {
+                        std::fs::set_permissions(staged, meta.permissions())?;
+                        std::fs::rename(target, backup)?;
+                    }
+                    Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(err) => return Err(err),
+                }
+                std::fs::rename(staged, target)
+            }
+            Step::Rename { from, to, backup } => {
+                if to.symlink_metadata().is_ok() {
+                    std::fs::rename(to, backup)?;
+                }
+                std::fs::rename(from, to)
+            }
+            Step::Remove { target, backup } => std::fs::rename(target, backup),
+        }
+    }
+
+    /// Undo the step, also when it stopped halfway, `applied` is unset if it failed
+    fn revert(&self, applied: bool) -> std::io::Result<()> {
+        match self {
+            Step::Replace {
+                staged,
+                target,
+                backup,
+            } => {
+                if !staged.exists() && target.exists() {
+                    std::fs::rename(target, staged)?;
+                }
+                // Both are left only when the target is overwritten in place
+                if backup.exists() && target.exists() {
+                    std::fs::copy(backup, target)?;
+                } else if backup.exists() {
+                    std::fs::rename(backup, target)?;
+                }
+            }
+            Step::Rename { from, to, backup } => {
+                // Moving is the last part of a rename, so a failed one left the target alone,
+                // which may be a file created meanwhile, only the backup is moved back
+                if applied && to.exists() {
+                    if from.exists() {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::AlreadyExists,
+                            format!("{} was created meanwhile", from.display()),
+                        ));
+                    }
+                    std::fs::rename(to, from)?;
+                }
+                if backup.exists() && !to.exists() {
+                    std::fs::rename(backup, to)?;
+                }
+            }
+            Step::Remove { target, backup } => {
+                if backup.exists() {
+                    std::fs::rename(backup, target)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Write the file and flush it to the disk, the journal must survive a crash
+fn write_synced(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let mut file = File::create(path)?;
+    file.write_all(contents)?;
+    file.sync_all()
+}
+
+/// Fail if the filesystem of the directory has less than `needed` bytes free for the files
+fn check_free_space(dir: &Path, needed: u64, files: usize) -> anyhow::Result<()> {
+    let free = free_space(dir).with_context(|| format!("Failed to check {}", dir.display()))?;
+    let Some(free) = free else {
+        return Ok(());
+    };
+    if needed > free {
+        anyhow::bail!(
+            "Staging the {} files needs {} bytes, but only {} bytes are free for {}",
+            files,
+            needed,
+            free,
+            dir.display()
+        );
+    }
+    Ok(())
+}
+
+/// The bytes available to the user on the filesystem of the path, `None` if unknown
+#[cfg(unix)]
+#[allow(clippy::unnecessary_cast)]
+fn free_space(path: &Path) -> std::io::Result<Option<u64>> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes())?;
+    let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: the path is a valid C string, and the struct is written on success
+    if unsafe { libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    // SAFETY: statvfs succeeded
+    let stat = unsafe { stat.assume_init() };
+    Ok(Some(stat.f_bavail as u64 * stat.f_frsize as u64))
+}
+
+#[cfg(not(unix))]
+fn free_space(_path: &Path) -> std::io::Result<Option<u64>> {
+    Ok(None)
+}
+
+/// The modifications of a transactional run, staged in a directory next to the world
+/// Nothing in the world is touched until `commit`, the directory is removed when dropped
+#[derive(Debug)]
+pub struct Staging {
+    dir: PathBuf,
+    next: AtomicUsize,
+    steps: Mutex<Vec<Step>>,
+    /// Set when a commit fails and can't be reverted, the journal is needed to recover
+    keep: AtomicBool,
+}
+
+impl Staging {
+    /// Next to the world, so the staged files are moved in place on the same filesystem
+    pub fn dir_for(world: &Path) -> anyhow::Result<PathBuf> {
+        let world = std::path::absolute(world)?;
+        let name = world.file_name().context("Invalid world path")?;
+        let mut staging = OsString::from(".");
+        staging.push(name);
+        staging.push(".uuid-remapper-staging");
+        Ok(world.with_file_name(staging))
+    }
+
+    /// Refused if the directory exists, as it holds the journal of a commit `recover` failed to revert
+    pub fn new(world: &Path) -> anyhow::Result<Self> {
+        let dir = Self::dir_for(world)?;
+        if dir.exists() {
+            anyhow::bail!(
+                "{} exists, a previous transactional run was interrupted and could not be \
+                reverted, revert it with {} and remove the directory",
+                dir.display(),
+                dir.join(JOURNAL).display()
+            );
+        }
+        for sub in ["files", "backup"] {
+            std::fs::create_dir_all(dir.join(sub))
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        Ok(Self {
+            dir,
+            next: AtomicUsize::new(0),
+            steps: Mutex::new(vec![]),
+            keep: AtomicBool::new(false),
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Refuse to stage the files if their total size is more than the free space of the
+    /// filesystem of the staging directory, each modified file is staged whole
+    pub fn check_space(&self, files: &[PathBuf]) -> anyhow::Result<()> {
+        let needed = files
+            .iter()
+            .filter_map(|file| std::fs::metadata(file).ok())
+            .map(|metadata| metadata.len())
+            .sum();
+        check_free_space(&self.dir, needed, files.len())
+    }
+
+    /// Number of modifications staged so far
+    pub fn steps(&self) -> usize {
+        self.steps.lock().unwrap().len()
+    }
+
+    /// A new file for staged content, and where the original is moved to
+    fn slot(&self) -> (PathBuf, PathBuf) {
+        let name = self.next.fetch_add(1, Ordering::Relaxed).to_string();
+        (
+            self.dir.join("files").join(&name),
+            self.dir.join("backup").join(name),
+        )
+    }
+
+    fn push(&self, step: Step) {
+        self.steps.lock().unwrap().push(step);
+    }
+
+    pub fn write(&self, path: &Path, contents: &[u8]) -> std::io::Result<()> {
+        let (staged, backup) = self.slot();
+        std::fs::write(&staged, contents)?;
+        self.push(Step::Replace {
+            staged,
+            target: std::path::absolute(path)?,
+            backup,
+        });
+        Ok(())
+    }
+
+    /// Create a staged file to be written incrementally
+    pub fn create(&self, path: &Path) -> std::io::Result<File> {
+        let (staged, backup) = self.slot();
+        let file = File::create(&staged)?;
+        self.push(Step::Replace {
+            staged,
+            target: std::path::absolute(path)?,
+            backup,
+        });
+        Ok(file)
+    }
+
+    pub fn rename(&self, from: &Path, to: &Path) -> std::io::Result<()> {
+        let (_, backup) = self.slot();
+        self.push(Step::Rename {
+            from: std::path::absolute(from)?,
+            to: std::path::absolute(to)?,
+            backup,
+        });
+        Ok(())
+    }
+
+    /// Stage the file created for `temp` as the new content of the target instead
+    pub fn replace(&self, temp: &Path, target: &Path) -> std::io::Result<()> {
+        let (temp, target) = (std::path::absolute(temp)?, std::path::absolute(target)?);
+        let mut steps = self.steps.lock().unwrap();
+        let staged = steps.iter_mut().rev().find_map(|step| match step {
+            Step::Replace { target: t, .. } if *t == temp => Some(t),
+            _ => None,
+        });
+        match staged {
+            Some(staged) => *staged = target,
+            None => steps.push(Step::Rename {
+                from: temp,
+                to: target,
+                backup: self.slot().1,
+            }),
+        }
+        Ok(())
+    }
+
+    pub fn remove(&self, path: &Path) -> std::io::Result<()> {
+        let (_, backup) = self.slot();
+        self.push(Step::Remove {
+            target: std::path::absolute(path)?,
+            backup,
+        });
+        Ok(())
+    }
+
+    /// Forget the last file staged for the path, e.g. an unfinished region
+    pub fn discard(&self, path: &Path) -> std::io::Result<()> {
+        let target = std::path::absolute(path)?;
+        let mut steps = self.steps.lock().unwrap();
+        let found = steps
+            .iter()
+            .rposition(|step| matches!(step, Step::Replace { target: t, .. } if *t == target));
+        if let Some(Step::Replace { staged, .. }) = found.map(|i| steps.remove(i)) {
+            std::fs::remove_file(staged)?;
+        }
+        Ok(())
+    }
+
+    /// Apply the steps in order, returning their number
+    /// If a step fails, the completed ones are reverted, and the journal is kept if that fails too
+    pub fn commit(&self) -> anyhow::Result<usize> {
+        let steps = std::mem::take(&mut *self.steps.lock().unwrap());
+        write_synced(&self.dir.join(JOURNAL), &serde_json::to_vec_pretty(&steps)?)?;
+        let mut done = File::create(self.dir.join(DONE))?;
+        for (i, step) in steps.iter().enumerate() {
+            let result = match step.apply() {
+                Ok(()) => writeln!(done, "{}", i)
+                    .and_then(|()| done.sync_data())
+                    .map_err(|err| (err, true)),
+                Err(err) => Err((err, false)),
+            };
+            let Err((err, applied)) = result else {
+                continue;
+            };
+            // The failed step may be half done
+            let reverted = std::iter::once((step, applied))
+                .chain(steps[..i].iter().rev().map(|step| (step, true)))
+                .try_for_each(|(step, applied)| step.revert(applied));
+            return Err(match reverted {
+                Ok(()) => {
+                    anyhow::anyhow!("Failed to apply {:?}: {}, the world is reverted", step, err)
+                }
+                Err(revert_err) => {
+                    self.keep.store(true, Ordering::Relaxed);
+                    anyhow::anyhow!(
+                        "Failed to apply {:?}: {}, then failed to revert: {}, \
+                        the steps in {} are recorded in {}",
+                        step,
+                        err,
+                        revert_err,
+                        self.dir.join(JOURNAL).display(),
+                        self.dir.join(DONE).display()
+                    )
+                }
+            });
+        }
+        Ok(steps.len())
+    }
+}
+
+/// Revert the commit of a transactional run on the world interrupted e.g. by a crash, from its
+/// journal, and remove the staging directory, returning the number of reverted steps
+/// `None` if there is no staging directory, the directory is kept if the revert fails
+/// A run interrupted before committing left the world untouched, a finished commit is kept
+pub fn recover(world: &Path) -> anyhow::Result<Option<usize>> {
+    let dir = Staging::dir_for(world)?;
+    if !dir.exists() {
+        return Ok(None);
+    }
+    let journal = dir.join(JOURNAL);
+    let mut reverted = 0;
+    if journal.exists() {
+        let steps: Vec<Step> = serde_json::from_slice(&std::fs::read(&journal)?)
+            .with_context(|| format!("Failed to read {}", journal.display()))?;
+        // The steps are applied in order, a line cut by the crash is not a completed step
+        let done = match std::fs::read_to_string(dir.join(DONE)) {
+            Ok(done) => done
+                .lines()
+                .filter(|line| line.parse::<usize>().is_ok())
+                .count(),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(err) => return Err(err.into()),
+        };
+        if done < steps.len() {
+            // The step after the completed ones may be done without being recorded
+            let next = &steps[done];
+            let applied = !matches!(next, Step::Rename { from, .. } if from.exists());
+            std::iter::once((next, applied))
+                .chain(steps[..done].iter().rev().map(|step| (step, true)))
+                .try_for_each(|(step, applied)| step.revert(applied))
+                .with_context(|| {
+                    format!(
+                        "Failed to revert the interrupted commit recorded in {}",
+                        journal.display()
+                    )
+                })?;
+            reverted = done + 1;
+        }
+    }
+    std::fs::remove_dir_all(&dir).with_context(|| format!("Failed to remove {}", dir.display()))?;
+    Ok(Some(reverted))
+}
+
+impl Drop for Staging {
+    /// Remove the staged files and the backups, unless they are needed to recover
+    fn drop(&mut self) {
+        if self.keep.load(Ordering::Relaxed) {
+            return;
+        }
+        if let Err(err) = std::fs::remove_dir_all(&self.dir) {
+            log::warn!("Failed to remove {}: {}", self.dir.display(), err);
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    use crate::setup_test_logger;
+
+    setup_test_logger();
+
+    let dir = std::env::temp_dir().join("test_transaction");
+    let _ = std::fs::remove_dir_all(&dir);
+    let world = dir.join("world");
+    std::fs::create_dir_all(&world).unwrap();
+    let (a, b, c) = (
+        world.join("a.txt"),
+        world.join("b.txt"),
+        world.join("c.txt"),
+    );
+    std::fs::write(&a, "a").unwrap();
+    std::fs::write(&c, "c").unwrap();
+    let read = |path: &Path| std::fs::read_to_string(path).ok();
+
+    let staging = Staging::new(&world).unwrap();
+    assert_eq!(
+        staging.dir(),
+        dir.join(".world.uuid-remapper-staging").as_path()
+    );
+    assert!(Staging::new(&world).is_err());
+    // The files fit, a world larger than the filesystem does not
+    staging.check_space(&[a.clone(), c.clone()]).unwrap();
+    #[cfg(unix)]
+    assert!(check_free_space(staging.dir(), u64::MAX, 2).is_err());
+    staging.write(&a, b"remapped").unwrap();
+    staging.rename(&a, &b).unwrap();
+    staging.remove(&c).unwrap();
+    write!(staging.create(&c).unwrap(), "unfinished").unwrap();
+    staging.discard(&c).unwrap();
+    // Nothing is modified until committed
+    assert_eq!(read(&a).as_deref(), Some("a"));
+    assert_eq!(staging.steps(), 3);
+    assert_eq!(staging.commit().unwrap(), 3);
+    drop(staging);
+    assert_eq!(read(&a), None);
+    assert_eq!(read(&b).as_deref(), Some("remapped"));
+    assert_eq!(read(&c), None);
+    assert!(!Staging::dir_for(&world).unwrap().exists());
+
+    // The third step fails, as the file is moved by the second one
+    let staging = Staging::new(&world).unwrap();
+    staging.write(&b, b"again").unwrap();
+    staging.rename(&b, &a).unwrap();
+    staging.remove(&b).unwrap();
+    assert!(staging.commit().is_err());
+    drop(staging);
+    assert_eq!(read(&a), None);
+    assert_eq!(read(&b).as_deref(), Some("remapped"));
+
+    // A file created at the target since the rename was staged is restored with the revert
+    std::fs::write(&a, "meanwhile").unwrap();
+    let staging = Staging::new(&world).unwrap();
+    staging.rename(&b, &a).unwrap();
+    staging.remove(&c).unwrap();
+    assert!(staging.commit().is_err());
+    drop(staging);
+    assert_eq!(read(&a).as_deref(), Some("meanwhile"));
+    assert_eq!(read(&b).as_deref(), Some("remapped"));
+    // Also when the rename itself fails, the target is not taken for the renamed file
+    let staging = Staging::new(&world).unwrap();
+    staging.rename(&c, &a).unwrap();
+    assert!(staging.commit().is_err());
+    drop(staging);
+    assert_eq!(read(&a).as_deref(), Some("meanwhile"));
+    assert_eq!(read(&c), None);
+    std::fs::remove_file(&a).unwrap();
+
+    // Interrupted after the first step and during the second, both are reverted by the next run
+    assert_eq!(recover(&world).unwrap(), None);
+    let staging = Staging::new(&world).unwrap();
+    staging.write(&b, b"crashed").unwrap();
+    staging.rename(&b, &a).unwrap();
+    let steps = staging.steps.lock().unwrap().clone();
+    write_synced(
+        &staging.dir().join(JOURNAL),
+        &serde_json::to_vec(&steps).unwrap(),
+    )
+    .unwrap();
+    steps[0].apply().unwrap();
+    std::fs::write(staging.dir().join(DONE), "0\n").unwrap();
+    steps[1].apply().unwrap();
+    staging.keep.store(true, Ordering::Relaxed);
+    drop(staging);
+    assert_eq!(read(&a).as_deref(), Some("crashed"));
+    assert_eq!(recover(&world).unwrap(), Some(2));
+    assert_eq!(read(&a), None);
+    assert_eq!(read(&b).as_deref(), Some("remapped"));
+    assert!(!Staging::dir_for(&world).unwrap().exists());
+
+    // Aborted
+    let staging = Staging::new(&world).unwrap();
+    staging.write(&b, b"discarded").unwrap();
+    drop(staging);
+    assert_eq!(read(&b).as_deref(), Some("remapped"));
+    assert!(!Staging::dir_for(&world).unwrap().exists());
+
+    // A file created next to the target is staged as its new content
+    let staging = Staging::new(&world).unwrap();
+    let temp = world.join("b.txt.tmp");
+    write!(staging.create(&temp).unwrap(), "replaced").unwrap();
+    staging.replace(&temp, &b).unwrap();
+    assert_eq!(staging.commit().unwrap(), 1);
+    drop(staging);
+    assert_eq!(read(&b).as_deref(), Some("replaced"));
+    assert!(!temp.exists());
+
+    // A hard-linked target is overwritten in place, and restored in place when reverted
+    #[cfg(unix)]
+    {
+        let link = dir.join("link.txt");
+        std::fs::hard_link(&b, &link).unwrap();
+        let staging = Staging::new(&world).unwrap();
+        staging.write(&b, b"linked").unwrap();
+        assert_eq!(staging.commit().unwrap(), 1);
+        drop(staging);
+        assert_eq!(read(&link).as_deref(), Some("linked"));
+        let staging = Staging::new(&world).unwrap();
+        staging.write(&b, b"reverted").unwrap();
+        // Fails, the file is removed already
+        staging.remove(&c).unwrap();
+        assert!(staging.commit().is_err());
+        drop(staging);
+        assert_eq!(read(&b).as_deref(), Some("linked"));
+        assert_eq!(read(&link).as_deref(), Some("linked"));
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}